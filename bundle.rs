@@ -6,7 +6,7 @@ edition = "2024"
 
 [dependencies]
 clap = { version = "4.5.45", features = ["derive"] }
-dbc = { path = "./server", features = ["bundle"] }
+dbc = { path = "./server", default-features = false, features = ["bundle"] }
 ---
 
 use clap::Parser;
@@ -80,7 +80,7 @@ fn main() {
         step("Building application bundle");
         exec_in(
             "./server",
-            "cargo bundle --release --features bundle,devtools",
+            "cargo bundle --release --no-default-features --features bundle,devtools",
         );
 
         // write timestamp (used to determine whether to re-copy bundle assets on launch)
@@ -109,7 +109,7 @@ fn main() {
         done("Installed to /Applications/dbc.app");
     } else {
         step("Running application");
-        exec_in("./server", "cargo run --features bundle");
+        exec_in("./server", "cargo run --no-default-features --features bundle");
     }
 }
 
@@ -8,6 +8,7 @@ import {
 } from "react-icons/hi";
 import {
   clearNetworkCache,
+  exportCsv,
   get,
   NetworkError,
   paginatedQuery,
@@ -397,6 +398,13 @@ function App() {
 
       setRes(res);
 
+      if (res.extra_statements_ignored) {
+        alertsRef.current?.addAlert({
+          style: "warning",
+          message: "Only the first statement was run",
+        });
+      }
+
       // if the statement modified structure, clear the network cache and refresh tables
       switch (res.type) {
         case "modify-structure": {
@@ -458,36 +466,48 @@ function App() {
     );
   }
 
-  async function handleDownload() {
-    setError(null);
-    setUiLoading(true);
+  async function downloadBlob(): Promise<Blob> {
+    const { query, params, sort, filters } = queryRef.current;
 
-    try {
-      const res = await paginatedQuery(
-        connection!.name,
-        database!,
-        {
-          query: queryRef.current.query,
-          params: queryRef.current.params,
-          sort: queryRef.current.sort,
-          page: 1,
-          pageSize: -1,
-          filters: queryRef.current.filters,
-          useCache: false,
-        },
-      );
+    // Postgres can format the CSV itself (much faster), unless the query needs to be
+    // parameterized, sorted or filtered
+    if (params.length === 0 && !sort && filters.length === 0) {
+      return exportCsv(connection!.name, database!, { query });
+    }
+
+    const res = await paginatedQuery(
+      connection!.name,
+      database!,
+      {
+        query,
+        params,
+        sort,
+        page: 1,
+        pageSize: -1,
+        filters,
+        useCache: false,
+      },
+    );
 
-      const entries = (res as PaginatedSelectQueryResult).entries;
+    const entries = (res as PaginatedSelectQueryResult).entries;
 
-      const headerRow = entries.columns.map((col) => csvEscape(col.name)) +
+    const headerRow = entries.columns.map((col) => csvEscape(col.name)) +
+      "\n";
+
+    const rows = entries.rows.map((row) => {
+      return row.map((val) => csvEscape(stringifyValue(val))).join(",") +
         "\n";
+    });
 
-      const rows = entries.rows.map((row) => {
-        return row.map((val) => csvEscape(stringifyValue(val))).join(",") +
-          "\n";
-      });
+    return new Blob([headerRow].concat(rows), { type: "text/csv" });
+  }
+
+  async function handleDownload() {
+    setError(null);
+    setUiLoading(true);
 
-      const blob = new Blob([headerRow].concat(rows), { type: "text/csv" });
+    try {
+      const blob = await downloadBlob();
       const url = URL.createObjectURL(blob);
       const a = document.createElement("a");
       a.href = url;
@@ -1,12 +1,28 @@
 import {
+  AsyncQueryJob,
+  BatchPrepareResult,
+  FederatedQueryResponse,
   Filter,
+  filterValue,
   PaginatedQueryResult,
   PrepareQueryResult,
+  QueryResponse,
   Sort,
 } from "./models/query.ts";
+import { ExtensionInfo } from "./models/database.ts";
+import {
+  EditableRows,
+  FunctionInfo,
+  RowUpdate,
+  TableColumns,
+} from "./models/table.ts";
 
-const baseUrl = `http://${import.meta.env.VITE_API_BASE}`;
-const socketUrl = `ws://${import.meta.env.VITE_API_BASE}`;
+// set `VITE_API_TLS=true` when the server is configured with `TLS_CERT`/`TLS_KEY`
+const secure = import.meta.env.VITE_API_TLS === "true";
+const baseUrl = `${secure ? "https" : "http"}://${import.meta.env.VITE_API_BASE}`;
+const socketUrl = `${secure ? "wss" : "ws"}://${import.meta.env.VITE_API_BASE}`;
+// must match the server's `API_TOKEN`, if set
+const apiToken: string | undefined = import.meta.env.VITE_API_TOKEN;
 
 const NO_CONTENT = 204;
 
@@ -86,6 +102,7 @@ const req = (method: string) => {
       method,
       headers: {
         ...(opts?.headers ?? {}),
+        ...(apiToken ? { authorization: `Bearer ${apiToken}` } : {}),
         "content-type": "application/json",
       },
       body: JSON.stringify(data),
@@ -152,18 +169,212 @@ export const prepareQuery = (
   connection: string,
   database: string,
   query: string,
+  paramTypes?: string[],
+) =>
+  post<PrepareQueryResult>("/prepare", { query, param_types: paramTypes }, {
+    headers: { "x-conn-name": connection, "x-database": database },
+  });
+
+export const prepareQueries = (
+  connection: string,
+  database: string,
+  queries: string[],
+) =>
+  post<BatchPrepareResult[]>("/prepare/batch", { queries }, {
+    headers: { "x-conn-name": connection, "x-database": database },
+  });
+
+// Send a notification on a channel (the payload must be shorter than 8000 bytes)
+export const notify = (
+  connection: string,
+  database: string,
+  channel: string,
+  payload = "",
 ) =>
-  post<PrepareQueryResult>("/prepare", { query }, {
+  post<null>("/db/notify", { channel, payload }, {
     headers: { "x-conn-name": connection, "x-database": database },
   });
 
+// List the extensions available on the server, and which are installed
+export const listExtensions = (connection: string, database: string) =>
+  rawQuery<ExtensionInfo[]>(connection, database, "/db/extensions");
+
+// Install an extension (if it isn't already), resolving to the updated list
+export const createExtension = (
+  connection: string,
+  database: string,
+  name: string,
+) =>
+  post<ExtensionInfo[]>("/db/extensions", { name }, {
+    headers: {
+      "x-conn-name": connection,
+      "x-database": database,
+      "x-confirm-destructive": "true",
+    },
+  });
+
+// List the columns of several tables in one request, grouped by table
+export const listColumns = (
+  connection: string,
+  database: string,
+  tables: Array<{ schema: string; table: string }>,
+) =>
+  post<TableColumns[]>("/db/columns", { tables }, {
+    headers: { "x-conn-name": connection, "x-database": database },
+  });
+
+export const listFunctions = (
+  connection: string,
+  database: string,
+  schema: string,
+) =>
+  rawQuery<FunctionInfo[]>(
+    connection,
+    database,
+    `/db/schemas/${schema}/functions`,
+  );
+
+// The DDL for one overload of a function, picked by its identity arguments
+export const functionDdl = (
+  connection: string,
+  database: string,
+  schema: string,
+  fn: FunctionInfo,
+) =>
+  rawQuery<{ ddl: string }>(
+    connection,
+    database,
+    `/db/ddl/schemas/${schema}/function/${fn.name}?args=${
+      encodeURIComponent(fn.identity_arguments)
+    }`,
+  );
+
+export const editableRows = (
+  connection: string,
+  database: string,
+  schema: string,
+  table: string,
+  page: number,
+  pageSize: number,
+) =>
+  rawQuery<EditableRows>(
+    connection,
+    database,
+    `/db/schemas/${schema}/tables/${table}/rows?page=${page}&page_size=${pageSize}`,
+  );
+
+// Resolves to the row's new version; fails with a 409 Conflict if the row has been
+// changed since `update.version` was read
+export const updateRow = (
+  connection: string,
+  database: string,
+  schema: string,
+  table: string,
+  update: RowUpdate,
+) =>
+  put<{ version: string }>(
+    `/db/schemas/${schema}/tables/${table}/rows`,
+    update,
+    { headers: { "x-conn-name": connection, "x-database": database } },
+  );
+
+export interface CsvOptions {
+  delimiter?: string;
+  // How NULLs are written (an unquoted empty string by default)
+  null?: string;
+  header?: boolean;
+}
+
+// Export a query's results as CSV, formatted by Postgres (queries can't have params)
+export const exportCsv = async (
+  connection: string,
+  database: string,
+  req: { query: string } & CsvOptions,
+): Promise<Blob> => {
+  const response = await fetch(`${baseUrl}/query/csv`, {
+    mode: "cors",
+    method: "POST",
+    headers: {
+      "x-conn-name": connection,
+      "x-database": database,
+      ...(apiToken ? { authorization: `Bearer ${apiToken}` } : {}),
+      "content-type": "application/json",
+    },
+    body: JSON.stringify(req),
+  });
+
+  if (!response.ok) {
+    throw new NetworkError("text", await response.text());
+  }
+
+  return response.blob();
+};
+
+export interface ScriptOptions {
+  // Parameters, for a script with a single statement
+  params?: Array<string | number | boolean | null>;
+  // Run the whole script in one transaction, rolled back if any statement fails
+  transaction?: boolean;
+}
+
+// Run a SQL file (e.g. a migration) statement by statement, uploading it as-is
+export const runScript = async (
+  connection: string,
+  database: string,
+  file: Blob,
+  opts: ScriptOptions = {},
+): Promise<{ results: PaginatedQueryResult[] }> => {
+  const form = new FormData();
+  form.append("file", file);
+  if (opts.params) form.append("params", JSON.stringify(opts.params));
+  if (opts.transaction) form.append("transaction", "true");
+
+  // the browser sets the multipart content type (with its boundary) itself
+  const response = await fetch(`${baseUrl}/query/script`, {
+    mode: "cors",
+    method: "POST",
+    headers: {
+      "x-conn-name": connection,
+      "x-database": database,
+      ...(apiToken ? { authorization: `Bearer ${apiToken}` } : {}),
+    },
+    body: form,
+  });
+
+  if (!response.ok) {
+    throw new NetworkError("text", await response.text());
+  }
+
+  return response.json();
+};
+
+export const formatQuery = (query: string) =>
+  post<{ query: string }>("/query/format", { query });
+
 export interface PaginatedQueryRequest {
   query: string;
   params: Array<string | number | boolean | null>;
   sort: Sort | null;
   page: number;
+  // Negative for every row on one page (capped at the connection's max page size,
+  // unless the query has its own `LIMIT`)
   pageSize: number;
   filters: Filter[];
+  // Explicit parameter types (e.g. `integer`), overriding Postgres' inference
+  paramTypes?: string[];
+  // For EXPLAIN queries, also run the query and return a few sample rows
+  withSample?: boolean;
+  // For SELECT queries, also return the plan for the page
+  withPlan?: boolean;
+  // Fail if the query has more than one statement, instead of only running the first
+  rejectMultiple?: boolean;
+  // Look up each column's source table and foreign key (on by default)
+  withExtended?: boolean;
+  // For SELECT queries, return the generated SQL instead of running it
+  dry?: boolean;
+  // Also retry writes that conflict with another transaction (reads always are, up
+  // to the connection's `conflict_retries`)
+  retryWrites?: boolean;
   useCache: boolean;
   signal?: AbortSignal;
 }
@@ -173,22 +384,76 @@ export const paginatedQuery = (
   database: string,
   req: PaginatedQueryRequest,
 ) =>
-  post<PaginatedQueryResult>("/query", {
+  post<QueryResponse>("/query", {
     query: req.query,
     params: req.params,
     sort: req.sort,
     page: req.page,
     page_size: req.pageSize,
-    filters: req.filters,
+    filters: req.filters.map((f) => ({ ...f, value: filterValue(f) })),
+    param_types: req.paramTypes,
+    with_sample: req.withSample,
+    with_plan: req.withPlan,
+    reject_multiple: req.rejectMultiple,
+    with_extended: req.withExtended,
+    dry: req.dry,
+    retry_writes: req.retryWrites,
   }, {
     signal: req.signal,
     cacheTimeoutSec: req.useCache ? CACHE_TIMEOUT_SEC : -1,
     headers: { "x-conn-name": connection, "x-database": database },
   });
 
+// Start running a query in the background, resolving to a job id to poll with
+// `asyncQueryStatus` (for queries too slow to wait on a single request)
+export const submitAsyncQuery = (
+  connection: string,
+  database: string,
+  req: Omit<PaginatedQueryRequest, "useCache" | "signal">,
+) =>
+  post<{ id: string }>("/query/async", {
+    query: req.query,
+    params: req.params,
+    sort: req.sort,
+    page: req.page,
+    page_size: req.pageSize,
+    filters: req.filters.map((f) => ({ ...f, value: filterValue(f) })),
+    param_types: req.paramTypes,
+    with_extended: req.withExtended,
+    retry_writes: req.retryWrites,
+  }, {
+    headers: { "x-conn-name": connection, "x-database": database },
+  });
+
+export const asyncQueryStatus = (id: string) =>
+  get<AsyncQueryJob>(`/query/async/${id}`);
+
+// Cancel a background query, or discard its result once it's done
+export const cancelAsyncQuery = (id: string) =>
+  del<null>(`/query/async/${id}`);
+
+export const federatedQuery = (
+  connections: string[],
+  req: Omit<PaginatedQueryRequest, "useCache" | "signal">,
+) =>
+  post<FederatedQueryResponse>("/query/federated", {
+    query: req.query,
+    params: req.params,
+    sort: req.sort,
+    page: req.page,
+    page_size: req.pageSize,
+    filters: req.filters.map((f) => ({ ...f, value: filterValue(f) })),
+    connections,
+  });
+
 export const get = req("GET");
 export const post = req("POST");
 export const put = req("PUT");
 export const del = req("DELETE");
+// websockets can't send headers, so the token is passed as a query parameter instead
 export const createSocket = (channel: string) =>
-  new WebSocket(`${socketUrl}/${channel}`);
+  new WebSocket(
+    apiToken
+      ? `${socketUrl}/${channel}?access_token=${encodeURIComponent(apiToken)}`
+      : `${socketUrl}/${channel}`,
+  );
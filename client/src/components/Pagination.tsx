@@ -66,7 +66,7 @@ export default function Pagination(
           className="flex items-center justify-center cursor-pointer w-6 h-6 rounded-full
           hover:bg-white/10 active:bg-white/20 disabled:opacity-50 disabled:pointer-events-none"
           onClick={() => setPage(page - 1)}
-          disabled={page === 1}
+          disabled={!query.has_prev}
         >
           <LeftIcon />
         </button>
@@ -75,7 +75,7 @@ export default function Pagination(
           className="flex items-center justify-center cursor-pointer w-6 h-6 rounded-full
           hover:bg-white/10 active:bg-white/20 disabled:opacity-50 disabled:pointer-events-none"
           onClick={() => setPage(page + 1)}
-          disabled={page === query.total_pages}
+          disabled={!query.has_next}
         >
           <RightIcon />
         </button>
@@ -11,8 +11,8 @@ import Modal, {
 } from "./Modal.tsx";
 import Fieldset from "./form/Fieldset.tsx";
 import Field from "./form/Field.tsx";
-import { get, put } from "../api.ts";
-import Config from "../models/config.ts";
+import { get, post, put } from "../api.ts";
+import Config, { ConfigProblem } from "../models/config.ts";
 import Connection, {
   connectionColorToClass,
   loadConnectionColors,
@@ -90,12 +90,20 @@ function SettingsModalBody({ actions, onSave }: SettingsModalBodyProps) {
       password_file: form.get("password_file") as string || null,
       database: form.get("database") as string || "postgres",
       ssl: form.get("ssl") === "on",
+      // leaving the password blank keeps the saved one
+      has_password: connection?.has_password,
+      has_password_file: connection?.has_password_file,
+      renamed_from: connection && connection.name !== form.get("name")
+        ? connection.name
+        : undefined,
+      clear_credentials: form.get("clear_credentials") === "on" || undefined,
     };
 
     if (
       !conn.name ||
       !conn.username ||
-      (!conn.password && !conn.password_file)
+      (!conn.password && !conn.password_file && !conn.has_password &&
+        !conn.has_password_file && !conn.clear_credentials)
     ) {
       alert("Name, username, and password/password file are required.");
       return;
@@ -117,9 +125,11 @@ function SettingsModalBody({ actions, onSave }: SettingsModalBodyProps) {
       connections.push(conn);
     }
 
-    const uniqueNames = connections.map((c) => c.name);
-    if (uniqueNames.length !== new Set(uniqueNames).size) {
-      alert("Connection names must be unique.");
+    const problems = await post<ConfigProblem[]>("/config/validate", {
+      connections,
+    });
+    if (problems.length > 0) {
+      alert(problems.map((p) => p.message).join("\n"));
       return;
     }
 
@@ -275,12 +285,25 @@ function SettingsModalBody({ actions, onSave }: SettingsModalBodyProps) {
             label="Port (default: 5432)"
           />
           <Field name="username" defaultValue={connection?.username} />
-          <Field name="password" defaultValue={connection?.password} />
+          <Field
+            name="password"
+            label={connection?.has_password
+              ? "Password (saved, leave blank to keep)"
+              : "Password"}
+          />
           <Field
             name="password_file"
-            label="Password File"
-            defaultValue={connection?.password_file}
+            label={connection?.has_password_file
+              ? "Password File (saved, leave blank to keep)"
+              : "Password File"}
           />
+          {(connection?.has_password || connection?.has_password_file) && (
+            <Field
+              type="checkbox"
+              name="clear_credentials"
+              label="Clear saved password (connect without one)"
+            />
+          )}
           <Field
             name="database"
             defaultValue={connection?.database}
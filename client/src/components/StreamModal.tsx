@@ -2,6 +2,11 @@ import { useEffect, useRef, useState } from "react";
 import { HiOutlineChip as ServerIcon } from "react-icons/hi";
 import Modal, { closeModal, ModalActions } from "./Modal.tsx";
 import { createSocket } from "../api.ts";
+import {
+  createStreamDecoder,
+  formatStreamEvent,
+  STREAM_PROTOCOL_VERSION,
+} from "../models/stream.ts";
 
 interface Props {
   actions: ModalActions;
@@ -15,8 +20,16 @@ function StreamModalBody({ actions }: Props) {
   useEffect(() => {
     if (!socketRef.current) {
       const socket = createSocket("blah");
+      const decode = createStreamDecoder();
 
-      socket.onmessage = ({ data }) => setOutput((o) => o + data);
+      socket.onmessage = ({ data }) => {
+        const event = decode(data);
+        if (!event) return;
+        if (event.version !== STREAM_PROTOCOL_VERSION) {
+          console.warn(`unexpected stream protocol version ${event.version}`);
+        }
+        setOutput((o) => o + formatStreamEvent(event));
+      };
       socket.onerror = (err) => console.error(err);
       // socket.onopen = () => socket.send("hello");
 
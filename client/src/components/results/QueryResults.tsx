@@ -152,15 +152,22 @@ export default function QueryResults({
     return <ExplainVisualize plan={page.plan} query={page.query} />;
   }
 
-  if (page.type === "modify-data" || page.type === "modify-structure") {
+  if (
+    page.type === "modify-data" || page.type === "modify-structure" ||
+    page.type === "command"
+  ) {
     return (
       <div className="py-4 px-6 text-sm">
         <span>Success!</span>
+        {page.type === "command" && (
+          <span className="opacity-40 pl-1 font-mono">{page.message}</span>
+        )}
         {page.type === "modify-data" &&
           (
             <span className="opacity-40 pl-1">
               Updated {page.affected_rows}{" "}
               row{page.affected_rows === 1 ? "" : "s"}.
+              <span className="pl-1 font-mono">{page.command}</span>
             </span>
           )}
       </div>
@@ -309,7 +316,9 @@ export default function QueryResults({
                     )}
                   </div>
                   <div className="font-normal text-xs text-neutral-content/60">
-                    {column.type}
+                    {column.unsupported_type
+                      ? `unsupported type: ${column.unsupported_type}`
+                      : column.type}
                   </div>
                 </th>
               ))}
@@ -5,6 +5,21 @@ export interface ConnectionStatus {
   database: string;
   status: "active" | "pending" | "failed";
   message: string;
+  // Open connections (for active pools), which may be fewer than the pool size
+  size: number | null;
+  // The host that was connected to (for active pools), e.g. "db2:5432"
+  host: string | null;
+  // Why text in results may not decode correctly (for active pools), e.g. because
+  // the database's encoding is SQL_ASCII
+  warning?: string | null;
+}
+
+// A problem found by `POST /config/validate`
+export interface ConfigProblem {
+  index: number;
+  connection: string;
+  field: string;
+  message: string;
 }
 
 export default interface Config {
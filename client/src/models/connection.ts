@@ -1,12 +1,38 @@
 export default interface Connection {
   name: string;
+  // may be a comma-separated list of hosts to try in order, e.g. "db1:5433,db2"
   host: string;
   port: number;
+  // which kind of server to accept when trying several hosts
+  target_session_attrs?: "any" | "read-write" | "read-only";
   username: string;
   password: string | null;
   password_file: string | null;
+  // program + arguments; takes precedence over `password_file`
+  password_command?: string[];
+  password_env?: Record<string, string>;
+  // the server never sends credentials back, only whether they're set
+  has_password?: boolean;
+  has_password_file?: boolean;
+  // only sent with updates: the name the connection was saved under, if renamed, so
+  // its saved credentials carry over
+  renamed_from?: string;
+  // only sent with updates: connect without a password instead of keeping the saved one
+  clear_credentials?: boolean;
   database: string;
   ssl: boolean;
+  // Extra server settings for each session, e.g. "-c timezone=UTC"
+  options?: string | null;
+  // SET statements run at the start of each session, e.g. "SET search_path = app"
+  session_setup?: string[];
+  // Run on each pooled connection when it opens (defaults to "SELECT 1")
+  validation_query?: string | null;
+  // Retries for queries failing with a serialization failure or deadlock (default 0)
+  conflict_retries?: number;
+  // Reset client_encoding to UTF-8 if session_setup changes it (default true)
+  force_utf8?: boolean | null;
+  // Free-form labels, e.g. "prod"; `GET /status?tag=` filters by these
+  tags?: string[];
 
   // client-side
   color?:
@@ -2,3 +2,24 @@
 export default interface Database {
   datname: string;
 }
+
+export interface SessionInfo {
+  current_user: string;
+  session_user: string;
+  current_database: string;
+  // null if none of the schemas in the search_path exist
+  current_schema: string | null;
+  search_path: string;
+  server_version: string;
+  server_encoding: string;
+  read_only: boolean;
+}
+
+export interface ExtensionInfo {
+  name: string;
+  default_version: string | null;
+  // null if the extension isn't installed in this database
+  installed_version: string | null;
+  schema: string | null;
+  comment: string | null;
+}
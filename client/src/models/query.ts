@@ -15,6 +15,8 @@ export const FILTER_OPS = [
   { op: "gte", label: "is greater than or equal to" },
   { op: "lt", label: "is less than" },
   { op: "lte", label: "is less than or equal to" },
+  // e.g. `{"color": "blue"}` for an hstore column
+  { op: "contains", label: "has entries (JSON)" },
 ] as const;
 
 export type ColumnType =
@@ -34,6 +36,18 @@ export interface Filter {
   value: string | number | boolean;
 }
 
+// Containment filters are entered as JSON, which the server expects as an object.
+export const filterValue = (filter: Filter): unknown => {
+  if (filter.operator !== "contains" || typeof filter.value !== "string") {
+    return filter.value;
+  }
+  try {
+    return JSON.parse(filter.value);
+  } catch {
+    return filter.value;
+  }
+};
+
 export interface QueryColumn {
   type: ColumnType;
   name: string;
@@ -43,6 +57,8 @@ export interface QueryColumn {
   fk_constraint: string | null;
   fk_table: string | null;
   fk_column: string | null;
+  // Set if the column's type can't be decoded; its values are all null
+  unsupported_type?: string;
 }
 
 export const columnLabel = (column: QueryColumn) => {
@@ -77,6 +93,7 @@ export const columnFilter = (
 export interface QueryResult {
   columns: QueryColumn[];
   rows: QueryValue[][];
+  encoding: "binary" | "text" | "partial";
 }
 
 export interface Sort {
@@ -88,7 +105,17 @@ export type PaginatedQueryResult =
   | PaginatedSelectQueryResult
   | PaginatedModifyDataQueryResult
   | PaginatedModifyStructureQueryResult
-  | PaginatedExplainQueryResult;
+  | PaginatedExplainQueryResult
+  | PaginatedDryRunQueryResult
+  | PaginatedCommandQueryResult;
+
+// The response of the query route, which also flags queries that had more than one
+// statement (only the first is run, unless `rejectMultiple` is set), and how many
+// times the query was retried after conflicting with another transaction
+export type QueryResponse = PaginatedQueryResult & {
+  extra_statements_ignored?: boolean;
+  retries?: number;
+};
 
 export interface PaginatedSelectQueryResult {
   type: "select";
@@ -97,13 +124,30 @@ export interface PaginatedSelectQueryResult {
   page_count: number;
   total_count: number;
   total_pages: number;
+  // Whether there are pages after/before this one (exact even with the "estimate"
+  // count mode, unlike `total_pages`)
+  has_next: boolean;
+  has_prev: boolean;
   sort: Sort | null;
   entries: QueryResult;
+  // Whether the page size was clamped because the query has no LIMIT
+  clamped: boolean;
+  // The requested page size, if it was clamped
+  requested_page_size?: number;
+  // With the "estimate" count mode, the requested page, if it was past the end; the
+  // last page (`page`) is returned instead
+  requested_page?: number;
+  // With the "estimate" count mode, the planner's (possibly inaccurate) row estimate
+  total_count_estimate?: number;
+  // The `EXPLAIN (FORMAT JSON)` plan for this page, if requested with `withPlan`
+  plan?: unknown;
 }
 
 export interface PaginatedModifyDataQueryResult {
   type: "modify-data";
   affected_rows: number;
+  // The command tag, e.g. "INSERT 0 3" (an upsert counts updated rows too)
+  command: string;
 }
 
 export interface PaginatedModifyStructureQueryResult {
@@ -114,6 +158,26 @@ export interface PaginatedExplainQueryResult {
   type: "explain";
   query: string;
   plan: string;
+  // The first few rows of the explained query, if requested with `withSample`
+  sample?: QueryResult;
+}
+
+// The SQL a SELECT query would run to fetch the page, returned with `dry`
+export interface PaginatedDryRunQueryResult {
+  type: "dry-run";
+  page_query: string;
+  // Only set for the "separate" count mode
+  count_query?: string;
+  // The query's own parameters, followed by the filters' values
+  params: unknown[];
+}
+
+export interface PaginatedCommandQueryResult {
+  type: "command";
+  // The command tag, e.g. "SET" or "LISTEN"
+  message: string;
+  // For a CALL of a procedure with output arguments, a single row of their values
+  output?: QueryResult;
 }
 
 export interface QueryParam {
@@ -126,3 +190,34 @@ export interface PrepareQueryResult {
   columns: QueryColumn[];
   params: QueryParam[];
 }
+
+export type BatchPrepareResult =
+  | ({ status: "ok" } & PrepareQueryResult)
+  | {
+    status: "error";
+    message: string;
+    code: string | null;
+    // 1-indexed character offset of the error within the query, if known
+    position: number | null;
+  };
+
+export type FederatedQueryResult =
+  | { status: "ok"; connection: string; result: PaginatedQueryResult }
+  | { status: "error"; connection: string; error: string };
+
+export interface FederatedQueryResponse {
+  results: FederatedQueryResult[];
+  // Every connection's rows, with a leading `__connection` column
+  merged: {
+    columns: QueryColumn[];
+    rows: QueryValue[][];
+    // Connections whose columns didn't match and were left out of `rows`
+    excluded: string[];
+  };
+}
+
+// A query running in the background (see `submitAsyncQuery`)
+export type AsyncQueryJob =
+  | { status: "pending" | "running" | "cancelled" }
+  | { status: "done"; result: QueryResponse }
+  | { status: "error"; message: string; code: string | null };
@@ -1,3 +1,8 @@
 export default interface Schema {
   schema_name: string;
+  owner: string;
+  table_count: number;
+  view_count: number;
+  // The schema's COMMENT, if any
+  description: string | null;
 }
@@ -0,0 +1,117 @@
+/** The stream envelope version this client understands. */
+export const STREAM_PROTOCOL_VERSION = 1;
+
+export type PoolStatus =
+  | "opening"
+  | "open"
+  | "failed"
+  | "reloading"
+  | "closing"
+  | "dormant";
+
+export type StreamEvent =
+  & { version: number }
+  & (
+    | { type: "log"; text: string }
+    | {
+      type: "pool_status";
+      connection: string | null;
+      database: string;
+      status: PoolStatus;
+      message: string;
+    }
+    | {
+      type: "pool_progress";
+      connection: string | null;
+      database: string;
+      opened: number;
+      failed: number;
+      total: number;
+      message: string;
+    }
+    | { type: "query_progress"; message: string }
+    | { type: "error"; message: string }
+    | {
+      type: "notification";
+      database: string;
+      channel: string;
+      payload: string;
+    }
+    | {
+      type: "config_changed";
+      added: string[];
+      removed: string[];
+      modified: string[];
+    }
+    | { type: "chunk"; id: number; part: ChunkPart; data: string }
+  );
+
+export type ChunkPart = "start" | "continue" | "end";
+
+/**
+ * Commands a client can send over the stream socket. Once subscribed to any
+ * connection's pool status, pool events for other connections aren't sent (even
+ * after unsubscribing from every connection).
+ */
+export type SocketCommand =
+  | { command: "subscribe_pool_status"; connection: string }
+  | { command: "unsubscribe_pool_status"; connection: string };
+
+/**
+ * Returns a function that parses raw stream messages, reassembling oversized
+ * messages the server split into chunks. Returns `null` until a chunked message
+ * is complete; chunks that arrive out of sequence (e.g. because some were
+ * dropped) are discarded.
+ */
+export function createStreamDecoder(): (data: string) => StreamEvent | null {
+  let pending: { id: number; data: string } | null = null;
+
+  return (data) => {
+    const event: StreamEvent = JSON.parse(data);
+    if (event.type !== "chunk") return event;
+
+    if (event.part === "start") {
+      pending = { id: event.id, data: event.data };
+      return null;
+    }
+    if (pending?.id !== event.id) {
+      pending = null;
+      return null;
+    }
+    pending.data += event.data;
+    if (event.part === "continue") return null;
+
+    const message = pending.data;
+    pending = null;
+    return JSON.parse(message);
+  };
+}
+
+/** Render an event as plain text, for display in a log. */
+export function formatStreamEvent(event: StreamEvent): string {
+  switch (event.type) {
+    case "log":
+      return event.text;
+    case "pool_status":
+    case "pool_progress":
+    case "query_progress":
+      return `${event.message}\n`;
+    case "error":
+      return `Error: ${event.message}\n`;
+    case "notification":
+      return `NOTIFY ${event.channel} (${event.database}): ${event.payload}\n`;
+    case "config_changed":
+      return `Connections updated (${
+        [
+          ["added", event.added],
+          ["removed", event.removed],
+          ["modified", event.modified],
+        ]
+          .filter(([, names]) => names.length > 0)
+          .map(([label, names]) => `${label}: ${names.join(", ")}`)
+          .join("; ") || "no changes"
+      })\n`;
+    case "chunk":
+      return "";
+  }
+}
@@ -1,16 +1,90 @@
+import { QueryResult, QueryValue } from "./query.ts";
+
 export default interface Table {
   type: "table" | "view" | "materialized_view";
   table_name: string;
   table_schema: string;
   // Estimated number of rows in table (from pg_class.reltuples)
   table_rows_est: number;
+  // Number of live rows tracked by the statistics collector (not set for views)
+  table_rows_live?: number | null;
+  // Whether the row estimate looks stale, suggesting an ANALYZE (not set for views)
+  needs_analyze?: boolean;
+  analyze_hint?: string | null;
   // Actual size of table in bytes (from pg_total_relation_size)
   table_size: number;
   // Pretty-printed size of table (from pg_size_pretty)
   table_size_pretty: string;
+  // The table's COMMENT, if any
+  description: string | null;
 }
 
 export interface Column {
   column_name: string;
   data_type: string;
+  // The column's COMMENT, if any
+  description: string | null;
+}
+
+// The columns of one of the tables requested from `POST /db/columns`
+export interface TableColumns {
+  schema: string;
+  table: string;
+  columns: Column[];
+}
+
+export interface ColumnStats {
+  // Fraction of rows where the column is null
+  null_frac: number;
+  // Number of distinct values, or the negated fraction of distinct rows if negative
+  n_distinct: number;
+  most_common_vals: unknown[] | null;
+  most_common_freqs: number[] | null;
+  histogram_bounds: unknown[] | null;
+  // Whether these come from pg_stats or were computed from a sample of rows
+  source: "pg_stats" | "sample";
+  sample_rows?: number;
+}
+
+export interface ColumnValues {
+  // Distinct values of the column, in ascending order
+  values: Array<string | number | boolean | null>;
+  // Whether there are more values than were returned
+  truncated: boolean;
+}
+
+export interface SearchResult {
+  kind: "table" | "view" | "materialized_view" | "column" | "function";
+  schema: string;
+  name: string;
+  // For columns, the table (or view) they belong to
+  parent: string | null;
+}
+
+// A page of a table's rows for editing, ordered by primary key
+export interface EditableRows extends QueryResult {
+  primary_key: string[];
+  // Each row's version (its `xmin`), to send back when updating it
+  versions: string[];
+}
+
+export interface RowUpdate {
+  // The row's primary key, by column name
+  key: Record<string, QueryValue>;
+  // The row's version as it was read; if it's since changed, the update is
+  // rejected with a 409 Conflict
+  version: string;
+  values: Record<string, QueryValue>;
+}
+
+export interface FunctionInfo {
+  name: string;
+  // The arguments that tell overloads apart, e.g. `a integer, VARIADIC b text[]`;
+  // show them as `name(identity_arguments)`, and pass them to `functionDdl`
+  identity_arguments: string;
+  // The full argument list, including defaults
+  arguments: string;
+  // `null` for procedures
+  result_type: string | null;
+  kind: "function" | "procedure" | "aggregate" | "window";
 }
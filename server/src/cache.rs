@@ -0,0 +1,119 @@
+use crate::db::{Invalidation, PaginatedQueryResult, QueryOptions};
+use std::collections::VecDeque;
+use tokio::sync::Mutex;
+
+/// The most results kept per pool; once full, the oldest is dropped.
+const MAX_ENTRIES: usize = 64;
+
+/// Results of read-only queries (see `QueryOptions::cache`), kept per pool so that e.g. a
+/// dashboard refreshing the same query doesn't re-run it. Results are only cached for
+/// pools that `LISTEN` for invalidations (see `db::INVALIDATE_CHANNEL`), which drop
+/// those that reference an invalidated table.
+#[derive(Default)]
+pub struct ResultCache {
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+struct Entry {
+    key: String,
+    query: String,
+    result: PaginatedQueryResult,
+}
+
+impl ResultCache {
+    /// The key a query's result is cached under; the same query only shares a result
+    /// when run with the same parameters and options (e.g. for the same page).
+    pub fn key(query: &str, params: &[serde_json::Value], options: &QueryOptions) -> String {
+        format!(
+            "{query}\0{}\0{options:?}",
+            serde_json::Value::from(params.to_vec())
+        )
+    }
+
+    pub async fn get(&self, key: &str) -> Option<PaginatedQueryResult> {
+        let entries = self.entries.lock().await;
+        let entry = entries.iter().find(|entry| entry.key == key)?;
+        Some(entry.result.clone())
+    }
+
+    pub async fn insert(&self, key: String, query: &str, result: PaginatedQueryResult) {
+        let mut entries = self.entries.lock().await;
+        entries.retain(|entry| entry.key != key);
+        if entries.len() >= MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            key,
+            query: query.to_owned(),
+            result,
+        });
+    }
+
+    /// Drop the cached results the invalidation applies to, returning how many were
+    /// dropped.
+    pub async fn invalidate(&self, invalidation: &Invalidation) -> usize {
+        let mut entries = self.entries.lock().await;
+        let before = entries.len();
+        match invalidation {
+            Invalidation::All => entries.clear(),
+            Invalidation::Table(table) => {
+                entries.retain(|entry| !references(&entry.query, table));
+            }
+        }
+        before - entries.len()
+    }
+}
+
+/// Whether the query mentions the table, which may be schema-qualified (only its name is
+/// compared). This errs on the side of matching: names are compared case-insensitively,
+/// and any identifier with the same name counts, whether or not it's actually the table
+/// (e.g. a column).
+fn references(query: &str, table: &str) -> bool {
+    let name = table.rsplit('.').next().unwrap_or(table).trim();
+    let name = name.trim_matches('"').to_lowercase();
+
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == '"' {
+            let mut ident = String::new();
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '"' if chars.next_if(|&(_, c)| c == '"').is_some() => ident.push('"'),
+                    '"' => break,
+                    c => ident.push(c),
+                }
+            }
+            if ident.to_lowercase() == name {
+                return true;
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start + c.len_utf8();
+            while let Some((i, c)) =
+                chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '$')
+            {
+                end = i + c.len_utf8();
+            }
+            if query[start..end].to_lowercase() == name {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_tables_by_name() {
+        let query = "SELECT * FROM public.users u JOIN \"Orders\" o ON o.user_id = u.id";
+        assert!(references(query, "users"));
+        assert!(references(query, "USERS"));
+        assert!(references(query, "public.users"));
+        assert!(references(query, "\"Orders\""));
+        assert!(references(query, "orders"));
+        assert!(!references(query, "user"));
+        assert!(!references("SELECT * FROM users_archive", "users"));
+    }
+}
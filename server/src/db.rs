@@ -6,22 +6,37 @@ use serde_with::{DeserializeFromStr, SerializeDisplay};
 use std::collections::{HashMap, HashSet};
 use time::macros::format_description;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::oneshot::{Receiver, Sender, channel};
-use tokio_postgres::{Socket, types::ToSql};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot::{Receiver, Sender, channel, error::TryRecvError};
+use tokio_postgres::{AsyncMessage, SimpleQueryMessage, Socket, error::SqlState, types::ToSql};
 
 pub type SqlParam<'a> = &'a (dyn ToSql + Sync);
 
 #[derive(Debug, bon::Builder)]
 pub struct Config {
+    /// The name of the connection this is for, to label its pool's status events.
+    pub name: Option<String>,
     pub username: String,
     pub password: String,
+    /// The host to connect to, or a comma-separated list of hosts to try in order
+    /// (e.g. for a primary and its standbys). Each host may override `port`, as in
+    /// `db1:5433,db2`.
     #[builder(default = "localhost".to_owned())]
     pub host: String,
     #[builder(default = 5432)]
     pub port: usize,
+    /// Which kind of server to accept when trying `host`s in order.
+    #[builder(default)]
+    pub target_session_attrs: TargetSessionAttrs,
     pub database: String,
     #[builder(default)]
     pub ssl: bool,
+    /// A PEM file of CA certificates to trust (in addition to the system's) when
+    /// verifying the server's certificate (requires `ssl`).
+    pub ssl_root_cert: Option<std::path::PathBuf>,
+    /// Whether to use SCRAM channel binding during authentication (requires `ssl`).
+    #[builder(default)]
+    pub channel_binding: ChannelBinding,
     #[builder(default = 5)]
     pub pool_size: usize,
     /// How long to wait (in seconds) when checking out a connection.
@@ -36,32 +51,336 @@ pub struct Config {
     /// Health check timeout (in seconds) for validating connections.
     #[builder(default = 5)]
     pub health_check_timeout_s: u64,
+    /// What to do when a query returns columns that can't be decoded using BINARY encoding.
+    #[builder(default)]
+    pub text_fallback: TextFallback,
+    /// The default isolation level for transactions; if not set, the server default is used.
+    pub isolation_level: Option<IsolationLevel>,
+    /// Whether to `LISTEN` on `INVALIDATE_CHANNEL` for cached result invalidations.
+    #[builder(default)]
+    pub listen_for_invalidation: bool,
+    /// Whether `DROP` / `TRUNCATE` statements must be explicitly confirmed before running.
+    #[builder(default)]
+    pub require_confirm_destructive: bool,
+    /// How values are formatted when serialized to JSON.
+    #[builder(default)]
+    pub display_format: DisplayFormat,
+    /// Whether to reset session state (see `RESET_SESSION_SQL`) when a connection is
+    /// returned to the pool.
+    #[builder(default)]
+    pub reset_session: bool,
+    /// Patterns that queries must (or must not) match to be run.
+    #[builder(default)]
+    pub query_policy: QueryPolicy,
+    /// The largest page returned for a `SELECT` without its own `LIMIT`; larger (or
+    /// unbounded) page sizes are clamped to this.
+    #[builder(default = DEFAULT_MAX_PAGE_SIZE)]
+    pub max_page_size: usize,
+    /// The page size used when a query doesn't request one.
+    #[builder(default = DEFAULT_PAGE_SIZE)]
+    pub default_page_size: usize,
+    /// Extra server settings for each session, e.g. `-c timezone=UTC` (see
+    /// `validate_options`).
+    pub options: Option<String>,
+    /// Statements run in order at the start of each session, e.g.
+    /// `SET search_path = app, public` (see `validate_session_setup`).
+    #[builder(default)]
+    pub session_setup: Vec<String>,
+    /// Run once on each of the pool's connections when it's opened, to check that it
+    /// can actually run queries.
+    #[builder(default = DEFAULT_VALIDATION_QUERY.to_owned())]
+    pub validation_query: String,
+    /// How many times a query that fails with a serialization failure or deadlock is
+    /// retried (see `paginated_query_with_retries`).
+    #[builder(default)]
+    pub conflict_retries: u32,
+    /// Whether to set the session's `client_encoding` back to UTF-8 if it's changed
+    /// (e.g. by `session_setup`), since results are always decoded as UTF-8. If not, a
+    /// warning is reported instead (see `Connection::encoding_warning`).
+    #[builder(default = true)]
+    pub force_utf8: bool,
 }
 
 impl Config {
-    pub fn conn_str(&self) -> String {
-        format!(
-            "postgres://{username}:{password}@{host}:{port}/{database}",
-            username = self.username,
-            password = self.password,
-            host = self.host,
-            port = self.port,
-            database = self.database
-        )
+    /// The `host`s to try, in order, with their ports.
+    pub fn hosts(&self) -> eyre::Result<Vec<(String, u16)>> {
+        let port = u16::try_from(self.port)
+            .map_err(|_| eyre::eyre!("port {} isn't between 1 and 65535", self.port))?;
+        parse_hosts(&self.host, port)
     }
 }
 
-pub fn spawn_conn<T>(conn: tokio_postgres::Connection<Socket, T>, tx: Sender<()>, rx: Receiver<()>)
-where
+/// Parse a comma-separated list of hosts, each with an optional port (see `Config::host`).
+pub fn parse_hosts(hosts: &str, default_port: u16) -> eyre::Result<Vec<(String, u16)>> {
+    let hosts = hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(|host| match host.rsplit_once(':') {
+            // a bare IPv6 address has colons of its own, so it has to be bracketed
+            // to give it a port, e.g. `[::1]:5433`
+            Some((name, port)) if !name.contains(':') || name.ends_with(']') => port
+                .parse()
+                .map(|port| (name.trim_matches(['[', ']']).to_owned(), port))
+                .map_err(|_| eyre::eyre!("invalid port in host {host:?}")),
+            _ => Ok((host.trim_matches(['[', ']']).to_owned(), default_port)),
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    if hosts.is_empty() {
+        eyre::bail!("a host is required");
+    }
+    Ok(hosts)
+}
+
+/// Which kind of server a connection will accept, when trying several hosts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetSessionAttrs {
+    /// Use the first host that accepts the connection.
+    #[default]
+    Any,
+    /// Only use a server that accepts writes, i.e. the primary.
+    ReadWrite,
+    /// Only use a read-only server, i.e. a standby.
+    ReadOnly,
+}
+
+impl From<TargetSessionAttrs> for tokio_postgres::config::TargetSessionAttrs {
+    fn from(attrs: TargetSessionAttrs) -> Self {
+        match attrs {
+            TargetSessionAttrs::Any => Self::Any,
+            TargetSessionAttrs::ReadWrite => Self::ReadWrite,
+            TargetSessionAttrs::ReadOnly => Self::ReadOnly,
+        }
+    }
+}
+
+/// Check that connection `options` only set server settings, as `-c name=value` or
+/// `--name=value`, rather than e.g. trying to pass other connection parameters
+/// (`host=...`) or arbitrary server flags.
+pub fn validate_options(options: &str) -> eyre::Result<()> {
+    let setting = |arg: &str| {
+        arg.split_once('=').is_some_and(|(name, _)| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        })
+    };
+
+    let mut args = options.split_whitespace();
+    while let Some(arg) = args.next() {
+        let valid = match arg {
+            "-c" => args.next().is_some_and(setting),
+            _ => arg
+                .strip_prefix("--")
+                .or_else(|| arg.strip_prefix("-c"))
+                .is_some_and(setting),
+        };
+        if !valid {
+            eyre::bail!(
+                "invalid connection option {arg:?}: options may only set server settings, \
+                 e.g. `-c timezone=UTC`"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check that a `session_setup` statement only changes settings, as a single `SET`
+/// or `SELECT set_config(...)`.
+pub fn validate_session_setup(statement: &str) -> eyre::Result<()> {
+    let (first, extra) = split_first_statement(statement);
+    let lowercase = first.trim().to_ascii_lowercase();
+    let mut tokens = lowercase.split_whitespace();
+    let valid = !extra
+        && match tokens.next() {
+            Some("set") => true,
+            Some("select") => tokens
+                .collect::<String>()
+                .strip_prefix("set_config(")
+                .is_some_and(closes_at_end),
+            _ => false,
+        };
+    if !valid {
+        eyre::bail!(
+            "invalid session setup statement {statement:?}: setup statements may only \
+             change settings, with `SET` or `SELECT set_config(...)`"
+        );
+    }
+    Ok(())
+}
+
+/// Whether the parenthesis opened just before `rest` is closed by its last character,
+/// so that nothing follows a function call's arguments.
+fn closes_at_end(rest: &str) -> bool {
+    let (mut depth, mut quoted) = (1, false);
+    for (idx, c) in rest.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx == rest.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl std::fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsolationLevel::ReadCommitted => write!(f, "READ COMMITTED"),
+            IsolationLevel::RepeatableRead => write!(f, "REPEATABLE READ"),
+            IsolationLevel::Serializable => write!(f, "SERIALIZABLE"),
+        }
+    }
+}
+
+impl IsolationLevel {
+    /// The statement used to begin a transaction at the given isolation level
+    /// (or the session default, if `None`).
+    pub fn begin_sql(level: Option<IsolationLevel>) -> String {
+        match level {
+            Some(level) => format!("BEGIN ISOLATION LEVEL {level}"),
+            None => "BEGIN".to_owned(),
+        }
+    }
+}
+
+/// Begin a transaction, optionally at the given isolation level.
+pub async fn begin(client: &Client, level: Option<IsolationLevel>) -> eyre::Result<()> {
+    client
+        .batch_execute(&IsolationLevel::begin_sql(level))
+        .await
+        .map_err(PgError::from)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelBinding {
+    /// Never use channel binding.
+    Disable,
+    /// Use channel binding if the server supports it.
+    #[default]
+    Prefer,
+    /// Fail to connect unless channel binding is used.
+    Require,
+}
+
+impl ChannelBinding {
+    /// Convert to the equivalent `tokio_postgres` setting. Channel binding relies on
+    /// the TLS layer, so requiring it without SSL is a configuration error.
+    fn resolve(self, ssl: bool) -> eyre::Result<tokio_postgres::config::ChannelBinding> {
+        use tokio_postgres::config::ChannelBinding as PgChannelBinding;
+        match (self, ssl) {
+            (ChannelBinding::Require, false) => eyre::bail!(
+                "channel binding requires SSL; enable `ssl` for this connection \
+                 or set `channel_binding` to \"prefer\""
+            ),
+            (ChannelBinding::Require, true) => Ok(PgChannelBinding::Require),
+            (ChannelBinding::Prefer, _) => Ok(PgChannelBinding::Prefer),
+            (ChannelBinding::Disable, _) => Ok(PgChannelBinding::Disable),
+        }
+    }
+
+    /// Replace the generic authentication error returned when channel binding
+    /// is required but wasn't negotiated with a more helpful one.
+    fn explain(self, err: tokio_postgres::Error) -> eyre::Report {
+        if self == ChannelBinding::Require && err.to_string().contains("channel binding") {
+            eyre::eyre!(
+                "channel binding is required, but the server didn't negotiate it \
+                 (is it configured for `scram-sha-256` authentication?)"
+            )
+        } else {
+            eyre::eyre!(err)
+        }
+    }
+}
+
+/// Explain why a connection couldn't be opened, for errors whose raw message doesn't
+/// say what to do about them.
+fn explain_connect_error(config: &Config, err: tokio_postgres::Error) -> eyre::Report {
+    if err.code() == Some(&SqlState::TOO_MANY_CONNECTIONS) {
+        // the server's message says which limit was hit (the server's, a role's or a database's)
+        let reason = err
+            .as_db_error()
+            .map_or_else(|| err.to_string(), |err| err.message().to_owned());
+        return eyre::eyre!("server connection limit reached; try a smaller pool_size ({reason})");
+    }
+    config.channel_binding.explain(err)
+}
+
+/// The channel that applications can `NOTIFY` to invalidate cached results that reference
+/// a table, e.g. `NOTIFY dbc_invalidate, 'users'`. An empty payload invalidates all results.
+pub const INVALIDATE_CHANNEL: &str = "dbc_invalidate";
+
+/// A request to invalidate cached results, received via `INVALIDATE_CHANNEL`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Invalidation {
+    /// Invalidate all cached results for the connection.
+    All,
+    /// Invalidate cached results that reference the given table.
+    Table(String),
+}
+
+impl Invalidation {
+    pub fn from_payload(payload: &str) -> Self {
+        match payload.trim() {
+            "" => Invalidation::All,
+            table => Invalidation::Table(table.to_owned()),
+        }
+    }
+}
+
+pub fn spawn_conn<T>(
+    mut conn: tokio_postgres::Connection<Socket, T>,
+    tx: Sender<()>,
+    rx: Receiver<()>,
+    forward: Option<UnboundedSender<AsyncMessage>>,
+) where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     tokio::spawn(async move {
+        use futures_util::StreamExt;
+        let mut messages = futures_util::stream::poll_fn(move |cx| conn.poll_message(cx));
+
         tokio::select! {
-            // the connection will never resolve to `Ok()`; if an error is
-            // encountered, it will resolve to `Err()`
-            Err(e) = conn => {
-                tracing::error!("connection error: {}", e);
-            }
+            // the connection will only stop producing messages when it's closed;
+            // if an error is encountered, it will produce `Err()`
+            _ = async {
+                while let Some(message) = messages.next().await {
+                    match message {
+                        Ok(message) => {
+                            if let AsyncMessage::Notice(notice) = &message {
+                                tracing::info!("{}: {}", notice.severity(), notice.message());
+                            }
+
+                            if let Some(tx) = forward.as_ref() {
+                                let _ = tx.send(message);
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("connection error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            } => {}
 
             // if a kill signal is received instead, terminate the connection
             _ = rx => {}
@@ -75,10 +394,29 @@ where
 pub struct Client {
     inner: tokio_postgres::Client,
 
-    /// The materialized view query. Postgres doesn't have an `information_schema.materialized_views`
-    /// table, so we need to fetch it manually. This can be done by slightly modifying the query that
-    /// backs `information_schema.views`. This should only be done once during the first connection.
-    mat_view_query: String,
+    /// What to do when a query returns columns that can't be decoded using BINARY encoding.
+    text_fallback: TextFallback,
+
+    /// Whether `DROP` / `TRUNCATE` statements must be explicitly confirmed before running.
+    require_confirm_destructive: bool,
+
+    /// How values are formatted when serialized to JSON (see `DisplayFormat`).
+    formatter: Formatter,
+
+    /// Patterns that queries are checked against before they're run (see `QueryPolicy`).
+    policy: Policy,
+
+    /// The largest page returned for a `SELECT` without its own `LIMIT`.
+    max_page_size: usize,
+
+    /// The page size used when a query doesn't request one.
+    default_page_size: usize,
+
+    /// How many times a query that conflicts with another transaction is retried.
+    conflict_retries: u32,
+
+    /// The server's reserved keywords, looked up the first time they're needed.
+    reserved_keywords: tokio::sync::OnceCell<ReservedKeywords>,
 }
 
 impl std::ops::Deref for Client {
@@ -90,37 +428,88 @@ impl std::ops::Deref for Client {
 }
 
 impl Client {
-    pub async fn new(inner: tokio_postgres::Client) -> eyre::Result<Self> {
-        // FIXME: this doesn't work on unprivileged databases that won't allow you to query
-        // the definition of the `views` view, ugh. probably need to just hard-code it here
-        // let mat_view_query = inner
-        //     .query_one(
-        //         "select view_definition
-        //         from information_schema.views
-        //         where table_schema = 'information_schema'
-        //         and table_name = 'views'",
-        //         &[],
-        //     )
-        //     .await
-        //     .map_err(PgError::from)
-        //     .map(|row| row.get::<_, String>(0))
-        //     .unwrap()
-        //     // by default, this view selects views; we want materialized views instead
-        //     .replace("c.relkind = 'v'", "c.relkind = 'm'")
-        //     .trim_end_matches(';')
-        //     .to_owned();
-
+    pub async fn new(inner: tokio_postgres::Client, config: &Config) -> eyre::Result<Self> {
         Ok(Self {
             inner,
-            mat_view_query: "".to_owned(),
+            text_fallback: config.text_fallback,
+            require_confirm_destructive: config.require_confirm_destructive,
+            formatter: config.display_format.compile()?,
+            policy: config.query_policy.compile()?,
+            max_page_size: config.max_page_size,
+            default_page_size: config.default_page_size,
+            conflict_retries: config.conflict_retries,
+            reserved_keywords: Default::default(),
+        })
+    }
+
+    /// The keywords this server needs quoted when they're used as identifiers.
+    pub async fn reserved_keywords(&self) -> eyre::Result<&ReservedKeywords> {
+        self.reserved_keywords
+            .get_or_try_init(|| async {
+                let rows = self
+                    .inner
+                    .query(
+                        "SELECT word FROM pg_get_keywords() WHERE catcode <> 'U'",
+                        &[],
+                    )
+                    .await
+                    .map_err(PgError::from)?;
+                Ok(ReservedKeywords(
+                    rows.iter().map(|row| row.get(0)).collect(),
+                ))
+            })
+            .await
+    }
+}
+
+/// Whether the session is inside a transaction block. Outside of one, each statement
+/// runs in its own implicit transaction, so the transaction and statement start at the
+/// same time.
+const IN_TRANSACTION_SQL: &str = "SELECT transaction_timestamp() <> statement_timestamp()";
+
+/// Everything `DISCARD ALL` does, except deallocating prepared statements: tokio_postgres
+/// keeps its own (e.g. the ones it looks up unknown types with) prepared for the life of
+/// the connection, and fails if they're gone.
+const RESET_SESSION_SQL: &str = "SET SESSION AUTHORIZATION DEFAULT;
+    RESET ALL;
+    CLOSE ALL;
+    UNLISTEN *;
+    SELECT pg_advisory_unlock_all();
+    DISCARD TEMP;
+    DISCARD SEQUENCES;";
+
+/// Where a connection's session is, relative to a transaction block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    /// Not inside a transaction block.
+    Idle,
+    /// Inside a transaction block (e.g. after a `BEGIN` that wasn't committed).
+    InTransaction,
+    /// Inside a transaction block that was aborted by an error, so every statement
+    /// fails until it's rolled back.
+    Failed,
+}
+
+impl std::fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TransactionStatus::Idle => "idle",
+            TransactionStatus::InTransaction => "in transaction",
+            TransactionStatus::Failed => "in failed transaction",
         })
     }
 }
 
 pub struct Connection {
     client: Client,
+    /// The host (and port) that was connected to, e.g. `db2:5432`.
+    host: String,
+    /// The TLS connector the connection was opened with, to send cancel requests.
+    tls: Option<MakeTlsConnector>,
     tx: Option<Sender<()>>,
     rx: Option<Receiver<()>>,
+    /// Set if text in results may not decode correctly (see `check_encoding`).
+    encoding_warning: Option<String>,
 }
 
 impl std::ops::Deref for Connection {
@@ -138,6 +527,26 @@ impl Drop for Connection {
 }
 
 impl Connection {
+    /// The host (and port) that was connected to, which is one of the configured hosts.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Why text in this connection's results may not decode correctly, if it may not
+    /// (e.g. because the database's encoding is `SQL_ASCII`).
+    pub fn encoding_warning(&self) -> Option<&str> {
+        self.encoding_warning.as_deref()
+    }
+
+    /// Get a handle that can cancel whatever query this connection is running,
+    /// from outside of it.
+    pub fn canceller(&self) -> Canceller {
+        Canceller {
+            token: self.client.cancel_token(),
+            tls: self.tls.clone(),
+        }
+    }
+
     /// Checks whether a connection is still live.
     ///
     /// If the connection's async task is still running, the sender
@@ -146,26 +555,96 @@ impl Connection {
     /// kill), the task will terminate and the connection will no longer
     /// be live.
     pub fn is_live(&mut self) -> bool {
-        self.rx.take_if(|rx| rx.try_recv().is_ok()).is_some()
+        self.rx
+            .as_mut()
+            .is_some_and(|rx| matches!(rx.try_recv(), Err(TryRecvError::Empty)))
     }
 
-    /// Performs a health check on the connection by executing a simple query.
+    /// Performs a health check on the connection by checking its transaction status.
     ///
-    /// Returns true if the connection is healthy, false otherwise.
-    pub async fn health_check(&self, timeout: std::time::Duration) -> bool {
-        let result = tokio::time::timeout(timeout, self.client.simple_query("SELECT 1")).await;
-
-        match result {
-            Ok(Ok(_)) => true,
+    /// Returns the connection's transaction status if it's healthy, `None` otherwise.
+    pub async fn health_check(&self, timeout: std::time::Duration) -> Option<TransactionStatus> {
+        match tokio::time::timeout(timeout, self.transaction_status()).await {
+            Ok(Ok(status)) => Some(status),
             Ok(Err(e)) => {
                 tracing::warn!("health check failed: {}", e);
-                false
+                None
             }
             Err(_) => {
                 tracing::warn!("health check timed out");
-                false
+                None
+            }
+        }
+    }
+
+    /// Whether the connection's session was left inside a transaction block, and if
+    /// so, whether that transaction was aborted.
+    pub async fn transaction_status(&self) -> Result<TransactionStatus, PgError> {
+        match self.client.simple_query(IN_TRANSACTION_SQL).await {
+            Ok(messages) => {
+                let in_transaction = messages.iter().any(|message| {
+                    matches!(message, SimpleQueryMessage::Row(row) if row.get(0) == Some("t"))
+                });
+                Ok(if in_transaction {
+                    TransactionStatus::InTransaction
+                } else {
+                    TransactionStatus::Idle
+                })
             }
+            // any statement in an aborted transaction fails until it's rolled back
+            Err(err) if err.code() == Some(&SqlState::IN_FAILED_SQL_TRANSACTION) => {
+                Ok(TransactionStatus::Failed)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Run a connection's `validation_query`, failing if it can't be run (e.g. because
+    /// the connection's `search_path` doesn't include a table it refers to).
+    pub async fn validate(&self, validation_query: &str) -> eyre::Result<()> {
+        self.client
+            .simple_query(validation_query)
+            .await
+            .map_err(|err| {
+                eyre::eyre!(
+                    "validation query {validation_query:?} failed: {}",
+                    PgError::from(err)
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Roll back any transaction that was left open (or aborted) on this connection,
+    /// so that whoever checks it out next doesn't inherit it. Returns whether a
+    /// rollback was needed.
+    pub async fn rollback_open_transaction(&self) -> eyre::Result<bool> {
+        let in_transaction = self.transaction_status().await? != TransactionStatus::Idle;
+
+        if in_transaction {
+            self.client
+                .batch_execute("ROLLBACK")
+                .await
+                .map_err(PgError::from)?;
         }
+
+        Ok(in_transaction)
+    }
+
+    /// Discard any session state (settings, roles, temp tables, etc.) left behind by
+    /// whoever last used this connection, then re-apply the connection's own settings.
+    /// This can't be run inside a transaction block.
+    pub async fn reset_session(
+        &self,
+        isolation_level: Option<IsolationLevel>,
+        session_setup: &[String],
+        force_utf8: bool,
+    ) -> eyre::Result<()> {
+        self.client
+            .batch_execute(RESET_SESSION_SQL)
+            .await
+            .map_err(PgError::from)?;
+        init_session(&self.client, isolation_level, session_setup, force_utf8).await?;
+        Ok(())
     }
 
     /// Kill the connection if it's still alive.
@@ -179,40 +658,293 @@ impl Connection {
     }
 }
 
+/// Cancels the query running on a connection (see `Connection::canceller`). The
+/// cancelled query fails with `57014` (query_canceled).
+#[derive(Clone)]
+pub struct Canceller {
+    token: tokio_postgres::CancelToken,
+    tls: Option<MakeTlsConnector>,
+}
+
+impl Canceller {
+    pub async fn cancel(&self) -> eyre::Result<()> {
+        match &self.tls {
+            Some(tls) => self.token.cancel_query(tls.clone()).await?,
+            None => self.token.cancel_query(tokio_postgres::NoTls).await?,
+        }
+        Ok(())
+    }
+}
+
 pub async fn connect(config: &Config) -> eyre::Result<Connection> {
+    connect_with_messages(config, None).await
+}
+
+/// Open a connection that will `LISTEN` on the given channel, forwarding any
+/// notifications (and notices) received to `messages`.
+pub async fn listen(
+    config: &Config,
+    channel: &str,
+    messages: UnboundedSender<AsyncMessage>,
+) -> eyre::Result<Connection> {
+    let conn = connect_with_messages(config, Some(messages)).await?;
+    conn.batch_execute(&format!("LISTEN {channel}"))
+        .await
+        .map_err(PgError::from)?;
+    Ok(conn)
+}
+
+/// Postgres rejects `NOTIFY` payloads that are this many bytes long (or longer).
+pub const MAX_NOTIFY_PAYLOAD: usize = 8000;
+
+/// Send a notification on `channel`, which any connections listening on it will receive
+/// (see `listen`). The channel name is used as-is, so isn't case-folded like in `NOTIFY`.
+pub async fn notify(client: &Client, channel: &str, payload: &str) -> eyre::Result<()> {
+    validate_identifiers([channel])
+        .map_err(|err| eyre::eyre!("invalid notification channel: {err}"))?;
+    if payload.len() >= MAX_NOTIFY_PAYLOAD {
+        eyre::bail!(
+            "notification payload is {} bytes long, but must be shorter than \
+             {MAX_NOTIFY_PAYLOAD} bytes",
+            payload.len()
+        );
+    }
+
+    query(client, "SELECT pg_notify($1, $2)", &[&channel, &payload]).await?;
+    Ok(())
+}
+
+/// Open a connection, forwarding any notices or notifications received to `messages`.
+pub async fn connect_with_messages(
+    config: &Config,
+    messages: Option<UnboundedSender<AsyncMessage>>,
+) -> eyre::Result<Connection> {
     let (live_tx, live_rx) = channel();
     let (kill_tx, kill_rx) = channel();
 
-    let mut conn_config = config.conn_str().parse::<tokio_postgres::Config>()?;
+    let mut conn_config = tokio_postgres::Config::new();
+    conn_config
+        .user(&config.username)
+        .password(&config.password)
+        .dbname(&config.database)
+        .target_session_attrs(config.target_session_attrs.into());
 
     conn_config.keepalives(true);
     conn_config.keepalives_idle(std::time::Duration::from_secs(config.tcp_keepalive_s));
     conn_config.keepalives_interval(std::time::Duration::from_secs(config.tcp_keepalive_s / 2));
     conn_config.keepalives_retries(3);
     conn_config.connect_timeout(std::time::Duration::from_secs(10));
+    conn_config.channel_binding(config.channel_binding.resolve(config.ssl)?);
 
-    let client = if config.ssl {
-        let tls = MakeTlsConnector::new(TlsConnector::new()?);
-        let (client, conn) = conn_config.connect(tls).await?;
+    if let Some(options) = &config.options {
+        validate_options(options)?;
+        conn_config.options(options);
+    }
+    for statement in &config.session_setup {
+        validate_session_setup(statement)?;
+    }
 
-        spawn_conn(conn, live_tx, kill_rx);
+    if config.ssl_root_cert.is_some() && !config.ssl {
+        eyre::bail!("`ssl_root_cert` requires SSL; enable `ssl` for this connection");
+    }
+    let tls = match config.ssl {
+        true => Some(MakeTlsConnector::new(tls_connector(
+            config.ssl_root_cert.as_deref(),
+        )?)),
+        false => None,
+    };
 
-        client
-    } else {
-        let (client, conn) = conn_config.connect(tokio_postgres::NoTls).await?;
+    // try each host in turn (rather than letting `tokio_postgres` do it) to know
+    // which one was connected to
+    let hosts = config.hosts()?;
+    let mut channels = Some((live_tx, kill_rx, messages));
+    let mut errors = Vec::new();
+    let mut connected = None;
+    for (host, port) in &hosts {
+        let mut host_config = conn_config.clone();
+        host_config.host(host).port(*port);
+
+        let result = match &tls {
+            Some(tls) => host_config
+                .connect(tls.clone())
+                .await
+                .map(|(client, conn)| {
+                    let (live_tx, kill_rx, messages) = channels.take().unwrap();
+                    spawn_conn(conn, live_tx, kill_rx, messages);
+                    client
+                }),
+            None => host_config
+                .connect(tokio_postgres::NoTls)
+                .await
+                .map(|(client, conn)| {
+                    let (live_tx, kill_rx, messages) = channels.take().unwrap();
+                    spawn_conn(conn, live_tx, kill_rx, messages);
+                    client
+                }),
+        };
 
-        spawn_conn(conn, live_tx, kill_rx);
+        match result {
+            Ok(client) => {
+                connected = Some((client, format!("{host}:{port}")));
+                break;
+            }
+            Err(err) => errors.push((format!("{host}:{port}"), err)),
+        }
+    }
 
-        client
+    let Some((client, host)) = connected else {
+        if errors.len() == 1 {
+            let (_, err) = errors.pop().unwrap();
+            return Err(explain_connect_error(config, err));
+        }
+        eyre::bail!(
+            "couldn't connect to any host: {}",
+            errors
+                .into_iter()
+                .map(|(host, err)| format!("{host}: {}", explain_connect_error(config, err)))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
     };
 
+    let encoding_warning = init_session(
+        &client,
+        config.isolation_level,
+        &config.session_setup,
+        config.force_utf8,
+    )
+    .await?;
+    if let Some(warning) = &encoding_warning {
+        tracing::warn!(
+            "connected to db \"{}\" on {host}: {warning}",
+            config.database
+        );
+    }
+
     Ok(Connection {
-        client: Client::new(client).await?,
+        client: Client::new(client, config).await?,
+        host,
+        tls,
         rx: Some(live_rx),
         tx: Some(kill_tx),
+        encoding_warning,
     })
 }
 
+/// Build a TLS connector, additionally trusting the CA certificate(s) in `root_cert`
+/// (e.g. a managed database provider's CA bundle) if given.
+fn tls_connector(root_cert: Option<&std::path::Path>) -> eyre::Result<TlsConnector> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = root_cert {
+        let pem = std::fs::read_to_string(path).map_err(|err| {
+            eyre::eyre!(
+                "couldn't read SSL root certificate {}: {err}",
+                path.display()
+            )
+        })?;
+
+        // a bundle may contain several certificates, which have to be added one by one
+        let mut rest = pem.as_str();
+        let mut count = 0;
+        while let Some(start) = rest.find(BEGIN) {
+            let Some(end) = rest[start..].find(END).map(|end| start + end + END.len()) else {
+                break;
+            };
+            let block = &rest[start..end];
+            let cert = native_tls::Certificate::from_pem(block.as_bytes())
+                .map_err(|err| eyre::eyre!("invalid certificate in {}: {err}", path.display()))?;
+            builder.add_root_certificate(cert);
+            count += 1;
+            rest = &rest[end..];
+        }
+        if count == 0 {
+            eyre::bail!(
+                "SSL root certificate {} doesn't contain any PEM certificates",
+                path.display()
+            );
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Apply a connection's session settings, either when it's first opened or after
+/// its session has been reset. Returns a warning if text in the session's results
+/// may not decode correctly (see `check_encoding`).
+async fn init_session(
+    client: &tokio_postgres::Client,
+    isolation_level: Option<IsolationLevel>,
+    session_setup: &[String],
+    force_utf8: bool,
+) -> eyre::Result<Option<String>> {
+    if let Some(level) = isolation_level {
+        client
+            .batch_execute(&format!(
+                "SET SESSION CHARACTERISTICS AS TRANSACTION ISOLATION LEVEL {level}"
+            ))
+            .await
+            .map_err(PgError::from)?;
+    }
+
+    // run with the extended protocol, which (unlike `batch_execute`) only allows a
+    // single statement
+    for statement in session_setup {
+        client
+            .execute(statement.as_str(), &[])
+            .await
+            .map_err(|err| {
+                eyre::eyre!("session setup {statement:?} failed: {}", PgError::from(err))
+            })?;
+    }
+
+    check_encoding(client, force_utf8).await
+}
+
+/// Check that the server sends text as UTF-8, which is all that results are decoded
+/// as. If the session's `client_encoding` isn't UTF-8, it's set back if `force_utf8` is
+/// set; otherwise, or if the database is `SQL_ASCII` (whose text is sent as-is, in
+/// whatever encoding it was stored in), returns a warning.
+async fn check_encoding(
+    client: &tokio_postgres::Client,
+    force_utf8: bool,
+) -> eyre::Result<Option<String>> {
+    let row = client
+        .query_one(
+            "SELECT current_setting('client_encoding'), current_setting('server_encoding')",
+            &[],
+        )
+        .await
+        .map_err(PgError::from)?;
+    let (client_encoding, server_encoding): (String, String) = (row.get(0), row.get(1));
+
+    let mut warnings = Vec::new();
+    if client_encoding != "UTF8" {
+        if force_utf8 {
+            client
+                .batch_execute("SET client_encoding = 'UTF8'")
+                .await
+                .map_err(PgError::from)?;
+        } else {
+            warnings.push(format!(
+                "client_encoding is {client_encoding} rather than UTF8, so text that isn't \
+                 ASCII will be garbled (or fail to decode)"
+            ));
+        }
+    }
+    if server_encoding == "SQL_ASCII" {
+        warnings.push(
+            "the database's encoding is SQL_ASCII, so text isn't converted to UTF8 (and \
+             any that isn't valid UTF8 will fail to decode)"
+                .to_owned(),
+        );
+    }
+
+    Ok((!warnings.is_empty()).then(|| warnings.join("; ")))
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum FilterOp {
@@ -226,6 +958,8 @@ pub enum FilterOp {
     Gte,
     Lt,
     Lte,
+    /// Containment (`@>`), e.g. an `hstore` or `jsonb` column containing the given keys.
+    Contains,
 }
 
 #[derive(Debug, Deserialize)]
@@ -284,6 +1018,7 @@ impl Filter {
             FilterOp::Gte => ">=",
             FilterOp::Lt => "<",
             FilterOp::Lte => "<=",
+            FilterOp::Contains => "@>",
         }
     }
 
@@ -295,68 +1030,410 @@ impl Filter {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QueryResult {
     pub columns: Vec<QueryResultColumn>,
     pub rows: Vec<Vec<serde_json::Value>>,
+    /// How the rows were decoded (see `TextFallback`).
+    pub encoding: ResultEncoding,
 }
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "kebab-case")]
-pub enum PaginatedQueryResult {
-    Select {
-        /// 1-indexed page number.
-        page: usize,
-        /// The number of rows included in a single page.
-        /// If negative, all available rows are included.
-        page_size: isize,
-        /// The number of rows contained in the current page.
-        page_count: usize,
-        /// The total number of rows available across all pages.
-        total_count: usize,
-        /// The total number of pages.
-        total_pages: usize,
-        /// The sort order used to generate this page. The sort `column_idx` can
-        /// be used to index into the `QueryResult`'s `columns` array to get the
-        /// column name.
-        sort: Option<Sort>,
-        /// The current page.
-        entries: QueryResult,
-    },
+/// Determines how queries that return columns with types that can't be decoded
+/// using BINARY encoding are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextFallback {
+    /// Return an error naming the unsupported column(s).
+    Strict,
+    /// Re-run the query using TEXT encoding, which supports every type but
+    /// returns all values as strings and doesn't support parameters.
+    #[default]
+    Fallback,
+    /// Return `null` for the unsupported column(s), which are flagged with
+    /// `unsupported_type`, and decode the rest using BINARY encoding.
+    Skip,
+}
 
-    ModifyData {
-        /// How many rows were updated/deleted.
-        affected_rows: u64,
-    },
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultEncoding {
+    /// Every column was decoded using BINARY encoding.
+    Binary,
+    /// Every column was decoded using TEXT encoding.
+    Text,
+    /// Unsupported columns were returned as `null`, and the rest were decoded using
+    /// BINARY encoding.
+    Partial,
+}
 
-    ModifyStructure,
+impl TextFallback {
+    /// Determine how to decode rows with the given `(name, type)` columns. Returns the
+    /// encoding to use and the indices of any columns that can't be decoded.
+    fn resolve<'a>(
+        &self,
+        columns: impl IntoIterator<Item = (&'a str, &'a tokio_postgres::types::Type)>,
+    ) -> eyre::Result<(ResultEncoding, Vec<usize>)> {
+        let unsupported = columns
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (_, type_))| !type_supported(type_))
+            .collect::<Vec<_>>();
 
-    Explain {
-        /// The query that was executed.
+        if unsupported.is_empty() {
+            return Ok((ResultEncoding::Binary, Vec::new()));
+        }
+
+        match self {
+            TextFallback::Strict => eyre::bail!(
+                "unsupported column type(s): {}",
+                unsupported
+                    .iter()
+                    .map(|(_, (name, type_))| format!("\"{name}\" ({})", type_display_name(type_)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            TextFallback::Fallback => Ok((ResultEncoding::Text, Vec::new())),
+            TextFallback::Skip => Ok((
+                ResultEncoding::Partial,
+                unsupported.into_iter().map(|(idx, _)| idx).collect(),
+            )),
+        }
+    }
+}
+
+/// Regex patterns that queries on a connection are checked against before they're run.
+/// Patterns are case-insensitive and match anywhere in the query (with comments removed)
+/// unless anchored, e.g. `^\s*SELECT\b`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryPolicy {
+    /// If non-empty, only queries matching at least one of these patterns may be run.
+    pub allow: Vec<String>,
+    /// Queries matching any of these patterns are blocked, even if they're allowed.
+    pub deny: Vec<String>,
+}
+
+/// A `QueryPolicy` with its patterns compiled, ready to check queries.
+#[derive(Debug, Default)]
+struct Policy {
+    allow: Vec<regex::Regex>,
+    deny: Vec<regex::Regex>,
+}
+
+impl QueryPolicy {
+    fn compile(&self) -> eyre::Result<Policy> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    regex::RegexBuilder::new(pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|err| {
+                            eyre::eyre!("invalid query policy pattern {pattern:?}: {err}")
+                        })
+                })
+                .collect::<eyre::Result<Vec<_>>>()
+        };
+
+        Ok(Policy {
+            allow: compile(&self.allow)?,
+            deny: compile(&self.deny)?,
+        })
+    }
+}
+
+impl Policy {
+    /// Check whether the query may be run, naming the rule that blocked it if not.
+    fn check(&self, query: &str) -> eyre::Result<()> {
+        if let Some(rule) = self.deny.iter().find(|rule| rule.is_match(query)) {
+            eyre::bail!("Query blocked by policy: it matches the denied pattern `{rule}`");
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|rule| rule.is_match(query)) {
+            eyre::bail!(
+                "Query blocked by policy: it doesn't match any allowed pattern ({})",
+                self.allow
+                    .iter()
+                    .map(|rule| format!("`{rule}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Overrides for how values are formatted when serialized to JSON. Unset fields
+/// keep the default formatting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayFormat {
+    /// A `time` format description for `DATE` values, e.g. `"[month]/[day]/[year]"`.
+    pub date: Option<String>,
+    /// A `time` format description for `TIMESTAMP` / `TIMESTAMPTZ` values, or `"epoch"`
+    /// for seconds since the Unix epoch. Timestamps without a time zone are treated as UTC.
+    pub timestamp: Option<String>,
+    /// How `NUMERIC` values are displayed.
+    pub numeric: NumericDisplay,
+    /// Display `INT8` values (and `NUMERIC` values displayed as numbers) outside of
+    /// JavaScript's safe integer range as strings, so that they aren't rounded.
+    pub safe_integers: bool,
+}
+
+/// The largest integer a JavaScript number (an `f64`) can represent exactly,
+/// `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumericDisplay {
+    /// As a string, which preserves arbitrary precision.
+    #[default]
+    String,
+    /// As a JSON number, which may lose precision.
+    Number,
+}
+
+/// A `DisplayFormat` with its format descriptions parsed, ready to format values.
+#[derive(Debug, Default)]
+struct Formatter {
+    date: Option<time::format_description::OwnedFormatItem>,
+    timestamp: Option<TimestampFormat>,
+    numeric: NumericDisplay,
+    safe_integers: bool,
+}
+
+#[derive(Debug)]
+enum TimestampFormat {
+    Epoch,
+    Custom(time::format_description::OwnedFormatItem),
+}
+
+impl DisplayFormat {
+    fn compile(&self) -> eyre::Result<Formatter> {
+        use time::format_description::parse_owned;
+        use time::macros::{date, datetime};
+
+        let date = self
+            .date
+            .as_deref()
+            .map(|fmt| {
+                let item = parse_owned::<2>(fmt)
+                    .map_err(|err| eyre::eyre!("invalid date format {fmt:?}: {err}"))?;
+                // catch components that can't be used to format a date (e.g. `[hour]`)
+                date!(2000 - 01 - 01)
+                    .format(&item)
+                    .map_err(|err| eyre::eyre!("invalid date format {fmt:?}: {err}"))?;
+                eyre::Ok(item)
+            })
+            .transpose()?;
+
+        let timestamp = self
+            .timestamp
+            .as_deref()
+            .map(|fmt| {
+                if fmt == "epoch" {
+                    return eyre::Ok(TimestampFormat::Epoch);
+                }
+                let item = parse_owned::<2>(fmt)
+                    .map_err(|err| eyre::eyre!("invalid timestamp format {fmt:?}: {err}"))?;
+                datetime!(2000-01-01 0:00 UTC)
+                    .format(&item)
+                    .map_err(|err| eyre::eyre!("invalid timestamp format {fmt:?}: {err}"))?;
+                Ok(TimestampFormat::Custom(item))
+            })
+            .transpose()?;
+
+        Ok(Formatter {
+            date,
+            timestamp,
+            numeric: self.numeric,
+            safe_integers: self.safe_integers,
+        })
+    }
+}
+
+impl Formatter {
+    fn date(&self, date: time::Date) -> serde_json::Value {
+        match &self.date {
+            Some(item) => date.format(item).ok().into(),
+            None => date
+                .format(format_description!("[year]-[month]-[day]"))
+                .ok()
+                .into(),
+        }
+    }
+
+    /// Format a timestamp, using `default` if no timestamp format is configured.
+    fn timestamp(
+        &self,
+        timestamp: time::OffsetDateTime,
+        default: &[time::format_description::BorrowedFormatItem<'_>],
+    ) -> serde_json::Value {
+        match &self.timestamp {
+            Some(TimestampFormat::Epoch) => timestamp.unix_timestamp().into(),
+            Some(TimestampFormat::Custom(item)) => timestamp.format(item).ok().into(),
+            None => timestamp.format(default).ok().into(),
+        }
+    }
+
+    fn int8(&self, val: i64) -> serde_json::Value {
+        if self.safe_integers && val.unsigned_abs() > MAX_SAFE_INTEGER as u64 {
+            val.to_string().into()
+        } else {
+            val.into()
+        }
+    }
+
+    fn numeric(&self, val: Decimal) -> serde_json::Value {
+        use rust_decimal::prelude::ToPrimitive;
+        match self.numeric {
+            NumericDisplay::String => val.to_string().into(),
+            NumericDisplay::Number
+                if self.safe_integers && val.abs() > Decimal::from(MAX_SAFE_INTEGER) =>
+            {
+                val.to_string().into()
+            }
+            NumericDisplay::Number => val
+                .to_f64()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number)
+                .unwrap_or_else(|| val.to_string().into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PaginatedQueryResult {
+    Select {
+        /// 1-indexed page number.
+        page: usize,
+        /// The number of rows included in a single page.
+        /// If negative, all available rows are included.
+        page_size: isize,
+        /// The number of rows contained in the current page.
+        page_count: usize,
+        /// The total number of rows available across all pages.
+        total_count: usize,
+        /// The total number of pages.
+        total_pages: usize,
+        /// Whether there's a page after this one. With `CountMode::Estimate`, this
+        /// is exact, unlike `total_pages`.
+        has_next: bool,
+        /// Whether there's a page before this one.
+        has_prev: bool,
+        /// The sort order used to generate this page. The sort `column_idx` can
+        /// be used to index into the `QueryResult`'s `columns` array to get the
+        /// column name.
+        sort: Option<Sort>,
+        /// The current page.
+        entries: QueryResult,
+        /// Whether the requested page size was clamped to the connection's
+        /// `max_page_size`, since the query doesn't have its own `LIMIT`.
+        clamped: bool,
+        /// The page size that was requested, if it was clamped.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        requested_page_size: Option<isize>,
+        /// With `CountMode::Estimate`, the page that was requested, if it was past the
+        /// last page, in which case the last page is returned instead.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        requested_page: Option<usize>,
+        /// With `CountMode::Estimate`, the planner's estimate of the total row count.
+        /// This can be far off, so `total_count` is this estimate adjusted to be
+        /// consistent with the rows actually returned.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total_count_estimate: Option<usize>,
+        /// With `QueryOptions::with_plan`, the `EXPLAIN (FORMAT JSON)` plan for the page.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        plan: Option<serde_json::Value>,
+    },
+
+    ModifyData {
+        /// How many rows were updated/deleted.
+        affected_rows: u64,
+        /// The command tag, e.g. `INSERT 0 3` or `UPDATE 2`. Note that for an upsert
+        /// (`INSERT ... ON CONFLICT DO UPDATE`), the count includes updated rows.
+        command: String,
+    },
+
+    ModifyStructure,
+
+    Explain {
+        /// The query that was executed.
         query: String,
         /// The query execution plan.
         plan: String,
+        /// The first few rows of the explained statement, if requested with
+        /// `QueryOptions::with_sample` (see `explained_statement`).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sample: Option<QueryResult>,
+    },
+
+    /// The SQL a `SELECT` query would run, with `QueryOptions::dry`.
+    DryRun {
+        /// The query for the requested page, with filters and sort applied.
+        page_query: String,
+        /// The query for the total row count, unless it's estimated
+        /// (with `CountMode::Estimate`) or included in the page (`CountMode::Window`).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        count_query: Option<String>,
+        /// The parameters bound to both queries: the query's own, followed by
+        /// the filters' values.
+        params: Vec<serde_json::Value>,
+    },
+
+    Command {
+        /// The command tag, e.g. `SET` or `LISTEN`.
+        message: String,
+        /// For a `CALL` of a procedure with output arguments, the single row of
+        /// their values.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<QueryResult>,
     },
 }
 
 pub type QueryRows = Vec<HashMap<String, serde_json::Value>>;
 
 impl QueryResult {
+    /// Each row as a map of column name to value. Duplicate column names (e.g. from
+    /// `SELECT a.id, b.id ...`) are disambiguated as in `column_keys`, so that no
+    /// values are lost.
     pub fn row_maps(&self) -> Vec<HashMap<String, serde_json::Value>> {
+        let keys = self.column_keys();
         self.rows
             .iter()
-            .map(|row| {
-                self.columns
-                    .iter()
-                    .zip(row)
-                    .map(|(col, val)| (col.name.clone(), val.clone()))
-                    .collect()
+            .map(|row| keys.iter().cloned().zip(row.iter().cloned()).collect())
+            .collect()
+    }
+
+    /// The column names, with repeated names suffixed by their occurrence, e.g.
+    /// `id`, `id_2`, `id_3` (skipping any suffixed name that's already a column).
+    pub fn column_keys(&self) -> Vec<String> {
+        let names = self
+            .columns
+            .iter()
+            .map(|col| col.name.as_str())
+            .collect::<HashSet<_>>();
+        let mut used = HashSet::new();
+        self.columns
+            .iter()
+            .map(|col| {
+                if used.insert(col.name.clone()) {
+                    return col.name.clone();
+                }
+                (2..)
+                    .map(|n| format!("{}_{n}", col.name))
+                    .find(|key| !names.contains(key.as_str()) && used.insert(key.clone()))
+                    .unwrap()
             })
             .collect()
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QueryResultColumn {
     #[serde(skip_serializing)]
     pub table_oid: Option<u32>,
@@ -367,11 +1444,15 @@ pub struct QueryResultColumn {
     pub index: usize,
     #[serde(rename = "type")]
     pub type_: String,
+    /// If set, this column's type can't be decoded and its values are all `null`;
+    /// this is the type's name, e.g. `record[]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsupported_type: Option<String>,
     #[serde(flatten)]
     pub extended: Option<QueryResultColumnExtended>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QueryResultColumnExtended {
     pub source_table: Option<String>,
     pub source_column: Option<String>,
@@ -381,9 +1462,35 @@ pub struct QueryResultColumnExtended {
 }
 
 impl QueryResultColumn {
-    /// Fetch additional information about the given set of columns, including the source table
-    /// and column names and FKs. This will be accomplished in a single batch of queries.
-    pub async fn fetch_extended(columns: &mut Vec<Self>, client: &Client) -> eyre::Result<()> {
+    /// Fill in extended info (source table/column, FKs) for each column. This is only
+    /// enrichment, so failures (e.g. lacking permission on `pg_attribute` or
+    /// `pg_constraint`) are logged and the columns are left as they are.
+    pub async fn fetch_extended(columns: &mut [Self], client: &Client) {
+        let extended = Self::try_fetch_extended(columns, client).await;
+        Self::apply_extended(columns, extended);
+    }
+
+    fn apply_extended(
+        columns: &mut [Self],
+        extended: eyre::Result<Vec<Option<QueryResultColumnExtended>>>,
+    ) {
+        match extended {
+            Ok(extended) => {
+                for (col, ext) in columns.iter_mut().zip(extended) {
+                    if ext.is_some() {
+                        col.extended = ext;
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("failed to fetch extended column info: {:#}", e),
+        }
+    }
+
+    /// Look up extended info for each column, in the same order as `columns`.
+    async fn try_fetch_extended(
+        columns: &[Self],
+        client: &Client,
+    ) -> eyre::Result<Vec<Option<QueryResultColumnExtended>>> {
         // this may overfetch a bit when the same column IDs exist across multiple tables,
         // but this is still better than not filtering by column ID at all
         let sql = "
@@ -414,11 +1521,11 @@ impl QueryResultColumn {
 
         // we won't always have table/column IDs
         if table_ids.is_empty() || column_ids.is_empty() {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
         let stmt = prepare(&client, sql).await?;
-        let rows = raw_query(client, &stmt, &[&table_ids, &column_ids]).await?;
+        let (rows, _, _) = raw_query(client, &stmt, &[&table_ids, &column_ids]).await?;
 
         let attr_lookup: HashMap<(u32, i16), (String, String, String)> =
             HashMap::from_iter(rows.into_iter().map(|row| {
@@ -497,7 +1604,7 @@ impl QueryResultColumn {
             .collect::<Vec<_>>();
 
         let stmt = prepare(&client, sql).await?;
-        let rows = raw_query(client, &stmt, &[&table_schemas, &table_names]).await?;
+        let (rows, _, _) = raw_query(client, &stmt, &[&table_schemas, &table_names]).await?;
 
         let fk_lookup: HashMap<(String, String), (String, String, String)> =
             HashMap::from_iter(rows.into_iter().map(|row| {
@@ -519,7 +1626,9 @@ impl QueryResultColumn {
                 )
             }));
 
-        for col in columns.iter_mut() {
+        let mut extended = Vec::with_capacity(columns.len());
+        for col in columns {
+            let mut found = None;
             if let Some(table_id) = col.table_oid
                 && let Some(column_id) = col.column_id
                 && let Some((_, table_name, column_name)) = attr_lookup.get(&(table_id, column_id))
@@ -540,14 +1649,49 @@ impl QueryResultColumn {
                     ext.fk_column = Some(target_column_name.clone());
                 }
 
-                col.extended = Some(ext);
+                found = Some(ext);
             }
+            extended.push(found);
         }
 
-        Ok(())
+        Ok(extended)
     }
 }
 
+/// The effective settings of a connection's session, e.g. to check that a role or
+/// `search_path` change took effect.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub current_user: String,
+    pub session_user: String,
+    pub current_database: String,
+    /// `null` if none of the schemas in the `search_path` exist.
+    pub current_schema: Option<String>,
+    pub search_path: String,
+    pub server_version: String,
+    pub server_encoding: String,
+    pub read_only: bool,
+}
+
+pub async fn session_info(client: &Client) -> eyre::Result<SessionInfo> {
+    let sql = "
+    SELECT
+        current_user::text AS \"current_user\",
+        session_user::text AS \"session_user\",
+        current_database()::text AS current_database,
+        current_schema()::text AS current_schema,
+        current_setting('search_path') AS search_path,
+        current_setting('server_version') AS server_version,
+        current_setting('server_encoding') AS server_encoding,
+        current_setting('transaction_read_only')::bool AS read_only";
+    let row = query(client, sql, &[])
+        .await?
+        .row_maps()
+        .pop()
+        .ok_or(eyre::eyre!("session info query returned no rows"))?;
+    Ok(serde_json::from_value(serde_json::to_value(row)?)?)
+}
+
 pub async fn version_info(client: &Client) -> eyre::Result<String> {
     let sql = "select version();";
     Ok(query(client, sql, &[]).await?.rows[0][0]
@@ -556,139 +1700,868 @@ pub async fn version_info(client: &Client) -> eyre::Result<String> {
         .to_owned())
 }
 
-pub async fn list_tables(client: &Client, schema: &str) -> eyre::Result<QueryRows> {
-    let table_sql = "
+/// Select a relation's total size in bytes as `{alias}` and, if `human_readable` is set,
+/// a pretty-printed version of it (e.g. `12 MB`) as `{alias}_pretty`. Relations without
+/// storage (i.e. `relation` is `None`) have a size of `-1` and a pretty size of `null`.
+fn size_columns(relation: Option<&str>, alias: &str, human_readable: bool) -> String {
+    let size = match relation {
+        Some(relation) => format!("pg_total_relation_size({relation})"),
+        None => "-1".to_owned(),
+    };
+
+    if !human_readable {
+        return format!("{size} as {alias}");
+    }
+
+    let pretty = match relation {
+        Some(_) => format!("pg_size_pretty({size})"),
+        None => "null".to_owned(),
+    };
+
+    format!("{size} as {alias},\n      {pretty} as {alias}_pretty")
+}
+
+/// The kinds of relations returned by `list_tables`, as named in its `type` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableKind {
+    Table,
+    View,
+    #[serde(alias = "matview")]
+    MaterializedView,
+}
+
+/// Narrows the relations returned by `list_tables`; the default returns all of them.
+#[derive(Debug, Clone, Default)]
+pub struct TableFilter {
+    /// An `ILIKE` pattern the name must match, e.g. `user%` for a prefix.
+    pub name_like: Option<String>,
+    pub kind: Option<TableKind>,
+}
+
+impl TableFilter {
+    fn includes(&self, kind: TableKind) -> bool {
+        self.kind.is_none_or(|k| k == kind)
+    }
+}
+
+/// What to order the relations returned by `list_tables` by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableOrder {
+    Name,
+    /// Total size on disk, including indexes and TOAST (views have no size).
+    Size,
+    /// The planner's row estimate (`reltuples`).
+    RowEstimate,
+}
+
+impl TableOrder {
+    fn compare(
+        self,
+        a: &HashMap<String, serde_json::Value>,
+        b: &HashMap<String, serde_json::Value>,
+    ) -> std::cmp::Ordering {
+        // views have no size (-1), and relations that were never analyzed may have no estimate
+        let number = |key: &str| {
+            let value =
+                |row: &HashMap<String, serde_json::Value>| row[key].as_f64().unwrap_or(-1.0);
+            value(a).total_cmp(&value(b))
+        };
+        match self {
+            TableOrder::Name => a["table_name"].as_str().cmp(&b["table_name"].as_str()),
+            TableOrder::Size => number("table_size"),
+            TableOrder::RowEstimate => number("table_rows_est"),
+        }
+    }
+}
+
+/// Relations are listed by kind (tables, then views, then materialized views) and
+/// then by name, unless `order` is given, in which case all kinds are interleaved
+/// (with ties ordered by name).
+pub async fn list_tables(
+    client: &Client,
+    schema: &str,
+    filter: &TableFilter,
+    order: Option<(TableOrder, SortDirection)>,
+    human_readable: bool,
+) -> eyre::Result<QueryRows> {
+    let table_sql = format!(
+        "
     SELECT
       'table' as type,
       t.table_schema,
       t.table_name,
       c.reltuples as table_rows_est,
-      pg_total_relation_size(concat(t.table_schema, '.', t.table_name)) as table_size,
-      pg_size_pretty(pg_total_relation_size(concat(t.table_schema, '.', t.table_name))) as table_size_pretty
+      s.n_live_tup as table_rows_live,
+      d.description,
+      {}
     FROM information_schema.tables t
     JOIN pg_namespace n ON n.nspname = t.table_schema
     JOIN pg_class c ON c.relnamespace = n.oid AND c.relname = t.table_name
+    LEFT JOIN pg_stat_user_tables s ON s.relid = c.oid
+    {}
     WHERE t.table_schema = $1
     AND t.table_type = 'BASE TABLE'
-    ORDER BY t.table_name";
+    AND ($2::text IS NULL OR t.table_name ILIKE $2)
+    ORDER BY t.table_name",
+        size_columns(Some("c.oid"), "table_size", human_readable),
+        DESCRIPTION_JOIN,
+    );
 
-    let view_sql = "
+    let view_sql = format!(
+        "
     SELECT
       'view' as type,
       v.table_schema,
       v.table_name,
       c.reltuples as table_rows_est,
-      -1 as table_size,
-      null as table_size_pretty
+      d.description,
+      {}
     FROM information_schema.views v
     JOIN pg_namespace n ON n.nspname = v.table_schema
     JOIN pg_class c ON c.relnamespace = n.oid AND c.relname = v.table_name
+    {}
     WHERE v.table_schema = $1
-    ORDER BY v.table_name";
-
-    // let mat_view_sql = format!(
-    //     "SELECT 'materialized_view' as type, *
-    //     FROM (\n{}\n) _
-    //     WHERE table_schema = $1
-    //     ORDER BY table_name",
-    //     client.mat_view_query
-    // );
-
-    let (tables, views) = futures_util::future::try_join(
-        query(client, table_sql, &[&schema]),
-        query(client, view_sql, &[&schema]),
-        // query(client, &mat_view_sql, &[&schema]),
+    AND ($2::text IS NULL OR v.table_name ILIKE $2)
+    ORDER BY v.table_name",
+        size_columns(None, "table_size", human_readable),
+        DESCRIPTION_JOIN,
+    );
+
+    // materialized views aren't included in `information_schema`, but unlike
+    // regular views, they have storage and thus a size
+    let mat_view_sql = format!(
+        "
+    SELECT
+      'materialized_view' as type,
+      m.schemaname as table_schema,
+      m.matviewname as table_name,
+      c.reltuples as table_rows_est,
+      s.n_live_tup as table_rows_live,
+      d.description,
+      {}
+    FROM pg_matviews m
+    JOIN pg_namespace n ON n.nspname = m.schemaname
+    JOIN pg_class c ON c.relnamespace = n.oid AND c.relname = m.matviewname
+    LEFT JOIN pg_stat_user_tables s ON s.relid = c.oid
+    {}
+    WHERE m.schemaname = $1
+    AND ($2::text IS NULL OR m.matviewname ILIKE $2)
+    ORDER BY m.matviewname",
+        size_columns(Some("c.oid"), "table_size", human_readable),
+        DESCRIPTION_JOIN,
+    );
+
+    // relations of kinds that were filtered out aren't queried at all
+    let params: Vec<SqlParam> = vec![&schema, &filter.name_like];
+    let list = |kind: TableKind, sql: String| {
+        let params = &params;
+        async move {
+            if !filter.includes(kind) {
+                return Ok(Vec::new());
+            }
+            query(client, &sql, params).await.map(|res| res.row_maps())
+        }
+    };
+    let (mut tables, views, mut mat_views) = futures_util::future::try_join3(
+        list(TableKind::Table, table_sql),
+        list(TableKind::View, view_sql),
+        list(TableKind::MaterializedView, mat_view_sql),
     )
     .await?;
 
-    Ok(tables
-        .row_maps()
+    // views have no storage, so they're never analyzed
+    for row in tables.iter_mut().chain(mat_views.iter_mut()) {
+        let hint = stale_estimate_hint(
+            row["table_rows_est"].as_f64().unwrap_or(-1.0),
+            row["table_rows_live"].as_i64(),
+        );
+        row.insert("needs_analyze".to_owned(), hint.is_some().into());
+        row.insert("analyze_hint".to_owned(), hint.into());
+    }
+
+    let mut rows = tables
         .into_iter()
-        .chain(views.row_maps().into_iter())
-        // .chain(mat_views.row_maps().into_iter())
-        .collect())
+        .chain(views)
+        .chain(mat_views)
+        .collect::<QueryRows>();
+
+    if let Some((order, direction)) = order {
+        rows.sort_by(|a, b| {
+            let ordering = order.compare(a, b);
+            let ordering = match direction {
+                SortDirection::Asc => ordering,
+                SortDirection::Desc => ordering.reverse(),
+            };
+            ordering.then_with(|| TableOrder::Name.compare(a, b))
+        });
+    }
+
+    Ok(rows)
+}
+
+/// Joins the `COMMENT` on the relation `c` (a `pg_class` row) as `d.description`.
+const DESCRIPTION_JOIN: &str = "LEFT JOIN pg_description d
+      ON d.objoid = c.oid
+      AND d.classoid = 'pg_class'::regclass
+      AND d.objsubid = 0";
+
+/// If a relation's planner row estimate looks unreliable, explain why (suggesting
+/// `ANALYZE`). `live_rows` is the statistics collector's count of live rows, which is
+/// kept up to date on every write and so is a cheap point of comparison.
+fn stale_estimate_hint(estimate: f64, live_rows: Option<i64>) -> Option<String> {
+    if estimate < 0.0 {
+        return Some("never analyzed; run ANALYZE to estimate its row count".to_owned());
+    }
+
+    let live_rows = live_rows? as f64;
+    let (low, high) = if estimate < live_rows {
+        (estimate, live_rows)
+    } else {
+        (live_rows, estimate)
+    };
+
+    // ignore small tables, where even a large relative difference doesn't matter
+    if high - low >= 1000.0 && high >= low * 10.0 {
+        Some(format!(
+            "estimated {estimate} rows, but has ~{live_rows} live rows; \
+             run ANALYZE to update its statistics"
+        ))
+    } else {
+        None
+    }
 }
 
 pub async fn list_columns(client: &Client, schema: &str, table: &str) -> eyre::Result<QueryResult> {
+    // `ordinal_position` is the column's `attnum`, which column comments are keyed by
     let sql = "
-    SELECT column_name, data_type
-    FROM information_schema.columns
-    WHERE table_schema = $1
-    AND table_name = $2
-    ORDER BY ordinal_position";
+    SELECT col.column_name, col.data_type, d.description
+    FROM information_schema.columns col
+    JOIN pg_namespace n ON n.nspname = col.table_schema
+    JOIN pg_class c ON c.relnamespace = n.oid AND c.relname = col.table_name
+    LEFT JOIN pg_description d
+      ON d.objoid = c.oid
+      AND d.classoid = 'pg_class'::regclass
+      AND d.objsubid = col.ordinal_position
+    WHERE col.table_schema = $1
+    AND col.table_name = $2
+    ORDER BY col.ordinal_position";
     query(client, sql, &[&schema, &table]).await
 }
 
-pub async fn list_schemas(client: &Client) -> eyre::Result<QueryResult> {
-    let sql = "
-    SELECT *
-    FROM information_schema.schemata
-    WHERE schema_name NOT IN ('pg_catalog', 'pg_toast')
-    ORDER BY schema_name";
-    query(client, sql, &[]).await
+/// A table's columns (see `list_columns_batch`).
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableColumns {
+    pub schema: String,
+    pub table: String,
+    pub columns: Vec<ColumnInfo>,
 }
 
-pub async fn list_databases(client: &Client) -> eyre::Result<QueryResult> {
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub column_name: String,
+    pub data_type: String,
+    /// The column's comment, if it has one.
+    pub description: Option<String>,
+}
+
+/// List the columns of several tables at once, given as `(schema, table)` pairs, in
+/// the order they were given. Tables that don't exist have no columns.
+pub async fn list_columns_batch(
+    client: &Client,
+    tables: &[(String, String)],
+) -> eyre::Result<Vec<TableColumns>> {
+    // match on each (schema, table) pair, rather than on schemas and tables separately,
+    // so that e.g. `a.users` doesn't also match `b.users` when `b.orders` is requested
     let sql = "
-    SELECT *
-    FROM pg_database
-    WHERE datname NOT IN ('template0', 'template1')
-    ORDER BY datname";
-    query(client, sql, &[]).await
+    SELECT
+        col.table_schema::text AS schema,
+        col.table_name::text AS table,
+        col.column_name::text,
+        col.data_type::text,
+        d.description
+    FROM information_schema.columns col
+    JOIN unnest($1::text[], $2::text[]) t(schema, name)
+      ON t.schema = col.table_schema AND t.name = col.table_name
+    JOIN pg_namespace n ON n.nspname = col.table_schema
+    JOIN pg_class c ON c.relnamespace = n.oid AND c.relname = col.table_name
+    LEFT JOIN pg_description d
+      ON d.objoid = c.oid
+      AND d.classoid = 'pg_class'::regclass
+      AND d.objsubid = col.ordinal_position
+    ORDER BY col.ordinal_position";
+
+    let (schemas, names): (Vec<&str>, Vec<&str>) = tables
+        .iter()
+        .map(|(schema, table)| (schema.as_str(), table.as_str()))
+        .unzip();
+    let rows = query(client, sql, &[&schemas, &names]).await?.row_maps();
+
+    let mut grouped = Vec::<TableColumns>::with_capacity(tables.len());
+    let mut index = HashMap::new();
+    for (schema, table) in tables {
+        index
+            .entry((schema.as_str(), table.as_str()))
+            .or_insert_with(|| {
+                grouped.push(TableColumns {
+                    schema: schema.clone(),
+                    table: table.clone(),
+                    columns: Vec::new(),
+                });
+                grouped.len() - 1
+            });
+    }
+    for row in rows.iter() {
+        let key = (
+            row["schema"].as_str().unwrap_or_default(),
+            row["table"].as_str().unwrap_or_default(),
+        );
+        if let Some(&idx) = index.get(&key) {
+            grouped[idx].columns.push(ColumnInfo {
+                column_name: row["column_name"].as_str().unwrap_or_default().to_owned(),
+                data_type: row["data_type"].as_str().unwrap_or_default().to_owned(),
+                description: row["description"].as_str().map(str::to_owned),
+            });
+        }
+    }
+    Ok(grouped)
 }
 
-pub async fn table_ddl(client: &Client, schema: &str, table: &str) -> eyre::Result<String> {
-    // precision = np_radix ^ np_precision
-    // typically uses 2 or 10 as the radix, e.g. 2^32 or 10^20
-    let columns_sql = "
+/// A function, procedure or aggregate (see `list_functions`).
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    /// The arguments that identify this overload, e.g. `a integer, VARIADIC b text[]`
+    /// (without any defaults). Pass these to `function_ddl` to pick the overload.
+    pub identity_arguments: String,
+    /// The full argument list, including defaults, e.g. `a integer, b text DEFAULT 'x'`.
+    pub arguments: String,
+    /// The return type, e.g. `SETOF record`; `null` for procedures.
+    pub result_type: Option<String>,
+    /// `function`, `procedure`, `aggregate` or `window`.
+    pub kind: String,
+}
+
+/// List a schema's functions, procedures and aggregates. Overloads share a name, so
+/// each is listed separately with the arguments that tell them apart.
+pub async fn list_functions(client: &Client, schema: &str) -> eyre::Result<Vec<FunctionInfo>> {
+    let sql = "
     SELECT
-      column_name,
-      column_default,
-      is_nullable,
-      data_type,
-      character_maximum_length,
-      numeric_precision,
-      -- numeric_precision_radix,
-      numeric_scale
-    FROM information_schema.columns
-    WHERE table_schema = $1
-    AND table_name = $2
-    ORDER BY ordinal_position";
+        p.proname::text AS name,
+        pg_get_function_identity_arguments(p.oid) AS identity_arguments,
+        pg_get_function_arguments(p.oid) AS arguments,
+        pg_get_function_result(p.oid) AS result_type,
+        CASE p.prokind
+            WHEN 'p' THEN 'procedure'
+            WHEN 'a' THEN 'aggregate'
+            WHEN 'w' THEN 'window'
+            ELSE 'function'
+        END AS kind
+    FROM pg_proc p
+    JOIN pg_namespace n ON n.oid = p.pronamespace
+    WHERE n.nspname = $1
+    ORDER BY p.proname, pg_get_function_identity_arguments(p.oid)";
+
+    let rows = query(client, sql, &[&schema]).await?.row_maps();
+    Ok(serde_json::from_value(serde_json::to_value(rows)?)?)
+}
 
-    let indexes_sql = "
-    SELECT indexname, indexdef
-    FROM pg_indexes
+/// The `CREATE OR REPLACE FUNCTION` (or `PROCEDURE`) statement for the overload of
+/// `schema.name` with the given `identity_arguments` (as listed by `list_functions`).
+pub async fn function_ddl(
+    client: &Client,
+    schema: &str,
+    name: &str,
+    identity_arguments: &str,
+) -> eyre::Result<String> {
+    // aggregates are created with `CREATE AGGREGATE`, which `pg_get_functiondef` can't
+    // produce (it raises an error for them instead)
+    let sql = "
+    SELECT p.prokind = 'a', CASE WHEN p.prokind <> 'a' THEN pg_get_functiondef(p.oid) END
+    FROM pg_proc p
+    JOIN pg_namespace n ON n.oid = p.pronamespace
+    WHERE n.nspname = $1
+    AND p.proname = $2
+    AND pg_get_function_identity_arguments(p.oid) = $3";
+
+    let res = query(client, sql, &[&schema, &name, &identity_arguments]).await?;
+    match res.rows.first() {
+        Some(row) if row[0] == true => {
+            eyre::bail!("{schema}.{name}({identity_arguments}) is an aggregate, which has no DDL")
+        }
+        Some(row) => Ok(row[1].as_str().unwrap().trim().to_owned()),
+        None => eyre::bail!("no function named {schema}.{name}({identity_arguments})"),
+    }
+}
+
+/// Per-column statistics for a table, keyed by column name. These come from `pg_stats`,
+/// which is only populated once the table has been analyzed; if it hasn't been and
+/// `sample` is set, a rough profile is computed from a sample of the table's rows instead.
+///
+/// As in `pg_stats`, a negative `n_distinct` is the negated fraction of rows that are
+/// distinct (e.g. `-1` means every value is unique).
+pub async fn column_stats(
+    client: &Client,
+    schema: &str,
+    table: &str,
+    sample: bool,
+) -> eyre::Result<serde_json::Map<String, serde_json::Value>> {
+    let sql = "
+    SELECT
+        attname::text AS column_name,
+        null_frac,
+        n_distinct,
+        array_to_json(most_common_vals) AS most_common_vals,
+        array_to_json(most_common_freqs) AS most_common_freqs,
+        array_to_json(histogram_bounds) AS histogram_bounds
+    FROM pg_stats
     WHERE schemaname = $1
-    AND tablename = $2";
+    AND tablename = $2
+    AND NOT inherited";
+    let stats = query(client, sql, &[&schema, &table]).await?.row_maps();
 
-    let params: Vec<SqlParam> = vec![&schema, &table];
-    let (columns, indexes) = futures_util::try_join!(
-        query(client, columns_sql, &params),
-        query(client, indexes_sql, &params),
-    )?;
+    if !stats.is_empty() || !sample {
+        return Ok(stats
+            .into_iter()
+            .map(|mut row| {
+                let name = row.remove("column_name").unwrap_or_default();
+                row.insert("source".to_owned(), "pg_stats".into());
+                (
+                    name.as_str().unwrap_or_default().to_owned(),
+                    serde_json::to_value(row).unwrap(),
+                )
+            })
+            .collect());
+    }
 
-    let mut indexes = indexes.row_maps();
-    let pkey_col_name = if let Some(i) = indexes
+    let columns = list_columns(client, schema, table).await?;
+    if columns.rows.is_empty() {
+        eyre::bail!("no table named {schema}.{table}");
+    }
+    let columns = columns
+        .rows
         .iter()
-        .position(|i| i["indexname"].as_str().unwrap().ends_with("_pkey"))
-    {
-        // determine primary key column by parsing index definition
-        // e.g. `CREATE UNIQUE INDEX users_pkey ON public.users USING btree (id)`
-        let index = indexes.remove(i);
-        let pkey_def = index["indexdef"].as_str().unwrap();
-        let start_paren_idx = pkey_def.find("(").unwrap();
-        let id_col_name = &pkey_def[start_paren_idx + 1..pkey_def.len() - 1];
-        Some(id_col_name.to_owned())
-    } else {
-        None
-    };
+        .map(|row| row[0].as_str().unwrap_or_default().to_owned())
+        .collect::<Vec<_>>();
+
+    let live_rows = query(
+        client,
+        "SELECT n_live_tup FROM pg_stat_user_tables WHERE schemaname = $1 AND relname = $2",
+        &[&schema, &table],
+    )
+    .await?
+    .rows
+    .first()
+    .and_then(|row| row[0].as_i64());
+
+    let sample = query(
+        client,
+        &sample_stats_sql(schema, table, &columns, sample_percent(live_rows)),
+        &[],
+    )
+    .await?;
+    let row = &sample.rows[0];
+    let sample_rows = row[0].as_i64().unwrap_or_default();
+
+    Ok(columns
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let nulls = row[1 + idx * 2].as_i64().unwrap_or_default();
+            let distinct = row[2 + idx * 2].as_i64().unwrap_or_default();
+            let stats = serde_json::json!({
+                "null_frac": if sample_rows > 0 { nulls as f64 / sample_rows as f64 } else { 0.0 },
+                "n_distinct": distinct,
+                "most_common_vals": null,
+                "most_common_freqs": null,
+                "histogram_bounds": null,
+                "source": "sample",
+                "sample_rows": sample_rows,
+            });
+            (name, stats)
+        })
+        .collect())
+}
+
+/// Roughly how many rows to sample when profiling an unanalyzed table.
+const SAMPLE_TARGET_ROWS: f64 = 30_000.0;
+
+/// The percentage of a table to sample to get roughly `SAMPLE_TARGET_ROWS` rows, or
+/// `None` if the whole table is small enough to scan.
+fn sample_percent(live_rows: Option<i64>) -> Option<f64> {
+    let live_rows = live_rows.unwrap_or_default() as f64;
+    (live_rows > SAMPLE_TARGET_ROWS).then(|| 100.0 * SAMPLE_TARGET_ROWS / live_rows)
+}
+
+/// Count the sampled rows, then the nulls and distinct values in each column.
+fn sample_stats_sql(schema: &str, table: &str, columns: &[String], percent: Option<f64>) -> String {
+    let counts = columns
+        .iter()
+        .map(|col| {
+            let col = quote_ident(col);
+            format!("COUNT(*) FILTER (WHERE {col} IS NULL), COUNT(DISTINCT {col}::text)")
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        "SELECT COUNT(*), {} FROM {}.{}{}",
+        counts.join(", "),
+        quote_ident(schema),
+        quote_ident(table),
+        percent
+            .map(|p| format!(" TABLESAMPLE SYSTEM ({p})"))
+            .unwrap_or_default()
+    )
+}
+
+/// The most distinct values `column_values` will return.
+pub const MAX_COLUMN_VALUES: usize = 1_000;
+
+#[derive(Debug, Serialize)]
+pub struct ColumnValues {
+    pub values: Vec<serde_json::Value>,
+    /// Whether there are more values than were returned.
+    pub truncated: bool,
+}
+
+/// The distinct values of a column in ascending order, e.g. to offer in a filter
+/// dropdown. At most `limit` values (capped at `MAX_COLUMN_VALUES`) are returned;
+/// `search` only includes values whose text starts with it.
+pub async fn column_values(
+    client: &Client,
+    schema: &str,
+    table: &str,
+    column: &str,
+    limit: usize,
+    search: Option<&str>,
+) -> eyre::Result<ColumnValues> {
+    let limit = limit.min(MAX_COLUMN_VALUES);
+    // fetch one extra value to tell whether there are more
+    let fetch = (limit + 1) as i64;
+    let sql = column_values_sql(schema, table, column, search.is_some());
+    let result = match search {
+        Some(search) => query(client, &sql, &[&fetch, &escape_like(search)]).await?,
+        None => query(client, &sql, &[&fetch]).await?,
+    };
+
+    Ok(take_column_values(result.rows, limit))
+}
+
+fn column_values_sql(schema: &str, table: &str, column: &str, search: bool) -> String {
+    let column = quote_ident(column);
+    let filter = if search {
+        format!("\nWHERE {column}::text LIKE $2 || '%'")
+    } else {
+        String::new()
+    };
+
+    format!(
+        "SELECT DISTINCT {column}\nFROM {}.{}{filter}\nORDER BY {column}\nLIMIT $1",
+        quote_ident(schema),
+        quote_ident(table),
+    )
+}
+
+/// Escape `LIKE` wildcards, so that `s` is matched literally.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn take_column_values(rows: Vec<Vec<serde_json::Value>>, limit: usize) -> ColumnValues {
+    let truncated = rows.len() > limit;
+    ColumnValues {
+        values: rows
+            .into_iter()
+            .take(limit)
+            .filter_map(|row| row.into_iter().next())
+            .collect(),
+        truncated,
+    }
+}
+
+/// The most results `search_objects` returns.
+pub const MAX_SEARCH_RESULTS: usize = 200;
+
+/// A table, view, column or function whose name matched a search.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    /// `table`, `view`, `materialized_view`, `column` or `function`.
+    pub kind: String,
+    pub schema: String,
+    pub name: String,
+    /// For columns, the table (or view) they belong to.
+    pub parent: Option<String>,
+}
+
+/// Search the names of tables, views, columns and functions for `term` (ignoring case)
+/// in `schema`, or in every non-system schema if `None`. Exact matches come first, then
+/// prefix matches, then any other match; shorter names are ranked higher within each.
+/// At most `limit` results (capped at `MAX_SEARCH_RESULTS`) are returned.
+pub async fn search_objects(
+    client: &Client,
+    term: &str,
+    schema: Option<&str>,
+    limit: usize,
+) -> eyre::Result<Vec<SearchResult>> {
+    let sql = "
+    WITH objects(kind, schema, name, parent) AS (
+        SELECT
+            CASE c.relkind
+                WHEN 'v' THEN 'view'
+                WHEN 'm' THEN 'materialized_view'
+                ELSE 'table'
+            END,
+            n.nspname::text,
+            c.relname::text,
+            NULL::text
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('r', 'p', 'v', 'm')
+        UNION ALL
+        SELECT 'column', n.nspname::text, a.attname::text, c.relname::text
+        FROM pg_attribute a
+        JOIN pg_class c ON c.oid = a.attrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('r', 'p', 'v', 'm')
+        AND a.attnum > 0
+        AND NOT a.attisdropped
+        UNION ALL
+        -- overloaded functions share a name, so only list each once
+        SELECT DISTINCT 'function', n.nspname::text, p.proname::text, NULL::text
+        FROM pg_proc p
+        JOIN pg_namespace n ON n.oid = p.pronamespace
+    )
+    SELECT kind, schema, name, parent
+    FROM objects
+    WHERE name ILIKE '%' || $1 || '%'
+    AND CASE
+        WHEN $3::text IS NULL THEN schema NOT IN ('pg_catalog', 'information_schema')
+            AND schema NOT LIKE 'pg\\_toast%'
+            AND schema NOT LIKE 'pg\\_temp\\_%'
+        ELSE schema = $3
+    END
+    ORDER BY
+        CASE
+            WHEN lower(name) = lower($2) THEN 0
+            WHEN name ILIKE $1 || '%' THEN 1
+            ELSE 2
+        END,
+        length(name),
+        name,
+        schema,
+        parent NULLS FIRST
+    LIMIT $4";
+
+    let limit = limit.min(MAX_SEARCH_RESULTS) as i64;
+    let rows = query(client, sql, &[&escape_like(term), &term, &schema, &limit])
+        .await?
+        .row_maps();
+    Ok(serde_json::from_value(serde_json::to_value(rows)?)?)
+}
+
+pub async fn list_schemas(client: &Client) -> eyre::Result<QueryResult> {
+    // count relations for all schemas in one pass, rather than once per schema
+    let sql = "
+    SELECT
+        s.*,
+        pg_get_userbyid(n.nspowner)::text AS owner,
+        COALESCE(c.table_count, 0) AS table_count,
+        COALESCE(c.view_count, 0) AS view_count,
+        d.description
+    FROM information_schema.schemata s
+    JOIN pg_namespace n ON n.nspname = s.schema_name
+    LEFT JOIN pg_description d
+      ON d.objoid = n.oid
+      AND d.classoid = 'pg_namespace'::regclass
+      AND d.objsubid = 0
+    LEFT JOIN (
+        SELECT
+            relnamespace,
+            COUNT(*) FILTER (WHERE relkind IN ('r', 'p')) AS table_count,
+            COUNT(*) FILTER (WHERE relkind IN ('v', 'm')) AS view_count
+        FROM pg_class
+        GROUP BY relnamespace
+    ) c ON c.relnamespace = n.oid
+    WHERE s.schema_name NOT IN ('pg_catalog', 'pg_toast')
+    ORDER BY s.schema_name";
+    query(client, sql, &[]).await
+}
+
+pub async fn list_databases(client: &Client) -> eyre::Result<QueryResult> {
+    let sql = "
+    SELECT *
+    FROM pg_database
+    WHERE datname NOT IN ('template0', 'template1')
+    ORDER BY datname";
+    query(client, sql, &[]).await
+}
+
+/// An extension that's available on the server, and whether it's installed in the
+/// current database (see `list_extensions`).
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionInfo {
+    pub name: String,
+    /// The version `CREATE EXTENSION` installs, unless told otherwise.
+    pub default_version: Option<String>,
+    /// `null` if the extension isn't installed.
+    pub installed_version: Option<String>,
+    /// The schema the extension's objects were installed into, if it's installed.
+    pub schema: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// List the extensions available on the server (e.g. PostGIS, `citext`), including
+/// which are installed in the current database and at what version.
+pub async fn list_extensions(client: &Client) -> eyre::Result<Vec<ExtensionInfo>> {
+    let sql = "
+    SELECT
+        a.name::text AS name,
+        a.default_version,
+        e.extversion AS installed_version,
+        n.nspname::text AS schema,
+        a.comment
+    FROM pg_available_extensions a
+    LEFT JOIN pg_extension e ON e.extname = a.name
+    LEFT JOIN pg_namespace n ON n.oid = e.extnamespace
+    ORDER BY a.name";
+
+    let rows = query(client, sql, &[]).await?.row_maps();
+    Ok(serde_json::from_value(serde_json::to_value(rows)?)?)
+}
+
+/// Install an extension in the current database, if it isn't already. Installing most
+/// extensions requires superuser (or database owner, for trusted extensions). The
+/// statement is checked against the connection's `QueryPolicy` like any other query.
+pub async fn create_extension(client: &Client, name: &str) -> eyre::Result<()> {
+    let sql = format!("CREATE EXTENSION IF NOT EXISTS {}", quote_ident(name));
+    client.policy.check(&sql)?;
+
+    // check first, for a clearer error than Postgres' "could not open extension
+    // control file"
+    let available = client
+        .query_opt(
+            "SELECT 1 FROM pg_available_extensions WHERE name = $1",
+            &[&name],
+        )
+        .await
+        .map_err(PgError::from)?;
+    if available.is_none() {
+        eyre::bail!("no extension named {name:?} is available on this server");
+    }
+
+    client.batch_execute(&sql).await.map_err(PgError::from)?;
+    Ok(())
+}
+
+/// A single column definition, as it would appear in `CREATE TABLE`.
+#[derive(Debug, Clone, PartialEq)]
+struct ColumnDef {
+    name: String,
+    /// The data type, including any precision/scale or length (e.g. `numeric(10, 2)`).
+    data_type: String,
+    nullable: bool,
+    default: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct IndexDef {
+    name: String,
+    /// The full `CREATE INDEX` statement (from `pg_indexes.indexdef`).
+    def: String,
+}
+
+/// The structure of a table, as needed to generate (or diff) its DDL.
+#[derive(Debug)]
+struct TableStructure {
+    columns: Vec<ColumnDef>,
+    /// The primary key's columns, in order (empty if the table has no primary key).
+    primary_key: Vec<String>,
+    /// The primary key constraint's name, if the table has one.
+    primary_key_name: Option<String>,
+    /// All indexes except the one backing the primary key.
+    indexes: Vec<IndexDef>,
+}
+
+/// A table's primary key index (whatever it's named) and its columns, in key order.
+const PRIMARY_KEY_SQL: &str = "
+    SELECT
+      i.relname::text AS index_name,
+      a.attname::text AS column_name
+    FROM pg_index x
+    JOIN pg_class t ON t.oid = x.indrelid
+    JOIN pg_namespace n ON n.oid = t.relnamespace
+    JOIN pg_class i ON i.oid = x.indexrelid
+    CROSS JOIN LATERAL unnest(x.indkey) WITH ORDINALITY AS k(attnum, ord)
+    JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = k.attnum
+    WHERE n.nspname = $1
+    AND t.relname = $2
+    AND x.indisprimary
+    ORDER BY k.ord";
+
+async fn table_structure(
+    client: &Client,
+    schema: &str,
+    table: &str,
+) -> eyre::Result<TableStructure> {
+    // precision = np_radix ^ np_precision
+    // typically uses 2 or 10 as the radix, e.g. 2^32 or 10^20
+    let columns_sql = "
+    SELECT
+      column_name,
+      column_default,
+      is_nullable,
+      data_type,
+      udt_name,
+      character_maximum_length,
+      numeric_precision,
+      -- numeric_precision_radix,
+      numeric_scale
+    FROM information_schema.columns
+    WHERE table_schema = $1
+    AND table_name = $2
+    ORDER BY ordinal_position";
+
+    let indexes_sql = "
+    SELECT indexname, indexdef
+    FROM pg_indexes
+    WHERE schemaname = $1
+    AND tablename = $2";
 
-    let column_defs = columns
+    let params: Vec<SqlParam> = vec![&schema, &table];
+    let (columns, indexes, primary_key) = futures_util::try_join!(
+        query(client, columns_sql, &params),
+        query(client, indexes_sql, &params),
+        query(client, PRIMARY_KEY_SQL, &params),
+    )?;
+
+    if columns.rows.is_empty() {
+        eyre::bail!("no table named {schema}.{table}");
+    }
+
+    let primary_key = primary_key.row_maps();
+    // a primary key constraint is always named after the index backing it
+    let primary_key_index = primary_key
+        .first()
+        .map(|row| row["index_name"].as_str().unwrap());
+    let primary_key_name = primary_key_index.map(str::to_owned);
+    let primary_key = primary_key
+        .iter()
+        .map(|row| row["column_name"].as_str().unwrap().to_owned())
+        .collect();
+
+    let columns = columns
         .row_maps()
         .into_iter()
         .map(|row| {
             let col_name = row["column_name"].as_str().unwrap();
-            let mut data_type = row["data_type"].as_str().unwrap();
+            let mut data_type = match row["data_type"].as_str().unwrap() {
+                // extension types (e.g. `hstore`, `citext`) are only named by `udt_name`
+                "USER-DEFINED" => row["udt_name"].as_str().unwrap(),
+                data_type => data_type,
+            };
 
             let prec_scale = if let Some(prec) = row["numeric_precision"].as_i64() {
                 // Postgres differentiates between integer types using `numeric_precision`,
@@ -729,777 +2602,6037 @@ pub async fn table_ddl(client: &Client, schema: &str, table: &str) -> eyre::Resu
                 ))
             };
 
-            format!(
-                "{} {}{}{}{}{}",
-                col_name,
-                data_type,
-                prec_scale.or(char_len).as_deref().unwrap_or(""),
-                if pkey_col_name.as_ref().is_some_and(|col| col == col_name) {
-                    " PRIMARY KEY"
-                } else {
-                    ""
-                },
-                if row["is_nullable"].as_str().unwrap() == "YES" {
-                    " NOT NULL"
-                } else {
-                    ""
-                },
-                // TODO: convert `int` / `nextval` to `serial`
-                if let Some(default_val) = row["column_default"].as_str() {
-                    format!(" DEFAULT {default_val}")
-                } else {
-                    "".to_owned()
-                }
-            )
+            ColumnDef {
+                name: col_name.to_owned(),
+                data_type: format!(
+                    "{}{}",
+                    data_type,
+                    prec_scale.or(char_len).as_deref().unwrap_or("")
+                ),
+                nullable: row["is_nullable"].as_str().unwrap() == "YES",
+                default: row["column_default"].as_str().map(str::to_owned),
+            }
         })
-        .collect::<Vec<_>>();
+        .collect();
 
-    Ok(format!(
-        "CREATE TABLE {} (\n  {}\n);{}",
-        table,
-        column_defs.join(",\n  "),
-        if indexes.is_empty() {
-            "".to_owned()
-        } else {
-            format!(
-                "\n\n{}",
-                indexes
-                    .iter()
-                    .map(|i| format!("{};", i["indexdef"].as_str().unwrap()))
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            )
-        },
-    ))
+    // expression and partial indexes are emitted as-is, via their definition
+    let indexes = indexes
+        .row_maps()
+        .into_iter()
+        .filter(|i| i["indexname"].as_str() != primary_key_index)
+        .map(|i| IndexDef {
+            name: i["indexname"].as_str().unwrap().to_owned(),
+            def: i["indexdef"].as_str().unwrap().to_owned(),
+        })
+        .collect();
+
+    Ok(TableStructure {
+        columns,
+        primary_key,
+        primary_key_name,
+        indexes,
+    })
 }
 
-pub async fn view_ddl(client: &Client, schema: &str, view: &str) -> eyre::Result<String> {
-    let sql = "
-    SELECT view_definition
-    FROM information_schema.views
-    WHERE table_schema = $1
-    AND table_name = $2";
+/// Postgres silently truncates identifiers longer than this many bytes (`NAMEDATALEN - 1`).
+pub const MAX_IDENTIFIER_LENGTH: usize = 63;
+
+/// Check that none of the given identifiers would be truncated by Postgres, which
+/// would otherwise make them refer to a different (or no) object, and that they're
+/// printable. Any other name is valid, as long as it's quoted (see `quote_ident`).
+pub fn validate_identifiers(names: impl IntoIterator<Item = impl AsRef<str>>) -> eyre::Result<()> {
+    for name in names {
+        let name = name.as_ref();
+        if name.is_empty() {
+            eyre::bail!("identifiers can't be empty");
+        }
+        if name.chars().any(char::is_control) {
+            eyre::bail!("identifier {name:?} contains control characters");
+        }
+        if name.len() > MAX_IDENTIFIER_LENGTH {
+            eyre::bail!(
+                "identifier \"{name}\" is {} bytes long, but Postgres identifiers are limited \
+                 to {MAX_IDENTIFIER_LENGTH} bytes",
+                name.len()
+            );
+        }
+    }
+    Ok(())
+}
 
-    let res = query(client, sql, &[&schema, &view]).await?;
-    Ok(res.rows[0][0].as_str().unwrap().to_owned())
+/// Quote an identifier for interpolation into SQL.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
 }
 
-pub async fn materialized_view_ddl(
-    client: &Client,
-    schema: &str,
-    view: &str,
-) -> eyre::Result<String> {
-    let sql = format!(
-        "SELECT view_definition
-        FROM (\n{}\n) _
-        WHERE table_schema = $1
-        AND table_name = $2",
-        client.mat_view_query,
-    );
+/// Keywords that can't be used as identifiers without quoting: every category in the
+/// server's `pg_get_keywords()` but `unreserved`, which differ between Postgres versions.
+#[derive(Debug, Default)]
+pub struct ReservedKeywords(HashSet<String>);
+
+impl ReservedKeywords {
+    /// Quote an identifier for generated SQL meant to be read, only if it needs quoting;
+    /// like Postgres' own `quote_ident`, and so matching the names in e.g. `pg_get_indexdef`.
+    fn display_ident<'a>(&self, name: &'a str) -> std::borrow::Cow<'a, str> {
+        let plain = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if plain && !self.0.contains(name) {
+            name.into()
+        } else {
+            quote_ident(name).into()
+        }
+    }
+}
 
-    let res = query(client, &sql, &[&schema, &view]).await?;
-    Ok(res.rows[0][0].as_str().unwrap().to_owned())
+/// Quote a string literal for interpolation into SQL.
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Sort {
-    pub column_idx: usize,
-    pub direction: SortDirection,
+pub async fn create_database(client: &Client, name: &str) -> eyre::Result<()> {
+    client
+        .batch_execute(&format!("CREATE DATABASE {}", quote_ident(name)))
+        .await
+        .map_err(PgError::from)?;
+    Ok(())
 }
 
-#[derive(Debug, Clone, SerializeDisplay, DeserializeFromStr)]
-pub enum SortDirection {
-    Asc,
-    Desc,
+pub async fn drop_database(client: &Client, name: &str) -> eyre::Result<()> {
+    client
+        .batch_execute(&format!("DROP DATABASE IF EXISTS {}", quote_ident(name)))
+        .await
+        .map_err(PgError::from)?;
+    Ok(())
 }
 
-impl std::fmt::Display for SortDirection {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceAction {
+    Vacuum,
+    VacuumAnalyze,
+    Reindex,
+}
+
+impl MaintenanceAction {
+    /// The statement to run for this action. `full` runs `VACUUM FULL`, which rewrites
+    /// the table and holds an exclusive lock on it until done.
+    fn sql(&self, schema: &str, table: &str, full: bool) -> eyre::Result<String> {
+        let table = format!("{}.{}", quote_ident(schema), quote_ident(table));
+        let full = if full { "FULL, " } else { "" };
+        Ok(match self {
+            MaintenanceAction::Vacuum => format!("VACUUM ({full}VERBOSE) {table}"),
+            MaintenanceAction::VacuumAnalyze => format!("VACUUM ({full}VERBOSE, ANALYZE) {table}"),
+            MaintenanceAction::Reindex if full.is_empty() => {
+                format!("REINDEX (VERBOSE) TABLE {table}")
+            }
+            MaintenanceAction::Reindex => eyre::bail!("REINDEX doesn't support FULL"),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
         match self {
-            SortDirection::Asc => write!(f, "ASC"),
-            SortDirection::Desc => write!(f, "DESC"),
+            MaintenanceAction::Vacuum => "VACUUM",
+            MaintenanceAction::VacuumAnalyze => "VACUUM ANALYZE",
+            MaintenanceAction::Reindex => "REINDEX",
         }
     }
 }
 
-impl std::str::FromStr for SortDirection {
-    type Err = eyre::Report;
+/// Run a maintenance command against a table. Output is only available as notices,
+/// so use a connection from `connect_with_messages` to capture it.
+pub async fn run_maintenance(
+    client: &Client,
+    action: MaintenanceAction,
+    schema: &str,
+    table: &str,
+    full: bool,
+) -> eyre::Result<()> {
+    // `VACUUM` can't run inside a transaction block; a single statement sent using the
+    // simple query protocol runs on its own, outside of any implicit transaction
+    client
+        .batch_execute(&action.sql(schema, table, full)?)
+        .await
+        .map_err(PgError::from)?;
+    Ok(())
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_uppercase().as_str() {
-            "ASC" => Ok(SortDirection::Asc),
-            "DESC" => Ok(SortDirection::Desc),
-            _ => Err(eyre::eyre!("Invalid sort direction: {}", s)),
-        }
-    }
+/// Refresh the planner statistics (including the row estimate) for a table.
+pub async fn analyze_table(client: &Client, schema: &str, table: &str) -> eyre::Result<()> {
+    client
+        .batch_execute(&format!(
+            "ANALYZE {}.{}",
+            quote_ident(schema),
+            quote_ident(table)
+        ))
+        .await
+        .map_err(PgError::from)?;
+    Ok(())
 }
 
-fn dyn_params(params: &Vec<Box<dyn ToSql + Sync + Send>>) -> Vec<SqlParam<'_>> {
-    params.iter().map(|p| p.as_ref() as _).collect()
+/// A page of a table's rows that can be edited in place (see `update_row`), ordered
+/// by primary key.
+#[derive(Debug, Serialize)]
+pub struct EditableRows {
+    pub primary_key: Vec<String>,
+    #[serde(flatten)]
+    pub entries: QueryResult,
+    /// Each row's version, to send back when updating it: its `xmin`, the ID of the
+    /// transaction that last wrote it.
+    pub versions: Vec<String>,
 }
 
-pub async fn paginated_query(
-    client: &Client,
-    raw_query: &str,
-    params: &[serde_json::Value],
-    filters: &[Filter],
-    page: usize,
-    page_size: isize,
-    sort: Option<Sort>,
-) -> eyre::Result<PaginatedQueryResult> {
-    let raw_query = parse_query(raw_query);
+/// A change to a single row, made only if the row hasn't changed since it was read.
+#[derive(Debug, Deserialize)]
+pub struct RowUpdate {
+    /// The row's primary key, by column name.
+    pub key: serde_json::Map<String, serde_json::Value>,
+    /// The row's version, as it was read (see `EditableRows::versions`).
+    pub version: String,
+    /// The new values, by column name.
+    pub values: serde_json::Map<String, serde_json::Value>,
+}
 
-    // DDL queries can't be counted/paginated like normal queries, but we
-    // still support a pagination wrapper around their results; they'll always
-    // return a single result representing the DDL command's output
-    let query_type = query_type(&raw_query);
-    if let QueryType::ModifyData | QueryType::ModifyStructure = query_type {
-        let (stmt, params) = prepare_params(client, &raw_query, params).await?;
-        let affected_rows = client.execute(&stmt.inner, &dyn_params(&params)).await?;
+/// A row couldn't be updated because it was changed (or deleted) since it was read.
+#[derive(Debug)]
+pub struct RowConflict;
 
-        return Ok(match query_type {
-            QueryType::ModifyData => PaginatedQueryResult::ModifyData { affected_rows },
-            QueryType::ModifyStructure => PaginatedQueryResult::ModifyStructure,
-            _ => unreachable!(),
-        });
+impl std::fmt::Display for RowConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the row was changed or deleted since it was read; reload it and try again"
+        )
     }
+}
 
-    // EXPLAIN queries just return a plan and are handled specially by the FE
-    if let QueryType::Explain = query_type {
-        let (stmt, params) = prepare_params(client, &raw_query, params).await?;
-        let rows = client.query(&stmt.inner, &dyn_params(&params)).await?;
-
-        let first_row = rows.get(0).unwrap();
-        use tokio_postgres::types::Type;
-        match first_row.columns()[0].type_() {
-            &Type::JSON => {
-                // with `FORMAT JSON`, everything's in the first row
-                return Ok(PaginatedQueryResult::Explain {
-                    query: raw_query,
-                    plan: serde_json::to_string(&first_row.get::<_, serde_json::Value>(0)).unwrap(),
-                });
-            }
+impl std::error::Error for RowConflict {}
 
-            &Type::TEXT => {
-                // with `FORMAT TEXT`, output is newline-delimited across all rows
-                return Ok(PaginatedQueryResult::Explain {
-                    query: raw_query,
-                    plan: rows
-                        .iter()
-                        .map(|row| row.get::<_, &str>(0))
-                        .collect::<Vec<_>>()
-                        .join("\n"),
-                });
-            }
+/// A request that can't be run as given (e.g. a page number that's out of range),
+/// rather than one that failed to run.
+#[derive(Debug)]
+pub struct InvalidRequest(pub String);
 
-            type_ => {
-                panic!("Unexpected EXPLAIN result type: {type_}");
-            }
-        }
+impl std::fmt::Display for InvalidRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    let inner_stmt = prepare(&client, &raw_query).await?;
+impl std::error::Error for InvalidRequest {}
 
-    let filter_prefix = format!(
-        "WITH q({}) AS (\n",
-        inner_stmt
-            .columns()
+async fn primary_key_columns(
+    client: &Client,
+    schema: &str,
+    table: &str,
+) -> eyre::Result<Vec<String>> {
+    let primary_key = query(client, PRIMARY_KEY_SQL, &[&schema, &table]).await?;
+    let primary_key = primary_key
+        .row_maps()
+        .iter()
+        .map(|row| row["column_name"].as_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    if primary_key.is_empty() {
+        eyre::bail!("{schema}.{table} has no primary key, so its rows can't be edited");
+    }
+    Ok(primary_key)
+}
+
+pub async fn editable_rows(
+    client: &Client,
+    schema: &str,
+    table: &str,
+    page: usize,
+    page_size: Option<usize>,
+) -> eyre::Result<EditableRows> {
+    // pages are validated as in `run_paginated_query`
+    if page == 0 {
+        return Err(InvalidRequest("invalid page 0: pages are numbered from 1".to_owned()).into());
+    }
+    if page_size == Some(0) {
+        return Err(InvalidRequest("invalid page_size 0".to_owned()).into());
+    }
+    let primary_key = primary_key_columns(client, schema, table).await?;
+    let page_size = page_size
+        .unwrap_or(client.default_page_size)
+        .min(client.max_page_size);
+    let sql = format!(
+        "SELECT xmin::text, * FROM {}.{} ORDER BY {} LIMIT $1 OFFSET $2",
+        quote_ident(schema),
+        quote_ident(table),
+        primary_key
             .iter()
-            .enumerate()
-            .map(|(i, c)| Filter::col_name(i, c.name()))
+            .map(|col| quote_ident(col))
             .collect::<Vec<_>>()
             .join(", ")
     );
+    let limit = i64::try_from(page_size).unwrap_or(i64::MAX);
+    let offset = i64::try_from(page - 1)
+        .ok()
+        .and_then(|page| page.checked_mul(limit))
+        .ok_or_else(|| InvalidRequest(format!("page {page} is out of range")))?;
+    let mut entries = query(client, &sql, &[&limit, &offset]).await?;
+
+    // split off the versions
+    entries.columns.remove(0);
+    for col in &mut entries.columns {
+        col.index -= 1;
+    }
+    let versions = entries
+        .rows
+        .iter_mut()
+        .map(|row| row.remove(0).as_str().unwrap().to_owned())
+        .collect();
+
+    Ok(EditableRows {
+        primary_key,
+        entries,
+        versions,
+    })
+}
 
-    let select_aliases = inner_stmt
-        .columns()
+/// Update a row, identified by its primary key, as long as its version still matches
+/// (i.e. nobody else has written to it since it was read); otherwise, fail with
+/// `RowConflict`. Returns the row's new version.
+pub async fn update_row(
+    client: &Client,
+    schema: &str,
+    table: &str,
+    update: &RowUpdate,
+) -> eyre::Result<String> {
+    if update.values.is_empty() {
+        eyre::bail!("no values to update");
+    }
+    validate_identifiers(update.key.keys().chain(update.values.keys()))?;
+    // primary key columns are never `NULL`, and `= NULL` would never match anyway
+    if let Some((col, _)) = update.key.iter().find(|(_, val)| val.is_null()) {
+        return Err(InvalidRequest(format!("key column {col} can't be NULL")).into());
+    }
+
+    // the key must be the whole primary key, so that only the one row is updated
+    let primary_key = primary_key_columns(client, schema, table).await?;
+    if update.key.len() != primary_key.len()
+        || !primary_key.iter().all(|col| update.key.contains_key(col))
+    {
+        eyre::bail!(
+            "rows of {schema}.{table} are identified by its primary key ({})",
+            primary_key.join(", ")
+        );
+    }
+
+    // `NULL`s are written inline, since their type can't be inferred from JSON
+    let mut params = Vec::new();
+    let mut param = |val: &serde_json::Value| match val {
+        serde_json::Value::Null => "NULL".to_owned(),
+        val => {
+            params.push(val.clone());
+            format!("${}", params.len())
+        }
+    };
+    let set = update
+        .values
         .iter()
-        .enumerate()
-        .map(|(i, c)| {
-            format!(
-                "{} AS \"{}\"",
-                Filter::col_name(i, c.name()),
-                c.name().replace('"', "\"\"")
-            )
-        })
+        .map(|(col, val)| format!("{} = {}", quote_ident(col), param(val)))
         .collect::<Vec<_>>()
         .join(", ");
-
-    let filtered_query = format!(
-        "{}{}\n)\nSELECT {} FROM q{}{}",
-        filter_prefix,
-        raw_query,
-        select_aliases,
-        if filters.is_empty() { "" } else { "\nWHERE " },
-        filters
-            .iter()
-            .scan(0, |i, f| {
-                match f.where_clause(*i) {
-                    (true, clause) => {
-                        *i = *i + 1;
-                        Some(clause)
-                    }
-                    (false, clause) => Some(clause),
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(" AND ")
-    );
-
-    let filter_params = filters
+    let key = update
+        .key
         .iter()
-        .filter_map(|f| f.uses_param().then(|| f.value.clone()))
-        .collect::<Vec<_>>();
-
-    let params = params
-        .into_iter()
-        .cloned()
-        .chain(filter_params.into_iter())
-        .collect::<Vec<_>>();
-
-    let (stmt, params) = prepare_params(&client, &filtered_query, &params).await?;
-
-    let base_query = stmt.sql.as_str();
-
-    let count_query = format!("SELECT COUNT(*) FROM (\n{base_query}\n) _;");
+        .map(|(col, val)| format!("{} = {}", quote_ident(col), param(val)))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let version = param(&serde_json::Value::String(update.version.clone()));
+    let sql = format!(
+        "UPDATE {}.{} SET {set} WHERE {key} AND xmin = {version}::text::xid RETURNING xmin::text",
+        quote_ident(schema),
+        quote_ident(table),
+    );
+    client.policy.check(&sql)?;
+
+    let (stmt, params) = prepare_params(client, &sql, &params, &[]).await?;
+    let rows = client
+        .query(&stmt.inner, &dyn_params(&params))
+        .await
+        .map_err(PgError::from)?;
+    match rows.first() {
+        Some(row) => Ok(row.get(0)),
+        None => Err(RowConflict.into()),
+    }
+}
 
-    let (page_query, page_query_offset) = if page_size < 0 {
-        (base_query.to_owned(), 0)
-    } else {
-        let limit = page_size as usize;
-        let offset = (page - 1) * limit;
-        let page_query = format!(
-            "SELECT * FROM (\n{base_query}\n) _ {} LIMIT {limit} OFFSET {offset};",
-            sort.as_ref()
-                .map(|s| format!("ORDER BY {} {}", s.column_idx + 1, s.direction))
-                .unwrap_or_default()
-        );
+pub async fn table_ddl(client: &Client, schema: &str, table: &str) -> eyre::Result<String> {
+    let (structure, keywords) = futures_util::try_join!(
+        table_structure(client, schema, table),
+        client.reserved_keywords(),
+    )?;
 
-        (page_query, -16)
+    // a single-column primary key is declared inline, and a composite one separately
+    let inline_primary_key = match structure.primary_key.as_slice() {
+        [column] => Some(column),
+        _ => None,
     };
 
-    let (mut result, count_result) = futures_util::future::try_join(
-        async {
-            query(client, &page_query, &dyn_params(&params))
-                .await
-                .map_err(|err| match err.downcast::<PgError>() {
-                    Ok(mut err) => {
-                        err.offset_position(page_query_offset - (filter_prefix.len() as i32));
-                        eyre::eyre!(err)
-                    }
-                    Err(err) => err,
-                })
-        },
-        async {
-            query(client, &count_query, &dyn_params(&params))
-                .await
-                .map_err(|err| match err.downcast::<PgError>() {
-                    Ok(mut err) => {
-                        err.offset_position(-23 - (filter_prefix.len() as i32));
-                        eyre::eyre!(err)
-                    }
-                    Err(err) => err,
-                })
-        },
-    )
-    .await?;
-
-    // fetch additional information, like source table and column names and FKs
-    QueryResultColumn::fetch_extended(&mut result.columns, client).await?;
+    let mut column_defs = structure
+        .columns
+        .iter()
+        .map(|col| {
+            format!(
+                "{} {}{}{}{}",
+                keywords.display_ident(&col.name),
+                col.data_type,
+                if inline_primary_key == Some(&col.name) {
+                    " PRIMARY KEY"
+                } else {
+                    ""
+                },
+                if col.nullable { "" } else { " NOT NULL" },
+                // TODO: convert `int` / `nextval` to `serial`
+                if let Some(default_val) = &col.default {
+                    format!(" DEFAULT {default_val}")
+                } else {
+                    "".to_owned()
+                }
+            )
+        })
+        .collect::<Vec<_>>();
 
-    let page_count = result.rows.len();
-    let total_count = count_result.rows[0][0].as_u64().unwrap() as usize;
-    let total_pages = if page_size < 0 {
-        1
-    } else {
-        total_count.div_ceil(page_size as usize)
-    };
+    if structure.primary_key.len() > 1 {
+        column_defs.push(format!(
+            "PRIMARY KEY ({})",
+            structure
+                .primary_key
+                .iter()
+                .map(|name| keywords.display_ident(name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
 
-    Ok(PaginatedQueryResult::Select {
-        page,
-        page_size,
-        page_count,
-        total_count,
-        total_pages,
-        sort,
-        entries: result,
-    })
+    Ok(format!(
+        "CREATE TABLE {} (\n  {}\n);{}",
+        keywords.display_ident(table),
+        column_defs.join(",\n  "),
+        if structure.indexes.is_empty() {
+            "".to_owned()
+        } else {
+            format!(
+                "\n\n{}",
+                structure
+                    .indexes
+                    .iter()
+                    .map(|i| format!("{};", i.def))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        },
+    ))
 }
 
-#[derive(Debug)]
-pub struct PreparedStatement {
-    pub sql: String,
-    pub inner: tokio_postgres::Statement,
-    pub columns: Vec<QueryResultColumn>,
+/// The statements that alter one table's structure to match another's (see
+/// `table_ddl_diff`).
+#[derive(Debug, Serialize)]
+pub struct TableDdlDiff {
+    pub statements: Vec<String>,
+    /// Why some of the statements will fail on the source table's current rows, e.g.
+    /// because a column that's made `NOT NULL` has `NULL` values.
+    pub warnings: Vec<String>,
 }
 
-impl std::ops::Deref for PreparedStatement {
-    type Target = tokio_postgres::Statement;
+/// Generate the statements needed to alter `source` so that its structure matches `target`.
+/// Each table is referenced by its `(schema, table)` name, and may live in different databases.
+///
+/// Columns (added, dropped, or changed type/nullability/default), the primary key and
+/// indexes are diffed. Other constraints (e.g. foreign keys) are not yet compared. Columns
+/// that are made `NOT NULL` are checked against the source table's rows, and reported
+/// in `warnings` if the statement doing so would fail.
+pub async fn table_ddl_diff(
+    source_client: &Client,
+    source: (&str, &str),
+    target_client: &Client,
+    target: (&str, &str),
+) -> eyre::Result<TableDdlDiff> {
+    let (source_structure, target_structure, source_keywords, target_keywords) = futures_util::try_join!(
+        table_structure(source_client, source.0, source.1),
+        table_structure(target_client, target.0, target.1),
+        source_client.reserved_keywords(),
+        target_client.reserved_keywords(),
+    )?;
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
-    }
+    let statements = diff_table_structures(
+        source,
+        &source_structure,
+        target,
+        &target_structure,
+        (source_keywords, target_keywords),
+    );
+    let warnings =
+        not_null_warnings(source_client, source, &source_structure, &target_structure).await?;
+    Ok(TableDdlDiff {
+        statements,
+        warnings,
+    })
 }
 
-pub async fn prepare(client: &Client, raw_sql: &str) -> eyre::Result<PreparedStatement> {
-    let sql = parse_query(raw_sql);
-    let stmt = client.prepare(&sql).await.map_err(PgError::from)?;
+/// Check the source table's rows against the columns that `diff_table_structures`
+/// makes `NOT NULL`: existing columns with `NULL` values, and columns added without
+/// a default to a table that isn't empty.
+async fn not_null_warnings(
+    client: &Client,
+    (schema, table): (&str, &str),
+    source: &TableStructure,
+    target: &TableStructure,
+) -> eyre::Result<Vec<String>> {
+    let mut made_not_null = Vec::new();
+    let mut added_without_default = Vec::new();
+    for col in target.columns.iter().filter(|col| !col.nullable) {
+        match source.columns.iter().find(|c| c.name == col.name) {
+            Some(existing) if existing.nullable => made_not_null.push(&col.name),
+            Some(_) => {}
+            None if col.default.is_none() => added_without_default.push(&col.name),
+            None => {}
+        }
+    }
+    if made_not_null.is_empty() && added_without_default.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    let columns = stmt
-        .columns()
+    let counts = made_not_null
         .iter()
-        .enumerate()
-        .map(|(idx, col)| QueryResultColumn {
-            index: idx,
-            table_oid: col.table_oid(),
-            column_id: col.column_id(),
-            name: col.name().to_owned(),
-            type_: col.type_().name().to_owned(),
-            extended: None,
-        })
+        .map(|name| format!("count(*) FILTER (WHERE {} IS NULL)", quote_ident(name)))
+        .chain(["count(*)".to_owned()])
         .collect::<Vec<_>>();
-
-    Ok(PreparedStatement {
-        sql,
-        inner: stmt,
-        columns,
-    })
+    let row = client
+        .query_one(
+            &format!(
+                "SELECT {} FROM {}.{}",
+                counts.join(", "),
+                quote_ident(schema),
+                quote_ident(table)
+            ),
+            &[],
+        )
+        .await
+        .map_err(PgError::from)?;
+
+    let mut warnings = Vec::new();
+    for (idx, name) in made_not_null.iter().enumerate() {
+        let nulls: i64 = row.get(idx);
+        if nulls > 0 {
+            warnings.push(format!(
+                "column {} is made NOT NULL, but has {nulls} NULL values",
+                quote_ident(name)
+            ));
+        }
+    }
+    let rows: i64 = row.get(made_not_null.len());
+    if rows > 0 {
+        for name in added_without_default {
+            warnings.push(format!(
+                "column {} is added as NOT NULL without a default, but the table has {rows} rows",
+                quote_ident(name)
+            ));
+        }
+    }
+    Ok(warnings)
 }
 
-pub async fn prepare_params(
-    client: &Client,
-    raw_sql: &str,
-    params: &[serde_json::Value],
-) -> eyre::Result<(PreparedStatement, Vec<Box<dyn ToSql + Sync + Send>>)> {
-    let stmt = prepare(&client, raw_sql).await?;
+fn diff_table_structures(
+    (source_schema, source_table): (&str, &str),
+    source: &TableStructure,
+    (target_schema, target_table): (&str, &str),
+    target: &TableStructure,
+    // each server's keywords, to match the names in its own index definitions; the
+    // generated statements are run against the source
+    (source_keywords, target_keywords): (&ReservedKeywords, &ReservedKeywords),
+) -> Vec<String> {
+    let qualified_name = |keywords: &ReservedKeywords, schema: &str, table: &str| {
+        format!(
+            "{}.{}",
+            keywords.display_ident(schema),
+            keywords.display_ident(table)
+        )
+    };
+    let table = qualified_name(source_keywords, source_schema, source_table);
+    let mut stmts = Vec::new();
+
+    // the primary key is dropped first, since dropping one of its columns would drop it
+    let primary_key_changed = source.primary_key != target.primary_key;
+    if primary_key_changed && let Some(name) = &source.primary_key_name {
+        stmts.push(format!(
+            "ALTER TABLE {table} DROP CONSTRAINT {};",
+            source_keywords.display_ident(name)
+        ));
+    }
 
-    if stmt.params().len() != params.len() {
-        eyre::bail!(
-            "Expected {} params, got {}",
-            stmt.params().len(),
-            params.len()
-        );
+    for col in source.columns.iter() {
+        if !target.columns.iter().any(|c| c.name == col.name) {
+            stmts.push(format!(
+                "ALTER TABLE {table} DROP COLUMN {};",
+                source_keywords.display_ident(&col.name)
+            ));
+        }
     }
 
-    let params = params
-        .iter()
-        .zip(stmt.params().iter().cloned())
-        .map(|(param, param_type)| from_json(param, param_type))
-        .collect::<Result<Vec<_>, _>>()?;
+    for col in target.columns.iter() {
+        let Some(existing) = source.columns.iter().find(|c| c.name == col.name) else {
+            stmts.push(format!(
+                "ALTER TABLE {table} ADD COLUMN {} {}{}{};",
+                source_keywords.display_ident(&col.name),
+                col.data_type,
+                if col.nullable { "" } else { " NOT NULL" },
+                col.default
+                    .as_ref()
+                    .map(|d| format!(" DEFAULT {d}"))
+                    .unwrap_or_default(),
+            ));
+            continue;
+        };
 
-    Ok((stmt, params))
-}
+        let alter_col = format!(
+            "ALTER TABLE {table} ALTER COLUMN {}",
+            source_keywords.display_ident(&col.name)
+        );
 
-pub async fn query(
-    client: &Client,
-    raw_sql: &str,
-    params: &[SqlParam<'_>],
-) -> eyre::Result<QueryResult> {
-    let stmt = prepare(client, raw_sql).await?;
-    Ok(QueryResult {
-        rows: raw_query(client, &stmt, params).await?,
-        columns: stmt.columns,
-    })
-}
+        if existing.data_type != col.data_type {
+            stmts.push(format!("{alter_col} TYPE {};", col.data_type));
+        }
 
-async fn raw_query(
-    client: &Client,
-    statement: &PreparedStatement,
-    params: &[SqlParam<'_>],
-) -> eyre::Result<Vec<Vec<serde_json::Value>>> {
-    if statement.columns().iter().all(col_supported) {
-        let rows = client
-            .query(&statement.inner, params)
-            .await
-            .map_err(PgError::from)?;
+        if existing.nullable != col.nullable {
+            stmts.push(format!(
+                "{alter_col} {} NOT NULL;",
+                if col.nullable { "DROP" } else { "SET" }
+            ));
+        }
 
-        let mut data_rows: Vec<Vec<serde_json::Value>> = Vec::with_capacity(rows.len());
-        for row in rows {
-            let mut data_row: Vec<serde_json::Value> =
-                Vec::with_capacity(statement.columns().len());
-            // use column index to get value instead of name in case of duplicate column names
-            for (idx, col) in statement.columns().iter().enumerate() {
-                if let Some(val) = to_json(&row, col, idx) {
-                    data_row.push(val);
-                }
-            }
-            data_rows.push(data_row);
+        if existing.default != col.default {
+            stmts.push(match &col.default {
+                Some(default_val) => format!("{alter_col} SET DEFAULT {default_val};"),
+                None => format!("{alter_col} DROP DEFAULT;"),
+            });
         }
+    }
 
-        Ok(data_rows)
-    } else {
-        // fall back on simple query (uses TEXT instead of BINARY encoding)
-        tracing::info!("falling back on TEXT encoding");
+    if primary_key_changed && !target.primary_key.is_empty() {
+        stmts.push(format!(
+            "ALTER TABLE {table} ADD PRIMARY KEY ({});",
+            target
+                .primary_key
+                .iter()
+                .map(|name| source_keywords.display_ident(name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
 
-        if !params.is_empty() {
-            eyre::bail!("TEXT encoding does not support parameters");
+    // index names are unique per-schema, so compare indexes by their definition instead;
+    // e.g. `CREATE INDEX users_email_idx ON public.users USING btree (email)` is compared
+    // as `CREATE INDEX ON USING btree (email)`
+    let normalize = |index: &IndexDef, keywords: &ReservedKeywords, schema: &str, table: &str| {
+        index
+            .def
+            .replacen(
+                &format!(" {} ", keywords.display_ident(&index.name)),
+                " ",
+                1,
+            )
+            .replacen(
+                &format!(" {} ", qualified_name(keywords, schema, table)),
+                " ",
+                1,
+            )
+    };
+    let normalize_source = |index| normalize(index, source_keywords, source_schema, source_table);
+    let normalize_target = |index| normalize(index, target_keywords, target_schema, target_table);
+
+    for index in source.indexes.iter() {
+        let def = normalize_source(index);
+        if !target.indexes.iter().any(|i| normalize_target(i) == def) {
+            stmts.push(format!(
+                "DROP INDEX {};",
+                qualified_name(source_keywords, source_schema, &index.name)
+            ));
         }
+    }
 
-        let rows = client
-            .simple_query(&statement.sql)
-            .await
-            .map_err(PgError::from)?;
-
-        let mut data_rows: Vec<Vec<serde_json::Value>> = Vec::with_capacity(rows.len());
-        for cmd in rows {
-            use tokio_postgres::SimpleQueryMessage::*;
-            match cmd {
-                RowDescription(_) => {}
-                CommandComplete(_) => {}
-                Row(row) => {
-                    let mut data_row: Vec<serde_json::Value> =
-                        Vec::with_capacity(statement.columns().len());
-                    for (idx, _) in statement.columns().iter().enumerate() {
-                        data_row.push(row.get(idx).into());
-                    }
-                    data_rows.push(data_row);
-                }
-                _ => unreachable!("non-exhaustive enum"),
-            }
+    for index in target.indexes.iter() {
+        let def = normalize_target(index);
+        if !source.indexes.iter().any(|i| normalize_source(i) == def) {
+            stmts.push(format!(
+                "{};",
+                index.def.replacen(
+                    &format!(
+                        " ON {} ",
+                        qualified_name(target_keywords, target_schema, target_table)
+                    ),
+                    &format!(" ON {table} "),
+                    1
+                )
+            ));
         }
-
-        Ok(data_rows)
     }
+
+    stmts
 }
 
-#[derive(Debug)]
-pub struct PgError {
-    source: tokio_postgres::error::Error,
-    inner: Option<PgErrorInner>,
+pub async fn view_ddl(client: &Client, schema: &str, view: &str) -> eyre::Result<String> {
+    let definition = view_definition(client, schema, view, 'v').await?;
+    Ok(format!(
+        "CREATE OR REPLACE VIEW {}.{} AS\n{definition}",
+        quote_ident(schema),
+        quote_ident(view)
+    ))
 }
 
-#[derive(Debug)]
-struct PgErrorInner {
-    code: String,
-    message: String,
-    severity: String,
-    position: Option<u32>,
+pub async fn materialized_view_ddl(
+    client: &Client,
+    schema: &str,
+    view: &str,
+) -> eyre::Result<String> {
+    let definition = view_definition(client, schema, view, 'm').await?;
+    Ok(format!(
+        "CREATE MATERIALIZED VIEW {}.{} AS\n{definition}",
+        quote_ident(schema),
+        quote_ident(view)
+    ))
 }
 
-impl PgError {
-    pub fn has_extended(&self) -> bool {
-        self.inner.is_some()
+/// The pretty-printed query behind a view (`relkind` `v`) or materialized view (`m`).
+/// Unlike `information_schema.views`, `pg_get_viewdef` works for views the user doesn't
+/// own, and formats the query over multiple lines.
+async fn view_definition(
+    client: &Client,
+    schema: &str,
+    view: &str,
+    relkind: char,
+) -> eyre::Result<String> {
+    let sql = "
+    SELECT pg_get_viewdef(c.oid, true)
+    FROM pg_class c
+    JOIN pg_namespace n ON n.oid = c.relnamespace
+    WHERE n.nspname = $1
+    AND c.relname = $2
+    AND c.relkind = $3::text::\"char\"";
+
+    let relkind = relkind.to_string();
+    let res = query(client, sql, &[&schema, &view, &relkind]).await?;
+    match res.rows.first() {
+        Some(row) => Ok(row[0].as_str().unwrap().trim().to_owned()),
+        None => eyre::bail!("no view named {schema}.{view}"),
     }
+}
 
-    pub fn code(&self) -> Option<&String> {
-        self.inner.as_ref().map(|inner| &inner.code)
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sort {
+    pub column_idx: usize,
+    pub direction: SortDirection,
+}
 
-    pub fn message(&self) -> Option<&String> {
-        self.inner.as_ref().map(|inner| &inner.message)
-    }
+#[derive(Debug, Clone, SerializeDisplay, DeserializeFromStr)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
 
-    pub fn severity(&self) -> Option<&String> {
-        self.inner.as_ref().map(|inner| &inner.severity)
+impl std::fmt::Display for SortDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortDirection::Asc => write!(f, "ASC"),
+            SortDirection::Desc => write!(f, "DESC"),
+        }
     }
+}
 
-    pub fn position(&self) -> Option<u32> {
-        self.inner.as_ref().and_then(|inner| inner.position)
-    }
+impl std::str::FromStr for SortDirection {
+    type Err = eyre::Report;
 
-    pub fn offset_position(&mut self, offset_by: i32) {
-        self.inner
-            .as_mut()
-            .and_then(|inner| inner.position.as_mut())
-            .map(|pos| *pos = ((*pos as i32) + offset_by) as u32);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "ASC" => Ok(SortDirection::Asc),
+            "DESC" => Ok(SortDirection::Desc),
+            _ => Err(eyre::eyre!("Invalid sort direction: {}", s)),
+        }
     }
 }
 
-impl std::error::Error for PgError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(&self.source)
-    }
+fn dyn_params(params: &Vec<Box<dyn ToSql + Sync + Send>>) -> Vec<SqlParam<'_>> {
+    params.iter().map(|p| p.as_ref() as _).collect()
 }
 
-impl std::fmt::Display for PgError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(inner) = &self.inner {
-            write!(f, "{} {}: {}", inner.severity, inner.code, inner.message)?;
-            if let Some(pos) = inner.position {
-                write!(f, " (at position {pos})")?;
-            }
-        } else {
-            write!(f, "{}", self.source)?;
-        }
+#[derive(Debug, Deserialize)]
+pub struct QueryOptions {
+    /// 1-indexed page number.
+    pub page: usize,
+    /// If page size is negative, return all rows. If not set, the connection's
+    /// `default_page_size` is used. A page size of 0 is rejected.
+    #[serde(default, deserialize_with = "deserialize_page_size")]
+    pub page_size: Option<isize>,
+    pub sort: Option<Sort>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    /// Confirm that destructive statements (`DROP` / `TRUNCATE`) should be run
+    /// on connections with `require_confirm_destructive` set.
+    #[serde(default)]
+    pub confirm: bool,
+    /// How the total row count is computed.
+    #[serde(default)]
+    pub count: CountMode,
+    /// Explicit types for the query's parameters (see `param_types`), in order. Any
+    /// parameters without one have their type inferred by Postgres.
+    #[serde(default)]
+    pub param_types: Vec<String>,
+    /// For `EXPLAIN` queries, also run the explained statement and include its first
+    /// `EXPLAIN_SAMPLE_ROWS` rows alongside the plan.
+    #[serde(default)]
+    pub with_sample: bool,
+    /// For `SELECT` queries, also include the plan for the page (see `explain_plan`).
+    /// This takes an extra round-trip, but the query isn't run again.
+    #[serde(default)]
+    pub with_plan: bool,
+    /// Fail if the query has more than one statement, instead of only running the
+    /// first one.
+    #[serde(default)]
+    pub reject_multiple: bool,
+    /// For `SELECT` queries, look up each column's source table and foreign key (see
+    /// `QueryResultColumn::fetch_extended`). This takes two extra catalog queries, so
+    /// it can be turned off e.g. when paging through results already shown.
+    #[serde(default = "default_true")]
+    pub with_extended: bool,
+    /// For `SELECT` queries, return the SQL generated to filter, sort, paginate and
+    /// count the query (see `PaginatedQueryResult::DryRun`) instead of running it.
+    #[serde(default)]
+    pub dry: bool,
+    /// Also retry statements that write when they conflict with another transaction
+    /// (see `paginated_query_with_retries`). Only read-only statements are retried
+    /// otherwise, since retrying a write re-runs any side effects it has outside the
+    /// transaction (e.g. advancing a sequence).
+    #[serde(default)]
+    pub retry_writes: bool,
+    /// For read-only queries, reuse the result of an earlier run of the same query
+    /// (see `cache::ResultCache`), and cache this one's. This only applies to connections
+    /// with `listen_for_invalidation` set, so that writes made elsewhere can invalidate it.
+    #[serde(default)]
+    pub cache: bool,
+}
 
-        Ok(())
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            page: 0,
+            page_size: None,
+            sort: None,
+            filters: Vec::new(),
+            confirm: false,
+            count: CountMode::default(),
+            param_types: Vec::new(),
+            with_sample: false,
+            with_plan: false,
+            reject_multiple: false,
+            with_extended: true,
+            dry: false,
+            retry_writes: false,
+            cache: false,
+        }
     }
 }
 
-impl From<tokio_postgres::error::Error> for PgError {
-    fn from(source: tokio_postgres::error::Error) -> Self {
-        let inner = if let Some(err) = source.as_db_error() {
-            Some(PgErrorInner {
-                code: err.code().code().to_owned(),
-                message: err.message().to_owned(),
-                severity: err.severity().to_owned(),
-                position: err
-                    .position()
-                    .and_then(|p| match p {
-                        tokio_postgres::error::ErrorPosition::Original(pos) => Some(pos),
-                        // TODO: handle `Internal` error position (this seems to occur when
-                        // Postgres generates its own query that's for some reason valid/erroneous)
-                        tokio_postgres::error::ErrorPosition::Internal { .. } => None,
-                    })
-                    .copied(),
-            })
-        } else {
-            None
-        };
+fn default_true() -> bool {
+    true
+}
 
-        Self { source, inner }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CountMode {
+    /// Run a separate `COUNT(*)` query alongside the page query.
+    #[default]
+    Separate,
+    /// Add a `COUNT(*) OVER ()` column to the page query and read the total from
+    /// its first row, saving a round-trip. If the page is empty (e.g. it's past the
+    /// last page), falls back to a separate count.
+    Window,
+    /// Skip the exact count, and instead estimate the total from the planner's row
+    /// estimate for the query (see `estimate_count`). This is cheap, but only as
+    /// accurate as the table statistics, so it can be far off (e.g. after bulk writes
+    /// or for queries with selective filters). A page past the actual end (e.g. one
+    /// picked from the estimated total) is replaced by the actual last page, which
+    /// takes a separate count.
+    Estimate,
+}
+
+/// The name of the column holding the total row count when using `CountMode::Window`.
+const WINDOW_TOTAL_COLUMN: &str = "__dbc_total_count";
+
+/// The default `Config::max_page_size`.
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 10_000;
+
+fn deserialize_page_size<'de, D>(deserializer: D) -> Result<Option<isize>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<isize>::deserialize(deserializer)? {
+        Some(0) => Err(serde::de::Error::custom(
+            "invalid page_size 0: use a negative page_size to return all rows",
+        )),
+        page_size => Ok(page_size),
     }
 }
 
-/// Remove any comments and takes the first semicolon-delimited query.
-fn parse_query(query: &str) -> String {
-    // remove any comments
-    let mut chars = query.chars().peekable();
-    let mut acc = String::new();
+/// The default `Config::default_page_size`.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
 
-    while let Some(c) = chars.next() {
-        match c {
-            '-' => {
-                if chars.next_if(|&c| c == '-').is_some() {
-                    // we're in a line comment, trim until newline
-                    while let Some(c) = chars.next() {
-                        if c == '\n' {
-                            break;
-                        }
-                    }
-                } else {
-                    acc.push('-');
-                }
-            }
-            '/' => {
-                if chars.next_if(|&c| c == '*').is_some() {
-                    loop {
-                        // we're in a block comment, trim until close delimiter
-                        match chars.next() {
-                            Some('*') => {
-                                if chars.next_if(|&c| c == '/').is_some() {
-                                    break;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                } else {
-                    acc.push('/');
-                }
-            }
-            _ => acc.push(c),
-        };
+/// The default `Config::validation_query`.
+pub const DEFAULT_VALIDATION_QUERY: &str = "SELECT 1";
+
+/// Clamp the page size for a query to `max` if it's larger (or unbounded) and the
+/// query doesn't limit its own results. Returns the page size to use, and the one
+/// that was requested if it was clamped.
+fn clamp_page_size(query: &str, page_size: isize, max: usize) -> (isize, Option<isize>) {
+    let max = isize::try_from(max).unwrap_or(isize::MAX);
+    if (page_size < 0 || page_size > max) && !has_limit(query) {
+        (max, Some(page_size))
+    } else {
+        (page_size, None)
     }
+}
+
+/// The `EXPLAIN (FORMAT JSON)` plan for a query, without running it.
+async fn explain_plan(
+    client: &Client,
+    query: &str,
+    params: &[SqlParam<'_>],
+    param_types: &[tokio_postgres::types::Type],
+) -> eyre::Result<serde_json::Value> {
+    let explain = prepare_typed(
+        client,
+        &format!("EXPLAIN (FORMAT JSON) {query}"),
+        param_types,
+    )
+    .await?;
+    let row = client
+        .query_one(&explain.inner, params)
+        .await
+        .map_err(PgError::from)?;
+    Ok(row.try_get(0).map_err(PgError::from)?)
+}
 
-    let query = acc.trim().to_string();
+/// Estimate how many rows a query returns, from the `Plan Rows` of the top node of
+/// its `EXPLAIN (FORMAT JSON)` plan.
+async fn estimate_count(
+    client: &Client,
+    query: &str,
+    params: &[SqlParam<'_>],
+    param_types: &[tokio_postgres::types::Type],
+) -> eyre::Result<usize> {
+    let plan = explain_plan(client, query, params, param_types).await?;
 
-    // only take the first statement
-    match query.split_once(';') {
-        None => query,
-        Some((q, "")) => q.to_string(),
-        Some((q, _)) => {
-            tracing::warn!("query contained more than one statement");
-            q.to_string()
-        }
+    plan_rows(&plan)
+        .map(|rows| rows.round() as usize)
+        .ok_or_else(|| eyre::eyre!("EXPLAIN output doesn't include a row estimate"))
+}
+
+/// The top node's `Plan Rows` in `EXPLAIN (FORMAT JSON)` output, which looks like
+/// `[{"Plan": {"Plan Rows": 100, ...}}]`.
+fn plan_rows(plan: &serde_json::Value) -> Option<f64> {
+    plan.get(0)?.get("Plan")?.get("Plan Rows")?.as_f64()
+}
+
+/// Remove the `CountMode::Window` total column from a page of results, returning the
+/// total row count (or `None` if the page is empty).
+fn take_window_total(result: &mut QueryResult) -> eyre::Result<Option<usize>> {
+    if result.columns.pop().map(|col| col.name).as_deref() != Some(WINDOW_TOTAL_COLUMN) {
+        eyre::bail!("expected a trailing {WINDOW_TOTAL_COLUMN} column");
     }
+
+    let mut total = None;
+    for row in result.rows.iter_mut() {
+        let val = row.pop();
+        // the count will be a string if rows were decoded using TEXT encoding
+        total = total.or(val.and_then(|val| match val {
+            serde_json::Value::Number(n) => n.as_u64(),
+            serde_json::Value::String(s) => s.parse().ok(),
+            _ => None,
+        }));
+    }
+
+    Ok(total.map(|t| t as usize))
 }
 
-#[derive(Debug, PartialEq, Serialize)]
-#[serde(rename_all = "kebab-case")]
-enum QueryType {
-    /// DML SELECT statement
-    Select,
-    /// DML INSERT / UPDATE / DELETE / REFRESH statement
-    ModifyData,
-    /// DDL CREATE / ALTER / DROP / TRUNCATE / COMMENT statement
-    ModifyStructure,
-    /// EXPLAIN statement
-    Explain,
+pub async fn paginated_query(
+    client: &Client,
+    raw_query: &str,
+    params: &[serde_json::Value],
+    options: &QueryOptions,
+) -> eyre::Result<PaginatedQueryResult> {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = run_paginated_query(client, raw_query, params, options).await;
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_query(started.elapsed(), result.as_ref().err());
+    result
 }
 
-fn query_type(query: &str) -> QueryType {
-    let query = query.to_ascii_lowercase();
-    let tokens = query.split(&[' ', '\n']);
+/// Whether statements on this connection (or in its current transaction) can't write,
+/// e.g. because it's connected to a standby.
+async fn is_read_only_session(client: &Client) -> eyre::Result<bool> {
+    let messages = client
+        .simple_query("SHOW transaction_read_only")
+        .await
+        .map_err(PgError::from)?;
+    Ok(messages
+        .iter()
+        .any(|message| matches!(message, SimpleQueryMessage::Row(row) if row.get(0) == Some("on"))))
+}
 
-    for token in tokens {
-        match token {
-            "explain" => return QueryType::Explain,
-            "insert" | "update" | "delete" | "refresh" => return QueryType::ModifyData,
-            "create" | "alter" | "drop" | "truncate" | "comment" => {
-                return QueryType::ModifyStructure;
+/// How long to wait before retrying a query that conflicted with another transaction,
+/// doubled for each further retry.
+const CONFLICT_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Like `paginated_query`, but if the query fails with a serialization failure or
+/// deadlock (which Postgres expects to be retried), run it again, up to the connection's
+/// `conflict_retries` times. Returns the result along with how many retries it took.
+///
+/// Each attempt is only rolled back in full if it ran in its own transaction, so this
+/// shouldn't be used on a connection that's inside a transaction block.
+pub async fn paginated_query_with_retries(
+    client: &Client,
+    raw_query: &str,
+    params: &[serde_json::Value],
+    options: &QueryOptions,
+) -> eyre::Result<(PaginatedQueryResult, u32)> {
+    let retry = options.retry_writes || is_read_only(raw_query);
+    let mut retries = 0;
+    loop {
+        match paginated_query(client, raw_query, params, options).await {
+            Err(err) if retry && retries < client.conflict_retries && is_conflict(&err) => {
+                tracing::warn!("query conflicted with another transaction, retrying: {err}");
+                tokio::time::sleep(CONFLICT_RETRY_BACKOFF * 2u32.pow(retries)).await;
+                retries += 1;
             }
-            _ => {}
+            result => return result.map(|result| (result, retries)),
         }
     }
+}
 
-    QueryType::Select
+/// Options for `run_script`.
+#[derive(Debug, Default)]
+pub struct ScriptOptions {
+    /// Parameters for the script, which must then have a single statement.
+    pub params: Vec<serde_json::Value>,
+    /// Run every statement in a single transaction, which is rolled back if any fails.
+    pub transaction: bool,
+    /// The isolation level the `transaction` is run at, if not the session's default.
+    pub isolation_level: Option<IsolationLevel>,
+    /// Confirm destructive statements (see `QueryOptions::confirm`).
+    pub confirm: bool,
 }
 
-fn col_supported(col: &tokio_postgres::Column) -> bool {
-    use tokio_postgres::types::Type;
-    match *col.type_() {
-        Type::TEXT
-        | Type::VARCHAR
-        | Type::NAME
-        | Type::CHAR
-        | Type::BOOL
-        | Type::INT8
-        | Type::INT4
-        | Type::INT2
-        | Type::FLOAT8
-        | Type::NUMERIC
-        | Type::FLOAT4
-        | Type::JSONB
-        | Type::JSON
-        | Type::DATE
-        | Type::TIME
-        | Type::TIMESTAMP
-        | Type::TIMESTAMPTZ => true,
-        _ => match col.type_().name() {
-            "citext" => true,
-            _ => false,
-        },
+/// Run each of a script's statements (see `split_statements`) in order, returning their
+/// results; a query's result has its first page of rows. Stops at the first statement
+/// that fails, after which the statements before it stay applied, unless the script
+/// is run in a `transaction`.
+pub async fn run_script(
+    client: &Client,
+    script: &str,
+    options: &ScriptOptions,
+) -> eyre::Result<Vec<PaginatedQueryResult>> {
+    let statements = split_statements(script);
+    if statements.is_empty() {
+        eyre::bail!("the script has no statements");
+    }
+    if !options.params.is_empty() && statements.len() > 1 {
+        eyre::bail!(
+            "parameters can only be used with a single statement, but the script has {}",
+            statements.len()
+        );
     }
-}
 
-// FIXME: add support for *_ARRAY types
-fn to_json(
-    row: &tokio_postgres::Row,
-    col: &tokio_postgres::Column,
-    idx: usize,
-) -> Option<serde_json::Value> {
-    use tokio_postgres::types::Type;
-    match *col.type_() {
-        Type::TEXT | Type::VARCHAR | Type::NAME | Type::CHAR => {
-            let val: Option<&str> = row.get(idx);
-            Some(val.into())
-        }
-        Type::BOOL => {
-            let val: Option<bool> = row.get(idx);
-            Some(val.into())
-        }
-        Type::INT8 => {
-            let val: Option<i64> = row.get(idx);
-            Some(val.into())
+    let query_options = QueryOptions {
+        page: 1,
+        confirm: options.confirm,
+        ..Default::default()
+    };
+    if options.isolation_level.is_some() && !options.transaction {
+        eyre::bail!("an isolation level can only be set for a script run in a transaction");
+    }
+    if options.transaction {
+        if let Some(idx) = statements.iter().position(|s| is_transaction_control(s)) {
+            eyre::bail!(
+                "statement {} of {} starts or ends a transaction, which can't be done in a \
+                 script that's run in a transaction",
+                idx + 1,
+                statements.len()
+            );
         }
-        Type::INT4 => {
-            let val: Option<i32> = row.get(idx);
-            Some(val.into())
+        begin(client, options.isolation_level).await?;
+    }
+
+    let mut results = Vec::with_capacity(statements.len());
+    for (idx, statement) in statements.iter().enumerate() {
+        match paginated_query(client, statement, &options.params, &query_options).await {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                if options.transaction {
+                    client
+                        .batch_execute("ROLLBACK")
+                        .await
+                        .map_err(PgError::from)?;
+                }
+                eyre::bail!(
+                    "statement {} of {} failed: {err}",
+                    idx + 1,
+                    statements.len()
+                );
+            }
         }
-        Type::INT2 => {
-            let val: Option<i16> = row.get(idx);
-            Some(val.into())
+    }
+
+    if options.transaction {
+        client
+            .batch_execute("COMMIT")
+            .await
+            .map_err(PgError::from)?;
+    }
+    Ok(results)
+}
+
+/// Whether a statement starts or ends a transaction block (e.g. `BEGIN` / `COMMIT`).
+fn is_transaction_control(statement: &str) -> bool {
+    let statement = parse_query(statement).to_ascii_lowercase();
+    let mut tokens = statement.split_whitespace();
+    match tokens.next() {
+        Some("begin" | "start" | "commit" | "end" | "abort") => true,
+        // `ROLLBACK TO [SAVEPOINT] ...` stays inside the transaction block
+        Some("rollback") => tokens.next() != Some("to"),
+        Some("prepare") => tokens.next() == Some("transaction"),
+        _ => false,
+    }
+}
+
+fn is_conflict(err: &eyre::Report) -> bool {
+    match err.downcast_ref::<PgError>() {
+        Some(err) => err.is_retryable(),
+        None => err
+            .downcast_ref::<tokio_postgres::Error>()
+            .and_then(tokio_postgres::Error::code)
+            .is_some_and(|code| {
+                *code == SqlState::T_R_SERIALIZATION_FAILURE
+                    || *code == SqlState::T_R_DEADLOCK_DETECTED
+            }),
+    }
+}
+
+async fn run_paginated_query(
+    client: &Client,
+    raw_query: &str,
+    params: &[serde_json::Value],
+    options: &QueryOptions,
+) -> eyre::Result<PaginatedQueryResult> {
+    let QueryOptions {
+        page,
+        page_size,
+        ref sort,
+        ref filters,
+        ..
+    } = *options;
+
+    let (raw_query, extra_statements) = split_first_statement(raw_query);
+    if extra_statements {
+        if options.reject_multiple {
+            eyre::bail!(
+                "query contains more than one statement; only one statement can be run at a time"
+            );
         }
-        Type::FLOAT8 => {
-            let val: Option<f64> = row.get(idx);
-            Some(val.into())
+        tracing::warn!("query contained more than one statement");
+    }
+    client.policy.check(&raw_query)?;
+    let param_types = param_types(&options.param_types)?;
+
+    // DDL queries can't be counted/paginated like normal queries, but we
+    // still support a pagination wrapper around their results; they'll always
+    // return a single result representing the DDL command's output
+    let query_type = query_type(&raw_query);
+    let page_size = page_size
+        .unwrap_or_else(|| isize::try_from(client.default_page_size).unwrap_or(isize::MAX));
+    let (page_size, requested_page_size) = match query_type {
+        QueryType::Select => clamp_page_size(&raw_query, page_size, client.max_page_size),
+        _ => (page_size, None),
+    };
+
+    if options.dry && query_type != QueryType::Select {
+        eyre::bail!("only SELECT queries can be dry run");
+    }
+
+    // `DO` blocks run arbitrary statements, so they're checked too
+    if let QueryType::ModifyStructure | QueryType::Command = query_type
+        && client.require_confirm_destructive
+        && !options.confirm
+        && let Some(keyword) = destructive_keyword(&raw_query)
+    {
+        eyre::bail!(
+            "Blocked {keyword} statement: this connection requires destructive statements \
+             to be confirmed. To run it anyway, resend the query with `\"confirm\": true` \
+             (or the `X-Confirm-Destructive: true` header)."
+        );
+    }
+
+    // Postgres rejects these too, but with an error that doesn't say why they count as
+    // writes
+    if query_type == QueryType::Select
+        && !options.dry
+        && let Some(clause) = locking_clause(&raw_query)
+        && is_read_only_session(client).await?
+    {
+        eyre::bail!(
+            "Blocked SELECT ... {clause}: it locks the rows it selects (as if to update \
+             them), which can't be done in a read-only session (e.g. on a standby, or with \
+             `default_transaction_read_only` set). Remove the {clause} clause to run it."
+        );
+    }
+
+    if let QueryType::ModifyData | QueryType::ModifyStructure = query_type {
+        let (stmt, params) = prepare_params(client, &raw_query, params, &param_types).await?;
+        let affected_rows = client
+            .execute(&stmt.inner, &dyn_params(&params))
+            .await
+            .map_err(PgError::from)?;
+
+        return Ok(match query_type {
+            QueryType::ModifyData => PaginatedQueryResult::ModifyData {
+                affected_rows,
+                command: modify_data_tag(&raw_query, affected_rows),
+            },
+            QueryType::ModifyStructure => PaginatedQueryResult::ModifyStructure,
+            _ => unreachable!(),
+        });
+    }
+
+    // commands don't return rows and can't take parameters, so just run them as-is
+    if let QueryType::Command = query_type {
+        if !params.is_empty() {
+            eyre::bail!(
+                "{} statements don't take parameters",
+                command_tag(&raw_query)
+            );
         }
-        Type::FLOAT4 => {
-            let val: Option<f32> = row.get(idx);
-            Some(val.into())
+        client
+            .simple_query(&raw_query)
+            .await
+            .map_err(PgError::from)?;
+        return Ok(PaginatedQueryResult::Command {
+            message: command_tag(&raw_query),
+            output: None,
+        });
+    }
+
+    // procedures can't be wrapped in a query to paginate them, but unlike other
+    // commands they can take parameters, and return their output arguments as a row
+    if let QueryType::Call = query_type {
+        let (stmt, params) = prepare_params(client, &raw_query, params, &param_types).await?;
+        let output = if stmt.columns().is_empty() {
+            client.execute(&stmt.inner, &dyn_params(&params)).await?;
+            None
+        } else {
+            Some(query_typed(client, &stmt.sql, &dyn_params(&params), stmt.params()).await?)
+        };
+        return Ok(PaginatedQueryResult::Command {
+            message: "CALL".to_owned(),
+            output,
+        });
+    }
+
+    // EXPLAIN queries just return a plan and are handled specially by the FE
+    if let QueryType::Explain = query_type {
+        let (stmt, params) = prepare_params(client, &raw_query, params, &param_types).await?;
+        let rows = client.query(&stmt.inner, &dyn_params(&params)).await?;
+
+        let first_row = rows.get(0).unwrap();
+        use tokio_postgres::types::Type;
+        let plan = match first_row.columns()[0].type_() {
+            // with `FORMAT JSON`, everything's in the first row
+            &Type::JSON => {
+                serde_json::to_string(&first_row.get::<_, serde_json::Value>(0)).unwrap()
+            }
+
+            // with `FORMAT TEXT`, output is newline-delimited across all rows
+            &Type::TEXT => rows
+                .iter()
+                .map(|row| row.get::<_, &str>(0))
+                .collect::<Vec<_>>()
+                .join("\n"),
+
+            type_ => {
+                panic!("Unexpected EXPLAIN result type: {type_}");
+            }
+        };
+
+        let sample = match explained_statement(&raw_query) {
+            Some(statement) if options.with_sample && is_read_only(statement) => {
+                let sample_query =
+                    format!("SELECT * FROM (\n{statement}\n) _ LIMIT {EXPLAIN_SAMPLE_ROWS};");
+                Some(query_typed(client, &sample_query, &dyn_params(&params), stmt.params()).await?)
+            }
+            _ => None,
+        };
+
+        return Ok(PaginatedQueryResult::Explain {
+            query: raw_query,
+            plan,
+            sample,
+        });
+    }
+
+    if page == 0 {
+        return Err(InvalidRequest("invalid page 0: pages are numbered from 1".to_owned()).into());
+    }
+    // `QueryOptions` can't ask for this, but a connection's page size settings could
+    if page_size == 0 {
+        return Err(InvalidRequest("invalid page_size 0".to_owned()).into());
+    }
+    // the page's offset (and so every smaller page's) must fit in a `usize`
+    if page_size > 0 && (page - 1).checked_mul(page_size as usize).is_none() {
+        return Err(InvalidRequest(format!("page {page} is out of range")).into());
+    }
+
+    let inner_stmt = prepare_typed(&client, &raw_query, &param_types).await?;
+
+    let filter_prefix = format!(
+        "WITH q({}) AS (\n",
+        inner_stmt
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Filter::col_name(i, c.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let select_aliases = inner_stmt
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            format!(
+                "{} AS \"{}\"",
+                Filter::col_name(i, c.name()),
+                c.name().replace('"', "\"\"")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let filtered_query = format!(
+        "{}{}\n)\nSELECT {} FROM q{}{}",
+        filter_prefix,
+        raw_query,
+        select_aliases,
+        if filters.is_empty() { "" } else { "\nWHERE " },
+        filters
+            .iter()
+            .scan(0, |i, f| {
+                match f.where_clause(*i) {
+                    (true, clause) => {
+                        *i = *i + 1;
+                        Some(clause)
+                    }
+                    (false, clause) => Some(clause),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    );
+
+    let filter_params = filters
+        .iter()
+        .filter_map(|f| f.uses_param().then(|| f.value.clone()))
+        .collect::<Vec<_>>();
+
+    let all_params = params
+        .into_iter()
+        .cloned()
+        .chain(filter_params.into_iter())
+        .collect::<Vec<_>>();
+
+    let (stmt, params) =
+        prepare_params(&client, &filtered_query, &all_params, &param_types).await?;
+
+    let base_query = stmt.sql.as_str();
+    // the page and count queries wrap the base query, so make sure its parameters
+    // are resolved to the same types (which `params` were converted to)
+    let param_types = stmt.params();
+
+    let count_query = format!("SELECT COUNT(*) FROM (\n{base_query}\n) _;");
+
+    let page_query_for = |page: usize| {
+        if page_size < 0 && options.count != CountMode::Window {
+            return (base_query.to_owned(), 0);
         }
-        Type::NUMERIC => {
-            let val: Option<Decimal> = row.get(idx);
-            Some(val.map(|d| d.to_string()).into())
+        let select = match options.count {
+            CountMode::Separate | CountMode::Estimate => "SELECT * FROM (\n".to_owned(),
+            CountMode::Window => {
+                format!("SELECT *, COUNT(*) OVER () AS \"{WINDOW_TOTAL_COLUMN}\" FROM (\n")
+            }
+        };
+        let order_by = sort
+            .as_ref()
+            .map(|s| format!("ORDER BY {} {}", s.column_idx + 1, s.direction))
+            .unwrap_or_default();
+        let limit = if page_size < 0 {
+            String::new()
+        } else {
+            let limit = page_size as usize;
+            let offset = (page - 1) * limit;
+            // without an exact count, fetch an extra row to tell if there's a next page
+            let fetched = match options.count {
+                CountMode::Estimate => limit + 1,
+                CountMode::Separate | CountMode::Window => limit,
+            };
+            format!(" LIMIT {fetched} OFFSET {offset}")
+        };
+
+        (
+            format!("{select}{base_query}\n) _ {order_by}{limit};"),
+            -(select.len() as i32),
+        )
+    };
+    let (mut page_query, page_query_offset) = page_query_for(page);
+
+    if options.dry {
+        return Ok(PaginatedQueryResult::DryRun {
+            page_query,
+            count_query: (options.count == CountMode::Separate).then_some(count_query),
+            params: all_params,
+        });
+    }
+
+    let params = &params;
+    let page_error_offset = page_query_offset - (filter_prefix.len() as i32);
+    let run_page_query = |page_query: String| async move {
+        query_typed(client, &page_query, &dyn_params(params), param_types)
+            .await
+            .map_err(|err| match err.downcast::<PgError>() {
+                Ok(mut err) => {
+                    err.offset_position(page_error_offset);
+                    eyre::eyre!(err)
+                }
+                Err(err) => err,
+            })
+    };
+
+    let run_count_query = async {
+        let count_result = query_typed(client, &count_query, &dyn_params(params), param_types)
+            .await
+            .map_err(|err| match err.downcast::<PgError>() {
+                Ok(mut err) => {
+                    err.offset_position(-23 - (filter_prefix.len() as i32));
+                    eyre::eyre!(err)
+                }
+                Err(err) => err,
+            })?;
+        eyre::Ok(count_result.rows[0][0].as_u64().unwrap() as usize)
+    };
+
+    let mut page = page;
+    let mut requested_page = None;
+    let mut total_count_estimate = None;
+    let mut has_more = None;
+    let (mut result, total_count) = match options.count {
+        CountMode::Separate => {
+            futures_util::future::try_join(run_page_query(page_query.clone()), run_count_query)
+                .await?
         }
-        Type::JSONB | Type::JSON => {
-            let val: Option<serde_json::Value> = row.get(idx);
-            Some(val.into())
+        CountMode::Estimate => {
+            let (mut result, estimate) = futures_util::future::try_join(
+                run_page_query(page_query.clone()),
+                estimate_count(client, base_query, &dyn_params(params), param_types),
+            )
+            .await?;
+            total_count_estimate = Some(estimate);
+
+            // a page picked from the estimated total (e.g. jumping to the last page) can
+            // be past the actual end: if so, count the rows (which the empty page has
+            // already scanned past) and show the actual last page instead
+            if result.rows.is_empty() && page > 1 && page_size > 0 {
+                let last_page = run_count_query.await?.div_ceil(page_size as usize).max(1);
+                if last_page < page {
+                    requested_page = Some(page);
+                    page = last_page;
+                    page_query = page_query_for(page).0;
+                    result = run_page_query(page_query.clone()).await?;
+                }
+            }
+
+            // the extra row fetched past the page only says whether there's a next page
+            let more = page_size >= 0 && result.rows.len() > page_size as usize;
+            if more {
+                result.rows.truncate(page_size as usize);
+            }
+            has_more = Some(more);
+
+            // keep the total consistent with the rows actually seen: if there are no
+            // more rows, this is the last page, and so the total is exact
+            let offset = if page_size < 0 {
+                0
+            } else {
+                (page - 1) * page_size as usize
+            };
+            let seen = offset + result.rows.len();
+            let total_count = if !more {
+                if result.rows.is_empty() && page > 1 {
+                    estimate.min(offset)
+                } else {
+                    seen
+                }
+            } else {
+                estimate.max(seen + 1)
+            };
+            (result, total_count)
         }
-        Type::DATE => {
-            let iso_date = format_description!("[year]-[month]-[day]");
-            let val: Option<time::Date> = row.get(idx);
-            Some(val.map(|d| d.format(&iso_date).unwrap()).into())
+        CountMode::Window => {
+            let mut result = run_page_query(page_query.clone()).await?;
+            let total_count = match take_window_total(&mut result)? {
+                Some(total_count) => total_count,
+                // the first page is only empty if there are no rows at all
+                None if page <= 1 => 0,
+                None => run_count_query.await?,
+            };
+            (result, total_count)
         }
-        Type::TIME => {
-            let iso_time = format_description!("[hour]:[minute]:[second]");
-            let val: Option<time::Time> = row.get(idx);
-            Some(val.map(|t| t.format(&iso_time).unwrap()).into())
+    };
+
+    // fetch additional information, like source table and column names and FKs
+    if options.with_extended {
+        QueryResultColumn::fetch_extended(&mut result.columns, client).await;
+    }
+
+    let plan = match options.with_plan {
+        true => Some(explain_plan(client, &page_query, &dyn_params(params), param_types).await?),
+        false => None,
+    };
+
+    let page_count = result.rows.len();
+    let total_pages = if page_size < 0 {
+        1
+    } else {
+        total_count.div_ceil(page_size as usize)
+    };
+    // with every row on one page, there's no other page
+    let has_next = page_size >= 0 && has_more.unwrap_or(page < total_pages);
+    let has_prev = page_size >= 0 && page > 1;
+
+    Ok(PaginatedQueryResult::Select {
+        page,
+        page_size,
+        page_count,
+        total_count,
+        total_pages,
+        has_next,
+        has_prev,
+        sort: sort.clone(),
+        entries: result,
+        clamped: requested_page_size.is_some(),
+        requested_page_size,
+        requested_page,
+        total_count_estimate,
+        plan,
+    })
+}
+
+/// Whether a query only reads data, i.e. it's a plain `SELECT`.
+pub fn is_read_only(query: &str) -> bool {
+    let query = parse_query(query);
+    // a `SELECT` that locks rows does so in order to write them
+    query_type(&query) == QueryType::Select && locking_clause(&query).is_none()
+}
+
+/// The result of running the same query against one of several connections.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FederatedQueryResult {
+    Ok {
+        connection: String,
+        result: PaginatedQueryResult,
+    },
+    Error {
+        connection: String,
+        error: String,
+    },
+}
+
+/// The rows from several connections' results combined into a single result, with
+/// each row labeled by the connection it came from (in `CONNECTION_COLUMN`).
+#[derive(Debug, Serialize)]
+pub struct MergedQueryResult {
+    pub columns: Vec<QueryResultColumn>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    /// Connections whose columns didn't match the first result's, so their rows
+    /// were left out.
+    pub excluded: Vec<String>,
+}
+
+/// The name of the synthetic column holding each merged row's connection name.
+pub const CONNECTION_COLUMN: &str = "__connection";
+
+/// Merge each connection's `SELECT` results, which are expected to have the same
+/// columns (by name and type) as the first one.
+pub fn merge_results<'a>(
+    results: impl IntoIterator<Item = (&'a str, &'a QueryResult)>,
+) -> MergedQueryResult {
+    let mut merged = MergedQueryResult {
+        columns: Vec::new(),
+        rows: Vec::new(),
+        excluded: Vec::new(),
+    };
+    let mut shape: Option<Vec<(&str, &str)>> = None;
+
+    for (connection, result) in results {
+        let columns = result
+            .columns
+            .iter()
+            .map(|col| (col.name.as_str(), col.type_.as_str()))
+            .collect::<Vec<_>>();
+
+        match &shape {
+            None => {
+                merged.columns.push(QueryResultColumn {
+                    table_oid: None,
+                    column_id: None,
+                    name: CONNECTION_COLUMN.to_owned(),
+                    index: 0,
+                    type_: "text".to_owned(),
+                    unsupported_type: None,
+                    extended: None,
+                });
+                merged
+                    .columns
+                    .extend(result.columns.iter().cloned().map(|mut col| {
+                        col.index += 1;
+                        col
+                    }));
+                shape = Some(columns);
+            }
+            Some(shape) if *shape != columns => {
+                merged.excluded.push(connection.to_owned());
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        merged.rows.extend(result.rows.iter().map(|row| {
+            std::iter::once(serde_json::Value::from(connection))
+                .chain(row.iter().cloned())
+                .collect()
+        }));
+    }
+
+    merged
+}
+
+#[derive(Debug)]
+pub struct PreparedStatement {
+    pub sql: String,
+    pub inner: tokio_postgres::Statement,
+    pub columns: Vec<QueryResultColumn>,
+}
+
+impl std::ops::Deref for PreparedStatement {
+    type Target = tokio_postgres::Statement;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+pub async fn prepare(client: &Client, raw_sql: &str) -> eyre::Result<PreparedStatement> {
+    prepare_typed(client, raw_sql, &[]).await
+}
+
+/// Prepare a statement, giving the types of its first `param_types.len()` parameters
+/// rather than letting Postgres infer them (e.g. `SELECT $1` would otherwise be `text`).
+pub async fn prepare_typed(
+    client: &Client,
+    raw_sql: &str,
+    param_types: &[tokio_postgres::types::Type],
+) -> eyre::Result<PreparedStatement> {
+    let sql = parse_query(raw_sql);
+    let stmt = client
+        .prepare_typed(&sql, param_types)
+        .await
+        .map_err(PgError::from)?;
+
+    let columns = stmt
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(idx, col)| QueryResultColumn {
+            index: idx,
+            table_oid: col.table_oid(),
+            column_id: col.column_id(),
+            name: col.name().to_owned(),
+            type_: col.type_().name().to_owned(),
+            unsupported_type: None,
+            extended: None,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(PreparedStatement {
+        sql,
+        inner: stmt,
+        columns,
+    })
+}
+
+/// A parameter of a prepared statement, e.g. `$1` of type `int4`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreparedParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+impl PreparedStatement {
+    pub fn prepared_params(&self) -> Vec<PreparedParam> {
+        self.params()
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| PreparedParam {
+                name: format!("${}", i + 1),
+                type_: ty.name().to_owned(),
+            })
+            .collect()
+    }
+}
+
+/// How many statements of a batch are prepared at once (on the same connection).
+const PREPARE_BATCH_CONCURRENCY: usize = 8;
+
+/// The result of preparing one query of a batch (see `prepare_batch`).
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PrepareResult {
+    Ok {
+        columns: Vec<QueryResultColumn>,
+        params: Vec<PreparedParam>,
+    },
+    Error {
+        message: String,
+        code: Option<String>,
+        position: Option<u32>,
+    },
+}
+
+/// Prepare each query without running it, e.g. to validate every statement in a script.
+/// A query that fails to prepare is reported in its result, in the same order as
+/// `queries`, instead of failing the whole batch.
+pub async fn prepare_batch(client: &Client, queries: &[String]) -> Vec<PrepareResult> {
+    use futures_util::StreamExt;
+
+    // collect the futures up front, so the stream doesn't borrow from its closure
+    let prepares = queries
+        .iter()
+        .map(|query| prepare_one(client, query))
+        .collect::<Vec<_>>();
+    futures_util::stream::iter(prepares)
+        .buffered(PREPARE_BATCH_CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn prepare_one(client: &Client, query: &str) -> PrepareResult {
+    let err = match prepare(client, query).await {
+        Ok(stmt) => {
+            return PrepareResult::Ok {
+                params: stmt.prepared_params(),
+                columns: stmt.columns,
+            };
+        }
+        Err(err) => err,
+    };
+
+    match err.downcast::<PgError>() {
+        Ok(err) if err.has_extended() => PrepareResult::Error {
+            message: err.message().cloned().unwrap_or_default(),
+            code: err.code().cloned(),
+            position: err.position(),
+        },
+        Ok(err) => PrepareResult::Error {
+            message: err.to_string(),
+            code: None,
+            position: None,
+        },
+        Err(err) => PrepareResult::Error {
+            message: err.to_string(),
+            code: None,
+            position: None,
+        },
+    }
+}
+
+/// Options for exporting a query's results as CSV (see `copy_csv`), mapped to the
+/// options of `COPY ... TO`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    /// How `NULL`s are written; by default, as an unquoted empty string.
+    pub null: String,
+    /// Whether to write a header line with the column names.
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            null: String::new(),
+            header: true,
+        }
+    }
+}
+
+/// Export a query's results as CSV, formatted by Postgres with `COPY ... TO STDOUT`.
+/// This is much faster than decoding the rows, and handles every type Postgres can
+/// output as text. `COPY` doesn't support parameters, so neither does this.
+pub async fn copy_csv(
+    client: &Client,
+    raw_query: &str,
+    options: &CsvOptions,
+) -> eyre::Result<tokio_postgres::CopyOutStream> {
+    let raw_query = parse_query(raw_query);
+    if query_type(&raw_query) != QueryType::Select {
+        eyre::bail!("only SELECT queries can be exported as CSV");
+    }
+    client.policy.check(&raw_query)?;
+
+    let sql = format!(
+        "COPY ({raw_query}) TO STDOUT WITH (FORMAT csv, HEADER {}, DELIMITER {}, NULL {})",
+        options.header,
+        quote_literal(&options.delimiter.to_string()),
+        quote_literal(&options.null)
+    );
+    Ok(client.copy_out(&sql).await.map_err(PgError::from)?)
+}
+
+pub async fn prepare_params(
+    client: &Client,
+    raw_sql: &str,
+    params: &[serde_json::Value],
+    param_types: &[tokio_postgres::types::Type],
+) -> eyre::Result<(PreparedStatement, Vec<Box<dyn ToSql + Sync + Send>>)> {
+    let stmt = prepare_typed(&client, raw_sql, param_types).await?;
+
+    if stmt.params().len() != params.len() {
+        eyre::bail!(
+            "Expected {} params, got {}",
+            stmt.params().len(),
+            params.len()
+        );
+    }
+
+    let params = params
+        .iter()
+        .zip(stmt.params().iter().cloned())
+        .map(|(param, param_type)| from_json(param, param_type))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((stmt, params))
+}
+
+pub async fn query(
+    client: &Client,
+    raw_sql: &str,
+    params: &[SqlParam<'_>],
+) -> eyre::Result<QueryResult> {
+    query_typed(client, raw_sql, params, &[]).await
+}
+
+/// Like `query`, but with explicit types for (some of) the parameters (see `prepare_typed`).
+pub async fn query_typed(
+    client: &Client,
+    raw_sql: &str,
+    params: &[SqlParam<'_>],
+    param_types: &[tokio_postgres::types::Type],
+) -> eyre::Result<QueryResult> {
+    let stmt = prepare_typed(client, raw_sql, param_types).await?;
+    let (rows, encoding, unsupported) = raw_query(client, &stmt, params).await?;
+    let mut columns = stmt.columns;
+    for idx in unsupported {
+        columns[idx].unsupported_type = Some(type_display_name(stmt.inner.columns()[idx].type_()));
+    }
+
+    Ok(QueryResult {
+        columns,
+        rows,
+        encoding,
+    })
+}
+
+/// Run a prepared statement, returning the decoded rows, how they were decoded,
+/// and the indices of any columns that couldn't be decoded (see `TextFallback`).
+async fn raw_query(
+    client: &Client,
+    statement: &PreparedStatement,
+    params: &[SqlParam<'_>],
+) -> eyre::Result<(Vec<Vec<serde_json::Value>>, ResultEncoding, Vec<usize>)> {
+    let (encoding, unsupported) = client.text_fallback.resolve(
+        statement
+            .columns()
+            .iter()
+            .map(|col| (col.name(), col.type_())),
+    )?;
+
+    if encoding != ResultEncoding::Text {
+        if !unsupported.is_empty() {
+            tracing::info!("skipping {} unsupported column(s)", unsupported.len());
+        }
+
+        let rows = client
+            .query(&statement.inner, params)
+            .await
+            .map_err(PgError::from)?;
+
+        let mut data_rows: Vec<Vec<serde_json::Value>> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut data_row: Vec<serde_json::Value> =
+                Vec::with_capacity(statement.columns().len());
+            // use column index to get value instead of name in case of duplicate column names
+            for (idx, col) in statement.columns().iter().enumerate() {
+                // push `null` rather than skipping values, so that rows line up with columns
+                let val = if unsupported.contains(&idx) {
+                    None
+                } else {
+                    to_json(&row, col, idx, &client.formatter)
+                };
+                data_row.push(val.unwrap_or(serde_json::Value::Null));
+            }
+            data_rows.push(data_row);
+        }
+
+        Ok((data_rows, encoding, unsupported))
+    } else {
+        // fall back on simple query (uses TEXT instead of BINARY encoding)
+        tracing::info!("falling back on TEXT encoding");
+
+        if !params.is_empty() {
+            eyre::bail!("TEXT encoding does not support parameters");
+        }
+
+        let rows = client
+            .simple_query(&statement.sql)
+            .await
+            .map_err(PgError::from)?;
+
+        // the simple query runs the same SQL as the prepared statement, so its columns
+        // should line up; check (once, since every row shares the description) rather
+        // than shift values into the wrong columns if not
+        let check_columns = |columns: &[tokio_postgres::SimpleColumn]| {
+            let names = columns.iter().map(|col| col.name());
+            if !names.eq(statement.columns().iter().map(|col| col.name())) {
+                eyre::bail!(
+                    "TEXT encoding returned columns ({}) that don't match the query's ({})",
+                    columns
+                        .iter()
+                        .map(|col| col.name())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    statement
+                        .columns()
+                        .iter()
+                        .map(|col| col.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            Ok(())
+        };
+
+        let mut data_rows: Vec<Vec<serde_json::Value>> = Vec::with_capacity(rows.len());
+        for cmd in rows {
+            use tokio_postgres::SimpleQueryMessage::*;
+            match cmd {
+                RowDescription(columns) => check_columns(&columns)?,
+                CommandComplete(_) => {}
+                Row(row) => {
+                    let data_row = (0..row.len())
+                        .map(|idx| match *statement.columns()[idx].type_() {
+                            // `void` is sent as an empty string, which would read as one
+                            tokio_postgres::types::Type::VOID => serde_json::Value::Null,
+                            _ => row.get(idx).into(),
+                        })
+                        .collect();
+                    data_rows.push(data_row);
+                }
+                _ => unreachable!("non-exhaustive enum"),
+            }
+        }
+
+        Ok((data_rows, encoding, unsupported))
+    }
+}
+
+#[derive(Debug)]
+pub struct PgError {
+    source: tokio_postgres::error::Error,
+    inner: Option<PgErrorInner>,
+}
+
+#[derive(Debug)]
+struct PgErrorInner {
+    code: String,
+    message: String,
+    severity: String,
+    position: Option<u32>,
+}
+
+impl PgError {
+    pub fn has_extended(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn code(&self) -> Option<&String> {
+        self.inner.as_ref().map(|inner| &inner.code)
+    }
+
+    pub fn message(&self) -> Option<&String> {
+        self.inner.as_ref().map(|inner| &inner.message)
+    }
+
+    pub fn severity(&self) -> Option<&String> {
+        self.inner.as_ref().map(|inner| &inner.severity)
+    }
+
+    pub fn position(&self) -> Option<u32> {
+        self.inner.as_ref().and_then(|inner| inner.position)
+    }
+
+    /// Whether the statement failed due to a transient conflict with another transaction
+    /// (`serialization_failure` or `deadlock_detected`), and can be safely retried.
+    pub fn is_retryable(&self) -> bool {
+        self.code()
+            .is_some_and(|code| code == "40001" || code == "40P01")
+    }
+
+    /// Whether the statement failed because the connection was lost (e.g. the server
+    /// restarted, or the backend was terminated) rather than because of the statement.
+    pub fn is_disconnect(&self) -> bool {
+        is_disconnect_error(&self.source)
+    }
+
+    pub fn offset_position(&mut self, offset_by: i32) {
+        self.inner
+            .as_mut()
+            .and_then(|inner| inner.position.as_mut())
+            .map(|pos| *pos = ((*pos as i32) + offset_by) as u32);
+    }
+}
+
+impl std::error::Error for PgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl std::fmt::Display for PgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(inner) = &self.inner {
+            write!(f, "{} {}: {}", inner.severity, inner.code, inner.message)?;
+            if let Some(pos) = inner.position {
+                write!(f, " (at position {pos})")?;
+            }
+        } else {
+            write!(f, "{}", self.source)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<tokio_postgres::error::Error> for PgError {
+    fn from(source: tokio_postgres::error::Error) -> Self {
+        let inner = if let Some(err) = source.as_db_error() {
+            Some(PgErrorInner {
+                code: err.code().code().to_owned(),
+                message: err.message().to_owned(),
+                severity: err.severity().to_owned(),
+                position: err
+                    .position()
+                    .and_then(|p| match p {
+                        tokio_postgres::error::ErrorPosition::Original(pos) => Some(pos),
+                        // TODO: handle `Internal` error position (this seems to occur when
+                        // Postgres generates its own query that's for some reason valid/erroneous)
+                        tokio_postgres::error::ErrorPosition::Internal { .. } => None,
+                    })
+                    .copied(),
+            })
+        } else {
+            None
+        };
+
+        Self { source, inner }
+    }
+}
+
+/// Run `attempt`, which should check out a connection and use it. If `retry` is set and
+/// the connection is lost partway through, run it once more (checking out a fresh one).
+/// `attempt` should `kill` a connection that was lost, so it isn't checked out again.
+/// Only set `retry` for statements that are safe to repeat, since one that writes may
+/// have been applied before the connection was lost.
+pub async fn retry_if_disconnected<T, F>(
+    retry: bool,
+    mut attempt: impl FnMut() -> F,
+) -> eyre::Result<T>
+where
+    F: Future<Output = eyre::Result<T>>,
+{
+    match attempt().await {
+        Err(err) if retry && is_disconnect(&err) => {
+            tracing::warn!("connection lost, retrying with a new connection: {err}");
+            attempt().await
+        }
+        result => result,
+    }
+}
+
+/// Whether the error means the connection was lost (see `retry_if_disconnected`).
+pub fn is_disconnect(err: &eyre::Report) -> bool {
+    match err.downcast_ref::<PgError>() {
+        Some(err) => err.is_disconnect(),
+        None => err
+            .downcast_ref::<tokio_postgres::Error>()
+            .is_some_and(is_disconnect_error),
+    }
+}
+
+fn is_disconnect_error(err: &tokio_postgres::Error) -> bool {
+    err.is_closed()
+        || err.code().is_some_and(|code| {
+            // `connection_exception`, or `admin_shutdown` / `crash_shutdown` /
+            // `cannot_connect_now`
+            let code = code.code();
+            code.starts_with("08") || matches!(code, "57P01" | "57P02" | "57P03")
+        })
+}
+
+/// Remove any comments and takes the first semicolon-delimited query.
+fn parse_query(query: &str) -> String {
+    split_first_statement(query).0
+}
+
+/// Like `parse_query`, also returning whether any statements after the first were
+/// dropped.
+fn split_first_statement(query: &str) -> (String, bool) {
+    // remove any comments
+    let mut chars = query.chars().peekable();
+    let mut acc = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '-' => {
+                if chars.next_if(|&c| c == '-').is_some() {
+                    // we're in a line comment, trim until newline
+                    while let Some(c) = chars.next() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                } else {
+                    acc.push('-');
+                }
+            }
+            '/' => {
+                if chars.next_if(|&c| c == '*').is_some() {
+                    loop {
+                        // we're in a block comment, trim until close delimiter
+                        match chars.next() {
+                            Some('*') => {
+                                if chars.next_if(|&c| c == '/').is_some() {
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                } else {
+                    acc.push('/');
+                }
+            }
+            _ => acc.push(c),
+        };
+    }
+
+    // only take the first statement
+    let mut statements = split_statements(&acc).into_iter();
+    match statements.next() {
+        None => (String::new(), false),
+        Some(q) => (q, statements.next().is_some()),
+    }
+}
+
+/// Whether `query` has more than one statement, in which case only the first is run.
+pub fn has_extra_statements(query: &str) -> bool {
+    split_first_statement(query).1
+}
+
+/// Split a script into its statements, on semicolons that aren't inside a quoted
+/// string or identifier, a dollar-quoted string (e.g. a function body) or a comment.
+/// Statements with nothing but comments in them are dropped.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let bytes = script.as_bytes();
+    let mut statements = Vec::new();
+    let (mut start, mut has_code, mut i) = (0, false, 0);
+
+    while i < bytes.len() {
+        if let Some(end) = skip_quoted(script, i) {
+            has_code |= !matches!(bytes[i], b'-' | b'/');
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            b';' => {
+                if has_code {
+                    statements.push(script[start..i].trim().to_owned());
+                }
+                (start, has_code) = (i + 1, false);
+            }
+            b if !b.is_ascii_whitespace() => has_code = true,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if has_code {
+        statements.push(script[start..].trim().to_owned());
+    }
+    statements
+}
+
+/// If a quoted string or identifier, a dollar-quoted string or a comment starts at byte
+/// `i` of `sql`, returns the index just past its end (or the end of `sql`, if it's
+/// unterminated).
+fn skip_quoted(sql: &str, i: usize) -> Option<usize> {
+    let bytes = sql.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    // every delimiter is ASCII, so scanning bytes never splits a character
+    let skip_past = |from: usize, delimiter: &str| {
+        sql[from..]
+            .find(delimiter)
+            .map_or(sql.len(), |idx| from + idx + delimiter.len())
+    };
+
+    match bytes[i] {
+        // in an escape string (`E'...'`), a backslash escapes the character after it
+        b'\''
+            if i > 0
+                && matches!(bytes[i - 1], b'E' | b'e')
+                && (i == 1 || !is_word(bytes[i - 2])) =>
+        {
+            let mut i = i + 1;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'\\' => i += 2,
+                    b'\'' => return Some(i + 1),
+                    _ => i += 1,
+                }
+            }
+            Some(bytes.len())
+        }
+        // a doubled quote is just an empty string followed by another one
+        b'\'' => Some(skip_past(i + 1, "'")),
+        b'"' => Some(skip_past(i + 1, "\"")),
+        b'-' if bytes.get(i + 1) == Some(&b'-') => Some(skip_past(i, "\n")),
+        b'/' if bytes.get(i + 1) == Some(&b'*') => Some(skip_past(i + 2, "*/")),
+        // `$tag$ ... $tag$` (or `$$ ... $$`), unless it's a parameter like `$1`
+        // or part of an identifier like `a$b`
+        b'$' if i == 0 || !is_word(bytes[i - 1]) => {
+            let tag_len = bytes[i + 1..].iter().take_while(|&&b| is_word(b)).count();
+            let tag_end = i + 1 + tag_len;
+            (bytes.get(tag_end) == Some(&b'$') && !bytes[i + 1].is_ascii_digit())
+                .then(|| skip_past(tag_end + 1, &sql[i..=tag_end]))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum QueryType {
+    /// DML SELECT statement
+    Select,
+    /// DML INSERT / UPDATE / DELETE / REFRESH statement
+    ModifyData,
+    /// DDL CREATE / ALTER / DROP / TRUNCATE / COMMENT statement, or a maintenance
+    /// VACUUM / ANALYZE / REINDEX / CLUSTER statement (which can't be wrapped in a query)
+    ModifyStructure,
+    /// EXPLAIN statement
+    Explain,
+    /// A statement that only runs a command, returning no rows or affected count,
+    /// e.g. SET / DISCARD / LISTEN / DO
+    Command,
+    /// CALL statement, which runs a procedure
+    Call,
+}
+
+/// Leading keywords of `QueryType::Command` statements.
+const COMMAND_KEYWORDS: [&str; 7] = [
+    "set", "reset", "discard", "listen", "unlisten", "notify", "do",
+];
+
+/// Leading keywords of maintenance statements, which are `QueryType::ModifyStructure`.
+const MAINTENANCE_KEYWORDS: [&str; 4] = ["vacuum", "analyze", "reindex", "cluster"];
+
+fn query_type(query: &str) -> QueryType {
+    let query = query.to_ascii_lowercase();
+    let mut tokens = query.split(&[' ', '\n']).filter(|token| !token.is_empty());
+
+    // these keywords also appear in other statements (e.g. `UPDATE ... SET`,
+    // `ON CONFLICT DO NOTHING`), so they only count at the very start
+    let first = tokens.next().map(|token| token.trim_end_matches(';'));
+    if first.is_some_and(|first| COMMAND_KEYWORDS.contains(&first)) {
+        return QueryType::Command;
+    }
+    if first == Some("call") {
+        return QueryType::Call;
+    }
+    // and these are also common column names (e.g. `SELECT cluster FROM nodes`)
+    if first.is_some_and(|first| MAINTENANCE_KEYWORDS.contains(&first)) {
+        return QueryType::ModifyStructure;
+    }
+
+    let mut prev = None;
+    for token in first.into_iter().chain(tokens) {
+        match token {
+            "explain" => return QueryType::Explain,
+            // `SELECT ... FOR [NO KEY] UPDATE` only locks the rows it selects
+            "update" if matches!(prev, Some("for" | "key")) => {}
+            "insert" | "update" | "delete" | "refresh" => return QueryType::ModifyData,
+            "create" | "alter" | "drop" | "truncate" | "comment" => {
+                return QueryType::ModifyStructure;
+            }
+            _ => {}
+        }
+        prev = Some(token);
+    }
+
+    QueryType::Select
+}
+
+/// The tag Postgres reports for a `QueryType::Command` statement, e.g. `SET` or
+/// `DISCARD ALL`. tokio_postgres doesn't expose command tags, but for these statements
+/// they're just the leading keyword (or two, for `DISCARD`).
+fn command_tag(query: &str) -> String {
+    let mut tokens = query
+        .split_whitespace()
+        .map(|token| token.trim_end_matches(';').to_ascii_uppercase());
+    let keyword = tokens.next().unwrap_or_default();
+    match (keyword.as_str(), tokens.next()) {
+        ("DISCARD", Some(target)) => format!("{keyword} {target}"),
+        _ => keyword,
+    }
+}
+
+/// How many rows of the explained statement are included with `QueryOptions::with_sample`.
+const EXPLAIN_SAMPLE_ROWS: usize = 5;
+
+/// The statement being explained by an `EXPLAIN` query, unless it's `EXPLAIN ANALYZE`
+/// (which has already run the statement, so it shouldn't be run again for a sample).
+fn explained_statement(query: &str) -> Option<&str> {
+    let rest = query.trim_start();
+    let keyword = rest.get(.."explain".len())?;
+    if !keyword.eq_ignore_ascii_case("explain") {
+        return None;
+    }
+    let mut rest = rest["explain".len()..].trim_start();
+
+    // either `EXPLAIN (option [value], ...) statement`...
+    if let Some(options) = rest.strip_prefix('(') {
+        let (options, statement) = options.split_once(')')?;
+        let analyze = options.split(',').any(|option| {
+            let mut words = option.split_whitespace().map(str::to_ascii_lowercase);
+            let name = words.next().unwrap_or_default();
+            let enabled = words
+                .next()
+                .is_none_or(|value| !matches!(value.as_str(), "false" | "off" | "0"));
+            matches!(name.as_str(), "analyze" | "analyse") && enabled
+        });
+        if analyze {
+            return None;
+        }
+        rest = statement;
+    } else {
+        // ...or `EXPLAIN [ANALYZE] [VERBOSE] statement`
+        while let Some((word, statement)) = rest.split_once(char::is_whitespace) {
+            match word.to_ascii_lowercase().as_str() {
+                "analyze" | "analyse" => return None,
+                "verbose" => rest = statement.trim_start(),
+                _ => break,
+            }
+        }
+    }
+
+    let statement = rest.trim().trim_end_matches(';').trim_end();
+    (!statement.is_empty()).then_some(statement)
+}
+
+/// The tag Postgres reports for a `QueryType::ModifyData` statement that affected
+/// `rows` rows, e.g. `INSERT 0 3`. Like `command_tag`, this is reconstructed from the
+/// statement, since tokio_postgres only exposes the row count.
+fn modify_data_tag(query: &str, rows: u64) -> String {
+    let query = query.to_ascii_lowercase();
+    let keyword = query
+        .split(&[' ', '\n', '('])
+        .find(|token| matches!(*token, "insert" | "update" | "delete" | "refresh"));
+
+    match keyword {
+        // the `0` is a leftover OID field, which is always zero
+        Some("insert") => format!("INSERT 0 {rows}"),
+        Some("refresh") => "REFRESH MATERIALIZED VIEW".to_owned(),
+        Some(keyword) => format!("{} {rows}", keyword.to_ascii_uppercase()),
+        None => format!("{rows}"),
+    }
+}
+
+/// Whether the query limits how many rows it returns, with `LIMIT` or `FETCH`. Only the
+/// top-level statement counts, not a subquery (or a string) that happens to have a limit.
+fn has_limit(query: &str) -> bool {
+    let query = query.to_ascii_lowercase();
+    let bytes = query.as_bytes();
+
+    // blank out everything that's quoted or inside parentheses
+    let mut top_level = vec![b' '; bytes.len()];
+    let (mut depth, mut i) = (0usize, 0);
+    while i < bytes.len() {
+        if let Some(end) = skip_quoted(&query, i) {
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth = depth.saturating_sub(1),
+            b if depth == 0 => top_level[i] = b,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&top_level)
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .any(|token| matches!(token, "limit" | "fetch"))
+}
+
+/// If the query contains a destructive DDL statement (`DROP` / `TRUNCATE`),
+/// returns the keyword that was matched. Words inside string literals and dollar-quoted
+/// bodies count too, since a `DO` block can run them (e.g. with `EXECUTE 'DROP ...'`).
+fn destructive_keyword(query: &str) -> Option<&'static str> {
+    let query = query.to_ascii_lowercase();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    query.split(|c| !is_word(c)).find_map(|token| match token {
+        "drop" => Some("DROP"),
+        "truncate" => Some("TRUNCATE"),
+        _ => None,
+    })
+}
+
+/// If the query has a row-locking clause (e.g. `SELECT ... FOR UPDATE`), returns the
+/// clause that was matched.
+fn locking_clause(query: &str) -> Option<&'static str> {
+    let query = query.to_ascii_lowercase();
+    let tokens = query
+        .split_whitespace()
+        .map(|token| token.trim_end_matches(';'))
+        .collect::<Vec<_>>();
+    let token = |idx: usize| tokens.get(idx).copied();
+    (0..tokens.len())
+        .filter(|&idx| tokens[idx] == "for")
+        .find_map(
+            |idx| match (token(idx + 1), token(idx + 2), token(idx + 3)) {
+                (Some("update"), _, _) => Some("FOR UPDATE"),
+                (Some("share"), _, _) => Some("FOR SHARE"),
+                (Some("no"), Some("key"), Some("update")) => Some("FOR NO KEY UPDATE"),
+                (Some("key"), Some("share"), _) => Some("FOR KEY SHARE"),
+                _ => None,
+            },
+        )
+}
+
+fn type_supported(type_: &tokio_postgres::types::Type) -> bool {
+    use tokio_postgres::types::Type;
+    match *type_ {
+        Type::TEXT
+        | Type::VARCHAR
+        | Type::NAME
+        | Type::CHAR
+        | Type::BOOL
+        | Type::INT8
+        | Type::INT4
+        | Type::INT2
+        | Type::FLOAT8
+        | Type::NUMERIC
+        | Type::FLOAT4
+        | Type::JSONB
+        | Type::JSON
+        | Type::DATE
+        | Type::TIME
+        | Type::TIMESTAMP
+        | Type::TIMESTAMPTZ
+        | Type::POINT
+        | Type::VOID => true,
+        _ => match type_.name() {
+            "citext" | "geometry" | "hstore" => true,
+            _ => false,
+        },
+    }
+}
+
+/// A human-friendly type name, e.g. `int4[]` rather than `_int4`.
+fn type_display_name(type_: &tokio_postgres::types::Type) -> String {
+    match type_.kind() {
+        tokio_postgres::types::Kind::Array(inner) => format!("{}[]", type_display_name(inner)),
+        // some array types (e.g. `_record`) are pseudo-types, but follow the same naming
+        _ => match type_.name().strip_prefix('_') {
+            Some(elem) => format!("{elem}[]"),
+            None => type_.name().to_owned(),
+        },
+    }
+}
+
+// FIXME: add support for *_ARRAY types
+fn to_json(
+    row: &tokio_postgres::Row,
+    col: &tokio_postgres::Column,
+    idx: usize,
+    formatter: &Formatter,
+) -> Option<serde_json::Value> {
+    use tokio_postgres::types::Type;
+    match *col.type_() {
+        Type::TEXT | Type::VARCHAR | Type::NAME | Type::CHAR => {
+            let val: Option<&str> = row.get(idx);
+            Some(val.into())
+        }
+        Type::BOOL => {
+            let val: Option<bool> = row.get(idx);
+            Some(val.into())
+        }
+        Type::INT8 => {
+            let val: Option<i64> = row.get(idx);
+            Some(val.map_or(serde_json::Value::Null, |i| formatter.int8(i)))
+        }
+        Type::INT4 => {
+            let val: Option<i32> = row.get(idx);
+            Some(val.into())
+        }
+        Type::INT2 => {
+            let val: Option<i16> = row.get(idx);
+            Some(val.into())
+        }
+        Type::FLOAT8 => {
+            let val: Option<f64> = row.get(idx);
+            Some(val.into())
+        }
+        Type::FLOAT4 => {
+            let val: Option<f32> = row.get(idx);
+            Some(val.into())
+        }
+        Type::NUMERIC => {
+            let val: Option<Decimal> = row.get(idx);
+            Some(val.map_or(serde_json::Value::Null, |d| formatter.numeric(d)))
+        }
+        Type::JSONB | Type::JSON => {
+            let val: Option<serde_json::Value> = row.get(idx);
+            Some(val.into())
+        }
+        Type::DATE => {
+            let val: Option<time::Date> = row.get(idx);
+            Some(val.map_or(serde_json::Value::Null, |d| formatter.date(d)))
+        }
+        Type::TIME => {
+            let iso_time = format_description!("[hour]:[minute]:[second]");
+            let val: Option<time::Time> = row.get(idx);
+            Some(val.map(|t| t.format(&iso_time).unwrap()).into())
+        }
+        Type::TIMESTAMP => {
+            let iso_datetime = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+            let val: Option<time::PrimitiveDateTime> = row.get(idx);
+            Some(val.map_or(serde_json::Value::Null, |t| {
+                formatter.timestamp(t.assume_utc(), iso_datetime)
+            }))
+        }
+        Type::TIMESTAMPTZ => {
+            let iso_datetime_tz = format_description!(
+                "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour]:[offset_minute]"
+            );
+            let val: Option<time::OffsetDateTime> = row.get(idx);
+            Some(val.map_or(serde_json::Value::Null, |t| {
+                formatter.timestamp(t, iso_datetime_tz)
+            }))
+        }
+        Type::POINT => {
+            let val: Option<crate::geometry::Point> = row.get(idx);
+            Some(val.into())
+        }
+        // a `void` function's result has no value (and isn't SQL NULL, so it can't be
+        // decoded as an `Option`), but `null` is what it means
+        Type::VOID => Some(serde_json::Value::Null),
+        _ => {
+            match col.type_().name() {
+                // citext is a case-insensitive text type
+                "citext" => {
+                    let val: Option<&str> = row.get(idx);
+                    Some(val.into())
+                }
+                // PostGIS geometries are returned as EWKT
+                "geometry" => {
+                    let val: Option<crate::geometry::Ewkt> = row.get(idx);
+                    Some(val.map(|g| g.0).into())
+                }
+                // hstore is a map of string keys to nullable string values
+                "hstore" => {
+                    let val: Option<HashMap<String, Option<String>>> = row.get(idx);
+                    Some(val.map_or(serde_json::Value::Null, |map| {
+                        map.into_iter()
+                            .map(|(k, v)| (k, serde_json::Value::from(v)))
+                            .collect()
+                    }))
+                }
+                _ => {
+                    tracing::warn!("unsupported type: {:?}", col.type_());
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Resolve explicit parameter type names. Only types that parameters can be bound
+/// to (see `from_json`) are allowed, under their SQL names or aliases.
+pub fn param_types(names: &[String]) -> eyre::Result<Vec<tokio_postgres::types::Type>> {
+    use tokio_postgres::types::Type;
+    names
+        .iter()
+        .map(|name| {
+            Ok(match name.trim().to_ascii_lowercase().as_str() {
+                "text" => Type::TEXT,
+                "varchar" | "character varying" => Type::VARCHAR,
+                "name" => Type::NAME,
+                "char" | "character" => Type::CHAR,
+                "bool" | "boolean" => Type::BOOL,
+                "int8" | "bigint" => Type::INT8,
+                "int4" | "int" | "integer" => Type::INT4,
+                "int2" | "smallint" => Type::INT2,
+                "oid" => Type::OID,
+                "float8" | "double precision" => Type::FLOAT8,
+                "float4" | "real" => Type::FLOAT4,
+                "numeric" | "decimal" => Type::NUMERIC,
+                "timestamp" | "timestamp without time zone" => Type::TIMESTAMP,
+                "interval" => Type::INTERVAL,
+                _ => eyre::bail!("unsupported parameter type: {name}"),
+            })
+        })
+        .collect()
+}
+
+fn from_json(
+    json: &serde_json::Value,
+    type_: tokio_postgres::types::Type,
+) -> eyre::Result<Box<dyn ToSql + Sync + Send>> {
+    use tokio_postgres::types::Type;
+    match type_ {
+        Type::TEXT | Type::VARCHAR | Type::NAME | Type::CHAR => json
+            .as_str()
+            .ok_or(eyre::eyre!("expected string"))
+            .map(|s| Box::new(s.to_owned()) as _),
+        Type::BOOL => json
+            .as_bool()
+            .ok_or(eyre::eyre!("expected boolean"))
+            .map(|b| Box::new(b) as _),
+        Type::INT8 => int_param::<i64>(json, &type_).map(|i| Box::new(i) as _),
+        Type::INT4 => int_param::<i32>(json, &type_).map(|i| Box::new(i) as _),
+        Type::INT2 => int_param::<i16>(json, &type_).map(|i| Box::new(i) as _),
+        Type::OID => int_param::<u32>(json, &type_).map(|i| Box::new(i) as _),
+        Type::FLOAT8 | Type::FLOAT4 => json
+            .as_f64()
+            .ok_or(eyre::eyre!("expected float"))
+            .map(|f| Box::new(f) as _),
+        Type::NUMERIC => match json {
+            // strings keep their full precision
+            serde_json::Value::String(s) => s
+                .parse::<Decimal>()
+                .map(|d| Box::new(d) as _)
+                .map_err(|err| eyre::eyre!("invalid numeric {s:?}: {err}")),
+            _ => json
+                .as_f64()
+                .ok_or(eyre::eyre!("expected float or string"))
+                .map(|f| Box::new(Decimal::from_f64_retain(f).unwrap()) as _),
+        },
+        Type::INTERVAL => json
+            .as_str()
+            .ok_or(eyre::eyre!("expected string"))
+            .and_then(crate::interval::Interval::parse)
+            .map(|i| Box::new(i) as _),
+        Type::TIMESTAMP => {
+            let s = json.as_str().ok_or(eyre::eyre!("expected string"))?;
+            let date_time =
+                match s.len() {
+                    // parse as date, assume 00:00:00 for time
+                    10 => time::Date::parse(s, format_description!("[year]-[month]-[day]")).map(
+                        |d| time::PrimitiveDateTime::new(d, time::Time::from_hms(0, 0, 0).unwrap()),
+                    )?,
+                    // parse as datetime
+                    19 => time::PrimitiveDateTime::parse(
+                        s,
+                        format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+                    )?,
+                    _ => eyre::bail!(
+                        "invalid timestamp format, expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS"
+                    ),
+                };
+
+            Ok(Box::new(date_time) as _)
+        }
+        _ => {
+            match type_.name() {
+                // citext is a case-insensitive text type
+                "citext" => json
+                    .as_str()
+                    .ok_or(eyre::eyre!("expected string"))
+                    .map(|s| Box::new(s.to_owned()) as _),
+                "hstore" => hstore_param(json).map(|map| Box::new(map) as _),
+                _ => Err(eyre::eyre!("unsupported type: {:?}", type_)),
+            }
+        }
+    }
+}
+
+/// Read an `hstore` parameter from a JSON object whose values are strings or `null`.
+fn hstore_param(json: &serde_json::Value) -> eyre::Result<HashMap<String, Option<String>>> {
+    let obj = json
+        .as_object()
+        .ok_or(eyre::eyre!("expected object of strings"))?;
+    obj.iter()
+        .map(|(key, val)| match val {
+            serde_json::Value::String(s) => Ok((key.clone(), Some(s.clone()))),
+            serde_json::Value::Null => Ok((key.clone(), None)),
+            _ => Err(eyre::eyre!(
+                "expected string or null for hstore key {key:?}"
+            )),
+        })
+        .collect()
+}
+
+/// Read an integer parameter, checking that it fits in the target type so out-of-range
+/// values are rejected with a clear error rather than by Postgres.
+fn int_param<T: TryFrom<i128>>(
+    json: &serde_json::Value,
+    type_: &tokio_postgres::types::Type,
+) -> eyre::Result<T> {
+    let val = json
+        .as_i64()
+        .map(i128::from)
+        .or_else(|| json.as_u64().map(i128::from))
+        .ok_or(eyre::eyre!("expected integer"))?;
+    T::try_from(val).map_err(|_| eyre::eyre!("integer {val} out of range for type {type_}"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio_postgres::types::Type;
+
+    /// Config for the database described by the standard `PG*` environment variables,
+    /// or `None` if `PGHOST` isn't set (in which case tests that need a database are
+    /// skipped).
+    pub(crate) fn test_config() -> Option<Config> {
+        let var = |key, default: &str| std::env::var(key).unwrap_or_else(|_| default.to_owned());
+        Some(
+            Config::builder()
+                .host(std::env::var("PGHOST").ok()?)
+                .port(var("PGPORT", "5432").parse().unwrap())
+                .username(var("PGUSER", "postgres"))
+                .password(var("PGPASSWORD", ""))
+                .database(var("PGDATABASE", "postgres"))
+                .build(),
+        )
+    }
+
+    #[tokio::test]
+    async fn explains_connection_limit_errors() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        // a role that can't hold any connections, so connecting fails with `53300`
+        let admin = connect(&config).await.unwrap();
+        admin
+            .batch_execute(
+                "DROP ROLE IF EXISTS dbc_no_connections;
+                 CREATE ROLE dbc_no_connections LOGIN CONNECTION LIMIT 0 PASSWORD 'dbc'",
+            )
+            .await
+            .unwrap();
+        config.username = "dbc_no_connections".to_owned();
+        config.password = "dbc".to_owned();
+
+        let err = connect(&config).await.err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "server connection limit reached; try a smaller pool_size \
+             (too many connections for role \"dbc_no_connections\")"
+        );
+
+        admin
+            .batch_execute("DROP ROLE dbc_no_connections")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocks_destructive_do_blocks_unless_confirmed() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.require_confirm_destructive = true;
+        let conn = connect(&config).await.unwrap();
+        conn.batch_execute("CREATE TEMP TABLE dbc_do_guard (n int)")
+            .await
+            .unwrap();
+        let sql = "DO $$ BEGIN EXECUTE 'DROP TABLE dbc_do_guard'; END $$";
+
+        let options = QueryOptions {
+            page: 1,
+            ..Default::default()
+        };
+        let err = paginated_query(&conn, sql, &[], &options)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Blocked DROP statement"),
+            "{err}"
+        );
+        query(&conn, "SELECT * FROM dbc_do_guard", &[])
+            .await
+            .unwrap();
+
+        let options = QueryOptions {
+            confirm: true,
+            ..options
+        };
+        paginated_query(&conn, sql, &[], &options).await.unwrap();
+        assert!(
+            query(&conn, "SELECT * FROM dbc_do_guard", &[])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_locking_selects_in_read_only_sessions() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let options = QueryOptions {
+            page: 1,
+            ..Default::default()
+        };
+        let sql = "SELECT n FROM dbc_locks WHERE n = 1 FOR UPDATE";
+
+        // outside of read-only mode, the rows are locked as usual
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute("CREATE TEMP TABLE dbc_locks AS SELECT 1 AS n")
+            .await
+            .unwrap();
+        let result = paginated_query(&conn, sql, &[], &options).await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+        assert!(
+            matches!(result, Ok(PaginatedQueryResult::Select { .. })),
+            "{result:?}"
+        );
+
+        config.session_setup = vec!["SET default_transaction_read_only = on".to_owned()];
+        let conn = connect(&config).await.unwrap();
+        let err = paginated_query(&conn, sql, &[], &options)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Blocked SELECT ... FOR UPDATE: it locks the rows it selects"),
+            "{err}"
+        );
+        // other queries still run
+        let result = paginated_query(&conn, "SELECT 1", &[], &options).await;
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn lists_extensions() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let find = |extensions: &[ExtensionInfo], name: &str| {
+            extensions
+                .iter()
+                .find(|ext| ext.name == name)
+                .map(|ext| (ext.installed_version.clone(), ext.schema.clone()))
+                .unwrap()
+        };
+
+        begin(&conn, None).await.unwrap();
+        let before = list_extensions(&conn).await.unwrap();
+        create_extension(&conn, "hstore").await.unwrap();
+        // already installed, so this does nothing
+        create_extension(&conn, "hstore").await.unwrap();
+        let after = list_extensions(&conn).await.unwrap();
+        let err = create_extension(&conn, "dbc_missing").await.unwrap_err();
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        // `plpgsql` is installed in every database
+        let (version, schema) = find(&before, "plpgsql");
+        assert!(version.is_some());
+        assert_eq!(schema.as_deref(), Some("pg_catalog"));
+        assert_eq!(find(&before, "hstore"), (None, None));
+
+        let hstore = after.iter().find(|ext| ext.name == "hstore").unwrap();
+        assert_eq!(hstore.installed_version, hstore.default_version);
+        assert_eq!(hstore.schema.as_deref(), Some("public"));
+        assert!(hstore.comment.is_some());
+        assert_eq!(
+            err.to_string(),
+            "no extension named \"dbc_missing\" is available on this server"
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_installing_extensions_against_the_query_policy() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.query_policy = QueryPolicy {
+            deny: vec![r"^create extension".to_owned()],
+            ..Default::default()
+        };
+        let conn = connect(&config).await.unwrap();
+        let err = create_extension(&conn, "hstore").await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("Query blocked by policy"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_columns_for_several_tables() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_batch_a;
+             CREATE SCHEMA dbc_batch_b;
+             CREATE TABLE dbc_batch_a.widgets (id int, name text);
+             CREATE TABLE dbc_batch_a.gadgets (id int);
+             CREATE TABLE dbc_batch_b.widgets (sku text);
+             CREATE TABLE dbc_batch_b.gadgets (price numeric);
+             COMMENT ON COLUMN dbc_batch_a.widgets.name IS 'display name';",
+        )
+        .await
+        .unwrap();
+
+        let table = |schema: &str, table: &str| (schema.to_owned(), table.to_owned());
+        let tables = list_columns_batch(
+            &conn,
+            &[
+                table("dbc_batch_b", "widgets"),
+                table("dbc_batch_a", "widgets"),
+                table("dbc_batch_a", "gadgets"),
+                table("dbc_batch_a", "missing"),
+            ],
+        )
+        .await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let column = |name: &str, data_type: &str| ColumnInfo {
+            column_name: name.to_owned(),
+            data_type: data_type.to_owned(),
+            description: None,
+        };
+        let grouped = |schema: &str, table: &str, columns| TableColumns {
+            schema: schema.to_owned(),
+            table: table.to_owned(),
+            columns,
+        };
+        assert_eq!(
+            tables.unwrap(),
+            [
+                grouped("dbc_batch_b", "widgets", vec![column("sku", "text")]),
+                grouped(
+                    "dbc_batch_a",
+                    "widgets",
+                    vec![
+                        column("id", "integer"),
+                        ColumnInfo {
+                            description: Some("display name".to_owned()),
+                            ..column("name", "text")
+                        }
+                    ]
+                ),
+                grouped("dbc_batch_a", "gadgets", vec![column("id", "integer")]),
+                grouped("dbc_batch_a", "missing", vec![]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn forces_utf8_client_encoding() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let client_encoding = async |conn: &Connection| {
+            conn.query_one("SHOW client_encoding", &[])
+                .await
+                .unwrap()
+                .get::<_, String>(0)
+        };
+
+        // (the test database may be `SQL_ASCII`, which is warned about regardless)
+        let warns_about_client_encoding = |conn: &Connection| {
+            conn.encoding_warning()
+                .is_some_and(|warning| warning.contains("client_encoding"))
+        };
+
+        config.session_setup = vec!["SET client_encoding = 'LATIN1'".to_owned()];
+        let conn = connect(&config).await.unwrap();
+        assert_eq!(client_encoding(&conn).await, "UTF8");
+        assert!(!warns_about_client_encoding(&conn));
+
+        config.force_utf8 = false;
+        let conn = connect(&config).await.unwrap();
+        assert_eq!(client_encoding(&conn).await, "LATIN1");
+        assert!(
+            conn.encoding_warning()
+                .unwrap()
+                .starts_with("client_encoding is LATIN1 rather than UTF8")
+        );
+        assert!(warns_about_client_encoding(&conn));
+    }
+
+    #[tokio::test]
+    async fn warns_about_sql_ascii_databases() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let admin = connect(&config).await.unwrap();
+        // these can't be run in a (multi-statement) transaction block
+        admin
+            .batch_execute("DROP DATABASE IF EXISTS dbc_sql_ascii")
+            .await
+            .unwrap();
+        admin
+            .batch_execute(
+                "CREATE DATABASE dbc_sql_ascii \
+                 ENCODING 'SQL_ASCII' LC_COLLATE 'C' LC_CTYPE 'C' TEMPLATE template0",
+            )
+            .await
+            .unwrap();
+
+        config.database = "dbc_sql_ascii".to_owned();
+        let conn = connect(&config).await.unwrap();
+        assert!(
+            conn.encoding_warning()
+                .unwrap()
+                .starts_with("the database's encoding is SQL_ASCII")
+        );
+        drop(conn);
+
+        admin
+            .batch_execute("DROP DATABASE dbc_sql_ascii WITH (FORCE)")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_host() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let reachable = format!("{}:{}", config.host, config.port);
+        // nothing listens on port 1, so the first host refuses the connection
+        config.host = format!("127.0.0.1:1,{reachable}");
+        let conn = connect(&config).await.unwrap();
+        assert_eq!(conn.host(), reachable);
+        conn.batch_execute("SELECT 1").await.unwrap();
+
+        // the test server is a primary, so it's skipped when looking for a standby
+        config.target_session_attrs = TargetSessionAttrs::ReadOnly;
+        let err = connect(&config).await.err().unwrap().to_string();
+        assert!(
+            err.starts_with("couldn't connect to any host: 127.0.0.1:1: "),
+            "{err}"
+        );
+        assert!(err.contains(&format!("; {reachable}: ")), "{err}");
+    }
+
+    #[test]
+    fn parses_host_lists() {
+        assert_eq!(
+            parse_hosts("db1:5433, db2,[::1]:6432,::1", 5432).unwrap(),
+            [
+                ("db1".to_owned(), 5433),
+                ("db2".to_owned(), 5432),
+                ("::1".to_owned(), 6432),
+                ("::1".to_owned(), 5432),
+            ]
+        );
+        assert!(parse_hosts(" , ", 5432).is_err());
+        assert!(parse_hosts("db1:http", 5432).is_err());
+    }
+
+    #[tokio::test]
+    async fn applies_connection_options() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.options = Some("-c timezone=Asia/Tokyo --application_name=dbc_options".to_owned());
+        let conn = connect(&config).await.unwrap();
+
+        let res = query(
+            &conn,
+            "SELECT current_setting('timezone'), current_setting('application_name')",
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.rows, [["Asia/Tokyo", "dbc_options"]]);
+    }
+
+    #[tokio::test]
+    async fn sends_notifications() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let _listener = listen(&config, "dbc_test_notify", tx).await.unwrap();
+        let conn = connect(&config).await.unwrap();
+
+        notify(&conn, "dbc_test_notify", "deployed").await.unwrap();
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let AsyncMessage::Notification(notification) = message else {
+            panic!("expected a notification, got {message:?}");
+        };
+        assert_eq!(notification.channel(), "dbc_test_notify");
+        assert_eq!(notification.payload(), "deployed");
+
+        // the largest payload Postgres accepts is sent, and anything larger is rejected
+        let max = "x".repeat(MAX_NOTIFY_PAYLOAD - 1);
+        notify(&conn, "dbc_test_notify", &max).await.unwrap();
+        let err = notify(&conn, "dbc_test_notify", &format!("{max}x"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "notification payload is 8000 bytes long, but must be shorter than 8000 bytes"
+        );
+
+        for channel in ["", "dbc\ntest", &"c".repeat(MAX_IDENTIFIER_LENGTH + 1)] {
+            let err = notify(&conn, channel, "").await.unwrap_err();
+            assert!(
+                err.to_string().starts_with("invalid notification channel"),
+                "{channel:?}: {err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_session_setup() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.session_setup = vec![
+            "SET timezone = 'UTC'".to_owned(),
+            "set statement_timeout = '30s'".to_owned(),
+            "SET search_path = dbc_missing, public".to_owned(),
+            "SELECT set_config('application_name', 'dbc_setup', false)".to_owned(),
+        ];
+        let conn = connect(&config).await.unwrap();
+
+        let res = query(
+            &conn,
+            "SELECT current_setting('timezone'), current_setting('statement_timeout'),
+                    current_setting('search_path'), current_setting('application_name')",
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            res.rows,
+            [["UTC", "30s", "dbc_missing, public", "dbc_setup"]]
+        );
+
+        // settings are re-applied after the session is reset
+        conn.reset_session(None, &config.session_setup, config.force_utf8)
+            .await
+            .unwrap();
+        let res = query(&conn, "SELECT current_setting('application_name')", &[])
+            .await
+            .unwrap();
+        assert_eq!(res.rows, [["dbc_setup"]]);
+
+        // a failing statement fails the connection
+        config.session_setup = vec!["SET dbc_missing_setting = 1".to_owned()];
+        let err = connect(&config).await.err().unwrap();
+        assert!(err.to_string().contains("dbc_missing_setting"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn looks_up_types_after_resetting_the_session() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        // looking up a type that isn't built in prepares tokio_postgres' own statements,
+        // which have to survive the reset for the next lookup
+        conn.batch_execute("CREATE TYPE pg_temp.dbc_mood AS ENUM ('happy', 'sad')")
+            .await
+            .unwrap();
+        query(&conn, "SELECT 'happy'::pg_temp.dbc_mood", &[])
+            .await
+            .unwrap();
+
+        conn.reset_session(None, &config.session_setup, config.force_utf8)
+            .await
+            .unwrap();
+        conn.batch_execute("CREATE TYPE pg_temp.dbc_size AS ENUM ('small', 'large')")
+            .await
+            .unwrap();
+        let res = query(&conn, "SELECT 'large'::pg_temp.dbc_size", &[])
+            .await
+            .unwrap();
+        assert_eq!(res.rows, [["large"]]);
+    }
+
+    #[test]
+    fn validates_session_setup() {
+        for statement in [
+            "SET timezone = 'UTC'",
+            "  set local_preload_libraries = ''",
+            "SELECT set_config('search_path', 'app, public', false);",
+            "select set_config('dbc.note', 'a) (b', false)",
+        ] {
+            assert!(validate_session_setup(statement).is_ok(), "{statement:?}");
+        }
+        for statement in [
+            "",
+            "RESET ALL",
+            "SELECT 1",
+            "SELECT set_config('a.b', 'c', false), pg_sleep(10)",
+            "SET timezone = 'UTC'; DROP TABLE users",
+            "DROP TABLE users; SET timezone = 'UTC'",
+        ] {
+            let err = validate_session_setup(statement).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("invalid session setup statement"),
+                "{statement:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_connection_options() {
+        for options in [
+            "",
+            "-c timezone=UTC",
+            "-ctimezone=UTC  --statement_timeout=30s",
+            "-c pg_stat_statements.track=all",
+        ] {
+            assert!(validate_options(options).is_ok(), "{options:?}");
+        }
+        for options in [
+            "target_session_attrs=read-write",
+            "-c timezone=UTC host=evil.example.com",
+            "-c",
+            "-c =UTC",
+            "-c 'timezone=UTC'",
+            "-d 5",
+        ] {
+            let err = validate_options(options).unwrap_err();
+            assert!(
+                err.to_string().starts_with("invalid connection option"),
+                "{options:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_object_names() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        // catalog changes are visible inside the transaction, and rolled back after
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_search;
+             CREATE TABLE dbc_search.widgets (id int, widget_name text);
+             CREATE TABLE dbc_search.old_widgets (id int);",
+        )
+        .await
+        .unwrap();
+
+        let results = search_objects(&conn, "WIDG", Some("dbc_search"), 10).await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let result = |kind: &str, name: &str, parent: Option<&str>| SearchResult {
+            kind: kind.to_owned(),
+            schema: "dbc_search".to_owned(),
+            name: name.to_owned(),
+            parent: parent.map(str::to_owned),
+        };
+        assert_eq!(
+            results.unwrap(),
+            vec![
+                result("table", "widgets", None),
+                result("column", "widget_name", Some("widgets")),
+                result("table", "old_widgets", None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn prepares_batches_of_queries() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        let queries = [
+            "SELECT 1 AS one, $1::text AS name",
+            "SELEC 1",
+            "SELECT * FROM dbc_missing_table",
+            "SELECT now()",
+        ]
+        .map(str::to_owned);
+        let results = prepare_batch(&conn, &queries).await;
+
+        assert_eq!(results.len(), 4);
+        match &results[0] {
+            PrepareResult::Ok { columns, params } => {
+                let names = columns
+                    .iter()
+                    .map(|col| col.name.as_str())
+                    .collect::<Vec<_>>();
+                assert_eq!(names, ["one", "name"]);
+                assert_eq!(
+                    params,
+                    &[PreparedParam {
+                        name: "$1".to_owned(),
+                        type_: "text".to_owned(),
+                    }]
+                );
+            }
+            res => panic!("expected the first query to prepare, got {res:?}"),
+        }
+        match &results[1] {
+            PrepareResult::Error { code, position, .. } => {
+                assert_eq!(code.as_deref(), Some("42601"));
+                assert_eq!(*position, Some(1));
+            }
+            res => panic!("expected a syntax error, got {res:?}"),
+        }
+        match &results[2] {
+            PrepareResult::Error { code, message, .. } => {
+                assert_eq!(code.as_deref(), Some("42P01"));
+                assert!(message.contains("dbc_missing_table"), "{message}");
+            }
+            res => panic!("expected a missing table error, got {res:?}"),
+        }
+        // later queries are still prepared after an error
+        assert!(matches!(results[3], PrepareResult::Ok { .. }));
+    }
+
+    #[tokio::test]
+    async fn text_fallback_keeps_values_in_their_columns() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        // `interval` can't be decoded using BINARY encoding, so the whole query falls
+        // back on TEXT encoding
+        let result = query(
+            &conn,
+            "SELECT 1 AS id, NULL::text AS missing, interval '1 day' AS span, 'x' AS name",
+            &[],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.encoding, ResultEncoding::Text);
+        let names = result
+            .columns
+            .iter()
+            .map(|col| col.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["id", "missing", "span", "name"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                serde_json::json!("1"),
+                serde_json::Value::Null,
+                serde_json::json!("1 day"),
+                serde_json::json!("x"),
+            ]]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_tables_by_name_and_kind() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_filter;
+             CREATE TABLE dbc_filter.users (id int);
+             CREATE TABLE dbc_filter.user_roles (id int);
+             CREATE TABLE dbc_filter.orders (id int);
+             CREATE VIEW dbc_filter.user_names AS SELECT id FROM dbc_filter.users;
+             CREATE MATERIALIZED VIEW dbc_filter.user_counts AS SELECT COUNT(*) FROM dbc_filter.users;",
+        )
+        .await
+        .unwrap();
+
+        let list = |name_like: Option<&str>, kind: Option<TableKind>| {
+            let conn = &conn;
+            let filter = TableFilter {
+                name_like: name_like.map(str::to_owned),
+                kind,
+            };
+            async move {
+                list_tables(conn, "dbc_filter", &filter, None, false)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|row| {
+                        let name = row["table_name"].as_str().unwrap();
+                        format!("{} {name}", row["type"].as_str().unwrap())
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        let all = list(None, None).await;
+        let by_prefix = list(Some("USER%"), None).await;
+        let by_kind = list(None, Some(TableKind::Table)).await;
+        let by_both = list(Some("user%"), Some(TableKind::MaterializedView)).await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        assert_eq!(
+            all,
+            [
+                "table orders",
+                "table user_roles",
+                "table users",
+                "view user_names",
+                "materialized_view user_counts",
+            ]
+        );
+        assert_eq!(
+            by_prefix,
+            [
+                "table user_roles",
+                "table users",
+                "view user_names",
+                "materialized_view user_counts",
+            ]
+        );
+        assert_eq!(by_kind, ["table orders", "table user_roles", "table users"]);
+        assert_eq!(by_both, ["materialized_view user_counts"]);
+    }
+
+    #[tokio::test]
+    async fn reports_materialized_view_sizes() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_sizes;
+             CREATE TABLE dbc_sizes.orders AS SELECT n FROM generate_series(1, 1000) n;
+             CREATE VIEW dbc_sizes.recent AS SELECT n FROM dbc_sizes.orders;
+             CREATE MATERIALIZED VIEW dbc_sizes.totals AS SELECT n FROM dbc_sizes.orders;",
+        )
+        .await
+        .unwrap();
+        let rows = list_tables(&conn, "dbc_sizes", &TableFilter::default(), None, true)
+            .await
+            .unwrap();
+        let size: i64 = conn
+            .query_one("SELECT pg_total_relation_size('dbc_sizes.totals')", &[])
+            .await
+            .unwrap()
+            .get(0);
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let row = |name: &str| rows.iter().find(|row| row["table_name"] == name).unwrap();
+        // materialized views have storage, unlike regular views
+        let totals = row("totals");
+        assert!(size > 0);
+        assert_eq!(totals["table_size"], size);
+        assert!(
+            totals["table_size_pretty"]
+                .as_str()
+                .is_some_and(|pretty| pretty.ends_with("kB")),
+            "{totals:?}"
+        );
+        let recent = row("recent");
+        assert_eq!(recent["table_size"], -1);
+        assert_eq!(recent["table_size_pretty"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn orders_tables_by_size() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_sizes;
+             CREATE TABLE dbc_sizes.a_small (id int);
+             CREATE TABLE dbc_sizes.b_large (id int);
+             CREATE TABLE dbc_sizes.c_medium (id int);
+             CREATE VIEW dbc_sizes.d_view AS SELECT id FROM dbc_sizes.b_large;
+             INSERT INTO dbc_sizes.b_large SELECT generate_series(1, 10000);
+             INSERT INTO dbc_sizes.c_medium SELECT generate_series(1, 1000);",
+        )
+        .await
+        .unwrap();
+
+        let list = |order| {
+            let conn = &conn;
+            async move {
+                list_tables(conn, "dbc_sizes", &TableFilter::default(), order, false)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|row| row["table_name"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>()
+            }
+        };
+        let by_size = list(Some((TableOrder::Size, SortDirection::Desc))).await;
+        let by_name = list(Some((TableOrder::Name, SortDirection::Desc))).await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        // views have no size, so they come last
+        assert_eq!(by_size, ["b_large", "c_medium", "a_small", "d_view"]);
+        assert_eq!(by_name, ["d_view", "c_medium", "b_large", "a_small"]);
+    }
+
+    #[tokio::test]
+    async fn lists_object_comments() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        // catalog changes are visible inside the transaction, and rolled back after
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_comments;
+             COMMENT ON SCHEMA dbc_comments IS 'schema comment';
+             CREATE TABLE dbc_comments.widgets (id int, name text);
+             COMMENT ON TABLE dbc_comments.widgets IS 'table comment';
+             COMMENT ON COLUMN dbc_comments.widgets.name IS 'column comment';
+             CREATE VIEW dbc_comments.widget_names AS SELECT name FROM dbc_comments.widgets;",
+        )
+        .await
+        .unwrap();
+
+        let schemas = list_schemas(&conn).await;
+        let tables = list_tables(&conn, "dbc_comments", &TableFilter::default(), None, false).await;
+        let columns = list_columns(&conn, "dbc_comments", "widgets").await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let schemas = schemas.unwrap().row_maps();
+        let schema = schemas
+            .iter()
+            .find(|row| row["schema_name"] == "dbc_comments")
+            .unwrap();
+        assert_eq!(schema["description"], "schema comment");
+
+        let description = |rows: &QueryRows, key: &str, name: &str| {
+            rows.iter().find(|row| row[key] == name).unwrap()["description"].clone()
+        };
+        let tables = tables.unwrap();
+        assert_eq!(
+            description(&tables, "table_name", "widgets"),
+            "table comment"
+        );
+        assert_eq!(
+            description(&tables, "table_name", "widget_names"),
+            serde_json::Value::Null
+        );
+
+        let columns = columns.unwrap().row_maps();
+        assert_eq!(
+            description(&columns, "column_name", "name"),
+            "column comment"
+        );
+        assert_eq!(
+            description(&columns, "column_name", "id"),
+            serde_json::Value::Null
+        );
+    }
+
+    const COLUMNS: [(&str, &Type); 3] = [
+        ("id", &Type::INT4),
+        ("area", &Type::POLYGON),
+        ("name", &Type::TEXT),
+    ];
+
+    #[test]
+    fn query_policy_blocks_and_allows_queries() {
+        let policy = QueryPolicy {
+            allow: vec![r"^\s*select\b".to_owned()],
+            deny: vec![r"pg_sleep".to_owned()],
+        }
+        .compile()
+        .unwrap();
+
+        assert!(policy.check("SELECT * FROM users").is_ok());
+
+        let err = policy.check("SELECT pg_sleep(10)").unwrap_err();
+        assert!(err.to_string().contains("denied pattern `pg_sleep`"));
+
+        let err = policy.check("DROP DATABASE prod").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r"allowed pattern (`^\s*select\b`)")
+        );
+
+        // no patterns allows everything
+        assert!(
+            QueryPolicy::default()
+                .compile()
+                .unwrap()
+                .check("DROP DATABASE prod")
+                .is_ok()
+        );
+        assert!(
+            QueryPolicy {
+                deny: vec!["(".to_owned()],
+                ..Default::default()
+            }
+            .compile()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn classifies_command_statements() {
+        assert_eq!(
+            query_type("SET statement_timeout = '5s'"),
+            QueryType::Command
+        );
+        assert_eq!(query_type("\n  listen events;"), QueryType::Command);
+        assert_eq!(
+            query_type("DO $$ BEGIN UPDATE t SET x = 1; END $$"),
+            QueryType::Command
+        );
+        assert_eq!(query_type("UPDATE t SET x = 1"), QueryType::ModifyData);
+        assert_eq!(
+            query_type("INSERT INTO t VALUES (1) ON CONFLICT DO NOTHING"),
+            QueryType::ModifyData
+        );
+        assert_eq!(query_type("CALL refresh_totals($1)"), QueryType::Call);
+        assert!(!is_read_only("call refresh_totals()"));
+
+        assert_eq!(command_tag("set search_path = public"), "SET");
+        assert_eq!(command_tag("listen events;"), "LISTEN");
+        assert_eq!(command_tag("discard all;"), "DISCARD ALL");
+    }
+
+    #[test]
+    fn classifies_maintenance_statements_by_their_leading_keyword() {
+        assert_eq!(
+            query_type("VACUUM ANALYZE users"),
+            QueryType::ModifyStructure
+        );
+        assert_eq!(query_type("\n  cluster users;"), QueryType::ModifyStructure);
+        assert_eq!(
+            query_type("SELECT cluster, analyze FROM nodes"),
+            QueryType::Select
+        );
+        assert_eq!(query_type("EXPLAIN ANALYZE SELECT 1"), QueryType::Explain);
+        assert_eq!(
+            query_type("UPDATE nodes SET cluster = 'b'"),
+            QueryType::ModifyData
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_command_statements() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let run = |sql: &'static str| {
+            let conn = &conn;
+            async move {
+                paginated_query(conn, sql, &[], &QueryOptions::default())
+                    .await
+                    .map(|result| serde_json::to_value(result).unwrap())
+            }
+        };
+
+        assert_eq!(
+            run("SET statement_timeout = '42s'").await.unwrap(),
+            serde_json::json!({ "type": "command", "message": "SET" })
+        );
+        let timeout: String = conn
+            .query_one("SHOW statement_timeout", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(timeout, "42s");
+
+        assert_eq!(
+            run("LISTEN dbc_test_events").await.unwrap(),
+            serde_json::json!({ "type": "command", "message": "LISTEN" })
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_procedures() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE TEMP TABLE dbc_calls (n int);
+             CREATE PROCEDURE pg_temp.dbc_record(n int)
+                 LANGUAGE sql AS $$ INSERT INTO dbc_calls VALUES (n) $$;
+             CREATE PROCEDURE pg_temp.dbc_double(INOUT n int)
+                 LANGUAGE plpgsql AS $$ BEGIN n := n * 2; END $$;",
+        )
+        .await
+        .unwrap();
+
+        let result = paginated_query(
+            &conn,
+            "CALL pg_temp.dbc_record($1)",
+            &[serde_json::json!(7)],
+            &QueryOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(result).unwrap(),
+            serde_json::json!({ "type": "command", "message": "CALL" })
+        );
+        let n: i32 = conn
+            .query_one("SELECT n FROM dbc_calls", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 7);
+
+        // output arguments come back as a single row
+        let result = paginated_query(
+            &conn,
+            "CALL pg_temp.dbc_double(21)",
+            &[],
+            &QueryOptions::default(),
+        )
+        .await
+        .unwrap();
+        let PaginatedQueryResult::Command {
+            output: Some(output),
+            ..
+        } = result
+        else {
+            panic!("expected the procedure's output, got {result:?}");
+        };
+        assert_eq!(output.columns[0].name, "n");
+        assert_eq!(output.rows, vec![vec![serde_json::json!(42)]]);
+
+        conn.batch_execute("ROLLBACK").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn returns_null_for_void_results() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE FUNCTION pg_temp.dbc_nothing() RETURNS void
+                 LANGUAGE sql AS $$ SELECT $$;",
+        )
+        .await
+        .unwrap();
+
+        let result = paginated_query(
+            &conn,
+            "SELECT pg_temp.dbc_nothing(), NULL::void AS missing",
+            &[],
+            &QueryOptions {
+                page: 1,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let PaginatedQueryResult::Select { entries, .. } = result else {
+            panic!("expected a select result");
+        };
+        assert_eq!(entries.columns[0].type_, "void");
+        assert_eq!(entries.columns[0].unsupported_type, None);
+        assert_eq!(
+            entries.rows,
+            vec![vec![serde_json::Value::Null, serde_json::Value::Null]]
+        );
+
+        conn.batch_execute("ROLLBACK").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retries_queries_once_the_connection_is_lost() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let admin = connect(&config).await.unwrap();
+
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let attempt = || async {
+            let conn = connect(&config).await?;
+            if attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                // terminate this connection's backend from another session
+                let pid: i32 = conn.query_one("SELECT pg_backend_pid()", &[]).await?.get(0);
+                admin
+                    .execute("SELECT pg_terminate_backend($1)", &[&pid])
+                    .await?;
+            }
+            query(&conn, "SELECT 1 AS one", &[]).await
+        };
+
+        let result = retry_if_disconnected(true, attempt).await.unwrap();
+        assert_eq!(result.rows, vec![vec![serde_json::json!(1)]]);
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // without `retry`, the error is surfaced
+        attempts.store(0, std::sync::atomic::Ordering::SeqCst);
+        let err = retry_if_disconnected(false, attempt).await.unwrap_err();
+        assert!(is_disconnect(&err), "{err}");
+    }
+
+    #[test]
+    fn rejects_a_page_size_of_zero() {
+        let options = |page_size: i64| {
+            serde_json::from_value::<QueryOptions>(
+                serde_json::json!({ "page": 1, "page_size": page_size }),
+            )
+        };
+        assert_eq!(options(-1).unwrap().page_size, Some(-1));
+        assert_eq!(options(10).unwrap().page_size, Some(10));
+        let err = options(0).unwrap_err();
+        assert!(err.to_string().starts_with("invalid page_size 0"), "{err}");
+
+        let options = serde_json::from_value::<QueryOptions>(serde_json::json!({ "page": 1 }));
+        assert_eq!(options.unwrap().page_size, None);
+    }
+
+    #[test]
+    fn clamp_page_size_applies_to_unlimited_queries() {
+        assert_eq!(clamp_page_size("select * from t", 50, 100), (50, None));
+        assert_eq!(
+            clamp_page_size("select * from t", 500, 100),
+            (100, Some(500))
+        );
+        assert_eq!(clamp_page_size("select * from t", -1, 100), (100, Some(-1)));
+
+        // queries that limit themselves are left alone
+        assert_eq!(
+            clamp_page_size("select * from t limit 500", 500, 100),
+            (500, None)
+        );
+        assert_eq!(
+            clamp_page_size("select * from t\nfetch first 500 rows only", -1, 100),
+            (-1, None)
+        );
+        assert_eq!(
+            clamp_page_size(
+                "with top as (select 1 limit 1) select * from t, top",
+                -1,
+                100
+            ),
+            (100, Some(-1))
+        );
+
+        // a limit in a subquery or a string doesn't limit the query itself
+        for query in [
+            "select * from t where id in (select id from u limit 5)",
+            "select * from t where note = 'no limit'",
+            "select * from t where note = E'it\\'s ( limit'",
+            "select $$limit$$, \"limit\" from t",
+        ] {
+            assert_eq!(clamp_page_size(query, -1, 100), (100, Some(-1)), "{query}");
+        }
+        assert_eq!(
+            clamp_page_size("(select * from t) limit 5", -1, 100),
+            (-1, None)
+        );
+    }
+
+    #[test]
+    fn destructive_keyword_detects_drop_and_truncate() {
+        assert_eq!(destructive_keyword("DROP TABLE users"), Some("DROP"));
+        assert_eq!(destructive_keyword("truncate\nusers"), Some("TRUNCATE"));
+        assert_eq!(
+            destructive_keyword("ALTER TABLE users DROP COLUMN email"),
+            Some("DROP")
+        );
+        assert_eq!(destructive_keyword("CREATE TABLE drops (id int)"), None);
+        assert_eq!(
+            destructive_keyword("DO $$ BEGIN DROP TABLE users; END $$"),
+            Some("DROP")
+        );
+        assert_eq!(
+            destructive_keyword("do $$begin execute 'truncate users'; end$$;"),
+            Some("TRUNCATE")
+        );
+    }
+
+    #[test]
+    fn locking_clause_detects_row_locks() {
+        assert_eq!(
+            locking_clause("SELECT * FROM jobs LIMIT 1 FOR UPDATE SKIP LOCKED"),
+            Some("FOR UPDATE")
+        );
+        assert_eq!(
+            locking_clause("select * from jobs\nfor no key update;"),
+            Some("FOR NO KEY UPDATE")
+        );
+        assert_eq!(locking_clause("SELECT 1 FOR SHARE"), Some("FOR SHARE"));
+        assert_eq!(
+            locking_clause("SELECT 1 FOR KEY SHARE"),
+            Some("FOR KEY SHARE")
+        );
+        assert_eq!(locking_clause("SELECT * FROM for_update"), None);
+        assert!(!is_read_only("SELECT * FROM jobs FOR UPDATE"));
+        assert!(is_read_only("SELECT * FROM jobs"));
+        assert_eq!(
+            query_type("SELECT * FROM jobs FOR NO KEY UPDATE"),
+            QueryType::Select
+        );
+        assert_eq!(
+            query_type("UPDATE jobs SET n = 1 WHERE n = 0"),
+            QueryType::ModifyData
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_or_invalid_root_certs() {
+        let config = |ssl_root_cert: &str| {
+            Config::builder()
+                .username("postgres".to_owned())
+                .password(String::new())
+                .database("postgres".to_owned())
+                .ssl(true)
+                .ssl_root_cert(ssl_root_cert.into())
+                .build()
+        };
+
+        let err = connect(&config("/nonexistent/root.crt"))
+            .await
+            .map(drop)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("couldn't read SSL root certificate /nonexistent/root.crt"),
+            "{err}"
+        );
+
+        let path = std::env::temp_dir().join(format!("dbc-bogus-{}.crt", std::process::id()));
+        std::fs::write(&path, "not a certificate").unwrap();
+        let err = connect(&config(path.to_str().unwrap()))
+            .await
+            .map(drop)
+            .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            err.to_string()
+                .contains("doesn't contain any PEM certificates"),
+            "{err}"
+        );
+
+        let err = connect(&Config {
+            ssl: false,
+            ..config("/nonexistent/root.crt")
+        })
+        .await
+        .map(drop)
+        .unwrap_err();
+        assert!(err.to_string().contains("requires SSL"), "{err}");
+    }
+
+    #[test]
+    fn finds_explained_statements() {
+        assert_eq!(
+            explained_statement("EXPLAIN SELECT * FROM users;"),
+            Some("SELECT * FROM users")
+        );
+        assert_eq!(
+            explained_statement("explain verbose\nselect 1"),
+            Some("select 1")
+        );
+        assert_eq!(
+            explained_statement("EXPLAIN (FORMAT JSON, COSTS off) SELECT 1"),
+            Some("SELECT 1")
+        );
+        assert_eq!(
+            explained_statement("EXPLAIN (ANALYZE false) SELECT 1"),
+            Some("SELECT 1")
+        );
+        assert_eq!(explained_statement("EXPLAIN ANALYZE SELECT 1"), None);
+        assert_eq!(
+            explained_statement("EXPLAIN (ANALYZE, BUFFERS) SELECT 1"),
+            None
+        );
+        assert_eq!(explained_statement("SELECT 1"), None);
+    }
+
+    #[tokio::test]
+    async fn explain_includes_sample_rows_when_requested() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        let explain = |with_sample: bool| {
+            let options = QueryOptions {
+                page: 1,
+                page_size: Some(10),
+                with_sample,
+                ..Default::default()
+            };
+            let conn = &conn;
+            async move {
+                paginated_query(
+                    conn,
+                    "EXPLAIN SELECT n FROM generate_series(1, $1::int) n",
+                    &[serde_json::json!(100)],
+                    &options,
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        match explain(true).await {
+            PaginatedQueryResult::Explain { plan, sample, .. } => {
+                assert!(plan.contains("Function Scan"), "{plan}");
+                let sample = sample.expect("sample rows");
+                assert_eq!(sample.columns[0].name, "n");
+                assert_eq!(
+                    sample.rows,
+                    (1..=EXPLAIN_SAMPLE_ROWS)
+                        .map(|n| vec![serde_json::json!(n)])
+                        .collect::<Vec<_>>()
+                );
+            }
+            res => panic!("expected an explain result, got {res:?}"),
+        }
+
+        // without `with_sample`, the query isn't run
+        match explain(false).await {
+            PaginatedQueryResult::Explain { sample, .. } => assert!(sample.is_none()),
+            res => panic!("expected an explain result, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn channel_binding_requires_ssl() {
+        assert!(ChannelBinding::Require.resolve(false).is_err());
+        assert!(ChannelBinding::Require.resolve(true).is_ok());
+        assert!(ChannelBinding::Prefer.resolve(false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_require_channel_binding_without_ssl() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.ssl = false;
+        config.channel_binding = ChannelBinding::Require;
+        let err = connect(&config).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "channel binding requires SSL; enable `ssl` for this connection \
+             or set `channel_binding` to \"prefer\""
+        );
+
+        // preferring it falls back to connecting without it
+        config.channel_binding = ChannelBinding::Prefer;
+        connect(&config).await.unwrap();
+    }
+
+    #[test]
+    fn invalidation_is_scoped_by_payload() {
+        assert_eq!(Invalidation::from_payload(""), Invalidation::All);
+        assert_eq!(Invalidation::from_payload("  "), Invalidation::All);
+        assert_eq!(
+            Invalidation::from_payload("users"),
+            Invalidation::Table("users".to_owned())
+        );
+    }
+
+    #[test]
+    fn begin_sql_includes_isolation_level() {
+        assert_eq!(IsolationLevel::begin_sql(None), "BEGIN");
+        assert_eq!(
+            IsolationLevel::begin_sql(Some(IsolationLevel::Serializable)),
+            "BEGIN ISOLATION LEVEL SERIALIZABLE"
+        );
+    }
+
+    #[test]
+    fn diff_table_structures_alters_columns_and_indexes() {
+        let keywords = ReservedKeywords::default();
+        let col = |name: &str, data_type: &str, nullable: bool| ColumnDef {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+            nullable,
+            default: None,
+        };
+
+        let source = TableStructure {
+            columns: vec![
+                col("id", "integer", false),
+                col("name", "character varying(50)", true),
+                col("legacy", "text", true),
+            ],
+            primary_key: vec!["id".to_owned()],
+            primary_key_name: Some("users_pkey".to_owned()),
+            indexes: vec![IndexDef {
+                name: "users_legacy_idx".to_owned(),
+                def: "CREATE INDEX users_legacy_idx ON public.users USING btree (legacy)"
+                    .to_owned(),
+            }],
+        };
+
+        let target = TableStructure {
+            columns: vec![
+                col("id", "integer", false),
+                col("name", "text", false),
+                ColumnDef {
+                    default: Some("now()".to_owned()),
+                    ..col("created_at", "timestamp without time zone", false)
+                },
+            ],
+            primary_key: vec!["id".to_owned()],
+            primary_key_name: Some("users_v2_pkey".to_owned()),
+            indexes: vec![IndexDef {
+                name: "users_v2_name_idx".to_owned(),
+                def: "CREATE INDEX users_v2_name_idx ON app.users_v2 USING btree (name)".to_owned(),
+            }],
+        };
+
+        assert_eq!(
+            diff_table_structures(
+                ("public", "users"),
+                &source,
+                ("app", "users_v2"),
+                &target,
+                (&keywords, &keywords)
+            ),
+            vec![
+                "ALTER TABLE public.users DROP COLUMN legacy;",
+                "ALTER TABLE public.users ALTER COLUMN name TYPE text;",
+                "ALTER TABLE public.users ALTER COLUMN name SET NOT NULL;",
+                "ALTER TABLE public.users ADD COLUMN created_at timestamp without time zone \
+                 NOT NULL DEFAULT now();",
+                "DROP INDEX public.users_legacy_idx;",
+                "CREATE INDEX users_v2_name_idx ON public.users USING btree (name);",
+            ]
+        );
+
+        assert!(
+            diff_table_structures(
+                ("a", "t"),
+                &source,
+                ("b", "t"),
+                &source,
+                (&keywords, &keywords)
+            )
+            .is_empty()
+        );
+
+        // a changed primary key is dropped, then added back once its columns exist
+        let target = TableStructure {
+            primary_key: vec!["id".to_owned(), "created_at".to_owned()],
+            ..target
+        };
+        let statements = diff_table_structures(
+            ("public", "users"),
+            &source,
+            ("app", "users_v2"),
+            &target,
+            (&keywords, &keywords),
+        );
+        assert_eq!(
+            statements[0],
+            "ALTER TABLE public.users DROP CONSTRAINT users_pkey;"
+        );
+        assert_eq!(
+            statements[5],
+            "ALTER TABLE public.users ADD PRIMARY KEY (id, created_at);"
+        );
+        let without_primary_key = TableStructure {
+            primary_key: Vec::new(),
+            primary_key_name: None,
+            columns: target.columns.clone(),
+            indexes: Vec::new(),
+        };
+        assert_eq!(
+            diff_table_structures(
+                ("public", "users"),
+                &without_primary_key,
+                ("app", "users_v2"),
+                &target,
+                (&keywords, &keywords)
+            ),
+            [
+                "ALTER TABLE public.users ADD PRIMARY KEY (id, created_at);",
+                "CREATE INDEX users_v2_name_idx ON public.users USING btree (name);",
+            ]
+        );
+    }
+
+    #[test]
+    fn size_columns_are_optionally_human_readable() {
+        assert_eq!(
+            size_columns(Some("c.oid"), "table_size", false),
+            "pg_total_relation_size(c.oid) as table_size"
+        );
+        assert_eq!(
+            size_columns(Some("c.oid"), "table_size", true),
+            "pg_total_relation_size(c.oid) as table_size,\n      \
+             pg_size_pretty(pg_total_relation_size(c.oid)) as table_size_pretty"
+        );
+        assert_eq!(
+            size_columns(None, "table_size", true),
+            "-1 as table_size,\n      null as table_size_pretty"
+        );
+    }
+
+    #[test]
+    fn text_fallback_uses_binary_when_supported() {
+        for mode in [
+            TextFallback::Strict,
+            TextFallback::Fallback,
+            TextFallback::Skip,
+        ] {
+            let (encoding, skipped) = mode.resolve([COLUMNS[0], COLUMNS[2]]).unwrap();
+            assert_eq!(encoding, ResultEncoding::Binary);
+            assert!(skipped.is_empty());
+        }
+    }
+
+    #[test]
+    fn text_fallback_strict_names_unsupported_columns() {
+        let err = TextFallback::Strict.resolve(COLUMNS).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported column type(s): \"area\" (polygon)"
+        );
+    }
+
+    #[test]
+    fn text_fallback_fallback_uses_text() {
+        let (encoding, skipped) = TextFallback::Fallback.resolve(COLUMNS).unwrap();
+        assert_eq!(encoding, ResultEncoding::Text);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn text_fallback_skip_flags_unsupported_columns() {
+        let (encoding, unsupported) = TextFallback::Skip.resolve(COLUMNS).unwrap();
+        assert_eq!(encoding, ResultEncoding::Partial);
+        assert_eq!(unsupported, vec![1]);
+
+        let columns = [("id", &Type::INT4), ("pairs", &Type::RECORD_ARRAY)];
+        let (_, unsupported) = TextFallback::Skip.resolve(columns).unwrap();
+        assert_eq!(unsupported, vec![1]);
+        assert_eq!(type_display_name(columns[1].1), "record[]");
+    }
+
+    #[tokio::test]
+    async fn reports_killed_connections_as_not_live() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let mut conn = connect(&config).await.unwrap();
+        assert!(conn.is_live());
+        // checking doesn't use up the connection
+        assert!(conn.is_live());
+
+        conn.kill();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while conn.is_live() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn text_fallback_skip_keeps_rows_aligned_with_columns() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.text_fallback = TextFallback::Skip;
+        let conn = connect(&config).await.unwrap();
+
+        let result = query(
+            &conn,
+            "SELECT 1::int4 AS a, '(0,1)'::tid AS p, 'x'::text AS b, NULL::int4 AS c, \
+             '(0,2)'::tid AS q, true AS d",
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.encoding, ResultEncoding::Partial);
+        let names = result.columns.iter().map(|col| col.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["a", "p", "b", "c", "q", "d"]);
+        let unsupported = result
+            .columns
+            .iter()
+            .map(|col| col.unsupported_type.as_deref());
+        assert_eq!(
+            unsupported.collect::<Vec<_>>(),
+            [None, Some("tid"), None, None, Some("tid"), None]
+        );
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                serde_json::json!(1),
+                serde_json::Value::Null,
+                serde_json::json!("x"),
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::json!(true),
+            ]]
+        );
+    }
+
+    #[test]
+    fn display_format_custom_timestamp() {
+        let formatter = DisplayFormat {
+            timestamp: Some("[day]/[month]/[year] [hour]:[minute]".to_owned()),
+            ..Default::default()
+        }
+        .compile()
+        .unwrap();
+        let ts = time::macros::datetime!(2024-03-09 14:30:15 UTC);
+        assert_eq!(
+            formatter.timestamp(ts, format_description!("[year]")),
+            serde_json::json!("09/03/2024 14:30")
+        );
+
+        let epoch = DisplayFormat {
+            timestamp: Some("epoch".to_owned()),
+            ..Default::default()
+        }
+        .compile()
+        .unwrap();
+        assert_eq!(
+            epoch.timestamp(ts, format_description!("[year]")),
+            serde_json::json!(1709994615)
+        );
+
+        assert!(
+            DisplayFormat {
+                date: Some("[hour]".to_owned()),
+                ..Default::default()
+            }
+            .compile()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn display_format_numeric_as_number() {
+        let val = Decimal::new(12345, 2);
+        assert_eq!(
+            Formatter::default().numeric(val),
+            serde_json::json!("123.45")
+        );
+
+        let formatter = DisplayFormat {
+            numeric: NumericDisplay::Number,
+            ..Default::default()
+        }
+        .compile()
+        .unwrap();
+        assert_eq!(formatter.numeric(val), serde_json::json!(123.45));
+    }
+
+    #[test]
+    fn display_format_safe_integers() {
+        let unsafe_int = MAX_SAFE_INTEGER + 2;
+        assert_eq!(
+            Formatter::default().int8(unsafe_int),
+            serde_json::json!(unsafe_int)
+        );
+
+        let formatter = DisplayFormat {
+            numeric: NumericDisplay::Number,
+            safe_integers: true,
+            ..Default::default()
+        }
+        .compile()
+        .unwrap();
+        assert_eq!(
+            formatter.int8(MAX_SAFE_INTEGER),
+            serde_json::json!(MAX_SAFE_INTEGER)
+        );
+        assert_eq!(
+            formatter.int8(unsafe_int),
+            serde_json::json!("9007199254740993")
+        );
+        assert_eq!(
+            formatter.int8(-unsafe_int),
+            serde_json::json!("-9007199254740993")
+        );
+        assert_eq!(
+            formatter.int8(i64::MIN),
+            serde_json::json!(i64::MIN.to_string())
+        );
+        assert_eq!(
+            formatter.numeric(Decimal::from(unsafe_int)),
+            serde_json::json!("9007199254740993")
+        );
+        assert_eq!(
+            formatter.numeric(Decimal::new(12345, 2)),
+            serde_json::json!(123.45)
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_unsafe_integers_as_strings() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.display_format.safe_integers = true;
+        let conn = connect(&config).await.unwrap();
+
+        let res = query(
+            &conn,
+            "SELECT 9007199254740993::int8, 42::int8, NULL::int8",
+            &[],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            res.rows,
+            [[
+                serde_json::json!("9007199254740993"),
+                serde_json::json!(42),
+                serde_json::Value::Null
+            ]]
+        );
+    }
+
+    #[test]
+    fn from_json_binds_oid() {
+        use tokio_postgres::types::private::BytesMut;
+
+        let param = from_json(&serde_json::json!(3_000_000_000u32), Type::OID).unwrap();
+        let mut buf = BytesMut::new();
+        param.to_sql_checked(&Type::OID, &mut buf).unwrap();
+        assert_eq!(&buf[..], 3_000_000_000u32.to_be_bytes());
+
+        assert!(from_json(&serde_json::json!(-1), Type::OID).is_err());
+    }
+
+    #[test]
+    fn from_json_binds_numeric_strings() {
+        use tokio_postgres::types::private::BytesMut;
+
+        let mut exact = BytesMut::new();
+        from_json(&serde_json::json!("12345678901234567890.5"), Type::NUMERIC)
+            .unwrap()
+            .to_sql_checked(&Type::NUMERIC, &mut exact)
+            .unwrap();
+        let mut expected = BytesMut::new();
+        "12345678901234567890.5"
+            .parse::<Decimal>()
+            .unwrap()
+            .to_sql_checked(&Type::NUMERIC, &mut expected)
+            .unwrap();
+        assert_eq!(exact, expected);
+
+        assert!(from_json(&serde_json::json!("twelve"), Type::NUMERIC).is_err());
+    }
+
+    #[tokio::test]
+    async fn from_json_binds_interval() {
+        let err = from_json(&serde_json::json!("1 fortnight"), Type::INTERVAL)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .starts_with("invalid interval \"1 fortnight\"")
+        );
+
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        let param = from_json(&serde_json::json!("1 day"), Type::INTERVAL).unwrap();
+        let row = conn
+            .query_one(
+                "SELECT $1 = '1 day'::interval, now() - $1 < now()",
+                &[param.as_ref() as SqlParam],
+            )
+            .await
+            .unwrap();
+        assert!(row.get::<_, bool>(0));
+        assert!(row.get::<_, bool>(1));
+    }
+
+    #[test]
+    fn from_json_rejects_out_of_range_int2() {
+        assert!(from_json(&serde_json::json!(32767), Type::INT2).is_ok());
+        let err = from_json(&serde_json::json!(32768), Type::INT2)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "integer 32768 out of range for type int2");
+    }
+
+    #[test]
+    fn stale_estimate_flags_unanalyzed_tables() {
+        // freshly created tables have an estimate of -1 until they're analyzed
+        assert!(stale_estimate_hint(-1.0, Some(0)).is_some());
+        assert!(stale_estimate_hint(-1.0, None).is_some());
+
+        assert!(stale_estimate_hint(0.0, Some(50_000)).is_some());
+        assert!(stale_estimate_hint(100.0, Some(120)).is_none());
+        assert!(stale_estimate_hint(0.0, Some(500)).is_none());
+    }
+
+    #[test]
+    fn window_total_is_removed_from_results() {
+        let column = |index: usize, name: &str| QueryResultColumn {
+            table_oid: None,
+            column_id: None,
+            name: name.to_owned(),
+            index,
+            type_: "int8".to_owned(),
+            unsupported_type: None,
+            extended: None,
+        };
+        let mut result = QueryResult {
+            columns: vec![column(0, "id"), column(1, WINDOW_TOTAL_COLUMN)],
+            rows: vec![
+                vec![serde_json::json!(1), serde_json::json!(42)],
+                vec![serde_json::json!(2), serde_json::json!(42)],
+            ],
+            encoding: ResultEncoding::Binary,
+        };
+
+        assert_eq!(take_window_total(&mut result).unwrap(), Some(42));
+        assert_eq!(result.columns.len(), 1);
+        assert_eq!(
+            result.rows,
+            vec![vec![serde_json::json!(1)], vec![serde_json::json!(2)]]
+        );
+
+        let mut empty = QueryResult {
+            columns: vec![column(0, "id"), column(1, WINDOW_TOTAL_COLUMN)],
+            rows: vec![],
+            encoding: ResultEncoding::Text,
+        };
+        assert_eq!(take_window_total(&mut empty).unwrap(), None);
+    }
+
+    #[test]
+    fn plan_rows_reads_the_top_node() {
+        let plan = serde_json::json!([{
+            "Plan": {
+                "Node Type": "Limit",
+                "Plan Rows": 250,
+                "Plans": [{ "Node Type": "Seq Scan", "Plan Rows": 1000 }],
+            },
+        }]);
+        assert_eq!(plan_rows(&plan), Some(250.0));
+        assert_eq!(plan_rows(&serde_json::json!([{ "Plan": {} }])), None);
+        assert_eq!(plan_rows(&serde_json::json!({})), None);
+    }
+
+    #[tokio::test]
+    async fn estimates_total_count_from_plan() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        let options = |page: usize| QueryOptions {
+            page,
+            page_size: Some(10),
+            count: CountMode::Estimate,
+            ..Default::default()
+        };
+        let run = |page: usize| {
+            let (conn, options) = (&conn, options(page));
+            async move {
+                let query = "SELECT n FROM generate_series(1, 25) n";
+                match paginated_query(conn, query, &[], &options).await.unwrap() {
+                    PaginatedQueryResult::Select {
+                        total_count,
+                        total_count_estimate,
+                        page_count,
+                        ..
+                    } => (total_count, total_count_estimate, page_count),
+                    res => panic!("expected a select result, got {res:?}"),
+                }
+            }
+        };
+
+        // `generate_series` with constant bounds is estimated exactly
+        assert_eq!(run(1).await, (25, Some(25), 10));
+        // the last (partial) page pins down the exact total
+        assert_eq!(run(3).await, (25, Some(25), 5));
+    }
+
+    #[tokio::test]
+    async fn jumps_to_the_last_page_when_the_estimate_overshoots() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let run = |page: usize| {
+            let conn = &conn;
+            let options = QueryOptions {
+                page,
+                page_size: Some(10),
+                count: CountMode::Estimate,
+                ..Default::default()
+            };
+            async move {
+                // the filter's selectivity is guessed, so the estimate is far too high
+                let query = "SELECT n FROM generate_series(1, 1000) n WHERE n <= 25";
+                match paginated_query(conn, query, &[], &options).await.unwrap() {
+                    PaginatedQueryResult::Select {
+                        page,
+                        requested_page,
+                        total_count,
+                        total_count_estimate,
+                        has_next,
+                        entries,
+                        ..
+                    } => {
+                        assert!(total_count_estimate.unwrap() > 100);
+                        let first = entries.rows.first().map(|row| row[0].clone());
+                        (page, requested_page, total_count, has_next, first)
+                    }
+                    res => panic!("expected a select result, got {res:?}"),
+                }
+            }
+        };
+
+        // the actual last page is found without a jump
+        assert_eq!(
+            run(3).await,
+            (3, None, 25, false, Some(serde_json::json!(21)))
+        );
+        // jumping to the estimated last page overshoots, so the actual last page is
+        // shown instead
+        assert_eq!(
+            run(10).await,
+            (3, Some(10), 25, false, Some(serde_json::json!(21)))
+        );
+        assert_eq!(
+            run(4).await,
+            (3, Some(4), 25, false, Some(serde_json::json!(21)))
+        );
+        // pages before the end aren't affected by the estimate
+        let (page, requested_page, _, has_next, first) = run(2).await;
+        assert_eq!(
+            (page, requested_page, has_next, first),
+            (2, None, true, Some(serde_json::json!(11)))
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_adjacent_pages() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let run = |query: &'static str, page: usize, page_size: isize, count: CountMode| {
+            let conn = &conn;
+            let options = QueryOptions {
+                page,
+                page_size: Some(page_size),
+                count,
+                ..Default::default()
+            };
+            async move {
+                match paginated_query(conn, query, &[], &options).await.unwrap() {
+                    PaginatedQueryResult::Select {
+                        has_prev,
+                        has_next,
+                        page_count,
+                        ..
+                    } => (has_prev, has_next, page_count),
+                    res => panic!("expected a select result, got {res:?}"),
+                }
+            }
+        };
+
+        let query = "SELECT n FROM generate_series(1, 25) n";
+        assert_eq!(
+            run(query, 1, 10, CountMode::Separate).await,
+            (false, true, 10)
+        );
+        assert_eq!(
+            run(query, 2, 10, CountMode::Separate).await,
+            (true, true, 10)
+        );
+        assert_eq!(
+            run(query, 3, 10, CountMode::Separate).await,
+            (true, false, 5)
+        );
+        assert_eq!(
+            run(query, 1, -1, CountMode::Separate).await,
+            (false, false, 25)
+        );
+
+        // the planner's estimate of how many rows match is far off, but the next page
+        // is only reported if a row past this page was actually found
+        let query = "SELECT n FROM generate_series(1, 20) n WHERE n % 2 = 0";
+        assert_eq!(
+            run(query, 1, 5, CountMode::Estimate).await,
+            (false, true, 5)
+        );
+        assert_eq!(
+            run(query, 2, 5, CountMode::Estimate).await,
+            (true, false, 5)
+        );
+        assert_eq!(
+            run(query, 1, -1, CountMode::Estimate).await,
+            (false, false, 10)
+        );
+    }
+
+    #[tokio::test]
+    async fn includes_plan_when_requested() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        let run = |with_plan: bool| {
+            let conn = &conn;
+            let options = QueryOptions {
+                page: 1,
+                page_size: Some(10),
+                with_plan,
+                ..Default::default()
+            };
+            async move {
+                let query = "SELECT n FROM generate_series(1, $1::int) n";
+                let params = [serde_json::json!(25)];
+                match paginated_query(conn, query, &params, &options)
+                    .await
+                    .unwrap()
+                {
+                    PaginatedQueryResult::Select { plan, entries, .. } => {
+                        (plan, entries.rows.len())
+                    }
+                    res => panic!("expected a select result, got {res:?}"),
+                }
+            }
+        };
+
+        let (plan, rows) = run(true).await;
+        assert_eq!(rows, 10);
+        // the plan is for the page, so it's limited to the page size
+        let plan = plan.expect("plan is included");
+        assert_eq!(plan[0]["Plan"]["Node Type"], "Limit");
+        assert_eq!(plan_rows(&plan), Some(10.0));
+
+        assert_eq!(run(false).await, (None, 10));
+    }
+
+    #[tokio::test]
+    async fn tells_function_overloads_apart() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_functions;
+             CREATE FUNCTION dbc_functions.greet(name text) RETURNS text
+               LANGUAGE sql AS $$ SELECT 'hi ' || name $$;
+             CREATE FUNCTION dbc_functions.greet(times integer, name text DEFAULT 'you')
+               RETURNS text LANGUAGE sql AS $$ SELECT repeat('hi ' || name, times) $$;
+             CREATE FUNCTION dbc_functions.greet(VARIADIC names text[]) RETURNS text
+               LANGUAGE sql AS $$ SELECT 'hi ' || array_to_string(names, ', ') $$;
+             CREATE PROCEDURE dbc_functions.reset() LANGUAGE sql AS $$ SELECT 1 $$;",
+        )
+        .await
+        .unwrap();
+
+        let functions = list_functions(&conn, "dbc_functions").await;
+        let ddl = function_ddl(&conn, "dbc_functions", "greet", "times integer, name text").await;
+        let variadic = function_ddl(&conn, "dbc_functions", "greet", "VARIADIC names text[]").await;
+        let missing = function_ddl(&conn, "dbc_functions", "greet", "integer").await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let signatures = functions
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.name, f.identity_arguments, f.arguments, f.kind))
+            .collect::<Vec<_>>();
+        let signature = |name: &str, identity: &str, args: &str, kind: &str| {
+            (
+                name.to_owned(),
+                identity.to_owned(),
+                args.to_owned(),
+                kind.to_owned(),
+            )
+        };
+        assert_eq!(
+            signatures,
+            [
+                signature(
+                    "greet",
+                    "VARIADIC names text[]",
+                    "VARIADIC names text[]",
+                    "function"
+                ),
+                signature("greet", "name text", "name text", "function"),
+                signature(
+                    "greet",
+                    "times integer, name text",
+                    "times integer, name text DEFAULT 'you'::text",
+                    "function"
+                ),
+                signature("reset", "", "", "procedure"),
+            ]
+        );
+
+        let ddl = ddl.unwrap();
+        assert!(
+            ddl.starts_with(
+                "CREATE OR REPLACE FUNCTION dbc_functions.greet(times integer, name text DEFAULT"
+            ),
+            "{ddl}"
+        );
+        assert!(ddl.contains("repeat("), "{ddl}");
+        assert!(variadic.unwrap().contains("array_to_string"));
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "no function named dbc_functions.greet(integer)"
+        );
+    }
+
+    #[tokio::test]
+    async fn view_ddl_recreates_multi_join_views() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_views;
+             CREATE TABLE dbc_views.users (id bigint PRIMARY KEY, name text);
+             CREATE TABLE dbc_views.orgs (id bigint PRIMARY KEY, name text);
+             CREATE TABLE dbc_views.memberships (user_id bigint, org_id bigint);
+             CREATE VIEW dbc_views.members AS
+               SELECT u.name AS user_name, o.name AS org_name
+               FROM dbc_views.memberships m
+               JOIN dbc_views.users u ON u.id = m.user_id
+               LEFT JOIN dbc_views.orgs o ON o.id = m.org_id;
+             CREATE MATERIALIZED VIEW dbc_views.org_sizes AS
+               SELECT o.name, count(*) AS members
+               FROM dbc_views.orgs o
+               JOIN dbc_views.memberships m ON m.org_id = o.id
+               GROUP BY o.name;",
+        )
+        .await
+        .unwrap();
+
+        let view = view_ddl(&conn, "dbc_views", "members").await.unwrap();
+        let mat_view = materialized_view_ddl(&conn, "dbc_views", "org_sizes")
+            .await
+            .unwrap();
+        let missing = view_ddl(&conn, "dbc_views", "org_sizes").await;
+
+        // the DDL is valid, and recreates the same views
+        let recreated = conn
+            .batch_execute(&format!(
+                "{view}
+                 DROP MATERIALIZED VIEW dbc_views.org_sizes;
+                 {mat_view}"
+            ))
+            .await;
+        let view_again = view_ddl(&conn, "dbc_views", "members").await.unwrap();
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        assert!(
+            view.starts_with("CREATE OR REPLACE VIEW \"dbc_views\".\"members\" AS\nSELECT"),
+            "{view}"
+        );
+        assert!(view.contains("\n     JOIN dbc_views.users u"), "{view}");
+        assert!(
+            mat_view.starts_with("CREATE MATERIALIZED VIEW \"dbc_views\".\"org_sizes\" AS\n"),
+            "{mat_view}"
+        );
+        assert!(missing.is_err());
+        recreated.unwrap();
+        assert_eq!(view_again, view);
+    }
+
+    #[tokio::test]
+    async fn detects_primary_keys_alongside_expression_indexes() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_pkeys;
+             CREATE TABLE dbc_pkeys.widgets (
+               name text NOT NULL,
+               id bigint CONSTRAINT widget_key PRIMARY KEY,
+               active bool NOT NULL
+             );
+             CREATE INDEX widgets_lower_name_idx ON dbc_pkeys.widgets (lower(name));
+             CREATE UNIQUE INDEX widgets_active_pkey ON dbc_pkeys.widgets (name) WHERE active;
+             CREATE TABLE dbc_pkeys.memberships (org_id bigint, user_id bigint, PRIMARY KEY (user_id, org_id));",
+        )
+        .await
+        .unwrap();
+
+        let widgets = table_ddl(&conn, "dbc_pkeys", "widgets").await;
+        let memberships = table_ddl(&conn, "dbc_pkeys", "memberships").await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        assert_eq!(
+            widgets.unwrap(),
+            "CREATE TABLE widgets (\n  \
+             name text NOT NULL,\n  \
+             id bigint PRIMARY KEY NOT NULL,\n  \
+             active boolean NOT NULL\n\
+             );\n\n\
+             CREATE INDEX widgets_lower_name_idx ON dbc_pkeys.widgets USING btree (lower(name));\n\
+             CREATE UNIQUE INDEX widgets_active_pkey ON dbc_pkeys.widgets USING btree (name) WHERE active;"
+        );
+        assert_eq!(
+            memberships.unwrap(),
+            "CREATE TABLE memberships (\n  \
+             org_id bigint NOT NULL,\n  \
+             user_id bigint NOT NULL,\n  \
+             PRIMARY KEY (user_id, org_id)\n\
+             );"
+        );
+    }
+
+    #[tokio::test]
+    async fn quotes_adversarial_identifiers_in_ddl() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let table = "we\"ird; DROP TABLE x";
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            r#"CREATE SCHEMA dbc_quotes;
+             SET LOCAL search_path = dbc_quotes;
+             CREATE TABLE "we""ird; DROP TABLE x" (
+               "select" bigint PRIMARY KEY,
+               "Mixed Case" text,
+               "a;b" text
+             );
+             CREATE INDEX "idx; --" ON "we""ird; DROP TABLE x" ("a;b");
+             CREATE SCHEMA "dbc quotes";
+             CREATE TABLE "dbc quotes".plain ("select" bigint PRIMARY KEY, "Mixed Case" text);"#,
+        )
+        .await
+        .unwrap();
+
+        let ddl = table_ddl(&conn, "dbc_quotes", table).await.unwrap();
+        assert!(
+            ddl.starts_with(
+                "CREATE TABLE \"we\"\"ird; DROP TABLE x\" (\n  \
+             \"select\" bigint PRIMARY KEY NOT NULL,\n  \
+             \"Mixed Case\" text,\n  \
+             \"a;b\" text\n\
+             );"
+            ),
+            "{ddl}"
+        );
+
+        // the generated DDL recreates the same table
+        conn.batch_execute(&format!("DROP TABLE {}; {ddl}", quote_ident(table)))
+            .await
+            .unwrap();
+        assert_eq!(table_ddl(&conn, "dbc_quotes", table).await.unwrap(), ddl);
+
+        // as do the statements altering another table to match it (in another schema,
+        // since its indexes are created with the same names)
+        let diff = table_ddl_diff(&conn, ("dbc quotes", "plain"), &conn, ("dbc_quotes", table))
+            .await
+            .unwrap();
+        let statements = diff.statements;
+        assert!(statements.len() == 2, "{statements:?}");
+        conn.batch_execute(&statements.join("\n")).await.unwrap();
+        let plain = table_ddl(&conn, "dbc quotes", "plain").await.unwrap();
+        assert!(plain.contains("\"a;b\" text"), "{plain}");
+
+        conn.batch_execute("ROLLBACK").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn warns_about_columns_made_not_null() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE SCHEMA dbc_not_null;
+             CREATE TABLE dbc_not_null.old (id int, name text);
+             INSERT INTO dbc_not_null.old VALUES (1, 'a'), (NULL, 'b'), (NULL, NULL);
+             CREATE TABLE dbc_not_null.new (
+               id int PRIMARY KEY,
+               name text NOT NULL,
+               email text NOT NULL,
+               created_at timestamptz NOT NULL DEFAULT now()
+             );",
+        )
+        .await
+        .unwrap();
+
+        let diff = table_ddl_diff(
+            &conn,
+            ("dbc_not_null", "old"),
+            &conn,
+            ("dbc_not_null", "new"),
+        )
+        .await
+        .unwrap();
+        assert!(
+            diff.statements
+                .contains(&"ALTER TABLE dbc_not_null.old ADD PRIMARY KEY (id);".to_owned()),
+            "{:?}",
+            diff.statements
+        );
+        assert_eq!(
+            diff.warnings,
+            [
+                "column \"id\" is made NOT NULL, but has 2 NULL values",
+                "column \"name\" is made NOT NULL, but has 1 NULL values",
+                "column \"email\" is added as NOT NULL without a default, but the table has 3 rows",
+            ]
+        );
+
+        conn.batch_execute("ROLLBACK").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_duplicate_column_names() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let sql = "SELECT a.id, b.id, 'x' AS id_2 FROM (VALUES (1)) a(id), (VALUES (2), (3)) b(id)";
+
+        let rows = query(&conn, sql, &[]).await.unwrap().row_maps();
+        assert_eq!(
+            serde_json::to_value(&rows).unwrap(),
+            serde_json::json!([
+                { "id": 1, "id_3": 2, "id_2": "x" },
+                { "id": 1, "id_3": 3, "id_2": "x" },
+            ])
+        );
+
+        // paginated results are positional, and filters refer to columns by index
+        let options = QueryOptions {
+            page: 1,
+            page_size: Some(10),
+            filters: vec![Filter {
+                index: 1,
+                column: "id".to_owned(),
+                operator: FilterOp::Eq,
+                value: serde_json::json!(3),
+            }],
+            ..Default::default()
+        };
+        match paginated_query(&conn, sql, &[], &options).await.unwrap() {
+            PaginatedQueryResult::Select { entries, .. } => {
+                let names = entries.columns.iter().map(|c| c.name.as_str());
+                assert_eq!(names.collect::<Vec<_>>(), ["id", "id", "id_2"]);
+                assert_eq!(entries.rows, [[serde_json::json!(1), 3.into(), "x".into()]]);
+            }
+            res => panic!("expected a select result, got {res:?}"),
         }
-        Type::TIMESTAMP => {
-            let iso_datetime = format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
-            let val: Option<time::PrimitiveDateTime> = row.get(idx);
-            Some(val.map(|t| t.format(&iso_datetime).unwrap()).into())
+    }
+
+    #[tokio::test]
+    async fn reports_serialization_failures_as_retryable() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let (first, second) = (
+            connect(&config).await.unwrap(),
+            connect(&config).await.unwrap(),
+        );
+        first
+            .batch_execute(
+                "DROP TABLE IF EXISTS dbc_serializable;
+                 CREATE TABLE dbc_serializable (id int PRIMARY KEY, n int);
+                 INSERT INTO dbc_serializable VALUES (1, 0);",
+            )
+            .await
+            .unwrap();
+
+        // both transactions see the row before either updates it
+        let read = "SELECT n FROM dbc_serializable WHERE id = 1";
+        let options = QueryOptions {
+            page: 1,
+            ..Default::default()
+        };
+        for conn in [&first, &second] {
+            begin(conn, Some(IsolationLevel::Serializable))
+                .await
+                .unwrap();
+            paginated_query(conn, read, &[], &options).await.unwrap();
         }
-        Type::TIMESTAMPTZ => {
-            let iso_datetime_tz = format_description!(
-                "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour]:[offset_minute]"
-            );
-            let val: Option<time::OffsetDateTime> = row.get(idx);
-            Some(val.map(|t| t.format(&iso_datetime_tz).unwrap()).into())
+        let update = "UPDATE dbc_serializable SET n = n + 1 WHERE id = 1";
+        paginated_query(&first, update, &[], &options)
+            .await
+            .unwrap();
+        first.batch_execute("COMMIT").await.unwrap();
+
+        let err = paginated_query(&second, update, &[], &options)
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<PgError>().unwrap();
+        assert_eq!(err.code().map(String::as_str), Some("40001"));
+        assert!(err.is_retryable());
+        assert!(err.has_extended());
+        second.batch_execute("ROLLBACK").await.unwrap();
+
+        first
+            .batch_execute("DROP TABLE dbc_serializable")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn retries_queries_that_conflict() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let other = connect(&config).await.unwrap();
+        let watcher = connect(&config).await.unwrap();
+        other
+            .batch_execute(
+                "DROP TABLE IF EXISTS dbc_conflicts;
+                 CREATE TABLE dbc_conflicts (id int PRIMARY KEY, n int);
+                 INSERT INTO dbc_conflicts VALUES (1, 0);",
+            )
+            .await
+            .unwrap();
+
+        // under REPEATABLE READ, updating a row that another transaction updated (and
+        // committed) since the statement started fails with a serialization failure
+        config.isolation_level = Some(IsolationLevel::RepeatableRead);
+        config.conflict_retries = 2;
+        let conn = connect(&config).await.unwrap();
+        let options = QueryOptions {
+            retry_writes: true,
+            ..Default::default()
+        };
+        let update = "UPDATE dbc_conflicts SET n = n + 10 WHERE id = 1";
+        let pid: i32 = conn
+            .query_one("SELECT pg_backend_pid()", &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        // commit once the update is waiting on the other transaction's row lock
+        let commit_once_blocked = || async {
+            let blocked = "SELECT EXISTS (
+                SELECT FROM pg_stat_activity WHERE pid = $1 AND wait_event_type = 'Lock'
+            )";
+            while !watcher
+                .query_one(blocked, &[&pid])
+                .await
+                .unwrap()
+                .get::<_, bool>(0)
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            other.batch_execute("COMMIT").await.unwrap();
+        };
+
+        other
+            .batch_execute("BEGIN; UPDATE dbc_conflicts SET n = 1 WHERE id = 1")
+            .await
+            .unwrap();
+        let (result, ()) = tokio::join!(
+            paginated_query_with_retries(&conn, update, &[], &options),
+            commit_once_blocked()
+        );
+        let (result, retries) = result.unwrap();
+        assert!(matches!(
+            result,
+            PaginatedQueryResult::ModifyData {
+                affected_rows: 1,
+                ..
+            }
+        ));
+        assert_eq!(retries, 1);
+        let row = other
+            .query_one("SELECT n FROM dbc_conflicts WHERE id = 1", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, i32>(0), 11);
+
+        // without opting in, writes aren't retried
+        let options = QueryOptions::default();
+        other
+            .batch_execute("BEGIN; UPDATE dbc_conflicts SET n = 1 WHERE id = 1")
+            .await
+            .unwrap();
+        let (result, ()) = tokio::join!(
+            paginated_query_with_retries(&conn, update, &[], &options),
+            commit_once_blocked()
+        );
+        assert!(is_conflict(&result.unwrap_err()));
+
+        other
+            .batch_execute("DROP TABLE dbc_conflicts")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn returns_generated_sql_without_running_it() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let options = QueryOptions {
+            page: 2,
+            page_size: Some(10),
+            sort: Some(Sort {
+                column_idx: 1,
+                direction: SortDirection::Desc,
+            }),
+            filters: vec![Filter {
+                index: 0,
+                column: "n".to_owned(),
+                operator: FilterOp::Gt,
+                value: serde_json::json!(2),
+            }],
+            dry: true,
+            ..Default::default()
+        };
+
+        let result = paginated_query(
+            &conn,
+            "SELECT n, n * 2 AS doubled FROM generate_series(1, 100) n",
+            &[],
+            &options,
+        )
+        .await
+        .unwrap();
+        let PaginatedQueryResult::DryRun {
+            page_query,
+            count_query,
+            params,
+        } = result
+        else {
+            panic!("expected a dry run, got {result:?}");
+        };
+        assert!(page_query.contains("WHERE \"0.n\" > $1"), "{page_query}");
+        assert!(
+            page_query.ends_with("ORDER BY 2 DESC LIMIT 10 OFFSET 10;"),
+            "{page_query}"
+        );
+        assert!(count_query.unwrap().contains("WHERE \"0.n\" > $1"));
+        assert_eq!(params, [serde_json::json!(2)]);
+
+        // only the wrapped queries are generated, so nothing else can be dry run
+        conn.batch_execute("CREATE TEMP TABLE dbc_dry (n int)")
+            .await
+            .unwrap();
+        let err = paginated_query(&conn, "DELETE FROM dbc_dry", &[], &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "only SELECT queries can be dry run");
+    }
+
+    #[test]
+    fn detects_extra_statements() {
+        assert!(!has_extra_statements("SELECT 1"));
+        assert!(!has_extra_statements("SELECT 1;\n-- done\n"));
+        assert!(has_extra_statements("SELECT 1; SELECT 2"));
+        assert!(has_extra_statements("/* one */ SELECT 1;\nDELETE FROM t"));
+        assert!(!has_extra_statements("SELECT ';'; -- trailing"));
+    }
+
+    #[test]
+    fn splits_scripts_into_statements() {
+        let script = r#"
+            -- create the table; then fill it
+            CREATE TABLE "semi;colon" (note text DEFAULT 'a;b', n int);
+            /* skipped; */ INSERT INTO "semi;colon" (n) VALUES ($1);;
+            CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END $body$ LANGUAGE plpgsql;
+            SELECT $$;$$, a$b FROM t;
+            -- done;
+        "#;
+        assert_eq!(
+            split_statements(script),
+            [
+                "-- create the table; then fill it\n            \
+                 CREATE TABLE \"semi;colon\" (note text DEFAULT 'a;b', n int)",
+                "/* skipped; */ INSERT INTO \"semi;colon\" (n) VALUES ($1)",
+                "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END $body$ \
+                 LANGUAGE plpgsql",
+                "SELECT $$;$$, a$b FROM t",
+            ]
+        );
+        assert!(split_statements("  -- nothing here;\n /* or here */ ;").is_empty());
+        // an unterminated string runs to the end of the script
+        assert_eq!(split_statements("SELECT 'a; b"), ["SELECT 'a; b"]);
+        // backslashes only escape quotes in escape strings
+        assert_eq!(
+            split_statements(r"SELECT E'it\'s; fine', 'C:\'; SELECT 1"),
+            [r"SELECT E'it\'s; fine', 'C:\'", "SELECT 1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_multiple_statements_if_requested() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let sql = "SELECT 1 AS one; SELECT 2 AS two";
+
+        // by default, only the first statement is run
+        let mut options = QueryOptions {
+            page: 1,
+            page_size: Some(10),
+            ..Default::default()
+        };
+        match paginated_query(&conn, sql, &[], &options).await.unwrap() {
+            PaginatedQueryResult::Select { entries, .. } => {
+                assert_eq!(entries.columns[0].name, "one");
+            }
+            res => panic!("expected a select result, got {res:?}"),
         }
-        _ => {
-            match col.type_().name() {
-                // citext is a case-insensitive text type
-                "citext" => {
-                    let val: Option<&str> = row.get(idx);
-                    Some(val.into())
-                }
-                _ => {
-                    tracing::warn!("unsupported type: {:?}", col.type_());
-                    None
+
+        options.reject_multiple = true;
+        let err = paginated_query(&conn, sql, &[], &options)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("more than one statement"), "{err}");
+        paginated_query(&conn, "SELECT 1;", &[], &options)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_updates_to_rows_changed_since_read() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        // each statement commits on its own, so every write gets a new `xmin`
+        conn.batch_execute(
+            "CREATE TEMP TABLE dbc_edit (id bigint PRIMARY KEY, name text);
+             INSERT INTO dbc_edit VALUES (1, 'a'), (2, 'b');",
+        )
+        .await
+        .unwrap();
+        let schema = query(&conn, "SELECT pg_my_temp_schema()::regnamespace::text", &[])
+            .await
+            .unwrap()
+            .rows[0][0]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let rows = editable_rows(&conn, &schema, "dbc_edit", 1, Some(10))
+            .await
+            .unwrap();
+        assert_eq!(rows.primary_key, ["id"]);
+        assert_eq!(rows.entries.columns[0].name, "id");
+        assert_eq!(rows.entries.columns[0].index, 0);
+        assert_eq!(rows.entries.rows[1], [serde_json::json!(2), "b".into()]);
+        assert_eq!(rows.versions.len(), 2);
+
+        let update = |id: i64, version: &str, name: &str| RowUpdate {
+            key: serde_json::json!({ "id": id }).as_object().unwrap().clone(),
+            version: version.to_owned(),
+            values: serde_json::json!({ "name": name })
+                .as_object()
+                .unwrap()
+                .clone(),
+        };
+
+        // someone else modifies the first row between our read and write
+        conn.batch_execute("UPDATE dbc_edit SET name = 'theirs' WHERE id = 1")
+            .await
+            .unwrap();
+        let err = update_row(
+            &conn,
+            &schema,
+            "dbc_edit",
+            &update(1, &rows.versions[0], "ours"),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<RowConflict>().is_some(), "{err}");
+
+        // the second row is untouched, so it can be updated, and only once per version
+        let version = update_row(
+            &conn,
+            &schema,
+            "dbc_edit",
+            &update(2, &rows.versions[1], "c"),
+        )
+        .await
+        .unwrap();
+        assert_ne!(version, rows.versions[1]);
+        let err = update_row(
+            &conn,
+            &schema,
+            "dbc_edit",
+            &update(2, &rows.versions[1], "d"),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<RowConflict>().is_some(), "{err}");
+
+        let names = query(&conn, "SELECT name FROM dbc_edit ORDER BY id", &[])
+            .await
+            .unwrap();
+        assert_eq!(names.rows, [["theirs"], ["c"]]);
+
+        // rows must be identified by their whole primary key
+        let mut by_name = update(2, &version, "e");
+        by_name.key = serde_json::json!({ "name": "c" })
+            .as_object()
+            .unwrap()
+            .clone();
+        let err = update_row(&conn, &schema, "dbc_edit", &by_name)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("primary key"), "{err}");
+
+        // a `NULL` key is rejected outright, rather than reported as a conflict
+        let mut null_key = update(2, &version, "e");
+        null_key.key["id"] = serde_json::Value::Null;
+        let err = update_row(&conn, &schema, "dbc_edit", &null_key)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<InvalidRequest>().is_some(), "{err}");
+
+        // as is a page past the largest offset
+        let err = editable_rows(&conn, &schema, "dbc_edit", usize::MAX, Some(10))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<InvalidRequest>().is_some(), "{err}");
+
+        // and, as for queries, page 0 and empty pages
+        let err = editable_rows(&conn, &schema, "dbc_edit", 0, Some(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid page 0: pages are numbered from 1");
+        let err = editable_rows(&conn, &schema, "dbc_edit", 1, Some(0))
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<InvalidRequest>().is_some(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn supports_hstore_columns() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE EXTENSION IF NOT EXISTS hstore;
+             CREATE TABLE dbc_hstore (id int, attrs hstore NOT NULL);
+             INSERT INTO dbc_hstore VALUES
+               (1, 'color => blue, size => NULL'),
+               (2, 'color => red');",
+        )
+        .await
+        .unwrap();
+
+        let run = |filters: Vec<Filter>| {
+            let conn = &conn;
+            let options = QueryOptions {
+                page: 1,
+                page_size: Some(10),
+                filters,
+                ..Default::default()
+            };
+            async move {
+                let query = "SELECT * FROM dbc_hstore ORDER BY id";
+                match paginated_query(conn, query, &[], &options).await.unwrap() {
+                    PaginatedQueryResult::Select { entries, .. } => entries,
+                    res => panic!("expected a select result, got {res:?}"),
                 }
             }
+        };
+
+        let all = run(vec![]).await;
+        let contains_blue = run(vec![Filter {
+            index: 1,
+            column: "attrs".to_owned(),
+            operator: FilterOp::Contains,
+            value: serde_json::json!({ "color": "blue" }),
+        }])
+        .await;
+        let ddl = table_ddl(&conn, "public", "dbc_hstore").await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        assert_eq!(all.encoding, ResultEncoding::Binary);
+        assert_eq!(
+            all.rows,
+            [
+                vec![
+                    serde_json::json!(1),
+                    serde_json::json!({ "color": "blue", "size": null })
+                ],
+                vec![serde_json::json!(2), serde_json::json!({ "color": "red" })],
+            ]
+        );
+        assert_eq!(contains_blue.rows, all.rows[..1]);
+        assert!(ddl.unwrap().contains("attrs hstore NOT NULL"));
+    }
+
+    #[test]
+    fn from_json_binds_hstore_objects() {
+        use tokio_postgres::types::Kind;
+
+        let type_ = |name: &str| Type::new(name.to_owned(), 0, Kind::Simple, "public".to_owned());
+        assert!(from_json(&serde_json::json!({ "a": "1", "b": null }), type_("hstore")).is_ok());
+
+        let err = from_json(&serde_json::json!({ "a": 1 }), type_("hstore")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected string or null for hstore key \"a\""
+        );
+        let err = from_json(&serde_json::json!("a => 1"), type_("hstore")).unwrap_err();
+        assert_eq!(err.to_string(), "expected object of strings");
+    }
+
+    #[test]
+    fn modify_data_tags_match_postgres() {
+        assert_eq!(
+            modify_data_tag("INSERT INTO t VALUES (1), (2)", 2),
+            "INSERT 0 2"
+        );
+        assert_eq!(
+            modify_data_tag("WITH x AS (SELECT 1)\nupdate t SET v = 1", 5),
+            "UPDATE 5"
+        );
+        assert_eq!(modify_data_tag("DELETE FROM t", 0), "DELETE 0");
+        assert_eq!(
+            modify_data_tag("REFRESH MATERIALIZED VIEW mv", 0),
+            "REFRESH MATERIALIZED VIEW"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_command_tag_for_upserts() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        conn.batch_execute(
+            "CREATE TEMP TABLE dbc_upsert (id int PRIMARY KEY, name text);
+             INSERT INTO dbc_upsert VALUES (1, 'a');",
+        )
+        .await
+        .unwrap();
+
+        let result = paginated_query(
+            &conn,
+            "INSERT INTO dbc_upsert VALUES (1, 'b'), (2, 'c')
+             ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+            &[],
+            &QueryOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(result).unwrap(),
+            serde_json::json!({
+                "type": "modify-data",
+                "affected_rows": 2,
+                "command": "INSERT 0 2",
+            })
+        );
+    }
+
+    #[test]
+    fn maintenance_sql() {
+        use MaintenanceAction::*;
+        assert_eq!(
+            Vacuum.sql("public", "users", false).unwrap(),
+            "VACUUM (VERBOSE) \"public\".\"users\""
+        );
+        assert_eq!(
+            VacuumAnalyze.sql("public", "users", true).unwrap(),
+            "VACUUM (FULL, VERBOSE, ANALYZE) \"public\".\"users\""
+        );
+        assert!(Reindex.sql("public", "users", true).is_err());
+    }
+
+    #[test]
+    fn sample_stats_only_samples_large_tables() {
+        assert_eq!(sample_percent(None), None);
+        assert_eq!(sample_percent(Some(1_000)), None);
+        assert_eq!(sample_percent(Some(3_000_000)), Some(1.0));
+
+        assert_eq!(
+            sample_stats_sql("public", "users", &["id".to_owned()], Some(1.0)),
+            "SELECT COUNT(*), COUNT(*) FILTER (WHERE \"id\" IS NULL), \
+             COUNT(DISTINCT \"id\"::text) FROM \"public\".\"users\" TABLESAMPLE SYSTEM (1)"
+        );
+    }
+
+    #[test]
+    fn extended_column_info_is_best_effort() {
+        let column = |name: &str| QueryResultColumn {
+            table_oid: Some(1),
+            column_id: Some(1),
+            name: name.to_owned(),
+            index: 0,
+            type_: "int4".to_owned(),
+            unsupported_type: None,
+            extended: None,
+        };
+        let mut columns = vec![column("id"), column("total")];
+
+        QueryResultColumn::apply_extended(
+            &mut columns,
+            Err(eyre::eyre!("permission denied for table pg_constraint")),
+        );
+        assert!(columns.iter().all(|col| col.extended.is_none()));
+        assert_eq!(columns[1].name, "total");
+
+        QueryResultColumn::apply_extended(
+            &mut columns,
+            Ok(vec![
+                Some(QueryResultColumnExtended {
+                    source_table: Some("users".to_owned()),
+                    source_column: Some("id".to_owned()),
+                    fk_constraint: None,
+                    fk_table: None,
+                    fk_column: None,
+                }),
+                None,
+            ]),
+        );
+        assert_eq!(
+            columns[0]
+                .extended
+                .as_ref()
+                .unwrap()
+                .source_table
+                .as_deref(),
+            Some("users")
+        );
+        assert!(columns[1].extended.is_none());
+    }
+
+    #[tokio::test]
+    async fn exports_csv_with_copy() {
+        use futures_util::TryStreamExt;
+
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE TEMP TABLE dbc_export (
+               id bigint,
+               name text,
+               price numeric(6, 2),
+               tags text[],
+               attrs jsonb,
+               wait interval,
+               created_at timestamptz
+             );
+             SET LOCAL timezone = 'UTC';
+             INSERT INTO dbc_export VALUES
+               (1, 'plain', 1.5, '{a,b}', '{\"k\": 1}', '1 day 02:00:00', '2024-01-02 03:04:05+00'),
+               (2, 'has, \"quotes\"', NULL, NULL, NULL, NULL, NULL);",
+        )
+        .await
+        .unwrap();
+
+        let export = |options: CsvOptions| {
+            let conn = &conn;
+            async move {
+                let stream =
+                    copy_csv(conn, "SELECT * FROM dbc_export ORDER BY id;", &options).await?;
+                let chunks = stream
+                    .map_err(PgError::from)
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                eyre::Ok(String::from_utf8(chunks.concat()).unwrap())
+            }
+        };
+        let csv = export(CsvOptions::default()).await;
+        let tsv = export(CsvOptions {
+            delimiter: '\t',
+            null: "NULL".to_owned(),
+            header: false,
+        })
+        .await;
+        let modify = copy_csv(
+            &conn,
+            "DELETE FROM dbc_export RETURNING id",
+            &Default::default(),
+        )
+        .await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        assert_eq!(
+            csv.unwrap(),
+            "id,name,price,tags,attrs,wait,created_at\n\
+             1,plain,1.50,\"{a,b}\",\"{\"\"k\"\": 1}\",1 day 02:00:00,2024-01-02 03:04:05+00\n\
+             2,\"has, \"\"quotes\"\"\",,,,,\n"
+        );
+        assert_eq!(
+            tsv.unwrap(),
+            "1\tplain\t1.50\t{a,b}\t\"{\"\"k\"\": 1}\"\t1 day 02:00:00\t2024-01-02 03:04:05+00\n\
+             2\t\"has, \"\"quotes\"\"\"\tNULL\tNULL\tNULL\tNULL\tNULL\n"
+        );
+        assert!(modify.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_out_of_range_pages() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        for count in [CountMode::Separate, CountMode::Window, CountMode::Estimate] {
+            let options = QueryOptions {
+                page: 0,
+                page_size: Some(10),
+                count,
+                ..Default::default()
+            };
+            let err = paginated_query(&conn, "SELECT generate_series(1, 100)", &[], &options)
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "invalid page 0: pages are numbered from 1");
+            assert!(err.is::<InvalidRequest>());
         }
+
+        let options = QueryOptions {
+            page: usize::MAX,
+            page_size: Some(10),
+            ..Default::default()
+        };
+        let err = paginated_query(&conn, "SELECT generate_series(1, 100)", &[], &options)
+            .await
+            .unwrap_err();
+        assert!(err.is::<InvalidRequest>(), "{err}");
     }
-}
 
-fn from_json(
-    json: &serde_json::Value,
-    type_: tokio_postgres::types::Type,
-) -> eyre::Result<Box<dyn ToSql + Sync + Send>> {
-    use tokio_postgres::types::Type;
-    match type_ {
-        Type::TEXT | Type::VARCHAR | Type::NAME | Type::CHAR => json
-            .as_str()
-            .ok_or(eyre::eyre!("expected string"))
-            .map(|s| Box::new(s.to_owned()) as _),
-        Type::BOOL => json
-            .as_bool()
-            .ok_or(eyre::eyre!("expected boolean"))
-            .map(|b| Box::new(b) as _),
-        Type::INT8 | Type::INT4 | Type::INT2 => json
-            .as_i64()
-            .ok_or(eyre::eyre!("expected integer"))
-            .map(|i| Box::new(i) as _),
-        Type::FLOAT8 | Type::FLOAT4 => json
-            .as_f64()
-            .ok_or(eyre::eyre!("expected float"))
-            .map(|f| Box::new(f) as _),
-        Type::NUMERIC => json
-            .as_f64()
-            .ok_or(eyre::eyre!("expected float"))
-            .map(|f| Box::new(Decimal::from_f64_retain(f).unwrap()) as _),
-        Type::TIMESTAMP => {
-            let s = json.as_str().ok_or(eyre::eyre!("expected string"))?;
-            let date_time =
-                match s.len() {
-                    // parse as date, assume 00:00:00 for time
-                    10 => time::Date::parse(s, format_description!("[year]-[month]-[day]")).map(
-                        |d| time::PrimitiveDateTime::new(d, time::Time::from_hms(0, 0, 0).unwrap()),
-                    )?,
-                    // parse as datetime
-                    19 => time::PrimitiveDateTime::parse(
-                        s,
-                        format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
-                    )?,
-                    _ => eyre::bail!(
-                        "invalid timestamp format, expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS"
-                    ),
+    #[tokio::test]
+    async fn defaults_and_clamps_page_size() {
+        let Some(mut config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        config.default_page_size = 3;
+        config.max_page_size = 5;
+        let conn = connect(&config).await.unwrap();
+
+        let page = |query: &'static str, page_size: Option<isize>| {
+            let conn = &conn;
+            async move {
+                let options = QueryOptions {
+                    page: 1,
+                    page_size,
+                    ..Default::default()
                 };
+                match paginated_query(conn, query, &[], &options).await.unwrap() {
+                    PaginatedQueryResult::Select {
+                        page_size,
+                        page_count,
+                        requested_page_size,
+                        ..
+                    } => (page_size, page_count, requested_page_size),
+                    result => panic!("expected a select, got {result:?}"),
+                }
+            }
+        };
+        let query = "SELECT generate_series(1, 10)";
+
+        assert_eq!(page(query, None).await, (3, 3, None));
+        assert_eq!(page(query, Some(4)).await, (4, 4, None));
+        assert_eq!(page(query, Some(50)).await, (5, 5, Some(50)));
+        // all rows are clamped too, unless the query limits its own results
+        assert_eq!(page(query, Some(-1)).await, (5, 5, Some(-1)));
+        assert_eq!(
+            page("SELECT generate_series(1, 10) LIMIT 8", Some(-1)).await,
+            (-1, 8, None)
+        );
+    }
 
-            Ok(Box::new(date_time) as _)
-        }
-        _ => {
-            match type_.name() {
-                // citext is a case-insensitive text type
-                "citext" => json
-                    .as_str()
-                    .ok_or(eyre::eyre!("expected string"))
-                    .map(|s| Box::new(s.to_owned()) as _),
-                _ => Err(eyre::eyre!("unsupported type: {:?}", type_)),
+    #[tokio::test]
+    async fn skips_extended_column_info_if_not_requested() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+
+        begin(&conn, None).await.unwrap();
+        conn.batch_execute(
+            "CREATE TEMP TABLE dbc_ext (id bigint); INSERT INTO dbc_ext VALUES (1);",
+        )
+        .await
+        .unwrap();
+        let mut options = QueryOptions {
+            page: 1,
+            page_size: Some(10),
+            ..Default::default()
+        };
+        let with_extended = paginated_query(&conn, "SELECT id FROM dbc_ext", &[], &options).await;
+        options.with_extended = false;
+        let without = paginated_query(&conn, "SELECT id FROM dbc_ext", &[], &options).await;
+        conn.batch_execute("ROLLBACK").await.unwrap();
+
+        let column = |result: eyre::Result<PaginatedQueryResult>| match result.unwrap() {
+            PaginatedQueryResult::Select { entries, .. } => {
+                serde_json::to_value(&entries.columns[0]).unwrap()
             }
-        }
+            res => panic!("expected a select result, got {res:?}"),
+        };
+        assert_eq!(column(with_extended)["source_table"], "dbc_ext");
+        let column = column(without);
+        assert_eq!(column["name"], "id");
+        assert!(column.get("source_table").is_none(), "{column}");
+        assert!(column.get("fk_table").is_none(), "{column}");
+    }
+
+    #[test]
+    fn merges_matching_results() {
+        let column = |name: &str, type_: &str| QueryResultColumn {
+            table_oid: None,
+            column_id: None,
+            name: name.to_owned(),
+            index: 0,
+            type_: type_.to_owned(),
+            unsupported_type: None,
+            extended: None,
+        };
+        let result = |type_: &str, rows: Vec<Vec<serde_json::Value>>| QueryResult {
+            columns: vec![column("id", type_)],
+            rows,
+            encoding: ResultEncoding::Binary,
+        };
+        let staging = result("int4", vec![vec![1.into()], vec![2.into()]]);
+        let prod = result("int4", vec![vec![3.into()]]);
+        let legacy = result("text", vec![vec!["4".into()]]);
+
+        let merged = merge_results([("staging", &staging), ("prod", &prod), ("legacy", &legacy)]);
+        assert_eq!(
+            merged
+                .columns
+                .iter()
+                .map(|col| (col.name.as_str(), col.index))
+                .collect::<Vec<_>>(),
+            vec![(CONNECTION_COLUMN, 0), ("id", 1)]
+        );
+        assert_eq!(
+            serde_json::Value::from(merged.rows),
+            serde_json::json!([["staging", 1], ["staging", 2], ["prod", 3]])
+        );
+        assert_eq!(merged.excluded, vec!["legacy"]);
+    }
+
+    #[test]
+    fn param_types_are_allowlisted() {
+        let types = param_types(&["integer".to_owned(), " TEXT ".to_owned()]).unwrap();
+        assert_eq!(types, vec![Type::INT4, Type::TEXT]);
+        assert!(from_json(&serde_json::json!(42), types[0].clone()).is_ok());
+
+        let err = param_types(&["int; drop table users".to_owned()]).unwrap_err();
+        assert!(err.to_string().contains("unsupported parameter type"));
+    }
+
+    #[test]
+    fn column_values_are_ordered_and_capped() {
+        assert_eq!(
+            column_values_sql("public", "users", "role", true),
+            "SELECT DISTINCT \"role\"\nFROM \"public\".\"users\"\n\
+             WHERE \"role\"::text LIKE $2 || '%'\nORDER BY \"role\"\nLIMIT $1"
+        );
+        assert_eq!(escape_like("50%_off"), "50\\%\\_off");
+
+        let rows = vec![
+            vec!["admin".into()],
+            vec!["editor".into()],
+            vec!["viewer".into()],
+        ];
+        let values = take_column_values(rows.clone(), 2);
+        assert_eq!(values.values, vec!["admin", "editor"]);
+        assert!(values.truncated);
+        assert!(!take_column_values(rows, 3).truncated);
+    }
+
+    #[test]
+    fn session_info_requires_populated_fields() {
+        let mut row = serde_json::json!({
+            "current_user": "app",
+            "session_user": "postgres",
+            "current_database": "app",
+            "current_schema": null,
+            "search_path": "\"$user\", public",
+            "server_version": "16.4",
+            "server_encoding": "UTF8",
+            "read_only": true,
+        });
+        let info: SessionInfo = serde_json::from_value(row.clone()).unwrap();
+        assert_eq!(info.current_user, "app");
+        assert_eq!(info.current_schema, None);
+        assert!(info.read_only);
+
+        row["server_version"] = serde_json::Value::Null;
+        assert!(serde_json::from_value::<SessionInfo>(row).is_err());
+    }
+
+    #[test]
+    fn rejects_long_identifiers() {
+        let max = "a".repeat(MAX_IDENTIFIER_LENGTH);
+        assert!(validate_identifiers(["public", &max]).is_ok());
+
+        // multi-byte characters count by byte, as in Postgres
+        let long = "é".repeat(32);
+        let err = validate_identifiers(["public", &long]).unwrap_err();
+        assert!(err.to_string().contains("is 64 bytes long"));
+    }
+
+    #[test]
+    fn rejects_unprintable_identifiers() {
+        assert!(validate_identifiers(["we\"ird; DROP TABLE x", "Mixed Case"]).is_ok());
+        assert!(validate_identifiers(["public", ""]).is_err());
+        assert!(validate_identifiers(["users\n"]).is_err());
+        assert!(validate_identifiers(["nul\0"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn quotes_identifiers_only_when_needed() {
+        let Some(config) = test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = connect(&config).await.unwrap();
+        let keywords = conn.reserved_keywords().await.unwrap();
+        assert_eq!(keywords.display_ident("users"), "users");
+        assert_eq!(keywords.display_ident("_tmp_2"), "_tmp_2");
+        assert_eq!(keywords.display_ident("Users"), "\"Users\"");
+        assert_eq!(keywords.display_ident("2fa"), "\"2fa\"");
+        assert_eq!(keywords.display_ident("user"), "\"user\"");
+        assert_eq!(keywords.display_ident("a;b"), "\"a;b\"");
+        assert_eq!(keywords.display_ident("we\"ird"), "\"we\"\"ird\"");
+        // unreserved keywords don't need quoting
+        assert_eq!(keywords.display_ident("name"), "name");
     }
 }
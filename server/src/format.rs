@@ -0,0 +1,675 @@
+//! A conservative SQL pretty-printer.
+//!
+//! Queries are split into tokens and laid out again: keywords are uppercased, each clause
+//! starts on its own line, `AND`/`OR` conditions are indented beneath their clause and
+//! subqueries are indented inside their parentheses. Tokens are never added, removed or
+//! reordered, so comments and literals are preserved as written and the query's meaning
+//! doesn't change. Formatting is idempotent.
+
+/// Words that are uppercased when formatting. Function-like keywords (e.g. `coalesce`)
+/// are left alone, so that they read the same as any other function call.
+const KEYWORDS: &[&str] = &[
+    "ADD",
+    "ALL",
+    "ALTER",
+    "ANALYZE",
+    "AND",
+    "ANY",
+    "ARRAY",
+    "AS",
+    "ASC",
+    "BETWEEN",
+    "BY",
+    "CASCADE",
+    "CASE",
+    "COLUMN",
+    "CONFLICT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "ELSE",
+    "END",
+    "EXCEPT",
+    "EXISTS",
+    "EXPLAIN",
+    "FALSE",
+    "FETCH",
+    "FILTER",
+    "FIRST",
+    "FOR",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GROUP",
+    "HAVING",
+    "IF",
+    "ILIKE",
+    "IN",
+    "INDEX",
+    "INNER",
+    "INSERT",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "JOIN",
+    "KEY",
+    "LAST",
+    "LATERAL",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NATURAL",
+    "NOT",
+    "NOTHING",
+    "NULL",
+    "NULLS",
+    "OFFSET",
+    "ON",
+    "ONLY",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "PRIMARY",
+    "RECURSIVE",
+    "REFERENCES",
+    "RETURNING",
+    "RIGHT",
+    "ROWS",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "THEN",
+    "TRUE",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VALUES",
+    "VIEW",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+    "WITHIN",
+];
+
+/// Keywords that start a new line when they begin a clause.
+const CLAUSES: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "GROUP",
+    "ORDER",
+    "HAVING",
+    "LIMIT",
+    "OFFSET",
+    "FETCH",
+    "WINDOW",
+    "UNION",
+    "EXCEPT",
+    "INTERSECT",
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "VALUES",
+    "SET",
+    "RETURNING",
+    "JOIN",
+    "LEFT",
+    "RIGHT",
+    "INNER",
+    "FULL",
+    "CROSS",
+    "NATURAL",
+];
+
+/// Keywords after which a join keyword continues the same clause (e.g. `LEFT OUTER JOIN`).
+const JOIN_MODIFIERS: &[&str] = &[
+    "LEFT", "RIGHT", "INNER", "FULL", "CROSS", "NATURAL", "OUTER",
+];
+
+/// Keywords that are followed by a relation name, which is spaced from any column list
+/// after it (e.g. `INSERT INTO users (id, name)`).
+const RELATION_KEYWORDS: &[&str] = &["INTO", "TABLE", "EXISTS", "REFERENCES", "VIEW"];
+
+/// Keywords that end an expression, so a following `-` or `+` is a binary operator.
+const VALUE_KEYWORDS: &[&str] = &["NULL", "TRUE", "FALSE", "END"];
+
+const OPERATOR_CHARS: &str = "+-*/<>=~!@#%^&|`?";
+
+const INDENT: &str = "  ";
+
+/// Pretty-print `sql`, which may contain any number of statements.
+pub fn format(sql: &str) -> String {
+    Printer::new(&tokenize(sql)).print()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    /// A quoted identifier, e.g. `"User"`.
+    Quoted(&'a str),
+    /// A string or numeric literal.
+    Literal(&'a str),
+    Operator(&'a str),
+    Punct(char),
+    LineComment(&'a str),
+    BlockComment(&'a str),
+}
+
+impl Token<'_> {
+    fn is_comment(&self) -> bool {
+        matches!(self, Token::LineComment(_) | Token::BlockComment(_))
+    }
+}
+
+fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    while let Some(c) = sql[idx..].chars().next() {
+        let rest = &sql[idx..];
+
+        if c.is_whitespace() {
+            idx += c.len_utf8();
+            continue;
+        }
+
+        let (token, len) = if rest.starts_with("--") {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            (Token::LineComment(rest[..len].trim_end()), len)
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            let len = body.find("*/").map_or(rest.len(), |end| end + 4);
+            (Token::BlockComment(&rest[..len]), len)
+        } else if c == '\'' {
+            let len = quoted_len(rest, '\'', false);
+            (Token::Literal(&rest[..len]), len)
+        } else if c == '"' {
+            let len = quoted_len(rest, '"', false);
+            (Token::Quoted(&rest[..len]), len)
+        } else if c == '$'
+            && let Some(len) = dollar_quoted_len(rest)
+        {
+            (Token::Literal(&rest[..len]), len)
+        } else if c.is_ascii_digit()
+            || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let len = number_len(rest);
+            (Token::Literal(&rest[..len]), len)
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            match prefixed_literal_len(rest, len) {
+                Some(len) => (Token::Literal(&rest[..len]), len),
+                None => (Token::Word(&rest[..len]), len),
+            }
+        } else if c == ':' {
+            let len = if rest.starts_with("::") { 2 } else { 1 };
+            (Token::Operator(&rest[..len]), len)
+        } else if OPERATOR_CHARS.contains(c) {
+            let mut len = 0;
+            for c in rest.chars() {
+                let next = &rest[len..];
+                if !OPERATOR_CHARS.contains(c)
+                    || (len > 0 && (next.starts_with("--") || next.starts_with("/*")))
+                {
+                    break;
+                }
+                len += c.len_utf8();
+            }
+            (Token::Operator(&rest[..len]), len)
+        } else {
+            (Token::Punct(c), c.len_utf8())
+        };
+
+        tokens.push(token);
+        idx += len;
+    }
+
+    tokens
+}
+
+/// The length of a quoted string or identifier at the start of `s`, including its quotes.
+/// Doubled quotes are escapes, as are backslashes if `backslash_escapes` is set.
+fn quoted_len(s: &str, quote: char, backslash_escapes: bool) -> usize {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((idx, c)) = chars.next() {
+        if backslash_escapes && c == '\\' {
+            chars.next();
+        } else if c == quote {
+            match chars.clone().next() {
+                Some((_, next)) if next == quote => {
+                    chars.next();
+                }
+                _ => return idx + c.len_utf8(),
+            }
+        }
+    }
+    s.len()
+}
+
+/// The length of a dollar-quoted string (e.g. `$body$ ... $body$`) at the start of `s`.
+fn dollar_quoted_len(s: &str) -> Option<usize> {
+    let rest = &s[1..];
+    // the tag is followed by a `$`, so this is always a character boundary
+    let tag_end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+    if !rest[tag_end..].starts_with('$') || rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let tag_len = tag_end + 2;
+    let tag = &s[..tag_len];
+    Some(
+        s[tag_len..]
+            .find(tag)
+            .map_or(s.len(), |end| tag_len + end + tag_len),
+    )
+}
+
+fn number_len(s: &str) -> usize {
+    let mut len = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let exponent = &s[len..];
+    if exponent.starts_with(['e', 'E']) {
+        let sign = usize::from(exponent[1..].starts_with(['+', '-']));
+        let digits = exponent[1 + sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(exponent.len() - 1 - sign);
+        if digits > 0 {
+            len += 1 + sign + digits;
+        }
+    }
+    len
+}
+
+/// If the word of `len` bytes at the start of `s` prefixes a string literal (like `E'\n'`
+/// or `U&'\0041'`), the length of the whole literal.
+fn prefixed_literal_len(s: &str, len: usize) -> Option<usize> {
+    let prefix = &s[..len];
+    let rest = &s[len..];
+    if ["e", "b", "x", "n"]
+        .iter()
+        .any(|p| prefix.eq_ignore_ascii_case(p))
+        && rest.starts_with('\'')
+    {
+        let escapes = prefix.eq_ignore_ascii_case("e");
+        return Some(len + quoted_len(rest, '\'', escapes));
+    }
+    if prefix.eq_ignore_ascii_case("u") && (rest.starts_with("&'") || rest.starts_with("&\"")) {
+        let quote = if rest.starts_with("&'") { '\'' } else { '"' };
+        return Some(len + 1 + quoted_len(&rest[1..], quote, false));
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    /// A top-level statement.
+    Statement,
+    /// A parenthesized subquery, whose clauses are laid out like a statement's.
+    Subquery,
+    /// Any other parentheses (function calls, lists, etc.), which are kept on one line.
+    Inline,
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: FrameKind,
+    /// The indent level of this frame's clauses.
+    indent: usize,
+    /// The indent level of the line this frame's opening paren is on.
+    outer_indent: usize,
+    /// The most recent clause keyword in this frame.
+    clause: Option<String>,
+    /// How many `CASE` expressions we're currently inside of.
+    case_depth: usize,
+    /// Whether we've seen a `BETWEEN` that hasn't yet been followed by its `AND`.
+    between: bool,
+}
+
+impl Frame {
+    fn new(kind: FrameKind, indent: usize, outer_indent: usize) -> Self {
+        Self {
+            kind,
+            indent,
+            outer_indent,
+            clause: None,
+            case_depth: 0,
+            between: false,
+        }
+    }
+}
+
+struct Printer<'a, 't> {
+    tokens: &'t [Token<'a>],
+    out: String,
+    frames: Vec<Frame>,
+    /// The indent level of the line currently being written.
+    line_indent: usize,
+    /// If set, the next token starts a new line at this indent level.
+    newline: Option<usize>,
+    /// Whether the next newline should be preceded by a blank line.
+    blank_line: bool,
+    prev: Option<Token<'a>>,
+    /// Whether `prev` was a keyword, as opposed to some other word.
+    prev_keyword: bool,
+    /// The last keyword printed, uppercased.
+    prev_word: Option<String>,
+    /// Whether `prev` was a unary `-` or `+`.
+    prev_unary: bool,
+    /// Whether `prev` is one of `RELATION_KEYWORDS` or part of the relation name after it.
+    relation: bool,
+}
+
+impl<'a, 't> Printer<'a, 't> {
+    fn new(tokens: &'t [Token<'a>]) -> Self {
+        Self {
+            tokens,
+            out: String::new(),
+            frames: vec![Frame::new(FrameKind::Statement, 0, 0)],
+            line_indent: 0,
+            newline: None,
+            blank_line: false,
+            prev: None,
+            prev_keyword: false,
+            prev_word: None,
+            prev_unary: false,
+            relation: false,
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames
+            .last_mut()
+            .expect("there is always a statement frame")
+    }
+
+    /// The next token after `idx` that isn't a comment.
+    fn peek(&self, idx: usize) -> Option<Token<'a>> {
+        self.tokens[idx + 1..]
+            .iter()
+            .find(|token| !token.is_comment())
+            .copied()
+    }
+
+    fn print(mut self) -> String {
+        for (idx, &token) in self.tokens.iter().enumerate() {
+            match token {
+                Token::Word(word) => self.print_word(idx, word),
+                Token::Punct('(') => {
+                    self.push(token, "(");
+                    let subquery = matches!(
+                        self.peek(idx),
+                        Some(Token::Word(w)) if ["SELECT", "WITH", "VALUES"]
+                            .iter()
+                            .any(|k| w.eq_ignore_ascii_case(k))
+                    );
+                    let outer_indent = self.line_indent;
+                    if subquery {
+                        self.frames.push(Frame::new(
+                            FrameKind::Subquery,
+                            outer_indent + 1,
+                            outer_indent,
+                        ));
+                        self.newline = Some(outer_indent + 1);
+                    } else {
+                        let indent = self.frame().indent;
+                        self.frames
+                            .push(Frame::new(FrameKind::Inline, indent, outer_indent));
+                    }
+                }
+                Token::Punct(')') => {
+                    if self.frames.len() > 1 {
+                        let frame = self.frames.pop().unwrap();
+                        if frame.kind == FrameKind::Subquery {
+                            self.newline = Some(frame.outer_indent);
+                        }
+                    }
+                    self.push(token, ")");
+                }
+                Token::Punct(';') => {
+                    self.push(token, ";");
+                    self.frames = vec![Frame::new(FrameKind::Statement, 0, 0)];
+                    self.newline = Some(0);
+                    self.blank_line = true;
+                }
+                Token::Punct(c) => self.push(token, c.encode_utf8(&mut [0; 4])),
+                Token::Operator(op) => {
+                    let unary = matches!(op, "-" | "+")
+                        && match self.prev {
+                            None | Some(Token::Operator(_)) => true,
+                            Some(Token::Punct(c)) => matches!(c, '(' | ',' | '['),
+                            Some(Token::Word(_)) => {
+                                self.prev_keyword
+                                    && !self
+                                        .prev_word
+                                        .as_deref()
+                                        .is_some_and(|w| VALUE_KEYWORDS.contains(&w))
+                            }
+                            _ => false,
+                        };
+                    self.push(token, op);
+                    self.prev_unary = unary;
+                }
+                Token::LineComment(text) => {
+                    self.push(token, text);
+                    if self.newline.is_none() {
+                        self.newline = Some(self.frame().indent + 1);
+                    }
+                }
+                Token::Quoted(text) | Token::Literal(text) | Token::BlockComment(text) => {
+                    self.push(token, text)
+                }
+            }
+        }
+
+        self.out.trim_end().to_owned()
+    }
+
+    fn print_word(&mut self, idx: usize, word: &'a str) {
+        let upper = word.to_ascii_uppercase();
+        let next = self.peek(idx);
+
+        // `left(...)` and `right(...)` are functions, not joins
+        let keyword = KEYWORDS.contains(&upper.as_str())
+            && !(matches!(upper.as_str(), "LEFT" | "RIGHT") && next == Some(Token::Punct('(')));
+        if !keyword {
+            self.push(Token::Word(word), word);
+            self.prev_keyword = false;
+            return;
+        }
+
+        let prev_word = self.prev_word.take().unwrap_or_default();
+        let mut newline = None;
+        let frame = self.frame();
+        let layout = frame.kind != FrameKind::Inline && frame.case_depth == 0;
+        let indent = frame.indent;
+
+        match upper.as_str() {
+            "CASE" => frame.case_depth += 1,
+            "END" => frame.case_depth = frame.case_depth.saturating_sub(1),
+            "BETWEEN" => frame.between = true,
+            "AND" if frame.between => frame.between = false,
+            "AND" | "OR" if layout => newline = Some(indent + 1),
+            clause if CLAUSES.contains(&clause) => {
+                let continues = match clause {
+                    "JOIN" | "LEFT" | "RIGHT" | "INNER" | "FULL" | "CROSS" => {
+                        JOIN_MODIFIERS.contains(&prev_word.as_str())
+                    }
+                    // e.g. `DELETE FROM`, `IS DISTINCT FROM`
+                    "FROM" => matches!(prev_word.as_str(), "DELETE" | "DISTINCT"),
+                    // e.g. `FOR UPDATE`, `ON CONFLICT DO UPDATE`, `ON DELETE CASCADE`
+                    "UPDATE" | "DELETE" => matches!(prev_word.as_str(), "FOR" | "DO" | "ON"),
+                    "SET" => frame.clause.as_deref() != Some("UPDATE"),
+                    "VALUES" => prev_word == "DEFAULT",
+                    "GROUP" => prev_word == "WITHIN",
+                    _ => false,
+                };
+                frame.clause = Some(upper.clone());
+                if layout && !continues {
+                    newline = Some(indent);
+                }
+            }
+            _ => {}
+        }
+
+        if newline.is_some() {
+            self.newline = newline;
+        }
+
+        self.push(Token::Word(word), &upper);
+        self.prev_keyword = true;
+        self.relation = RELATION_KEYWORDS.contains(&upper.as_str());
+        self.prev_word = Some(upper);
+    }
+
+    fn push(&mut self, token: Token<'a>, text: &str) {
+        if let Some(indent) = self.newline.take() {
+            if !self.out.is_empty() {
+                self.out.push('\n');
+                if std::mem::take(&mut self.blank_line) {
+                    self.out.push('\n');
+                }
+            }
+            self.out.push_str(&INDENT.repeat(indent));
+            self.line_indent = indent;
+        } else if let Some(prev) = self.prev
+            && self.needs_space(prev, token)
+        {
+            self.out.push(' ');
+        }
+
+        self.out.push_str(text);
+        self.prev = Some(token);
+        self.prev_unary = false;
+        if !matches!(token, Token::Word(_)) {
+            self.prev_keyword = false;
+        }
+        if !matches!(token, Token::Word(_) | Token::Quoted(_) | Token::Punct('.')) {
+            self.relation = false;
+        }
+    }
+
+    fn needs_space(&self, prev: Token, token: Token) -> bool {
+        match (prev, token) {
+            // never join two operators, which could form a different operator or a comment
+            (Token::Operator(_), Token::Operator(_)) => {
+                !matches!(prev, Token::Operator("::" | ":"))
+                    && !matches!(token, Token::Operator("::" | ":"))
+            }
+            (_, Token::Punct(',' | ';' | ')' | ']' | '.' | '[')) => false,
+            (Token::Punct('(' | '[' | '.'), _) => false,
+            (Token::Operator("::" | ":"), _) | (_, Token::Operator("::" | ":")) => false,
+            (Token::Operator(_), _) if self.prev_unary => false,
+            // function calls, e.g. `count(*)`, but keep `IN (...)`, `VALUES (...)`, etc.
+            (Token::Word(_) | Token::Quoted(_), Token::Punct('(')) => {
+                self.prev_keyword || self.relation
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_formats(sql: &str, expected: &str) {
+        let formatted = format(sql);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted, "formatting isn't idempotent");
+    }
+
+    #[test]
+    fn formats_clauses_and_conditions() {
+        assert_formats(
+            "select id, count(*) as n from users u left outer join orders o on o.user_id = u.id \
+             where u.active and o.total between 1 and 10 or o.total is null \
+             group by id order by n desc limit 10",
+            "SELECT id, count(*) AS n\n\
+             FROM users u\n\
+             LEFT OUTER JOIN orders o ON o.user_id = u.id\n\
+             WHERE u.active\n  AND o.total BETWEEN 1 AND 10\n  OR o.total IS NULL\n\
+             GROUP BY id\n\
+             ORDER BY n DESC\n\
+             LIMIT 10",
+        );
+    }
+
+    #[test]
+    fn formats_subqueries() {
+        assert_formats(
+            "with recent as (select * from orders where created_at > now() - interval '1 day') \
+             select u.* from users u where u.id in (select user_id from recent) and u.id = any($1::int[])",
+            "WITH recent AS (\n  \
+               SELECT *\n  \
+               FROM orders\n  \
+               WHERE created_at > now() - interval '1 day'\n\
+             )\n\
+             SELECT u.*\n\
+             FROM users u\n\
+             WHERE u.id IN (\n  \
+               SELECT user_id\n  \
+               FROM recent\n\
+             )\n  \
+             AND u.id = ANY ($1::int[])",
+        );
+    }
+
+    #[test]
+    fn preserves_comments_and_literals() {
+        assert_formats(
+            "-- active users\nselect name, -- display name\n  'it''s' as \"Select\", \
+             E'a\\'b', $$ select  1 $$, -1.5e-3 /* inline */ from users;\n\n\
+             update users set name = left(name, 3) where id = -1 returning *;",
+            "-- active users\n\
+             SELECT name, -- display name\n  \
+               'it''s' AS \"Select\", E'a\\'b', $$ select  1 $$, -1.5e-3 /* inline */\n\
+             FROM users;\n\
+             \n\
+             UPDATE users\n\
+             SET name = left(name, 3)\n\
+             WHERE id = -1\n\
+             RETURNING *;",
+        );
+    }
+
+    #[test]
+    fn formats_non_ascii_text_after_a_dollar_sign() {
+        // not a dollar-quoted string, but mustn't be sliced mid-character to find out
+        assert_formats("select $€, 'x$é'", "SELECT $ €, 'x$é'");
+    }
+
+    #[test]
+    fn keeps_inline_expressions_together() {
+        assert_formats(
+            "SELECT CASE WHEN a AND b THEN 1 ELSE 0 END, rank() over (partition by a order by b), \
+             x - -1 FROM t",
+            "SELECT CASE WHEN a AND b THEN 1 ELSE 0 END, rank() OVER (PARTITION BY a ORDER BY b), \
+             x - -1\n\
+             FROM t",
+        );
+    }
+
+    #[test]
+    fn spaces_column_lists_from_relation_names() {
+        assert_formats(
+            "insert into public.users(id,name) values (1,'a') on conflict (id) do nothing; \
+             create table if not exists \"Log\"(id int references users(id))",
+            "INSERT INTO public.users (id, name)\n\
+             VALUES (1, 'a') ON CONFLICT (id) DO NOTHING;\n\
+             \n\
+             CREATE TABLE IF NOT EXISTS \"Log\" (id int REFERENCES users (id))",
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Decoding for geometric column types: Postgres' built-in `point`, and PostGIS'
+//! `geometry` (which is rendered as EWKT, e.g. `SRID=4326;POINT(1 2)`). The other
+//! built-in geometric types (`line`, `box`, `polygon`, etc.) aren't decoded, so they
+//! fall back on TEXT encoding (see `db::TextFallback`).
+
+use std::{error::Error, fmt::Write};
+use tokio_postgres::types::{FromSql, Type};
+
+type FromSqlResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// A `point`, which is serialized as `{ "x": ..., "y": ... }`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl<'a> FromSql<'a> for Point {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() != 16 {
+            return Err("invalid point".into());
+        }
+        Ok(Self {
+            x: f64::from_be_bytes(raw[..8].try_into()?),
+            y: f64::from_be_bytes(raw[8..].try_into()?),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::POINT
+    }
+}
+
+impl From<Point> for serde_json::Value {
+    fn from(point: Point) -> Self {
+        serde_json::json!({ "x": point.x, "y": point.y })
+    }
+}
+
+/// A PostGIS `geometry`, decoded from its binary (EWKB) representation into EWKT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ewkt(pub String);
+
+impl<'a> FromSql<'a> for Ewkt {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let mut out = String::new();
+        Ewkb(raw).write_geometry(&mut out, None)?;
+        Ok(Self(out))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "geometry"
+    }
+}
+
+const WKB_Z: u32 = 0x8000_0000;
+const WKB_M: u32 = 0x4000_0000;
+const WKB_SRID: u32 = 0x2000_0000;
+
+const POINT: u32 = 1;
+const MULTI_POINT: u32 = 4;
+const GEOMETRY_COLLECTION: u32 = 7;
+
+/// A reader over (E)WKB bytes.
+struct Ewkb<'a>(&'a [u8]);
+
+impl Ewkb<'_> {
+    fn take<const N: usize>(&mut self) -> FromSqlResult<[u8; N]> {
+        let (bytes, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or("truncated geometry")?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u32(&mut self, little_endian: bool) -> FromSqlResult<u32> {
+        let bytes = self.take()?;
+        Ok(match little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self, little_endian: bool) -> FromSqlResult<f64> {
+        let bytes = self.take()?;
+        Ok(match little_endian {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        })
+    }
+
+    /// Write the geometry as EWKT. `parent` is the type of the enclosing geometry, if
+    /// any: only the outermost geometry has an SRID, and members of `MULTI*` geometries
+    /// don't repeat their type (e.g. `MULTIPOINT(1 2,3 4)`).
+    fn write_geometry(&mut self, out: &mut String, parent: Option<u32>) -> FromSqlResult<()> {
+        let little_endian = self.take::<1>()? == [1];
+        let raw_kind = self.u32(little_endian)?;
+
+        // EWKB flags dimensions with high bits, ISO WKB by adding 1000/2000/3000
+        let mut z = raw_kind & WKB_Z != 0;
+        let mut m = raw_kind & WKB_M != 0;
+        let kind = raw_kind & 0x0fff_ffff;
+        z |= matches!(kind / 1000, 1 | 3);
+        m |= matches!(kind / 1000, 2 | 3);
+        let kind = kind % 1000;
+        let dims = 2 + usize::from(z) + usize::from(m);
+
+        if raw_kind & WKB_SRID != 0 {
+            let srid = self.u32(little_endian)?;
+            if parent.is_none() {
+                write!(out, "SRID={srid};")?;
+            }
+        }
+
+        if matches!(parent, None | Some(GEOMETRY_COLLECTION)) {
+            out.push_str(match kind {
+                1 => "POINT",
+                2 => "LINESTRING",
+                3 => "POLYGON",
+                4 => "MULTIPOINT",
+                5 => "MULTILINESTRING",
+                6 => "MULTIPOLYGON",
+                7 => "GEOMETRYCOLLECTION",
+                _ => return Err(format!("unsupported geometry type {kind}").into()),
+            });
+            // EWKT spells out measured (but not 3D) geometries, e.g. `POINTM(1 2 3)`
+            if m && !z {
+                out.push('M');
+            }
+        }
+
+        match kind {
+            POINT => {
+                let coords = (0..dims)
+                    .map(|_| self.f64(little_endian))
+                    .collect::<FromSqlResult<Vec<_>>>()?;
+                if coords.iter().all(|c| c.is_nan()) {
+                    out.push_str(" EMPTY");
+                } else if parent == Some(MULTI_POINT) {
+                    write_coords(out, &coords);
+                } else {
+                    out.push('(');
+                    write_coords(out, &coords);
+                    out.push(')');
+                }
+            }
+            2 => self.write_points(out, little_endian, dims)?,
+            3 => {
+                let rings = self.u32(little_endian)?;
+                self.write_list(out, rings, |this, out| {
+                    this.write_points(out, little_endian, dims)
+                })?;
+            }
+            _ => {
+                let members = self.u32(little_endian)?;
+                self.write_list(out, members, |this, out| {
+                    this.write_geometry(out, Some(kind))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a parenthesized list of points, preceded by a point count.
+    fn write_points(
+        &mut self,
+        out: &mut String,
+        little_endian: bool,
+        dims: usize,
+    ) -> FromSqlResult<()> {
+        let points = self.u32(little_endian)?;
+        self.write_list(out, points, |this, out| {
+            let coords = (0..dims)
+                .map(|_| this.f64(little_endian))
+                .collect::<FromSqlResult<Vec<_>>>()?;
+            write_coords(out, &coords);
+            Ok(())
+        })
+    }
+
+    /// Write `len` comma-separated items in parentheses, or `EMPTY` if there are none.
+    fn write_list(
+        &mut self,
+        out: &mut String,
+        len: u32,
+        mut write_item: impl FnMut(&mut Self, &mut String) -> FromSqlResult<()>,
+    ) -> FromSqlResult<()> {
+        if len == 0 {
+            out.push_str(" EMPTY");
+            return Ok(());
+        }
+
+        out.push('(');
+        for idx in 0..len {
+            if idx > 0 {
+                out.push(',');
+            }
+            write_item(self, out)?;
+        }
+        out.push(')');
+        Ok(())
+    }
+}
+
+fn write_coords(out: &mut String, coords: &[f64]) {
+    let coords = coords.iter().map(f64::to_string).collect::<Vec<_>>();
+    out.push_str(&coords.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ewkt(hex_str: &str) -> String {
+        let raw = hex::decode(hex_str).unwrap();
+        Ewkt::from_sql(&Type::BYTEA, &raw).unwrap().0
+    }
+
+    #[test]
+    fn decodes_point() {
+        // SELECT point(1.5, -2)
+        let raw = [1.5f64.to_be_bytes(), (-2f64).to_be_bytes()].concat();
+        let point = Point::from_sql(&Type::POINT, &raw).unwrap();
+        assert_eq!(point, Point { x: 1.5, y: -2.0 });
+        assert_eq!(
+            serde_json::Value::from(point),
+            serde_json::json!({ "x": 1.5, "y": -2.0 })
+        );
+        assert!(Point::from_sql(&Type::POINT, &raw[..8]).is_err());
+    }
+
+    #[test]
+    fn decodes_geometry_as_ewkt() {
+        // SELECT 'SRID=4326;POINT(1 2)'::geometry
+        assert_eq!(
+            ewkt("0101000020E6100000000000000000F03F0000000000000040"),
+            "SRID=4326;POINT(1 2)"
+        );
+        // SELECT 'MULTIPOINT(1 2,3 4)'::geometry
+        assert_eq!(
+            ewkt(
+                "0104000000020000000101000000000000000000F03F0000000000000040\
+                 010100000000000000000008400000000000001040"
+            ),
+            "MULTIPOINT(1 2,3 4)"
+        );
+        // SELECT 'POLYGON((0 0,1 0,0 0))'::geometry (big-endian)
+        assert_eq!(
+            ewkt(
+                "00000000030000000100000003\
+                 00000000000000000000000000000000\
+                 3FF00000000000000000000000000000\
+                 00000000000000000000000000000000"
+            ),
+            "POLYGON((0 0,1 0,0 0))"
+        );
+        // SELECT 'LINESTRING EMPTY'::geometry
+        assert_eq!(ewkt("010200000000000000"), "LINESTRING EMPTY");
+    }
+}
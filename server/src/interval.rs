@@ -0,0 +1,296 @@
+//! Binding for `interval` query parameters, which `tokio_postgres` has no encoder for.
+//! Intervals can be given either as an ISO 8601 duration (e.g. `P1DT12H`) or in
+//! Postgres' own format (e.g. `1 day 12 hours`, `1 day 12:00:00`, `3 days ago`).
+
+use std::error::Error;
+use tokio_postgres::types::{IsNull, ToSql, Type, to_sql_checked};
+
+const MICROS_PER_SECOND: f64 = 1_000_000.0;
+const MICROS_PER_DAY: f64 = 86_400.0 * MICROS_PER_SECOND;
+/// Like Postgres, fractional months are converted to days assuming 30 days per month.
+const DAYS_PER_MONTH: f64 = 30.0;
+
+/// An `interval`, stored the same way Postgres does: months, days and microseconds
+/// are kept separately, since they don't convert to one another exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+}
+
+impl Interval {
+    pub fn parse(s: &str) -> eyre::Result<Self> {
+        let trimmed = s.trim();
+        let parsed = match trimmed.strip_prefix('-') {
+            Some(rest) if rest.starts_with(['P', 'p']) => iso8601(rest).map(Parts::negate),
+            _ if trimmed.starts_with(['P', 'p']) => iso8601(trimmed),
+            _ => postgres(trimmed),
+        };
+
+        parsed.and_then(Parts::into_interval).ok_or_else(|| {
+            eyre::eyre!("invalid interval {s:?}, expected e.g. \"1 day 02:30:00\" or \"P1DT2H30M\"")
+        })
+    }
+}
+
+impl ToSql for Interval {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut tokio_postgres::types::private::BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.extend_from_slice(&self.microseconds.to_be_bytes());
+        out.extend_from_slice(&self.days.to_be_bytes());
+        out.extend_from_slice(&self.months.to_be_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+
+    to_sql_checked!();
+}
+
+/// An interval's components before fractional months and days are carried over.
+#[derive(Debug, Default)]
+struct Parts {
+    months: f64,
+    days: f64,
+    micros: f64,
+}
+
+impl Parts {
+    fn add(&mut self, value: f64, unit: &str) -> Option<()> {
+        let (months, days, micros) = match unit {
+            "millennium" | "millennia" | "millenniums" => (12_000.0, 0.0, 0.0),
+            "century" | "centuries" | "c" => (1_200.0, 0.0, 0.0),
+            "decade" | "decades" | "dec" | "decs" => (120.0, 0.0, 0.0),
+            "year" | "years" | "yr" | "yrs" | "y" => (12.0, 0.0, 0.0),
+            "month" | "months" | "mon" | "mons" => (1.0, 0.0, 0.0),
+            "week" | "weeks" | "w" => (0.0, 7.0, 0.0),
+            "day" | "days" | "d" => (0.0, 1.0, 0.0),
+            "hour" | "hours" | "hr" | "hrs" | "h" => (0.0, 0.0, 3_600.0 * MICROS_PER_SECOND),
+            "minute" | "minutes" | "min" | "mins" | "m" => (0.0, 0.0, 60.0 * MICROS_PER_SECOND),
+            "second" | "seconds" | "sec" | "secs" | "s" => (0.0, 0.0, MICROS_PER_SECOND),
+            "millisecond" | "milliseconds" | "msec" | "msecs" | "ms" => (0.0, 0.0, 1_000.0),
+            "microsecond" | "microseconds" | "usec" | "usecs" | "us" => (0.0, 0.0, 1.0),
+            _ => return None,
+        };
+        self.months += value * months;
+        self.days += value * days;
+        self.micros += value * micros;
+        Some(())
+    }
+
+    fn negate(self) -> Self {
+        Self {
+            months: -self.months,
+            days: -self.days,
+            micros: -self.micros,
+        }
+    }
+
+    fn into_interval(self) -> Option<Interval> {
+        let months = self.months.trunc();
+        let days = self.days + (self.months - months) * DAYS_PER_MONTH;
+        let whole_days = days.trunc();
+        let micros = (self.micros + (days - whole_days) * MICROS_PER_DAY).round();
+
+        let fits = |val: f64, min: f64, max: f64| val.is_finite() && (min..=max).contains(&val);
+        if !fits(months, i32::MIN.into(), i32::MAX.into())
+            || !fits(whole_days, i32::MIN.into(), i32::MAX.into())
+            || !fits(micros, i64::MIN as f64, i64::MAX as f64)
+        {
+            return None;
+        }
+
+        Some(Interval {
+            months: months as i32,
+            days: whole_days as i32,
+            microseconds: micros as i64,
+        })
+    }
+}
+
+/// Parse an ISO 8601 duration, e.g. `P1Y2M3DT4H5M6.5S` or `P2W`.
+fn iso8601(s: &str) -> Option<Parts> {
+    let s = s.get(1..)?.to_ascii_uppercase();
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) if !time.is_empty() => (date, Some(time)),
+        Some(_) => return None,
+        None => (s.as_str(), None),
+    };
+
+    let mut parts = Parts::default();
+    let mut any = false;
+    for (section, in_time) in [(date, false), (time.unwrap_or(""), true)] {
+        let mut rest = section;
+        while !rest.is_empty() {
+            let end = rest.find(|c: char| c.is_ascii_alphabetic())?;
+            let value = rest[..end].replace(',', ".").parse::<f64>().ok()?;
+            let unit = match (in_time, rest.as_bytes()[end]) {
+                (false, b'Y') => "year",
+                (false, b'M') => "month",
+                (false, b'W') => "week",
+                (false, b'D') => "day",
+                (true, b'H') => "hour",
+                (true, b'M') => "minute",
+                (true, b'S') => "second",
+                _ => return None,
+            };
+            parts.add(value, unit)?;
+            any = true;
+            rest = &rest[end + 1..];
+        }
+    }
+
+    any.then_some(parts)
+}
+
+/// Parse Postgres' interval format: `<number> <unit>` pairs and/or a `[-]HH:MM[:SS]`
+/// time, optionally followed by `ago`, e.g. `1 year 2 mons -3 days 04:05:06 ago`.
+fn postgres(s: &str) -> Option<Parts> {
+    let mut parts = Parts::default();
+    let mut any = false;
+    let mut tokens = tokenize(s).into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        if token.contains(':') {
+            parts.micros += time(&token)?;
+        } else if token.eq_ignore_ascii_case("ago") {
+            if tokens.peek().is_some() {
+                return None;
+            }
+            parts = parts.negate();
+        } else {
+            let value = token.parse::<f64>().ok()?;
+            let unit = tokens.next()?.to_ascii_lowercase();
+            parts.add(value, &unit)?;
+        }
+        any = true;
+    }
+
+    any.then_some(parts)
+}
+
+/// Split on whitespace, also separating numbers from units written without a space
+/// between them (e.g. `90min`).
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in s.split_whitespace() {
+        match word.find(|c: char| c.is_ascii_alphabetic()) {
+            Some(idx) if idx > 0 && !word.contains(':') => {
+                tokens.extend([word[..idx].to_owned(), word[idx..].to_owned()])
+            }
+            _ => tokens.push(word.to_owned()),
+        }
+    }
+    tokens
+}
+
+/// Parse a `[-]HH:MM[:SS[.ffffff]]` time into microseconds.
+fn time(s: &str) -> Option<f64> {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(s) => (-1.0, s),
+        None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let mut fields = s.split(':');
+    let hours = fields.next()?.parse::<u32>().ok()?;
+    let minutes = fields.next()?.parse::<u32>().ok()?;
+    let seconds = fields.next().map_or(Some(0.0), |s| s.parse::<f64>().ok())?;
+    if fields.next().is_some() || minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+
+    let seconds = f64::from(hours) * 3_600.0 + f64::from(minutes) * 60.0 + seconds;
+    Some(sign * seconds * MICROS_PER_SECOND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(months: i32, days: i32, microseconds: i64) -> Interval {
+        Interval {
+            months,
+            days,
+            microseconds,
+        }
+    }
+
+    const HOUR: i64 = 3_600_000_000;
+
+    #[test]
+    fn parses_postgres_intervals() {
+        assert_eq!(Interval::parse("1 day").unwrap(), interval(0, 1, 0));
+        assert_eq!(
+            Interval::parse("1 year 2 mons -3 days 04:05:06.5").unwrap(),
+            interval(14, -3, 4 * HOUR + 306_500_000)
+        );
+        assert_eq!(
+            Interval::parse("90min").unwrap(),
+            interval(0, 0, 3 * HOUR / 2)
+        );
+        assert_eq!(Interval::parse("2 weeks ago").unwrap(), interval(0, -14, 0));
+        // fractional units carry over into smaller ones
+        assert_eq!(Interval::parse("1.5 months").unwrap(), interval(1, 15, 0));
+        assert_eq!(
+            Interval::parse("1.5 days").unwrap(),
+            interval(0, 1, 12 * HOUR)
+        );
+    }
+
+    #[test]
+    fn parses_iso8601_intervals() {
+        assert_eq!(
+            Interval::parse("P1Y2M3DT4H5M6S").unwrap(),
+            interval(14, 3, 4 * HOUR + 306_000_000)
+        );
+        assert_eq!(
+            Interval::parse("PT1.5H").unwrap(),
+            interval(0, 0, 3 * HOUR / 2)
+        );
+        assert_eq!(Interval::parse("P2W").unwrap(), interval(0, 14, 0));
+        assert_eq!(Interval::parse("-P1D").unwrap(), interval(0, -1, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_intervals() {
+        for s in [
+            "",
+            "1",
+            "day",
+            "1 fortnight",
+            "P",
+            "PT",
+            "P1H",
+            "1 day ago 2 hours",
+            "1:75",
+        ] {
+            let err = Interval::parse(s).unwrap_err();
+            assert!(err.to_string().starts_with("invalid interval"), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn encodes_binary_interval() {
+        use tokio_postgres::types::private::BytesMut;
+
+        let mut buf = BytesMut::new();
+        interval(14, 3, 5)
+            .to_sql_checked(&Type::INTERVAL, &mut buf)
+            .unwrap();
+        assert_eq!(
+            &buf[..],
+            [
+                5i64.to_be_bytes().as_slice(),
+                &3i32.to_be_bytes(),
+                &14i32.to_be_bytes()
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! Queries run in the background (see `POST /query/async`), for queries that take
+//! too long to hold a request open for. Clients poll a job for its result, which is
+//! kept for `JOB_TTL` after the job finishes.
+
+use crate::{ConnectionKey, db};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tokio::{sync::Mutex, task::AbortHandle};
+
+/// How long a finished job's result is kept before it's discarded.
+pub const JOB_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// The most jobs kept at once, counting finished ones whose results haven't expired.
+pub const MAX_JOBS: usize = 100;
+
+/// The most unfinished jobs at once on each connection pool, so that background
+/// queries can't take up all of a pool's connections.
+pub const MAX_JOBS_PER_POOL: usize = 4;
+
+/// The largest result (serialized as JSON, in bytes) kept for a job; a job with a
+/// larger result fails instead.
+pub const MAX_RESULT_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for a connection.
+    Pending,
+    Running,
+    Done {
+        result: serde_json::Value,
+    },
+    Error {
+        message: String,
+        /// The SQLSTATE code, for errors raised by Postgres.
+        code: Option<String>,
+    },
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+struct Job {
+    pool: ConnectionKey,
+    status: JobStatus,
+    /// Aborts the job's task, while it's waiting for a connection.
+    task: Option<AbortHandle>,
+    /// Cancels the job's query, while it has a connection (see `JobConnection`).
+    canceller: Arc<Mutex<Option<db::Canceller>>>,
+    finished_at: Option<Instant>,
+}
+
+/// Returned by `Jobs::submit` when there are already too many jobs.
+#[derive(Debug)]
+pub struct TooManyJobs {
+    limit: usize,
+    per_pool: bool,
+}
+
+impl std::fmt::Display for TooManyJobs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = if self.per_pool {
+            " running on this connection"
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "too many background queries{scope} (at most {}); wait for one to finish, \
+             or cancel it",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for TooManyJobs {}
+
+/// Background query jobs, by id.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+/// A running job's handle on its own state, passed to the job's task.
+pub struct JobContext {
+    id: String,
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl JobContext {
+    /// Mark the job as running on `conn`, which cancelling the job cancels the query on
+    /// for as long as the returned `JobConnection` holds it. Fails if the job was
+    /// cancelled while it was waiting for the connection.
+    pub async fn started<C>(&self, conn: C) -> eyre::Result<JobConnection<C>>
+    where
+        C: std::ops::Deref<Target = db::Connection> + Send + 'static,
+    {
+        let mut jobs = self.jobs.lock().await;
+        match jobs.get_mut(&self.id) {
+            Some(job) if job.status == JobStatus::Pending => {
+                job.status = JobStatus::Running;
+                *job.canceller.lock().await = Some(conn.canceller());
+                Ok(JobConnection {
+                    conn: Some(conn),
+                    canceller: Arc::clone(&job.canceller),
+                })
+            }
+            _ => eyre::bail!("job {} was cancelled", self.id),
+        }
+    }
+}
+
+/// A job's connection. Until it's dropped, `Jobs::cancel` cancels the query running on
+/// it; once it's dropped, the connection is only let go of after any cancellation in
+/// progress is done, so that cancelling a job never reaches whoever uses the
+/// connection next.
+pub struct JobConnection<C: Send + 'static> {
+    conn: Option<C>,
+    canceller: Arc<Mutex<Option<db::Canceller>>>,
+}
+
+impl<C: Send + 'static> std::ops::Deref for JobConnection<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<C: Send + 'static> Drop for JobConnection<C> {
+    fn drop(&mut self) {
+        let conn = self.conn.take();
+        let canceller = Arc::clone(&self.canceller);
+        tokio::spawn(async move {
+            canceller.lock().await.take();
+            drop(conn);
+        });
+    }
+}
+
+impl Jobs {
+    /// Start a job, which runs `run` on a connection from `pool` in the background,
+    /// and return its id. The job's result is whatever `run` resolves to. Fails if
+    /// there are already `MAX_JOBS` jobs, or `MAX_JOBS_PER_POOL` unfinished jobs on
+    /// `pool`.
+    pub async fn submit<F>(
+        &self,
+        pool: ConnectionKey,
+        run: impl FnOnce(JobContext) -> F,
+    ) -> Result<String, TooManyJobs>
+    where
+        F: Future<Output = eyre::Result<serde_json::Value>> + Send + 'static,
+    {
+        let id = new_job_id();
+        let mut jobs = self.jobs.lock().await;
+        purge_expired(&mut jobs);
+        if jobs.len() >= MAX_JOBS {
+            return Err(TooManyJobs {
+                limit: MAX_JOBS,
+                per_pool: false,
+            });
+        }
+        let unfinished = jobs
+            .values()
+            .filter(|job| job.pool == pool && !job.status.is_finished())
+            .count();
+        if unfinished >= MAX_JOBS_PER_POOL {
+            return Err(TooManyJobs {
+                limit: MAX_JOBS_PER_POOL,
+                per_pool: true,
+            });
+        }
+        jobs.insert(
+            id.clone(),
+            Job {
+                pool,
+                status: JobStatus::Pending,
+                task: None,
+                canceller: Default::default(),
+                finished_at: None,
+            },
+        );
+
+        let run = run(JobContext {
+            id: id.clone(),
+            jobs: Arc::clone(&self.jobs),
+        });
+        let (job_id, all_jobs) = (id.clone(), Arc::clone(&self.jobs));
+        let task = tokio::spawn(async move {
+            let result = run.await.and_then(|result| {
+                let size = serde_json::to_vec(&result)?.len();
+                if size > MAX_RESULT_SIZE {
+                    eyre::bail!(
+                        "the result is too large to keep ({size} bytes, at most \
+                         {MAX_RESULT_SIZE}); try a smaller page_size"
+                    );
+                }
+                Ok(result)
+            });
+            let mut jobs = all_jobs.lock().await;
+            // a job that was cancelled stays cancelled, however its query ended
+            let Some(job) = jobs
+                .get_mut(&job_id)
+                .filter(|job| !job.status.is_finished())
+            else {
+                return;
+            };
+            job.status = match result {
+                Ok(result) => JobStatus::Done { result },
+                Err(err) => JobStatus::Error {
+                    code: err
+                        .downcast_ref::<db::PgError>()
+                        .and_then(db::PgError::code)
+                        .cloned(),
+                    message: err.to_string(),
+                },
+            };
+            job.task = None;
+            job.finished_at = Some(Instant::now());
+        });
+
+        // the task can't finish before this is set, since it needs the lock to finish
+        jobs.get_mut(&id).unwrap().task = Some(task.abort_handle());
+        Ok(id)
+    }
+
+    /// Get a job's status (with its result, once it's done), if it exists.
+    pub async fn status(&self, id: &str) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().await;
+        purge_expired(&mut jobs);
+        jobs.get(id).map(|job| job.status.clone())
+    }
+
+    /// Cancel a job that hasn't finished, or discard the result of one that has.
+    /// Returns whether the job existed.
+    pub async fn cancel(&self, id: &str) -> eyre::Result<bool> {
+        let mut jobs = self.jobs.lock().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return Ok(false);
+        };
+        if job.status.is_finished() {
+            jobs.remove(id);
+            return Ok(true);
+        }
+
+        if let Some(task) = job.task.take() {
+            // a running job's task is left to finish, returning its connection to the
+            // pool once the query is cancelled
+            if job.status == JobStatus::Pending {
+                task.abort();
+            }
+        }
+        job.status = JobStatus::Cancelled;
+        job.finished_at = Some(Instant::now());
+        let canceller = Arc::clone(&job.canceller);
+        drop(jobs);
+
+        // the job's connection isn't let go of until this is done (see `JobConnection`)
+        if let Some(canceller) = canceller.lock().await.as_ref() {
+            canceller.cancel().await?;
+        }
+        Ok(true)
+    }
+}
+
+fn purge_expired(jobs: &mut HashMap<String, Job>) {
+    jobs.retain(|_, job| job.finished_at.is_none_or(|at| at.elapsed() < JOB_TTL));
+}
+
+/// A random, hex-encoded id that can't be guessed from other jobs' ids.
+fn new_job_id() -> String {
+    use aes_gcm::aead::{OsRng, rand_core::RngCore};
+
+    let mut id = [0; 16];
+    OsRng.fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pool() -> ConnectionKey {
+        ConnectionKey {
+            connection: "jobs".to_owned(),
+            database: "postgres".to_owned(),
+        }
+    }
+
+    async fn wait_until_finished(jobs: &Jobs, id: &str) -> JobStatus {
+        loop {
+            let status = jobs.status(id).await.unwrap();
+            if status.is_finished() {
+                return status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_queries_in_the_background() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let jobs = Jobs::default();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let id = jobs
+            .submit(test_pool(), |job| async move {
+                // don't start until the job's been seen waiting for a connection
+                rx.await?;
+                let conn = job.started(Arc::new(db::connect(&config).await?)).await?;
+                let row = conn.query_one("SELECT 42::int4", &[]).await?;
+                Ok(serde_json::json!(row.get::<_, i32>(0)))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(jobs.status(&id).await, Some(JobStatus::Pending));
+        tx.send(()).unwrap();
+        assert_eq!(
+            wait_until_finished(&jobs, &id).await,
+            JobStatus::Done {
+                result: serde_json::json!(42)
+            }
+        );
+
+        // the result is discarded once it's been cancelled
+        assert!(jobs.cancel(&id).await.unwrap());
+        assert_eq!(jobs.status(&id).await, None);
+        assert!(!jobs.cancel(&id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reports_failed_queries() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let jobs = Jobs::default();
+        let id = jobs
+            .submit(test_pool(), |job| async move {
+                let conn = job.started(Arc::new(db::connect(&config).await?)).await?;
+                conn.batch_execute("SELECT 1 / 0")
+                    .await
+                    .map_err(db::PgError::from)?;
+                Ok(serde_json::Value::Null)
+            })
+            .await
+            .unwrap();
+
+        let JobStatus::Error { code, .. } = wait_until_finished(&jobs, &id).await else {
+            panic!("expected the job to fail");
+        };
+        assert_eq!(code.as_deref(), Some("22012"));
+    }
+
+    #[tokio::test]
+    async fn cancels_running_queries() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let jobs = Jobs::default();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let id = jobs
+            .submit(test_pool(), |job| async move {
+                let conn = job.started(Arc::new(db::connect(&config).await?)).await?;
+                let result = conn.batch_execute("SELECT pg_sleep(30)").await;
+                let _ = tx.send(result.map_err(db::PgError::from));
+                Ok(serde_json::Value::Null)
+            })
+            .await
+            .unwrap();
+
+        while jobs.status(&id).await != Some(JobStatus::Running) {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(jobs.cancel(&id).await.unwrap());
+        assert_eq!(jobs.status(&id).await, Some(JobStatus::Cancelled));
+
+        // the query itself was cancelled, rather than left running
+        let err = tokio::time::timeout(std::time::Duration::from_secs(5), rx)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code().map(String::as_str), Some("57014"));
+        assert_eq!(jobs.status(&id).await, Some(JobStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn cancels_pending_jobs() {
+        let jobs = Jobs::default();
+        let id = jobs
+            .submit(test_pool(), |_| async {
+                // never gets a connection
+                std::future::pending::<()>().await;
+                Ok(serde_json::Value::Null)
+            })
+            .await
+            .unwrap();
+
+        assert!(jobs.cancel(&id).await.unwrap());
+        assert_eq!(jobs.status(&id).await, Some(JobStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn doesnt_cancel_queries_on_connections_the_job_let_go_of() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let conn = Arc::new(db::connect(&config).await.unwrap());
+        let jobs = Jobs::default();
+        let job_conn = Arc::clone(&conn);
+        let id = jobs
+            .submit(test_pool(), |job| async move {
+                drop(job.started(job_conn).await?);
+                // keep running, without the connection
+                std::future::pending::<()>().await;
+                Ok(serde_json::Value::Null)
+            })
+            .await
+            .unwrap();
+
+        // wait until the job has let go of the connection...
+        loop {
+            let canceller = Arc::clone(&jobs.jobs.lock().await[&id].canceller);
+            if jobs.status(&id).await == Some(JobStatus::Running)
+                && canceller.lock().await.is_none()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // ...which someone else is now using
+        let query = conn.batch_execute("SELECT pg_sleep(0.2)");
+        let cancel = async {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            jobs.cancel(&id).await
+        };
+        let (result, cancelled) = tokio::join!(query, cancel);
+        assert!(cancelled.unwrap());
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_unfinished_jobs_per_pool() {
+        let jobs = Jobs::default();
+        let submit = |pool| {
+            jobs.submit(pool, |_| async {
+                std::future::pending::<()>().await;
+                Ok(serde_json::Value::Null)
+            })
+        };
+
+        let mut ids = Vec::new();
+        for _ in 0..MAX_JOBS_PER_POOL {
+            ids.push(submit(test_pool()).await.unwrap());
+        }
+        let err = submit(test_pool()).await.unwrap_err();
+        assert!(err.per_pool, "{err}");
+
+        // other pools aren't affected, and cancelled jobs don't count
+        let other_pool = ConnectionKey {
+            database: "other".to_owned(),
+            ..test_pool()
+        };
+        submit(other_pool).await.unwrap();
+        jobs.cancel(&ids[0]).await.unwrap();
+        submit(test_pool()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_jobs_with_oversized_results() {
+        let jobs = Jobs::default();
+        let id = jobs
+            .submit(test_pool(), |_| async {
+                Ok(serde_json::json!("x".repeat(MAX_RESULT_SIZE)))
+            })
+            .await
+            .unwrap();
+
+        let JobStatus::Error { message, code } = wait_until_finished(&jobs, &id).await else {
+            panic!("expected the job to fail");
+        };
+        assert!(message.starts_with("the result is too large"), "{message}");
+        assert_eq!(code, None);
+    }
+}
@@ -1,12 +1,19 @@
 use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::{Arc, OnceLock},
 };
 use tokio::sync::{Mutex, Notify, RwLock, oneshot};
 
+pub mod cache;
 pub mod db;
+pub mod format;
+pub mod geometry;
+pub mod interval;
+pub mod jobs;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod persistence;
 pub mod pool;
 pub mod server;
@@ -56,20 +63,37 @@ impl PoolState {
         }
     }
 
-    /// Returns a tuple of `(status, status_message)`.
-    pub async fn status(&mut self) -> eyre::Result<(PoolStatus, String)> {
+    /// Returns a tuple of `(status, status_message, host, encoding_warning)`: the pool's
+    /// status, a message describing it (the server version, once it's active), and, only
+    /// when it's active, the host that its connections are open to and any warning about
+    /// their encoding.
+    pub async fn status(
+        &mut self,
+    ) -> eyre::Result<(PoolStatus, String, Option<String>, Option<String>)> {
         match self {
             PoolState::Active(pool) => {
                 if pool.is_unstable().await {
-                    return Ok((PoolStatus::Active, "connection is unstable".to_string()));
+                    return Ok((
+                        PoolStatus::Active,
+                        "connection is unstable".to_string(),
+                        None,
+                        None,
+                    ));
                 }
 
                 let conn = pool.get_conn().await?;
                 let version_info = crate::db::version_info(&conn).await?;
-                Ok((PoolStatus::Active, version_info))
+                Ok((
+                    PoolStatus::Active,
+                    version_info,
+                    Some(conn.host().to_owned()),
+                    conn.encoding_warning().map(str::to_owned),
+                ))
             }
-            PoolState::Pending { .. } => Ok((PoolStatus::Pending, "connecting...".to_string())),
-            PoolState::Failed(err) => Ok((PoolStatus::Failed, err.clone())),
+            PoolState::Pending { .. } => {
+                Ok((PoolStatus::Pending, "connecting...".to_string(), None, None))
+            }
+            PoolState::Failed(err) => Ok((PoolStatus::Failed, err.clone(), None, None)),
         }
     }
 }
@@ -77,6 +101,52 @@ impl PoolState {
 pub struct State {
     pub pools: Mutex<HashMap<ConnectionKey, PoolState>>,
     pub config: RwLock<persistence::Store>,
+    /// Temporary databases created via `create_scratch_database`, which
+    /// should be dropped before shutting down.
+    pub scratch_databases: Mutex<HashSet<ConnectionKey>>,
+    /// Pooled connections pinned to a session id (see `session_conn`).
+    pub sessions: Mutex<HashMap<String, Session>>,
+    /// Queries running in the background (see `POST /query/async`).
+    pub jobs: jobs::Jobs,
+}
+
+/// What was closed by `State::reset_pools`.
+#[derive(Debug, Default, Serialize)]
+pub struct PoolReset {
+    pub pools: usize,
+    /// Connections open across all of the pools, including any that were checked out.
+    pub connections: usize,
+    pub sessions: usize,
+}
+
+/// A pooled connection that's kept checked out for a session, so that session
+/// state (e.g. temp tables) carries over from one query to the next.
+pub struct Session {
+    key: ConnectionKey,
+    conn: Arc<Mutex<pool::CheckedOutConnection>>,
+    last_used: std::time::Instant,
+}
+
+/// Sessions that haven't run a query in this long are ended, so that abandoned
+/// sessions don't hold on to their connection forever.
+const SESSION_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// How often `end_idle_sessions` is run.
+pub const SESSION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+impl Session {
+    /// Roll back any open transaction and discard the session's temp tables, then
+    /// return its connection to the pool (waiting for any query that's still running
+    /// on it to finish).
+    async fn end(self) -> eyre::Result<()> {
+        let conn = self.conn.lock().await;
+        // `DISCARD TEMP` can't be run inside a transaction block
+        conn.rollback_open_transaction().await?;
+        conn.batch_execute("DISCARD TEMP")
+            .await
+            .map_err(db::PgError::from)?;
+        Ok(())
+    }
 }
 
 impl State {
@@ -85,16 +155,139 @@ impl State {
         &self,
         connection: String,
     ) -> eyre::Result<pool::CheckedOutConnection> {
+        self.get_conn_or_default(connection, None).await
+    }
+
+    /// Get a connection to `database`, or to the connection's default database if
+    /// none is given.
+    pub async fn get_conn_or_default(
+        &self,
+        connection: String,
+        database: Option<String>,
+    ) -> eyre::Result<pool::CheckedOutConnection> {
+        let database = self
+            .config
+            .read()
+            .await
+            .resolve_database(&connection, database)?;
+
+        self.get_conn(connection, database).await
+    }
+
+    /// Get the connection pinned to session `id`, checking one out for the given
+    /// connection and database if the session doesn't exist yet. Every query run in
+    /// a session runs on the same connection, one at a time.
+    pub async fn session_conn(
+        &self,
+        id: &str,
+        connection: String,
+        database: String,
+    ) -> eyre::Result<Arc<Mutex<pool::CheckedOutConnection>>> {
+        let key = ConnectionKey {
+            connection,
+            database,
+        };
+
+        self.end_idle_sessions().await;
+
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = sessions.get_mut(id) {
+            if session.key != key {
+                eyre::bail!(
+                    "session {id} is connected to db \"{}\" on conn \"{}\"",
+                    session.key.database,
+                    session.key.connection
+                );
+            }
+            session.last_used = std::time::Instant::now();
+            return Ok(Arc::clone(&session.conn));
+        }
+
+        let max_sessions = self.config.read().await.settings.max_sessions_per_pool;
+        let check_session_limit = |sessions: &HashMap<String, Session>| {
+            if sessions.values().filter(|s| s.key == key).count() >= max_sessions {
+                eyre::bail!(
+                    "too many sessions on db \"{}\" on conn \"{}\" (at most \
+                     {max_sessions}); end one first",
+                    key.database,
+                    key.connection
+                );
+            }
+            Ok(())
+        };
+        check_session_limit(&sessions)?;
+
+        // don't hold up other sessions while waiting for a connection
+        drop(sessions);
+        let conn = self
+            .get_conn(key.connection.clone(), key.database.clone())
+            .await?;
+
+        // if the same session was started concurrently, the first one wins (and this
+        // connection is returned to the pool)
+        let mut sessions = self.sessions.lock().await;
+        if !sessions.contains_key(id) {
+            check_session_limit(&sessions)?;
+        }
+        let session = sessions.entry(id.to_owned()).or_insert_with(|| Session {
+            key,
+            conn: Arc::new(Mutex::new(conn)),
+            last_used: std::time::Instant::now(),
+        });
+        Ok(Arc::clone(&session.conn))
+    }
+
+    /// End every session that hasn't run a query in `SESSION_IDLE_TIMEOUT`. This runs
+    /// every `SESSION_SWEEP_INTERVAL`, as well as whenever a session is used.
+    pub async fn end_idle_sessions(&self) {
+        let mut sessions = self.sessions.lock().await;
+        let expired = sessions
+            .iter()
+            .filter(|(_, session)| session.last_used.elapsed() > SESSION_IDLE_TIMEOUT)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for expired_id in expired {
+            let session = sessions.remove(&expired_id).unwrap();
+            tracing::info!("ending idle session {expired_id}");
+            tokio::spawn(async move {
+                if let Err(err) = session.end().await {
+                    tracing::warn!("failed to end idle session {expired_id}: {err}");
+                }
+            });
+        }
+    }
+
+    /// End session `id`, discarding its temp tables and returning its connection to
+    /// the pool. Returns whether the session existed.
+    pub async fn end_session(&self, id: &str) -> eyre::Result<bool> {
+        let session = self.sessions.lock().await.remove(id);
+        match session {
+            Some(session) => session.end().await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Open a dedicated connection to the given database, outside of any pool. Any notices
+    /// or notifications received are forwarded to `messages`. The connection is closed
+    /// once dropped.
+    pub async fn connect_unpooled(
+        &self,
+        connection: String,
+        database: String,
+        messages: tokio::sync::mpsc::UnboundedSender<tokio_postgres::AsyncMessage>,
+    ) -> eyre::Result<db::Connection> {
         let config = self.config.read().await;
-        let conn = config
+        let mut conn = config
             .connections
             .iter()
             .find(|c| c.name == connection)
+            .cloned()
             .ok_or(eyre::eyre!("no connection named {}", connection))?;
-        let database = conn.database.clone();
         drop(config);
 
-        self.get_conn(connection, database).await
+        conn.database = database;
+        conn.load_password().await?;
+        db::connect_with_messages(&(&conn).into(), Some(messages)).await
     }
 
     /// Check out a database connection from the pool for the given connection name.
@@ -110,6 +303,8 @@ impl State {
             database,
         };
 
+        let max_pools = self.config.read().await.settings.max_pools;
+
         // use an existing connection pool if one already exists
         let mut pools = self.pools.lock().await;
         if let Some(state) = pools.get_mut(&conn_key) {
@@ -132,7 +327,17 @@ impl State {
             conn_key.database, conn_key.connection
         );
         tracing::info!("{msg}");
-        crate::stream::broadcast(msg).await;
+        // events are broadcast once the lock is released, so that slow subscribers
+        // don't hold up other requests for a pool
+        let mut events = vec![stream::StreamEvent::PoolStatus {
+            connection: Some(conn_key.connection.clone()),
+            database: conn_key.database.clone(),
+            status: stream::PoolStatus::Opening,
+            message: msg,
+        }];
+
+        // make room for the new pool, if necessary
+        events.extend(evict_lru(&mut pools, max_pools).await);
 
         // leave a `Pending` marker in the state, then spawn the connection pool
         // drop the lock while we're doing this so that we don't block the app
@@ -146,6 +351,9 @@ impl State {
             },
         );
         drop(pools);
+        for event in events {
+            crate::stream::broadcast_event(event).await;
+        }
 
         // if not, spawn a new connection pool
         let config = self.config.read().await;
@@ -157,10 +365,13 @@ impl State {
             .ok_or(eyre::eyre!("no connection named {}", conn_key.connection))?;
         drop(config);
 
-        // load password (run `password_file` if required)
+        // the connection's `database` is only a default; connect to the one requested
+        connection.database = conn_key.database.clone();
+
+        // load password (run the password helper if required)
         if let Err(err) = connection.load_password().await {
             let err = eyre::eyre!("Failed to load password: {}", err);
-            crate::stream::broadcast(err.to_string()).await;
+            crate::stream::broadcast_error(err.to_string()).await;
 
             let mut pools = self.pools.lock().await;
 
@@ -208,17 +419,186 @@ impl State {
         conn
     }
 
+    /// Create a temporary database on the given connection, returning its name. Use
+    /// `get_conn` with the returned name to query it, and `drop_scratch_database` to
+    /// drop it once you're done.
+    pub async fn create_scratch_database(&self, connection: String) -> eyre::Result<String> {
+        if !self.config.read().await.settings.allow_scratch_databases {
+            eyre::bail!("scratch databases are disabled (see `settings.allow_scratch_databases`)");
+        }
+
+        use aes_gcm::aead::{OsRng, rand_core::RngCore};
+        let database = format!("dbc_scratch_{:08x}", OsRng.next_u32());
+
+        // databases must be created from another database on the same server
+        let conn = self.get_default_conn(connection.clone()).await?;
+        crate::db::create_database(&conn, &database).await?;
+        drop(conn);
+
+        crate::stream::broadcast(format!(
+            "Created scratch database \"{database}\" on conn \"{connection}\"."
+        ))
+        .await;
+
+        self.scratch_databases.lock().await.insert(ConnectionKey {
+            connection,
+            database: database.clone(),
+        });
+
+        Ok(database)
+    }
+
+    /// Close any open pool for a scratch database and drop it.
+    pub async fn drop_scratch_database(
+        &self,
+        connection: String,
+        database: String,
+    ) -> eyre::Result<()> {
+        let conn_key = ConnectionKey {
+            connection,
+            database,
+        };
+
+        if !self.scratch_databases.lock().await.contains(&conn_key) {
+            eyre::bail!(
+                "no scratch database named {} on conn {}",
+                conn_key.database,
+                conn_key.connection
+            );
+        }
+
+        // connections to the database must be closed before it can be dropped
+        if let Some(PoolState::Active(pool)) = self.pools.lock().await.remove(&conn_key) {
+            pool.close().await;
+        }
+
+        let conn = self.get_default_conn(conn_key.connection.clone()).await?;
+        crate::db::drop_database(&conn, &conn_key.database).await?;
+
+        crate::stream::broadcast(format!(
+            "Dropped scratch database \"{}\" on conn \"{}\".",
+            conn_key.database, conn_key.connection
+        ))
+        .await;
+
+        self.scratch_databases.lock().await.remove(&conn_key);
+
+        Ok(())
+    }
+
+    /// Drop all scratch databases; this should be called before shutting down.
+    pub async fn drop_scratch_databases(&self) {
+        let scratch_databases = self.scratch_databases.lock().await.clone();
+        for conn_key in scratch_databases {
+            let database = conn_key.database.clone();
+            if let Err(err) = self
+                .drop_scratch_database(conn_key.connection, conn_key.database)
+                .await
+            {
+                tracing::error!("failed to drop scratch database {database}: {err}");
+            }
+        }
+    }
+
+    /// Close every connection pool (and end every session), so that the next request
+    /// for each connection opens a fresh pool. Pools that are still opening are
+    /// cancelled, and connections that are checked out are closed once they're returned.
+    pub async fn reset_pools(&self) -> PoolReset {
+        // a session's connection stays checked out, so it would outlive its pool
+        let sessions = std::mem::take(&mut *self.sessions.lock().await);
+        let mut reset = PoolReset {
+            sessions: sessions.len(),
+            ..Default::default()
+        };
+        drop(sessions);
+
+        let mut pools = self.pools.lock().await;
+        let mut events = Vec::new();
+        for (conn_key, pool) in pools.drain() {
+            reset.pools += 1;
+            match pool {
+                PoolState::Active(pool) => {
+                    reset.connections += pool.size().await;
+                    pool.close().await;
+                }
+                PoolState::Pending { cancel, .. } => {
+                    if let Some(cancel) = cancel {
+                        let _ = cancel.send(());
+                    }
+                }
+                PoolState::Failed(_) => {}
+            }
+            events.push(stream::StreamEvent::PoolStatus {
+                message: format!(
+                    "Reset connection pool for db \"{}\" on conn \"{}\".",
+                    conn_key.database, conn_key.connection
+                ),
+                connection: Some(conn_key.connection),
+                database: conn_key.database,
+                status: stream::PoolStatus::Closing,
+            });
+        }
+        drop(pools);
+        for event in events {
+            crate::stream::broadcast_event(event).await;
+        }
+
+        let msg = format!(
+            "Reset {} connection pools, closing {} connections and ending {} sessions.",
+            reset.pools, reset.connections, reset.sessions
+        );
+        tracing::warn!("{msg}");
+        crate::stream::broadcast(msg).await;
+
+        reset
+    }
+
+    /// The current status of each of a connection's pools, as `PoolStatus` events (e.g.
+    /// to catch up a client that's about to receive them as they change).
+    pub async fn pool_status_events(&self, connection: &str) -> Vec<stream::StreamEvent> {
+        let pools = self.pools.lock().await;
+        let mut events = Vec::new();
+        for (key, pool) in pools.iter().filter(|(key, _)| key.connection == connection) {
+            let (status, message) = match pool {
+                PoolState::Active(pool) if pool.is_dormant().await => (
+                    stream::PoolStatus::Dormant,
+                    "pool is dormant, and will reopen on next use".to_owned(),
+                ),
+                PoolState::Active(_) => (stream::PoolStatus::Open, "pool is open".to_owned()),
+                PoolState::Pending { .. } => {
+                    (stream::PoolStatus::Opening, "connecting...".to_owned())
+                }
+                PoolState::Failed(err) => (stream::PoolStatus::Failed, err.clone()),
+            };
+            events.push(stream::StreamEvent::PoolStatus {
+                connection: Some(key.connection.clone()),
+                database: key.database.clone(),
+                status,
+                message,
+            });
+        }
+        events
+    }
+
     pub async fn status(&self) -> eyre::Result<Vec<serde_json::Value>> {
         let mut pools = self.pools.lock().await;
         let mut acc = Vec::new();
 
         for (conn, pool) in pools.iter_mut() {
-            let (status, status_msg) = pool.status().await?;
+            let (status, status_msg, host, warning) = pool.status().await?;
+            // open connections, which can be fewer than the configured pool size
+            let size = match pool {
+                PoolState::Active(pool) => Some(pool.size().await),
+                _ => None,
+            };
             acc.push(serde_json::json!({
                 "connection": conn.connection,
                 "database": conn.database,
                 "status": status,
                 "message": status_msg,
+                "size": size,
+                "host": host,
+                "warning": warning,
             }));
         }
 
@@ -228,22 +608,136 @@ impl State {
     /// Print a debug representation of the application state. This has to
     /// be a method instead of a `Debug` implementation because it's `async`.
     pub async fn debug(&self) -> String {
+        // the pools and sessions are only locked long enough to list them, since probing
+        // their connections can take a while
         let pools = self.pools.lock().await;
+        let pools = pools
+            .iter()
+            .map(|(key, pool)| {
+                let status: futures_util::future::BoxFuture<'static, String> = match pool {
+                    PoolState::Active(pool) => Box::pin(pool.debug()),
+                    PoolState::Failed(err) => Box::pin(std::future::ready(err.clone())),
+                    PoolState::Pending { .. } => {
+                        Box::pin(std::future::ready("pending".to_string()))
+                    }
+                };
+                (key.clone(), status)
+            })
+            .collect::<Vec<_>>();
+
         let mut counts = vec![format!("{} active connection pools", pools.len())];
-        for (conn, pool) in pools.iter() {
+        for (conn, status) in pools {
             counts.push(format!(
                 "=== connection: \"{}\" on \"{}\" ===\n{}",
                 conn.database,
                 conn.connection,
-                match pool {
-                    PoolState::Active(pool) => pool.debug().await,
-                    PoolState::Failed(err) => err.clone(),
-                    PoolState::Pending { .. } => "pending".to_string(),
-                }
+                status.await
+            ));
+        }
+
+        // a session's connection stays checked out between queries, so a transaction
+        // it opened can be left open (or aborted) indefinitely
+        let sessions = self
+            .sessions
+            .lock()
+            .await
+            .iter()
+            .map(|(id, session)| (id.clone(), session.key.clone(), Arc::clone(&session.conn)))
+            .collect::<Vec<_>>();
+        counts.push(format!("{} active sessions", sessions.len()));
+        for (id, key, conn) in sessions {
+            let status = match conn.try_lock() {
+                Ok(conn) => match conn.transaction_status().await {
+                    Ok(status) => status.to_string(),
+                    Err(err) => err.to_string(),
+                },
+                Err(_) => "running a query".to_string(),
+            };
+            counts.push(format!(
+                "session {id}: \"{}\" on \"{}\", {status}",
+                key.database, key.connection
             ));
         }
         counts.join("\n")
     }
+
+    /// Render the server's metrics, including gauges for each open pool.
+    #[cfg(feature = "metrics")]
+    pub async fn metrics(&self) -> String {
+        let pools = self.pools.lock().await;
+        let mut open = Vec::new();
+        for (key, pool) in pools.iter() {
+            if let PoolState::Active(pool) = pool {
+                open.push(metrics::PoolMetrics {
+                    connection: &key.connection,
+                    database: &key.database,
+                    size: pool.size().await,
+                    checked_out: pool.checked_out().await,
+                });
+            }
+        }
+        metrics::render(open)
+    }
+}
+
+/// Close least-recently-used pools until there's room for another pool. Pools
+/// with connections checked out are never closed. Returns a `Closing` event for each
+/// pool that was closed, for the caller to broadcast once it's released the lock.
+async fn evict_lru(
+    pools: &mut HashMap<ConnectionKey, PoolState>,
+    max_pools: usize,
+) -> Vec<stream::StreamEvent> {
+    let mut events = Vec::new();
+    loop {
+        let mut open_pools = 0;
+        let mut candidates = Vec::new();
+        for (conn_key, state) in pools.iter() {
+            match state {
+                PoolState::Active(pool) => {
+                    open_pools += 1;
+                    if pool.checked_out().await == 0 {
+                        candidates.push((conn_key.clone(), pool.last_used()));
+                    }
+                }
+                PoolState::Pending { .. } => open_pools += 1,
+                PoolState::Failed(_) => {}
+            }
+        }
+
+        if open_pools < max_pools {
+            return events;
+        }
+
+        let Some(conn_key) = least_recently_used(candidates) else {
+            tracing::warn!("{open_pools} connection pools open, but all are in use");
+            return events;
+        };
+
+        let msg = format!(
+            "Closing least-recently-used connection pool for db \"{}\" on conn \"{}\"...",
+            conn_key.database, conn_key.connection
+        );
+        tracing::info!("{msg}");
+        events.push(stream::StreamEvent::PoolStatus {
+            connection: Some(conn_key.connection.clone()),
+            database: conn_key.database.clone(),
+            status: stream::PoolStatus::Closing,
+            message: msg,
+        });
+
+        if let Some(PoolState::Active(pool)) = pools.remove(&conn_key) {
+            pool.close().await;
+        }
+    }
+}
+
+fn least_recently_used<K>(
+    candidates: impl IntoIterator<Item = (K, std::time::Instant)>,
+) -> Option<K> {
+    candidates
+        .into_iter()
+        .min_by_key(|(_, last_used)| *last_used)
+        .map(|(k, _)| k)
 }
 
 pub(crate) async fn create_pool(conn: &crate::persistence::Connection) -> eyre::Result<PoolState> {
@@ -251,19 +745,45 @@ pub(crate) async fn create_pool(conn: &crate::persistence::Connection) -> eyre::
     match crate::pool::ConnectionPool::new(cfg).await {
         Ok(mut pool) => {
             let pool_size = pool.pool_size().await;
-            tracing::info!("Success! {pool_size} connections in pool.");
-            crate::stream::broadcast(format!("Success! {pool_size} connections in pool.")).await;
+            // nothing has been checked out yet, so every open connection is available
+            let opened = pool.available().await;
+            let message = if opened < pool_size {
+                format!("Opened {opened} of {pool_size} connections; the rest failed to open.")
+            } else {
+                format!("Success! {pool_size} connections in pool.")
+            };
+            tracing::info!("{message}");
+            crate::stream::broadcast_pool_status(
+                Some(&conn.name),
+                &conn.database,
+                stream::PoolStatus::Open,
+                message,
+            )
+            .await;
 
-            let conn = pool.get_conn().await?;
-            let version_info = crate::db::version_info(&conn).await?;
+            let checked_out = pool.get_conn().await?;
+            let version_info = crate::db::version_info(&checked_out).await?;
             crate::stream::broadcast(version_info).await;
+            if let Some(warning) = checked_out.encoding_warning() {
+                crate::stream::broadcast_error(format!(
+                    "Warning for db \"{}\" on conn \"{}\": {warning}",
+                    conn.database, conn.name
+                ))
+                .await;
+            }
 
             Ok(PoolState::Active(pool))
         }
 
         Err(err) => {
             tracing::error!("Error opening connection: {err}");
-            crate::stream::broadcast(format!("Failed to open connection\n{err}")).await;
+            crate::stream::broadcast_pool_status(
+                Some(&conn.name),
+                &conn.database,
+                stream::PoolStatus::Failed,
+                format!("Failed to open connection\n{err}"),
+            )
+            .await;
             Ok(PoolState::Failed(err.to_string()))
         }
     }
@@ -307,3 +827,290 @@ pub fn asset_dir_in(target_dir: &Path) -> &'static Path {
         return target_dir.parent().unwrap().join("Resources/assets");
     })
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn least_recently_used_picks_coldest() {
+        let now = Instant::now();
+        let pools = (0..5).map(|i| (format!("db{i}"), now + Duration::from_secs(i)));
+        assert_eq!(least_recently_used(pools), Some("db0".to_string()));
+        assert_eq!(least_recently_used(Vec::<(String, Instant)>::new()), None);
+    }
+
+    /// A state with a single connection, `name`, to the test database (see
+    /// `db::tests::test_config`).
+    pub(crate) fn test_state(name: &str, config: &db::Config) -> State {
+        state_with_connections(vec![test_connection(name, config)])
+    }
+
+    /// A saved connection, `name`, to the test database.
+    pub(crate) fn test_connection(name: &str, config: &db::Config) -> persistence::Connection {
+        toml::from_str(&format!(
+            r#"
+            name = {name:?}
+            host = {:?}
+            port = {}
+            username = {:?}
+            password = {:?}
+            database = {:?}
+            "#,
+            config.host, config.port, config.username, config.password, config.database
+        ))
+        .unwrap()
+    }
+
+    /// A state with the given saved connections, and no pools open yet.
+    pub(crate) fn state_with_connections(connections: Vec<persistence::Connection>) -> State {
+        State {
+            pools: Default::default(),
+            config: RwLock::new(persistence::Store {
+                connections,
+                ..Default::default()
+            }),
+            scratch_databases: Default::default(),
+            sessions: Default::default(),
+            jobs: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcasts_pool_transitions_in_order() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        stream::init_for_tests();
+        // leave room for other tests' (replayed) events, since any that don't fit are dropped
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1024);
+        stream::subscribe(tx).await.unwrap();
+
+        let state = test_state("transitions", &config);
+        assert!(state.pool_status_events("transitions").await.is_empty());
+
+        let conn = state
+            .get_conn("transitions".to_owned(), config.database.clone())
+            .await
+            .unwrap();
+        drop(conn);
+
+        // other tests share the stream, so only look at this connection's events
+        let mut statuses = Vec::new();
+        while statuses.len() < 2 {
+            let msg = rx.recv().await.unwrap();
+            if stream::pool_event_connection(&msg).as_deref() != Some("transitions") {
+                continue;
+            }
+            let msg = serde_json::from_str::<serde_json::Value>(&msg).unwrap();
+            if msg["type"] == "pool_status" {
+                statuses.push(msg["status"].as_str().unwrap().to_owned());
+            }
+        }
+        assert_eq!(statuses, ["opening", "open"]);
+
+        let events = state.pool_status_events("transitions").await;
+        assert!(
+            matches!(
+                events.as_slice(),
+                [stream::StreamEvent::PoolStatus {
+                    status: stream::PoolStatus::Open,
+                    ..
+                }]
+            ),
+            "{events:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used_pool() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        stream::init_for_tests();
+        let state = state_with_connections(
+            ["evict_a", "evict_b", "evict_c"]
+                .map(|name| test_connection(name, &config))
+                .into(),
+        );
+        state.config.write().await.settings.max_pools = 2;
+        let get_conn = |name: &str| state.get_conn(name.to_owned(), config.database.clone());
+        let open_pools = || async {
+            let mut names = state
+                .pools
+                .lock()
+                .await
+                .keys()
+                .map(|key| key.connection.clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        let checked_out = || async {
+            let mut total = 0;
+            for pool in state.pools.lock().await.values() {
+                if let PoolState::Active(pool) = pool {
+                    total += pool.checked_out().await;
+                }
+            }
+            total
+        };
+
+        drop(get_conn("evict_a").await.unwrap());
+        let b = get_conn("evict_b").await.unwrap();
+        assert_eq!(open_pools().await, ["evict_a", "evict_b"]);
+
+        // `evict_b` has a connection checked out, so `evict_a` is closed instead (once
+        // its connection is back)
+        while checked_out().await > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(get_conn("evict_c").await.unwrap());
+        assert_eq!(open_pools().await, ["evict_b", "evict_c"]);
+
+        // once nothing's checked out, the least recently used pool goes
+        drop(b);
+        while checked_out().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(get_conn("evict_a").await.unwrap());
+        assert_eq!(open_pools().await, ["evict_a", "evict_c"]);
+    }
+
+    #[tokio::test]
+    async fn limits_sessions_per_pool() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        let state = test_state("sessions", &config);
+        state.config.write().await.settings.max_sessions_per_pool = 1;
+        let session_conn =
+            |id| state.session_conn(id, "sessions".to_owned(), config.database.clone());
+
+        let conn = session_conn("s1").await.unwrap();
+        let err = session_conn("s2").await.err().unwrap();
+        assert!(err.to_string().starts_with("too many sessions"), "{err}");
+
+        // a session left in a transaction can still be ended, freeing up its slot
+        conn.lock().await.batch_execute("BEGIN").await.unwrap();
+        assert!(state.end_session("s1").await.unwrap());
+        session_conn("s2").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn resets_every_pool() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        stream::init_for_tests();
+        let state = test_state("reset", &config);
+
+        let conn = state
+            .get_conn("reset".to_owned(), config.database.clone())
+            .await
+            .unwrap();
+        let pid: i32 = conn
+            .query_one("SELECT pg_backend_pid()", &[])
+            .await
+            .unwrap()
+            .get(0);
+        state
+            .session_conn("s1", "reset".to_owned(), config.database.clone())
+            .await
+            .unwrap();
+
+        let reset = state.reset_pools().await;
+        assert_eq!(reset.pools, 1);
+        assert_eq!(reset.connections, config.pool_size);
+        assert_eq!(reset.sessions, 1);
+        assert!(state.pools.lock().await.is_empty());
+        assert!(state.sessions.lock().await.is_empty());
+
+        // the connection that was checked out isn't returned to a pool, and the next
+        // request opens a fresh one
+        drop(conn);
+        let conn = state
+            .get_conn("reset".to_owned(), config.database.clone())
+            .await
+            .unwrap();
+        let new_pid: i32 = conn
+            .query_one("SELECT pg_backend_pid()", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_ne!(new_pid, pid);
+        assert_eq!(state.pools.lock().await.len(), 1);
+    }
+    #[tokio::test]
+    async fn creates_and_drops_scratch_databases() {
+        let Some(config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        stream::init_for_tests();
+        let state = test_state("scratch", &config);
+        let err = state
+            .create_scratch_database("scratch".to_owned())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("scratch databases are disabled"),
+            "{err}"
+        );
+
+        state.config.write().await.settings.allow_scratch_databases = true;
+        let database = state
+            .create_scratch_database("scratch".to_owned())
+            .await
+            .unwrap();
+        assert!(database.starts_with("dbc_scratch_"), "{database}");
+        let conn = state
+            .get_conn("scratch".to_owned(), database.clone())
+            .await
+            .unwrap();
+        let current: String = conn
+            .query_one("SELECT current_database()", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(current, database);
+        drop(conn);
+
+        // its open pool is closed so the database can be dropped
+        state
+            .drop_scratch_database("scratch".to_owned(), database.clone())
+            .await
+            .unwrap();
+        assert!(state.scratch_databases.lock().await.is_empty());
+        let conn = state
+            .get_conn("scratch".to_owned(), config.database.clone())
+            .await
+            .unwrap();
+        let exists: bool = conn
+            .query_one(
+                "SELECT EXISTS (SELECT FROM pg_database WHERE datname = $1)",
+                &[&database],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert!(!exists);
+
+        // only scratch databases can be dropped this way
+        let err = state
+            .drop_scratch_database("scratch".to_owned(), config.database.clone())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("no scratch database named"),
+            "{err}"
+        );
+    }
+}
@@ -1,5 +1,8 @@
-use poem::{EndpointExt, Route, Server, get, post, put};
-use std::{collections::HashMap, sync::Arc};
+use poem::{EndpointExt, Route, Server, delete, get, post, put};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use tokio::sync::{Mutex, RwLock};
 
 #[tokio::main]
@@ -12,14 +15,42 @@ async fn main() -> eyre::Result<()> {
     #[cfg(feature = "bundle")]
     let _ = fix_path_env::fix();
 
-    // initialize logger
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .with_test_writer()
-        .init();
+    // initialize logger; `RUST_LOG` overrides the default level (e.g. `dbc=trace`),
+    // and `LOG_FORMAT=json` switches to structured logs
+    let default_level = if cfg!(debug_assertions) {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    // invalid `RUST_LOG` directives are skipped, rather than keeping the server from starting
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(default_level.into())
+        .from_env_lossy();
+    let logger = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_test_writer();
+    match std::env::var("LOG_FORMAT").ok().as_deref() {
+        None | Some("text") => logger.init(),
+        Some("json") => logger.json().init(),
+        Some(format) => {
+            logger.init();
+            tracing::warn!(
+                "unknown LOG_FORMAT {format:?} (expected \"text\" or \"json\"), using \"text\""
+            );
+        }
+    }
 
     // start up stream worker
-    dbc::stream::init();
+    let max_chunk_size = std::env::var("STREAM_MAX_CHUNK_SIZE")
+        .ok()
+        .map(|size| {
+            size.parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .expect("STREAM_MAX_CHUNK_SIZE is a positive integer")
+        })
+        .unwrap_or(dbc::stream::DEFAULT_MAX_CHUNK_SIZE);
+    dbc::stream::init(max_chunk_size);
 
     #[cfg(feature = "bundle")]
     let encryption_key = Some(dotenv_codegen::dotenv!("ENCRYPTION_KEY"));
@@ -38,33 +69,85 @@ async fn main() -> eyre::Result<()> {
     let state = Arc::new(dbc::State {
         pools: Mutex::new(HashMap::new()),
         config: RwLock::new(store),
+        scratch_databases: Mutex::new(HashSet::new()),
+        sessions: Mutex::new(HashMap::new()),
+        jobs: Default::default(),
     });
 
     use dbc::server::routes;
     let router = Route::new()
+        .at("/healthz", get(routes::healthz))
+        .at("/openapi.json", get(routes::openapi))
         .at("/:channel", get(routes::websocket))
         .nest(
             "/connections",
             Route::new()
+                .at("/export", post(routes::export_connections))
+                .at("/import", post(routes::import_connections))
                 .at("/:connection", get(routes::connection_info))
                 .at("/:connection/close", put(routes::close_connection))
-                .at("/:connection/reload", put(routes::reload_connection)),
+                .at("/:connection/reload", put(routes::reload_connection))
+                .at(
+                    "/:connection/scratch",
+                    post(routes::create_scratch_database),
+                )
+                .at(
+                    "/:connection/scratch/:database",
+                    delete(routes::drop_scratch_database),
+                ),
         )
         .nest(
             "/db",
             Route::new()
                 .at("/databases", get(routes::get_databases))
+                .at("/session", get(routes::get_session_info))
+                .at("/search", get(routes::search_objects))
+                .at("/notify", post(routes::notify))
                 .at("/schemas", get(routes::get_schemas))
+                .at(
+                    "/extensions",
+                    get(routes::get_extensions).post(routes::create_extension),
+                )
                 .at("/schemas/:schema/tables", get(routes::get_tables))
+                .at("/schemas/:schema/functions", get(routes::get_functions))
+                .at("/columns", post(routes::get_columns_batch))
                 .at(
                     "/schemas/:schema/tables/:table/columns",
                     get(routes::get_columns),
                 )
+                .at(
+                    "/schemas/:schema/tables/:table/columns/:column/values",
+                    get(routes::get_column_values),
+                )
+                .at(
+                    "/schemas/:schema/tables/:table/stats",
+                    get(routes::get_column_stats),
+                )
+                .at(
+                    "/schemas/:schema/tables/:table/analyze",
+                    post(routes::analyze_table),
+                )
+                .at(
+                    "/schemas/:schema/tables/:table/rows",
+                    get(routes::get_editable_rows).put(routes::update_row),
+                )
+                .at(
+                    "/schemas/:schema/tables/:table/maintenance",
+                    post(routes::run_table_maintenance),
+                )
                 .at(
                     "/ddl/schemas/:schema/table/:table",
                     get(routes::get_table_ddl),
                 )
+                .at(
+                    "/ddl/diff",
+                    post(routes::diff_table_ddl).around(routes::limit_query_length),
+                )
                 .at("/ddl/schemas/:schema/view/:view", get(routes::get_view_ddl))
+                .at(
+                    "/ddl/schemas/:schema/function/:function",
+                    get(routes::get_function_ddl),
+                )
                 .at(
                     "/ddl/schemas/:schema/materialized_view/:view",
                     get(routes::get_materialized_view_ddl),
@@ -74,39 +157,141 @@ async fn main() -> eyre::Result<()> {
             "/config",
             get(routes::get_config).put(routes::update_config),
         )
-        .at("/query", post(routes::handle_query))
-        .at("/prepare", post(routes::prepare_query));
+        .at("/config/validate", post(routes::validate_config))
+        .at("/status", get(routes::get_status))
+        .at(
+            "/query",
+            post(routes::handle_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/query/csv",
+            post(routes::export_csv).around(routes::limit_query_length),
+        )
+        .at(
+            "/query/format",
+            post(routes::format_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/query/async",
+            post(routes::submit_async_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/query/async/:id",
+            get(routes::get_async_query).delete(routes::cancel_async_query),
+        )
+        .at(
+            "/query/script",
+            post(routes::run_script).around(routes::limit_query_length),
+        )
+        .at("/session/:id", delete(routes::end_session))
+        .at(
+            "/session/:id/query",
+            post(routes::handle_session_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/query/federated",
+            post(routes::federated_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/prepare",
+            post(routes::prepare_query).around(routes::limit_query_length),
+        )
+        .at(
+            "/prepare/batch",
+            post(routes::prepare_batch).around(routes::limit_query_length),
+        );
+
+    #[cfg(feature = "metrics")]
+    let router = router.at("/metrics", get(routes::metrics));
 
     #[cfg(debug_assertions)]
     let router = router.nest(
         "/debug",
-        Route::new().at("/state", get(routes::debug::get_state)),
+        Route::new()
+            .at("/state", get(routes::debug::get_state))
+            .at("/reset", post(routes::debug::reset_pools)),
     );
 
+    // restrict cross-origin requests to `API_CORS_ORIGINS` (comma-separated), if set
+    let cors_origins = std::env::var("API_CORS_ORIGINS")
+        .ok()
+        .map(|origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().to_owned())
+                .filter(|origin| !origin.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|origins| !origins.is_empty());
+    let cors = match &cors_origins {
+        Some(origins) => poem::middleware::Cors::new().allow_origins(origins),
+        None => poem::middleware::Cors::new(),
+    };
+
+    // when bundled, the server is only reachable locally, so it's left open
+    #[cfg(feature = "bundle")]
+    let api_token = routes::ApiToken(None);
+    #[cfg(not(feature = "bundle"))]
+    let api_token = routes::ApiToken(std::env::var("API_TOKEN").ok().filter(|t| !t.is_empty()));
+
     let router = router
-        .with(poem::middleware::Cors::new())
+        // check the token inside `cors`, since CORS preflight requests never include it
+        .around(routes::require_api_token)
+        .with(cors)
         .with(poem::middleware::Tracing)
         .around(routes::format_eyre)
-        .data(Arc::clone(&state));
+        .around(routes::compress_response)
+        .data(Arc::clone(&state))
+        .data(api_token);
 
-    let server_port = if cfg!(feature = "bundle") {
-        // when bundled, have the system assign us a port
-        0
-    } else {
-        // otherwise, use the port specified in the config
-        std::env::var("API_PORT")
+    // when bundled, stay on loopback (over plain HTTP) and have the system assign us a port
+    #[cfg(feature = "bundle")]
+    let (server_addr, tls) = (dbc::server::server_addr(None, 0)?, None);
+
+    // otherwise, use the host and port specified in the config
+    #[cfg(not(feature = "bundle"))]
+    let (server_addr, tls) = {
+        let server_port = std::env::var("API_PORT")
             .expect("API_PORT is set")
-            .parse::<usize>()
-            .expect("API_PORT is valid")
+            .parse::<u16>()
+            .expect("API_PORT is valid");
+        let server_host = std::env::var("API_HOST").ok();
+        let server_addr = dbc::server::server_addr(server_host.as_deref(), server_port)?;
+        (server_addr, dbc::server::TlsConfig::from_env()?)
     };
 
-    let server_addr = format!("127.0.0.1:{server_port}");
-    let (acceptor, _server_port) = dbc::server::bind_acceptor(&server_addr).await;
+    if !server_addr.ip().is_loopback() && cors_origins.is_none() {
+        tracing::warn!(
+            "!!! listening on {server_addr}, which is reachable from other machines, and any \
+             website can make requests to this server; set API_CORS_ORIGINS to restrict them !!!"
+        );
+    }
+
+    let (acceptor, _server_port) =
+        dbc::server::bind_acceptor(&server_addr.to_string(), tls.as_ref()).await?;
+
+    // when running headless, shut down gracefully on Ctrl-C
+    let shutdown = async {
+        #[cfg(not(feature = "bundle"))]
+        let _ = tokio::signal::ctrl_c().await;
+        #[cfg(feature = "bundle")]
+        std::future::pending::<()>().await;
+    };
+
+    // end abandoned sessions, even if no other session is used to notice them
+    let sweep_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut sweep = tokio::time::interval(dbc::SESSION_SWEEP_INTERVAL);
+        loop {
+            sweep.tick().await;
+            sweep_state.end_idle_sessions().await;
+        }
+    });
 
     // spawn the server in a background task
     let _server_handle = tokio::spawn(async move {
         Server::new_with_acceptor(acceptor)
-            .run(router)
+            .run_with_graceful_shutdown(router, shutdown, None)
             .await
             .unwrap();
     });
@@ -119,6 +304,10 @@ async fn main() -> eyre::Result<()> {
     #[cfg(not(feature = "bundle"))]
     {
         _server_handle.await.unwrap();
+
+        // clean up any scratch databases before exiting
+        state.drop_scratch_databases().await;
+
         Ok(())
     }
 }
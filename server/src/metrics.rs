@@ -0,0 +1,195 @@
+//! A minimal registry of server metrics, rendered in the Prometheus text format
+//! (see `GET /metrics`). Pool gauges aren't tracked here; they're read from the
+//! application state when scraped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the histogram buckets.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Default)]
+struct Metrics {
+    queries: AtomicU64,
+    /// Failed queries, by SQLSTATE code (or `unknown` for errors that didn't come
+    /// from Postgres, e.g. a lost connection).
+    query_errors: Mutex<BTreeMap<String, u64>>,
+    query_duration: Histogram,
+    checkout_wait: Histogram,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket (not cumulative), with the last counting those
+    /// larger than every bound.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let idx = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = BUCKETS.get(i).map_or("+Inf".to_owned(), f64::to_string);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// Record a query that was run, and the error it failed with (if any).
+pub fn record_query(duration: Duration, error: Option<&eyre::Report>) {
+    METRICS.queries.fetch_add(1, Ordering::Relaxed);
+    METRICS.query_duration.observe(duration);
+    if let Some(err) = error {
+        let code = err
+            .downcast_ref::<crate::db::PgError>()
+            .and_then(crate::db::PgError::code)
+            .map_or("unknown", String::as_str);
+        let mut errors = METRICS.query_errors.lock().unwrap();
+        *errors.entry(code.to_owned()).or_default() += 1;
+    }
+}
+
+/// Record how long it took to check out a pooled connection (or give up waiting).
+pub fn record_checkout_wait(duration: Duration) {
+    METRICS.checkout_wait.observe(duration);
+}
+
+/// A connection pool's gauges, as of the scrape.
+pub struct PoolMetrics<'a> {
+    pub connection: &'a str,
+    pub database: &'a str,
+    pub size: usize,
+    pub checked_out: usize,
+}
+
+/// Render every metric in the Prometheus text exposition format.
+pub fn render<'a>(pools: impl IntoIterator<Item = PoolMetrics<'a>>) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "dbc_queries_total", "Queries run.", "counter");
+    let queries = METRICS.queries.load(Ordering::Relaxed);
+    let _ = writeln!(out, "dbc_queries_total {queries}");
+
+    header(
+        &mut out,
+        "dbc_query_errors_total",
+        "Queries that failed, by SQLSTATE code.",
+        "counter",
+    );
+    for (code, count) in METRICS.query_errors.lock().unwrap().iter() {
+        let code = escape(code);
+        let _ = writeln!(out, "dbc_query_errors_total{{code=\"{code}\"}} {count}");
+    }
+
+    METRICS.query_duration.render(
+        &mut out,
+        "dbc_query_duration_seconds",
+        "How long queries took to run.",
+    );
+    METRICS.checkout_wait.render(
+        &mut out,
+        "dbc_pool_checkout_wait_seconds",
+        "How long it took to check out a pooled connection.",
+    );
+
+    let pools = pools.into_iter().collect::<Vec<_>>();
+    header(
+        &mut out,
+        "dbc_pools_active",
+        "Connection pools that are open.",
+        "gauge",
+    );
+    let _ = writeln!(out, "dbc_pools_active {}", pools.len());
+
+    let mut pool_gauge = |name: &str, help: &str, value: fn(&PoolMetrics) -> usize| {
+        header(&mut out, name, help, "gauge");
+        for pool in &pools {
+            let _ = writeln!(
+                out,
+                "{name}{{connection=\"{}\",database=\"{}\"}} {}",
+                escape(pool.connection),
+                escape(pool.database),
+                value(pool)
+            );
+        }
+    };
+    pool_gauge(
+        "dbc_pool_connections",
+        "Open connections in each pool, whether available or checked out.",
+        |pool| pool.size,
+    );
+    pool_gauge(
+        "dbc_pool_checked_out_connections",
+        "Connections currently checked out of each pool.",
+        |pool| pool.checked_out,
+    );
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escape a label value, per the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_cumulative_histogram_buckets() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(60));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "wait_seconds", "Waits.");
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# HELP wait_seconds Waits.");
+        assert_eq!(lines[1], "# TYPE wait_seconds histogram");
+        assert_eq!(lines[2], "wait_seconds_bucket{le=\"0.001\"} 1");
+        assert_eq!(lines[5], "wait_seconds_bucket{le=\"0.025\"} 2");
+        assert_eq!(lines[13], "wait_seconds_bucket{le=\"10\"} 2");
+        assert_eq!(lines[14], "wait_seconds_bucket{le=\"+Inf\"} 3");
+        assert_eq!(lines[15], "wait_seconds_sum 60.0205");
+        assert_eq!(lines[16], "wait_seconds_count 3");
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape("a \"b\"\\c\n"), "a \\\"b\\\"\\\\c\\n");
+    }
+}
@@ -4,12 +4,20 @@ use aes_gcm::{
 };
 use dpi::{LogicalPosition, LogicalSize};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::os::unix::process::ExitStatusExt;
 use std::sync::OnceLock;
 use tokio::io::AsyncReadExt;
 
 const STORE_FILE: &str = "store.toml";
 
+/// The default `Connection::password_file_timeout_s`.
+pub const DEFAULT_PASSWORD_FILE_TIMEOUT_S: u64 = 10;
+
+/// The most output read from a `password_file` helper. Passwords (and even long-lived
+/// tokens) are far shorter, so anything larger means the helper is misbehaving.
+const MAX_PASSWORD_OUTPUT: usize = 64 * 1024;
+
 static ENCRYPTION_KEY: OnceLock<Key<Aes256Gcm>> = OnceLock::new();
 
 pub fn load_encryption_key(key_str: Option<&str>) -> eyre::Result<()> {
@@ -44,6 +52,39 @@ pub struct Store {
     pub connections: Vec<Connection>,
     #[serde(default)]
     pub window: WindowState,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+/// Application-wide settings that aren't specific to any one connection.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// The maximum number of connection pools to keep open at once. When exceeded,
+    /// the least-recently-used pool is closed.
+    pub max_pools: usize,
+    /// Whether clients may create (and drop) temporary scratch databases.
+    pub allow_scratch_databases: bool,
+    /// Whether clients may install extensions, whose scripts run with the connection's
+    /// privileges (often as a superuser).
+    pub allow_create_extensions: bool,
+    /// The largest request body, in bytes, accepted by routes that take a query.
+    pub max_query_length: usize,
+    /// The maximum number of sessions (see `State::session_conn`) open at once on each
+    /// connection pool, since each keeps one of the pool's connections checked out.
+    pub max_sessions_per_pool: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_pools: 10,
+            allow_scratch_databases: false,
+            allow_create_extensions: false,
+            max_query_length: 1024 * 1024,
+            max_sessions_per_pool: 3,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,57 +104,140 @@ impl Default for WindowState {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Connection {
     pub name: String,
+    /// The host, or a comma-separated list of hosts to try in order, each with an
+    /// optional port (e.g. `db1:5433,db2`).
     pub host: String,
     pub port: usize,
+    /// Which kind of server to accept when trying several hosts, e.g. `read-write`
+    /// to follow the primary across failovers.
+    #[serde(default)]
+    pub target_session_attrs: crate::db::TargetSessionAttrs,
     pub username: String,
     /// The plain-text password to use when connecting.
     pub password: Option<String>,
     /// A path to an executable file to run to generate the password to use when connecting.
     /// Any text printed to `stdout` by this executable will be included.
     pub password_file: Option<String>,
+    /// Like `password_file`, but the program to run followed by its arguments, e.g.
+    /// `["op", "read", "op://prod/db/password"]`. Takes precedence over `password_file`.
+    #[serde(default)]
+    pub password_command: Vec<String>,
+    /// Extra environment variables to set when running the password helper. The helper
+    /// also receives `DBC_CONN_NAME`, `DBC_HOST` and `DBC_DATABASE`.
+    #[serde(default)]
+    pub password_env: BTreeMap<String, String>,
+    /// How long (in seconds) to wait for `password_file` to print the password
+    /// before killing it (defaults to `DEFAULT_PASSWORD_FILE_TIMEOUT_S`).
+    pub password_file_timeout_s: Option<u64>,
     pub database: String,
     #[serde(default)]
     pub ssl: bool,
+    /// A PEM file of CA certificates to trust when verifying the server's certificate,
+    /// e.g. a managed database provider's CA bundle (requires `ssl`).
+    pub ssl_root_cert: Option<std::path::PathBuf>,
+    /// Whether to use SCRAM channel binding during authentication (requires `ssl`).
+    #[serde(default)]
+    pub channel_binding: crate::db::ChannelBinding,
+    /// What to do when a query returns columns that can't be decoded using BINARY encoding.
+    #[serde(default)]
+    pub text_fallback: crate::db::TextFallback,
+    /// The default isolation level for transactions on this connection.
+    pub isolation_level: Option<crate::db::IsolationLevel>,
+    /// Whether to `LISTEN` for `NOTIFY dbc_invalidate, '<table>'` to invalidate cached results.
+    #[serde(default)]
+    pub listen_for_invalidation: bool,
+    /// Whether `DROP` / `TRUNCATE` statements must be explicitly confirmed before running.
+    #[serde(default)]
+    pub require_confirm_destructive: bool,
+    /// How dates, timestamps and numerics are formatted in query results.
+    #[serde(default)]
+    pub display_format: crate::db::DisplayFormat,
+    /// Whether to reset session state (`SET` variables, roles, temp tables, etc.) when a
+    /// connection is returned to the pool. This isolates requests from one another, at
+    /// the cost of an extra round trip per request.
+    #[serde(default)]
+    pub reset_session: bool,
+    /// Regex patterns that queries must (`allow`) or must not (`deny`) match to be run.
+    #[serde(default)]
+    pub query_policy: crate::db::QueryPolicy,
+    /// The largest page returned for a `SELECT` without its own `LIMIT` (defaults to
+    /// `db::DEFAULT_MAX_PAGE_SIZE`); larger page sizes are clamped to this.
+    pub max_page_size: Option<usize>,
+    /// The page size used when a query doesn't request one (defaults to
+    /// `db::DEFAULT_PAGE_SIZE`).
+    pub default_page_size: Option<usize>,
+    /// Extra server settings to apply to each session, in libpq's `options` format,
+    /// e.g. `-c timezone=UTC -c statement_timeout=30s`.
+    pub options: Option<String>,
+    /// `SET` (or `SELECT set_config(...)`) statements run in order at the start of each
+    /// session, e.g. `SET search_path = app, public`. If any fails, so does the connection.
+    #[serde(default)]
+    pub session_setup: Vec<String>,
+    /// A query run on each of the pool's connections when it's opened (defaults to
+    /// `db::DEFAULT_VALIDATION_QUERY`); a connection it fails on isn't used.
+    pub validation_query: Option<String>,
+    /// How many times a query that fails with a serialization failure or deadlock is
+    /// retried, after a short backoff (defaults to 0).
+    #[serde(default)]
+    pub conflict_retries: u32,
+    /// Whether to set a session's `client_encoding` back to UTF-8 if `session_setup`
+    /// changes it (defaults to true); if not, a warning is shown instead.
+    pub force_utf8: Option<bool>,
+    /// Free-form labels for grouping connections, e.g. `prod` or `analytics`.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl Connection {
-    /// If `password_file` is set, runs the given executable and places the output
-    /// in `password`. If a password is already set (or if this function has already
+    /// If `password_command` or `password_file` is set, runs the given executable and
+    /// places the output in `password`. If a password is already set (or if this function has already
     /// been run before), does nothing.
     ///
     /// # Panics
     ///
-    /// Panics if neither `password` nor `password_file` is set.
+    /// Panics if none of `password`, `password_command` or `password_file` is set.
     pub async fn load_password(&mut self) -> eyre::Result<()> {
-        if let Some(bin) = self.password_file() {
+        if let Some((bin, args)) = self.password_helper() {
+            // arguments may hold secrets of their own, so only show the program
             crate::stream::broadcast(format!("Fetching password via \"{}\":", bin)).await;
 
             let bin = shellexpand::tilde(bin).to_string();
-            let mut cmd = tokio::process::Command::new(bin)
+            let mut cmd = tokio::process::Command::new(&bin)
+                .args(args)
+                .envs(&self.password_env)
+                .env("DBC_CONN_NAME", &self.name)
+                .env("DBC_HOST", &self.host)
+                .env("DBC_DATABASE", &self.database)
                 .stdout(std::process::Stdio::piped())
                 .stderr(std::process::Stdio::piped())
                 // if the command times out, kill it
                 .kill_on_drop(true)
                 .spawn()
-                .expect("valid executable file");
+                .map_err(|err| eyre::eyre!("Failed to run \"{bin}\": {err}"))?;
 
             let mut stdout = cmd.stdout.take().unwrap();
             let mut stderr = cmd.stderr.take().unwrap();
 
-            let (stdout_tx, stdout_rx) = tokio::sync::oneshot::channel::<String>();
+            let (stdout_tx, stdout_rx) = tokio::sync::oneshot::channel();
+            let timeout = std::time::Duration::from_secs(
+                self.password_file_timeout_s
+                    .unwrap_or(DEFAULT_PASSWORD_FILE_TIMEOUT_S),
+            );
 
-            // collect stdout and send it once complete
+            // collect stdout (up to `MAX_PASSWORD_OUTPUT`) and send it once complete
             tokio::spawn(async move {
-                let mut buf = String::new();
-                stdout.read_to_string(&mut buf).await.expect("valid utf-8");
-                let _ = stdout_tx.send(buf);
+                let mut buf = Vec::new();
+                let limit = MAX_PASSWORD_OUTPUT as u64 + 1;
+                let read = (&mut stdout).take(limit).read_to_end(&mut buf).await;
+                let _ = stdout_tx.send(read.map(|_| buf));
             });
 
-            // collect stderr and broadcast line-by-line as its received
-            tokio::spawn(async move {
+            // collect stderr and broadcast line-by-line as its received, for no longer
+            // than the helper is allowed to run (in case it leaves stderr open)
+            tokio::spawn(tokio::time::timeout(timeout, async move {
                 let mut buf = [0; 2048];
                 while let Ok(n) = stderr.read(&mut buf).await {
                     // once the process exits, break
@@ -124,17 +248,29 @@ impl Connection {
                     let line = String::from_utf8_lossy(&buf[..n]);
                     crate::stream::broadcast_raw(line).await;
                 }
-            });
+            }));
 
-            let timeout = std::time::Duration::from_secs(10);
-            let status = match tokio::time::timeout(timeout, cmd.wait()).await {
+            // stop as soon as the output gets too large, rather than waiting for the helper
+            // to exit; if it's still running, it's killed once `cmd` is dropped
+            let output = async {
+                let stdout = stdout_rx
+                    .await
+                    .unwrap()
+                    .map_err(|err| eyre::eyre!("Failed to read output:\n{err}"))?;
+                if stdout.len() > MAX_PASSWORD_OUTPUT {
+                    eyre::bail!("Password output is larger than {MAX_PASSWORD_OUTPUT} bytes");
+                }
+                let status = cmd
+                    .wait()
+                    .await
+                    .map_err(|err| eyre::eyre!("Failed to execute:\n{err}"))?;
+                Ok((stdout, status))
+            };
+            let (stdout, status) = match tokio::time::timeout(timeout, output).await {
                 Err(_) => eyre::bail!("Timeout after {}s", timeout.as_secs()),
-                Ok(Err(err)) => eyre::bail!("Failed to execute:\n{err}"),
-                Ok(Ok(output)) => output,
+                Ok(output) => output?,
             };
 
-            let stdout = stdout_rx.await.unwrap();
-
             if !status.success() {
                 eyre::bail!(
                     "exited with {}",
@@ -146,10 +282,14 @@ impl Connection {
                 );
             }
 
+            // like the password itself, don't echo the output in the error
+            let stdout = String::from_utf8(stdout)
+                .map_err(|_| eyre::eyre!("Password output isn't valid UTF-8"))?;
             self.password = Some(stdout.trim().to_owned());
         } else if self.password.is_none() {
-            panic!(
-                "{}: either `password` or `password_file` must be set",
+            // e.g. a connection imported from an export made without a passphrase
+            eyre::bail!(
+                "{}: one of `password`, `password_command` or `password_file` must be set",
                 self.name
             );
         }
@@ -160,6 +300,59 @@ impl Connection {
     pub fn password_file(&self) -> Option<&String> {
         self.password_file.as_ref().filter(|s| !s.is_empty())
     }
+
+    /// The program (and its arguments) to run to load the password, from either
+    /// `password_command` or `password_file`.
+    pub fn password_helper(&self) -> Option<(&str, &[String])> {
+        match self.password_command.split_first() {
+            Some((bin, args)) => Some((bin, args)),
+            None => self.password_file().map(|bin| (bin.as_str(), &[][..])),
+        }
+    }
+
+    /// This connection without its `password` or password helper, for sending to clients.
+    pub fn redacted(&self) -> RedactedConnection {
+        RedactedConnection {
+            // a password loaded from a helper doesn't count
+            has_password: self.password_helper().is_none() && self.password.is_some(),
+            has_password_file: self.password_helper().is_some(),
+            connection: Connection {
+                password: None,
+                password_file: None,
+                password_command: Vec::new(),
+                password_env: BTreeMap::new(),
+                ..self.clone()
+            },
+        }
+    }
+
+    /// Since clients only ever see redacted connections, an update that sets neither
+    /// `password` nor a password helper keeps whichever the saved connection had.
+    pub fn keep_credentials(&mut self, saved: &Connection) {
+        if self.password.is_none() && self.password_helper().is_none() {
+            self.password = saved.password.clone();
+            self.password_file = saved.password_file.clone();
+            self.password_command = saved.password_command.clone();
+            self.password_env = saved.password_env.clone();
+        }
+    }
+
+    /// Connect without a password, unless the update that's clearing the saved
+    /// credentials (see `keep_credentials`) set new ones.
+    pub fn clear_credentials(&mut self) {
+        if self.password.is_none() && self.password_helper().is_none() {
+            self.password = Some(String::new());
+        }
+    }
+}
+
+/// A `Connection` with its credentials removed, noting only whether they're set.
+#[derive(Debug, Serialize)]
+pub struct RedactedConnection {
+    #[serde(flatten)]
+    pub connection: Connection,
+    pub has_password: bool,
+    pub has_password_file: bool,
 }
 
 impl From<&Connection> for crate::db::Config {
@@ -170,17 +363,54 @@ impl From<&Connection> for crate::db::Config {
             .expect("`load_password` has been called");
 
         crate::db::Config::builder()
+            .name(conn.name.clone())
             .host(conn.host.clone())
             .port(conn.port)
+            .target_session_attrs(conn.target_session_attrs)
             .username(conn.username.clone())
             .password(password.clone())
             .database(conn.database.clone())
             .ssl(conn.ssl)
+            .maybe_ssl_root_cert(
+                conn.ssl_root_cert
+                    .as_ref()
+                    .map(|path| shellexpand::tilde(&path.to_string_lossy()).as_ref().into()),
+            )
+            .channel_binding(conn.channel_binding)
+            .text_fallback(conn.text_fallback)
+            .maybe_isolation_level(conn.isolation_level)
+            .listen_for_invalidation(conn.listen_for_invalidation)
+            .require_confirm_destructive(conn.require_confirm_destructive)
+            .display_format(conn.display_format.clone())
+            .reset_session(conn.reset_session)
+            .query_policy(conn.query_policy.clone())
+            .maybe_max_page_size(conn.max_page_size)
+            .maybe_default_page_size(conn.default_page_size)
+            .maybe_options(conn.options.clone())
+            .session_setup(conn.session_setup.clone())
+            .maybe_validation_query(conn.validation_query.clone())
+            .conflict_retries(conn.conflict_retries)
+            .maybe_force_utf8(conn.force_utf8)
             .build()
     }
 }
 
 impl Store {
+    /// The database to use for the given connection: `database` if one was requested,
+    /// otherwise the connection's configured default.
+    pub fn resolve_database(
+        &self,
+        connection: &str,
+        database: Option<String>,
+    ) -> eyre::Result<String> {
+        let conn = self
+            .connections
+            .iter()
+            .find(|c| c.name == connection)
+            .ok_or(eyre::eyre!("no connection named {}", connection))?;
+        Ok(database.unwrap_or_else(|| conn.database.clone()))
+    }
+
     pub fn load() -> eyre::Result<Self> {
         match std::fs::read_to_string(crate::config_dir().join(STORE_FILE)) {
             Ok(toml_str) => {
@@ -219,6 +449,320 @@ impl Store {
     }
 }
 
+/// The names of the connections that changed between two versions of the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectionChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl ConnectionChanges {
+    pub fn between(old: &[Connection], new: &[Connection]) -> Self {
+        // passwords loaded from a helper aren't part of the config itself
+        let settings = |conn: &Connection| {
+            let mut conn = conn.clone();
+            if conn.password_helper().is_some() {
+                conn.password = None;
+            }
+            conn
+        };
+
+        let mut changes = Self::default();
+        for conn in new {
+            match old.iter().find(|prev| prev.name == conn.name) {
+                None => changes.added.push(conn.name.clone()),
+                Some(prev) if settings(prev) != settings(conn) => {
+                    changes.modified.push(conn.name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .iter()
+            .filter(|conn| !new.iter().any(|next| next.name == conn.name))
+            .map(|conn| conn.name.clone())
+            .collect();
+
+        changes
+    }
+}
+
+/// A problem with one connection in a proposed config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigProblem {
+    /// The connection's position in the config (since its name may be the problem).
+    pub index: usize,
+    pub connection: String,
+    /// The setting at fault, e.g. `port`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.connection.as_str() {
+            "" => write!(f, "connection #{}: {}", self.index + 1, self.message),
+            name => write!(f, "{name}: {}", self.message),
+        }
+    }
+}
+
+/// Check a proposed set of connections for problems that would stop them from being
+/// used, e.g. duplicate names or missing credentials. Returns every problem found.
+pub fn validate_connections(connections: &[Connection]) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    for (index, conn) in connections.iter().enumerate() {
+        let mut problem = |field, message: String| {
+            problems.push(ConfigProblem {
+                index,
+                connection: conn.name.clone(),
+                field,
+                message,
+            })
+        };
+
+        if conn.name.trim().is_empty() {
+            problem("name", "a name is required".to_owned());
+        } else if connections[..index].iter().any(|c| c.name == conn.name) {
+            problem(
+                "name",
+                format!("another connection is named {:?}", conn.name),
+            );
+        }
+        if let Err(err) = crate::db::parse_hosts(&conn.host, 5432) {
+            problem("host", err.to_string());
+        }
+        if !(1..=65535).contains(&conn.port) {
+            problem(
+                "port",
+                format!("port {} isn't between 1 and 65535", conn.port),
+            );
+        }
+        if conn.password.is_none() && conn.password_helper().is_none() {
+            problem(
+                "password",
+                "one of `password`, `password_command` or `password_file` must be set".to_owned(),
+            );
+        }
+        if let Some(options) = &conn.options
+            && let Err(err) = crate::db::validate_options(options)
+        {
+            problem("options", err.to_string());
+        }
+        for statement in &conn.session_setup {
+            if let Err(err) = crate::db::validate_session_setup(statement) {
+                problem("session_setup", err.to_string());
+            }
+        }
+        if conn.max_page_size == Some(0) {
+            problem(
+                "max_page_size",
+                "the max page size must be at least 1".to_owned(),
+            );
+        }
+        if conn.default_page_size == Some(0) {
+            problem(
+                "default_page_size",
+                "the default page size must be at least 1".to_owned(),
+            );
+        }
+    }
+    problems
+}
+
+/// The current version of the `ConnectionExport` format.
+const EXPORT_VERSION: u32 = 1;
+
+/// A portable set of connections that can be imported on another machine. Passwords
+/// are never encrypted under this machine's `ENCRYPTION_KEY`; they're either blanked
+/// out or encrypted under a passphrase (see `encrypt_with_passphrase`).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConnectionExport {
+    pub version: u32,
+    /// Whether passwords are included, encrypted with a passphrase.
+    pub encrypted: bool,
+    pub connections: Vec<Connection>,
+}
+
+impl ConnectionExport {
+    /// Decrypt the passwords of an `encrypted` export in place (doing nothing if it isn't
+    /// encrypted). Like encrypting them, this is deliberately slow.
+    pub fn decrypt(&mut self, passphrase: Option<&str>) -> eyre::Result<()> {
+        if !self.encrypted {
+            return Ok(());
+        }
+
+        let passphrase =
+            passphrase.ok_or(eyre::eyre!("a passphrase is required for this export"))?;
+        let mut keys = PassphraseKeys::new(passphrase);
+        for conn in self.connections.iter_mut() {
+            let secrets = conn
+                .password
+                .iter_mut()
+                .chain(conn.password_command.iter_mut())
+                .chain(conn.password_env.values_mut());
+            for secret in secrets {
+                *secret = keys
+                    .decrypt(secret)
+                    .map_err(|err| eyre::eyre!("{}: {err}", conn.name))?;
+            }
+        }
+        self.encrypted = false;
+        Ok(())
+    }
+}
+
+/// What to do when an imported connection has the same name as an existing one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportConflict {
+    /// Keep the existing connection.
+    #[default]
+    Skip,
+    /// Replace the existing connection.
+    Overwrite,
+    /// Import the connection under a new name, e.g. `prod (2)`.
+    Rename,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    /// `(original name, new name)` pairs.
+    pub renamed: Vec<(String, String)>,
+    /// Connections (by their new names) that were exported without their credentials,
+    /// which have to be set before they can be used.
+    pub needs_credentials: Vec<String>,
+}
+
+impl Store {
+    /// Export all connections. If no passphrase is given, passwords (and the arguments
+    /// and environment of password helpers, which may hold secrets of their own) are
+    /// left out and must be re-entered after importing; otherwise, they're encrypted
+    /// under a key derived from it with `rounds` rounds of PBKDF2 (see
+    /// `PASSPHRASE_ROUNDS`). This is deliberately slow, so it shouldn't be run on an
+    /// async task.
+    pub fn export_connections(&self, passphrase: Option<&str>, rounds: u32) -> ConnectionExport {
+        let key = passphrase.map(|passphrase| PassphraseKey::new(passphrase, rounds));
+        let connections = self
+            .connections
+            .iter()
+            .cloned()
+            .map(|mut conn| {
+                match &key {
+                    Some(key) => {
+                        let secrets = conn
+                            .password
+                            .iter_mut()
+                            .chain(conn.password_command.iter_mut())
+                            .chain(conn.password_env.values_mut());
+                        for secret in secrets {
+                            *secret = key.encrypt(secret);
+                        }
+                    }
+                    None => {
+                        conn.password = None;
+                        conn.password_command.clear();
+                        conn.password_env.clear();
+                    }
+                }
+                conn
+            })
+            .collect();
+
+        ConnectionExport {
+            version: EXPORT_VERSION,
+            encrypted: passphrase.is_some(),
+            connections,
+        }
+    }
+
+    /// Import connections from an export, either merging them with the existing
+    /// connections (resolving name collisions with `on_conflict`) or replacing them.
+    /// An overwritten connection keeps its credentials if the export left them out. The
+    /// caller is responsible for persisting the store.
+    pub fn import_connections(
+        &mut self,
+        export: ConnectionExport,
+        passphrase: Option<&str>,
+        on_conflict: ImportConflict,
+        replace: bool,
+    ) -> eyre::Result<ImportSummary> {
+        if export.version > EXPORT_VERSION {
+            eyre::bail!(
+                "unsupported export version {} (expected {EXPORT_VERSION} or lower)",
+                export.version
+            );
+        }
+
+        // decrypt everything up front, so that a bad passphrase doesn't leave a partial import
+        let mut export = export;
+        export.decrypt(passphrase)?;
+        let imported = export.connections;
+
+        // connections exported without a passphrase have no credentials, which can be set
+        // afterwards; anything else that would stop them from being used is rejected
+        let problems = validate_connections(&imported)
+            .into_iter()
+            .filter(|problem| problem.field != "password")
+            .map(|problem| problem.to_string())
+            .collect::<Vec<_>>();
+        if !problems.is_empty() {
+            eyre::bail!("invalid export:\n{}", problems.join("\n"));
+        }
+
+        if replace {
+            self.connections.clear();
+        }
+
+        let mut summary = ImportSummary::default();
+        for mut conn in imported {
+            let Some(idx) = self.connections.iter().position(|c| c.name == conn.name) else {
+                summary.imported.push(conn.name.clone());
+                self.connections.push(conn);
+                continue;
+            };
+
+            match on_conflict {
+                ImportConflict::Skip => summary.skipped.push(conn.name),
+                ImportConflict::Overwrite => {
+                    summary.overwritten.push(conn.name.clone());
+                    conn.keep_credentials(&self.connections[idx]);
+                    self.connections[idx] = conn;
+                }
+                ImportConflict::Rename => {
+                    let name = (2..)
+                        .map(|n| format!("{} ({n})", conn.name))
+                        .find(|name| !self.connections.iter().any(|c| &c.name == name))
+                        .unwrap();
+                    summary.renamed.push((conn.name, name.clone()));
+                    conn.name = name;
+                    self.connections.push(conn);
+                }
+            }
+        }
+
+        let merged = summary
+            .imported
+            .iter()
+            .chain(&summary.overwritten)
+            .chain(summary.renamed.iter().map(|(_, name)| name));
+        summary.needs_credentials = merged
+            .filter(|name| {
+                self.connections.iter().any(|c| {
+                    &c.name == *name && c.password.is_none() && c.password_helper().is_none()
+                })
+            })
+            .cloned()
+            .collect();
+
+        Ok(summary)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EncryptedString(String);
 
@@ -256,6 +800,110 @@ impl EncryptedString {
     }
 }
 
+/// The number of PBKDF2 rounds used to derive a key from an export passphrase. This is
+/// stored alongside the ciphertext, so it can be raised without breaking old exports.
+pub const PASSPHRASE_ROUNDS: u32 = 600_000;
+
+/// The most PBKDF2 rounds an encrypted value may ask for. The count comes from the
+/// (untrusted) export, so without a limit, a single value could tie up a thread for hours.
+const MAX_PASSPHRASE_ROUNDS: u32 = PASSPHRASE_ROUNDS * 4;
+
+/// Encrypt `plaintext` under a key derived from `passphrase`. Unlike `EncryptedString`,
+/// which is tied to this machine's `ENCRYPTION_KEY`, this can be decrypted anywhere
+/// the passphrase is known.
+pub fn encrypt_with_passphrase(plaintext: &str, passphrase: &str, rounds: u32) -> String {
+    PassphraseKey::new(passphrase, rounds).encrypt(plaintext)
+}
+
+pub fn decrypt_with_passphrase(str: &str, passphrase: &str) -> eyre::Result<String> {
+    PassphraseKeys::new(passphrase).decrypt(str)
+}
+
+/// A key derived from a passphrase (with a random salt), which can encrypt any number
+/// of values while only paying for the derivation once.
+struct PassphraseKey {
+    rounds: u32,
+    salt: [u8; 16],
+    cipher: Aes256Gcm,
+}
+
+impl PassphraseKey {
+    fn new(passphrase: &str, rounds: u32) -> Self {
+        let salt: [u8; 16] = Aes256Gcm::generate_key(OsRng)[..16].try_into().unwrap();
+        Self {
+            rounds,
+            salt,
+            cipher: Aes256Gcm::new(&passphrase_key(passphrase, &salt, rounds)),
+        }
+    }
+
+    fn encrypt(&self, plaintext: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let encrypted = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("encryption works on utf-8 string");
+        format!(
+            "{}:{}:{:02x}:{}",
+            self.rounds,
+            hex::encode(self.salt),
+            nonce,
+            hex::encode(&encrypted)
+        )
+    }
+}
+
+/// Decrypts values encrypted under a passphrase, deriving the key for each salt (and
+/// number of rounds) only once.
+struct PassphraseKeys<'a> {
+    passphrase: &'a str,
+    ciphers: HashMap<(u32, Vec<u8>), Aes256Gcm>,
+}
+
+impl<'a> PassphraseKeys<'a> {
+    fn new(passphrase: &'a str) -> Self {
+        Self {
+            passphrase,
+            ciphers: HashMap::new(),
+        }
+    }
+
+    fn decrypt(&mut self, str: &str) -> eyre::Result<String> {
+        let mut parts = str.splitn(4, ':');
+        let (Some(rounds_str), Some(salt_str), Some(nonce_str), Some(encrypted_str)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            eyre::bail!("not a valid encrypted string");
+        };
+        let rounds: u32 = rounds_str.parse()?;
+        if !(1..=MAX_PASSPHRASE_ROUNDS).contains(&rounds) {
+            eyre::bail!(
+                "invalid number of rounds {rounds} (expected 1 to {MAX_PASSPHRASE_ROUNDS})"
+            );
+        }
+        let salt = hex::decode(salt_str)?;
+        let nonce: [u8; 12] = hex::decode(nonce_str)?
+            .try_into()
+            .map_err(|_| eyre::eyre!("invalid nonce"))?;
+        let encrypted = hex::decode(encrypted_str)?;
+        let passphrase = self.passphrase;
+        let cipher = self
+            .ciphers
+            .entry((rounds, salt))
+            .or_insert_with_key(|(rounds, salt)| {
+                Aes256Gcm::new(&passphrase_key(passphrase, salt, *rounds))
+            });
+        let plaintext = cipher
+            .decrypt(&nonce.into(), encrypted.as_ref())
+            .map_err(|_| eyre::eyre!("unable to decode (is the passphrase correct?)"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8], rounds: u32) -> Key<Aes256Gcm> {
+    pbkdf2::pbkdf2_hmac_array::<sha2::Sha256, 32>(passphrase.as_bytes(), salt, rounds).into()
+}
+
 impl std::ops::Deref for EncryptedString {
     type Target = String;
 
@@ -312,4 +960,369 @@ mod tests {
         let encrypted = dbg!(EncryptedString(plaintext.to_owned()).dump());
         assert_eq!(*EncryptedString::load(&encrypted).unwrap(), plaintext);
     }
+
+    /// Far fewer rounds than `PASSPHRASE_ROUNDS`, to keep the tests fast.
+    const TEST_ROUNDS: u32 = 1_000;
+
+    #[test]
+    fn passphrase_encryption_roundtrips() {
+        let plaintext = "hello, world!";
+        let encrypted = encrypt_with_passphrase(plaintext, "correct horse", TEST_ROUNDS);
+        assert_eq!(
+            decrypt_with_passphrase(&encrypted, "correct horse").unwrap(),
+            plaintext
+        );
+        assert!(decrypt_with_passphrase(&encrypted, "battery staple").is_err());
+
+        // the rounds are checked before deriving the key, so this fails immediately
+        let (_, rest) = encrypted.split_once(':').unwrap();
+        let err =
+            decrypt_with_passphrase(&format!("4294967295:{rest}"), "correct horse").unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid number of rounds"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_export_roundtrips() {
+        let conn: Connection = toml::from_str(
+            r#"
+            name = "prod"
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            password = "hunter2"
+            database = "postgres"
+            "#,
+        )
+        .unwrap();
+        let store = Store {
+            connections: vec![conn],
+            ..Default::default()
+        };
+
+        let export = store.export_connections(Some("correct horse"), TEST_ROUNDS);
+        assert_ne!(export.connections[0].password.as_deref(), Some("hunter2"));
+        let export = serde_json::to_string(&export).unwrap();
+
+        let mut other = store.clone();
+        let summary = other
+            .import_connections(
+                serde_json::from_str(&export).unwrap(),
+                Some("correct horse"),
+                ImportConflict::Rename,
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            summary.renamed,
+            vec![("prod".to_owned(), "prod (2)".to_owned())]
+        );
+        assert_eq!(other.connections[1].password.as_deref(), Some("hunter2"));
+
+        // without a passphrase, passwords are left out rather than blanked
+        let export = store.export_connections(None, TEST_ROUNDS);
+        assert!(!export.encrypted);
+        assert_eq!(export.connections[0].password, None);
+        let export = serde_json::to_string(&export).unwrap();
+
+        // so an imported connection has to have them set before it can be used, while
+        // an overwritten one keeps its own
+        let mut other = Store::default();
+        let summary = other
+            .import_connections(
+                serde_json::from_str(&export).unwrap(),
+                None,
+                ImportConflict::Skip,
+                false,
+            )
+            .unwrap();
+        assert_eq!(summary.needs_credentials, ["prod"]);
+        let err = other.connections[0].load_password().await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("prod: one of `password`"),
+            "{err}"
+        );
+
+        let mut other = store.clone();
+        let summary = other
+            .import_connections(
+                serde_json::from_str(&export).unwrap(),
+                None,
+                ImportConflict::Overwrite,
+                false,
+            )
+            .unwrap();
+        assert!(summary.needs_credentials.is_empty());
+        assert_eq!(other.connections[0].password.as_deref(), Some("hunter2"));
+
+        // anything else wrong with the export is rejected
+        let mut invalid: serde_json::Value = serde_json::from_str(&export).unwrap();
+        invalid["connections"][0]["port"] = 0.into();
+        let err = Store::default()
+            .import_connections(
+                serde_json::from_value(invalid).unwrap(),
+                None,
+                ImportConflict::Skip,
+                false,
+            )
+            .unwrap_err();
+        assert!(err.to_string().starts_with("invalid export:"), "{err}");
+    }
+
+    #[test]
+    fn never_exports_password_helper_secrets_in_plaintext() {
+        let conn: Connection = toml::from_str(
+            r#"
+            name = "prod"
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            password_command = ["op", "read", "op://vault/db-secret-arg"]
+            password_env = { OP_TOKEN = "secret-env-value" }
+            database = "postgres"
+            "#,
+        )
+        .unwrap();
+        let store = Store {
+            connections: vec![conn],
+            ..Default::default()
+        };
+
+        for passphrase in [Some("correct horse"), None] {
+            let export = store.export_connections(passphrase, TEST_ROUNDS);
+            let export = serde_json::to_string(&export).unwrap();
+            for secret in ["db-secret-arg", "secret-env-value"] {
+                assert!(!export.contains(secret), "{export}");
+            }
+        }
+
+        // with a passphrase, they're restored on import
+        let export = store.export_connections(Some("correct horse"), TEST_ROUNDS);
+        let mut other = Store::default();
+        other
+            .import_connections(export, Some("correct horse"), ImportConflict::Skip, false)
+            .unwrap();
+        assert_eq!(
+            other.connections[0].password_command,
+            ["op", "read", "op://vault/db-secret-arg"]
+        );
+        assert_eq!(
+            other.connections[0].password_env["OP_TOKEN"],
+            "secret-env-value"
+        );
+
+        // without one, they're left out along with the password
+        let export = store.export_connections(None, TEST_ROUNDS);
+        assert!(export.connections[0].password_command.is_empty());
+        assert!(export.connections[0].password_env.is_empty());
+    }
+
+    #[test]
+    fn diffs_connection_changes() {
+        let conn = |name: &str, port: usize| -> Connection {
+            toml::from_str(&format!(
+                r#"
+                name = "{name}"
+                host = "localhost"
+                port = {port}
+                username = "postgres"
+                password_file = "/bin/pw"
+                database = "postgres"
+                "#
+            ))
+            .unwrap()
+        };
+
+        let mut loaded = conn("prod", 5432);
+        loaded.password = Some("hunter2".to_owned());
+        let old = vec![loaded, conn("staging", 5432), conn("dev", 5432)];
+        let new = vec![
+            conn("prod", 5432),
+            conn("staging", 6543),
+            conn("test", 5432),
+        ];
+
+        assert_eq!(
+            ConnectionChanges::between(&old, &new),
+            ConnectionChanges {
+                added: vec!["test".to_owned()],
+                removed: vec!["dev".to_owned()],
+                modified: vec!["staging".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn redacts_connection_credentials() {
+        let conn = |credentials: &str| -> Connection {
+            toml::from_str(&format!(
+                r#"
+                name = "prod"
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                database = "postgres"
+                {credentials}
+                "#
+            ))
+            .unwrap()
+        };
+
+        let with_password = conn(r#"password = "hunter2""#);
+        let mut with_password_file = conn(r#"password_file = "/secret/pw.sh""#);
+        with_password_file.password = Some("loaded-from-file".to_owned());
+
+        let redacted =
+            serde_json::to_string(&[with_password.redacted(), with_password_file.redacted()])
+                .unwrap();
+        for secret in ["hunter2", "/secret/pw.sh", "loaded-from-file"] {
+            assert!(!redacted.contains(secret), "{redacted}");
+        }
+
+        let redacted = with_password.redacted();
+        assert!(redacted.has_password && !redacted.has_password_file);
+        let redacted = with_password_file.redacted();
+        assert!(!redacted.has_password && redacted.has_password_file);
+
+        // updates without credentials keep the saved ones
+        let mut updated = with_password.redacted().connection;
+        updated.keep_credentials(&with_password);
+        assert_eq!(updated.password.as_deref(), Some("hunter2"));
+
+        let mut updated = conn(r#"password = "correct horse""#);
+        updated.keep_credentials(&with_password);
+        assert_eq!(updated.password.as_deref(), Some("correct horse"));
+    }
+
+    /// Write an executable shell script running `body`, for use as a `password_file`.
+    fn helper_script(name: &str, body: &str) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("dbc-{name}-{}.sh", std::process::id()));
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Load the password for a connection using the given helper and `extra` settings.
+    async fn load_helper_password(
+        path: &std::path::Path,
+        extra: &str,
+    ) -> eyre::Result<Option<String>> {
+        let mut conn: Connection = toml::from_str(&format!(
+            r#"
+            name = "prod"
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            password_file = {:?}
+            database = "postgres"
+            {extra}
+            "#,
+            path.to_str().unwrap()
+        ))
+        .unwrap();
+        conn.load_password().await.map(|()| conn.password)
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_password_output() {
+        crate::stream::init_for_tests();
+
+        let invalid = helper_script("invalid-utf8", r"printf '\377\376hunter2'");
+        let endless = helper_script("endless", "yes hunter2");
+        let valid = helper_script("valid-utf8", "echo 'hunter2'");
+
+        let invalid_result = load_helper_password(&invalid, "").await;
+        let valid_result = load_helper_password(&valid, "").await;
+        // fails as soon as the output is too large, rather than after the timeout
+        let started = std::time::Instant::now();
+        let endless_result = load_helper_password(&endless, "").await;
+        for path in [&invalid, &valid, &endless] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let err = invalid_result.unwrap_err();
+        assert_eq!(err.to_string(), "Password output isn't valid UTF-8");
+        assert_eq!(valid_result.unwrap().as_deref(), Some("hunter2"));
+        let err = endless_result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Password output is larger than {MAX_PASSWORD_OUTPUT} bytes")
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn waits_for_slow_password_helpers() {
+        crate::stream::init_for_tests();
+
+        let slow = helper_script("slow", "sleep 1.5; echo 'hunter2'");
+        let timed_out = load_helper_password(&slow, "password_file_timeout_s = 1").await;
+        let loaded = load_helper_password(&slow, "password_file_timeout_s = 5").await;
+        std::fs::remove_file(&slow).unwrap();
+
+        assert_eq!(timed_out.unwrap_err().to_string(), "Timeout after 1s");
+        assert_eq!(loaded.unwrap().as_deref(), Some("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn passes_arguments_and_environment_to_password_helpers() {
+        crate::stream::init_for_tests();
+
+        let helper = helper_script(
+            "echo-args",
+            r#"echo "$1:$DBC_CONN_NAME:$DBC_HOST:$PROFILE""#,
+        );
+        let mut conn: Connection = toml::from_str(&format!(
+            r#"
+            name = "prod"
+            host = "db.example.com"
+            port = 5432
+            username = "postgres"
+            password_command = [{:?}, "hunter2"]
+            password_env = {{ PROFILE = "admin" }}
+            database = "postgres"
+            "#,
+            helper.to_str().unwrap()
+        ))
+        .unwrap();
+        let result = conn.load_password().await;
+        std::fs::remove_file(&helper).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            conn.password.as_deref(),
+            Some("hunter2:prod:db.example.com:admin")
+        );
+    }
+
+    #[test]
+    fn resolves_default_database() {
+        let conn: Connection = toml::from_str(
+            r#"
+            name = "prod"
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            database = "app"
+            "#,
+        )
+        .unwrap();
+        let store = Store {
+            connections: vec![conn],
+            ..Default::default()
+        };
+
+        assert_eq!(store.resolve_database("prod", None).unwrap(), "app");
+        assert_eq!(
+            store
+                .resolve_database("prod", Some("analytics".to_owned()))
+                .unwrap(),
+            "analytics"
+        );
+        assert!(store.resolve_database("staging", None).is_err());
+    }
 }
@@ -1,4 +1,6 @@
+use crate::cache::ResultCache;
 use crate::db;
+use crate::stream::{PoolStatus, StreamEvent};
 use std::{collections::VecDeque, sync::Arc};
 use tokio::{
     select,
@@ -6,10 +8,12 @@ use tokio::{
     sync::broadcast::{Sender, channel},
     sync::mpsc,
 };
+use tokio_postgres::AsyncMessage;
 
 pub struct ConnectionPool {
     inner: Arc<Mutex<ConnectionPoolInner>>,
     timeout: std::time::Duration,
+    last_used: std::time::Instant,
 }
 
 struct ConnectionPoolInner {
@@ -21,20 +25,76 @@ struct ConnectionPoolInner {
     health_check_timeout: std::time::Duration,
     not_idle: Option<mpsc::Sender<()>>,
     failed_health_checks: usize,
+    checked_out: usize,
+    /// Incremented whenever the pool's connections are (re)opened, so that connections
+    /// checked out before then aren't returned to the pool.
+    generation: u64,
+    /// A dedicated connection that `LISTEN`s for cached result invalidations, if enabled.
+    listener: Option<db::Connection>,
+    /// Results cached by queries run on the pool's connections; only used while there's
+    /// a `listener` to invalidate them.
+    cache: Arc<ResultCache>,
 }
 
 pub struct CheckedOutConnection {
     conn: Option<db::Connection>,
     pool: Option<Arc<Mutex<ConnectionPoolInner>>>,
+    /// The pool's generation when the connection was checked out.
+    generation: u64,
+    /// The pool's result cache, if it's listening for invalidations.
+    cache: Option<Arc<ResultCache>>,
 }
 
 impl Drop for CheckedOutConnection {
     fn drop(&mut self) {
         let mut conn = self.conn.take().unwrap();
         let pool = self.pool.take().unwrap();
+        let generation = self.generation;
 
         tokio::spawn(async move {
+            let (reset_session, isolation_level, session_setup, force_utf8, current) = {
+                let pool = pool.lock().await;
+                (
+                    pool.config.reset_session,
+                    pool.config.isolation_level,
+                    pool.config.session_setup.clone(),
+                    pool.config.force_utf8,
+                    pool.generation == generation,
+                )
+            };
+
+            let reusable = if !current {
+                // connections from before the pool was reloaded may use an outdated
+                // config, so they're never reused
+                tracing::debug!("pool was reloaded, discarding returned connection");
+                conn.kill();
+                false
+            } else {
+                // don't let the next checkout inherit an open (or aborted) transaction, or
+                // (if enabled) any session state; if either can't be reset, the connection
+                // isn't safe to reuse
+                let reset = async {
+                    if conn.rollback_open_transaction().await? {
+                        tracing::debug!("rolled back open transaction on returned connection");
+                    }
+                    if reset_session {
+                        conn.reset_session(isolation_level, &session_setup, force_utf8)
+                            .await?;
+                    }
+                    eyre::Ok(())
+                };
+                match reset.await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        tracing::warn!("failed to reset returned connection: {err}");
+                        conn.kill();
+                        false
+                    }
+                }
+            };
+
             let mut pool = pool.lock().await;
+            pool.checked_out = pool.checked_out.saturating_sub(1);
 
             // if the pool has been shut down, don't check the connection back in
             if !pool.live {
@@ -44,14 +104,23 @@ impl Drop for CheckedOutConnection {
             let was_empty = pool.conns.is_empty();
 
             // if this connection has terminated, we don't need to put it back into the pool;
-            // instead, ask the pool to spawn a new connection
-            if conn.is_live() {
+            // instead, ask the pool to spawn a new connection (unless reloading the pool
+            // already replaced it)
+            if reusable && conn.is_live() {
                 pool.conns.push_front(conn);
-            } else {
-                pool.spawn_conn().await.unwrap();
+            } else if pool.size() >= pool.config.pool_size {
+                // reloading the pool already opened a replacement
+            } else if let Err(err) = pool.spawn_conn().await {
+                // the pool shrinks until a connection is next needed (see `wait_for_conn`)
+                tracing::warn!(
+                    "failed to replace returned connection ({}/{} open): {err}",
+                    pool.size(),
+                    pool.config.pool_size
+                );
             }
 
-            // if pool was empty, notify that a connection is now available
+            // if pool was empty, notify waiters, even if the connection couldn't be
+            // replaced, so that they can try to open one themselves
             if was_empty {
                 let _ = pool.conn_avail.send(());
             }
@@ -59,6 +128,37 @@ impl Drop for CheckedOutConnection {
     }
 }
 
+impl CheckedOutConnection {
+    /// The pool's result cache (see `QueryOptions::cache`), unless the pool's connection
+    /// doesn't have `listen_for_invalidation` set.
+    pub fn result_cache(&self) -> Option<&ResultCache> {
+        self.cache.as_deref()
+    }
+
+    /// Drop the pool's cached results after writing through this connection, since the
+    /// write may have changed what any of them read. Every route that writes should call
+    /// this (or `invalidate_cache_after`), whether or not the write succeeded.
+    pub async fn invalidate_cache(&self) {
+        if let Some(cache) = self.result_cache() {
+            cache.invalidate(&db::Invalidation::All).await;
+        }
+    }
+
+    /// Like `invalidate_cache`, unless the query only reads (see `db::is_read_only`).
+    pub async fn invalidate_cache_after(&self, query: &str) {
+        if !db::is_read_only(query) {
+            self.invalidate_cache().await;
+        }
+    }
+
+    /// Kill the connection, so that it's replaced rather than returned to the pool.
+    pub fn kill(&mut self) {
+        if let Some(conn) = self.conn.as_mut() {
+            conn.kill();
+        }
+    }
+}
+
 impl std::ops::Deref for CheckedOutConnection {
     type Target = db::Connection;
 
@@ -96,6 +196,10 @@ impl ConnectionPool {
             // will be set by `spawn_idle_watcher`
             not_idle: None,
             failed_health_checks: 0,
+            checked_out: 0,
+            generation: 0,
+            listener: None,
+            cache: Default::default(),
         };
 
         // spawn initial connection tasks
@@ -104,6 +208,7 @@ impl ConnectionPool {
         let mut this = Self {
             inner: Arc::new(Mutex::new(inner)),
             timeout: std::time::Duration::from_secs(timeout_s),
+            last_used: std::time::Instant::now(),
         };
 
         // spawn idle watcher
@@ -130,8 +235,14 @@ impl ConnectionPool {
 
                 if let Err(_) = tokio::time::timeout(idle_timeout, not_idle_rx.recv()).await {
                     tracing::info!("pool idle timeout reached, shutting down...");
-                    crate::stream::broadcast("pool idle timeout reached, shutting down...").await;
                     let mut inner = inner.lock().await;
+                    crate::stream::broadcast_pool_status(
+                        inner.config.name.as_deref(),
+                        &inner.config.database,
+                        PoolStatus::Dormant,
+                        "pool idle timeout reached, shutting down...",
+                    )
+                    .await;
                     inner.go_dormant().await;
                     break;
                 }
@@ -146,17 +257,46 @@ impl ConnectionPool {
         inner.config.pool_size
     }
 
+    /// The number of open connections that aren't checked out. This can be less than
+    /// `pool_size` if some connections failed to open.
+    pub async fn available(&self) -> usize {
+        let inner = self.inner.lock().await;
+        inner.conns.len()
+    }
+
+    /// The number of open connections, whether available or checked out. This is less
+    /// than `pool_size` if some connections failed to open (or be replaced).
+    pub async fn size(&self) -> usize {
+        let inner = self.inner.lock().await;
+        inner.size()
+    }
+
+    /// When a connection was last checked out of this pool.
+    pub fn last_used(&self) -> std::time::Instant {
+        self.last_used
+    }
+
+    /// The number of connections that are currently checked out of this pool.
+    pub async fn checked_out(&self) -> usize {
+        let inner = self.inner.lock().await;
+        inner.checked_out
+    }
+
     pub async fn get_conn(&mut self) -> eyre::Result<CheckedOutConnection> {
         let timeout = self.timeout;
+        self.last_used = std::time::Instant::now();
 
-        select! {
+        let conn = select! {
             // when a connection is checked back in, try to get it
             // it's possible that this fails if another thread was also
             // waiting for a connection, in which case we'll keep waiting
             conn = self.wait_for_conn() => {
+                let (conn, generation, cache) = conn?;
                 Ok(CheckedOutConnection {
-                    conn: Some(conn?),
+                    conn: Some(conn),
                     pool: Some(Arc::clone(&self.inner)),
+                    generation,
+                    cache,
                 })
             }
 
@@ -164,10 +304,19 @@ impl ConnectionPool {
             _ = tokio::time::sleep(timeout) => {
                 Err(eyre::eyre!("no connection available after {}s", timeout.as_secs()))
             }
-        }
+        };
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_checkout_wait(self.last_used.elapsed());
+
+        conn
     }
 
-    async fn wait_for_conn(&mut self) -> eyre::Result<db::Connection> {
+    /// Wait for a connection, returning it along with the pool's current generation and
+    /// (if it's listening for invalidations) result cache.
+    async fn wait_for_conn(
+        &mut self,
+    ) -> eyre::Result<(db::Connection, u64, Option<Arc<ResultCache>>)> {
         // try to get a connection from the pool
         let mut inner = self.inner.lock().await;
 
@@ -176,7 +325,13 @@ impl ConnectionPool {
         // and don't recurse infinitely
         if !inner.live {
             tracing::debug!("pool is dormant, reloading...");
-            crate::stream::broadcast("pool is dormant, reloading...").await;
+            crate::stream::broadcast_pool_status(
+                inner.config.name.as_deref(),
+                &inner.config.database,
+                PoolStatus::Reloading,
+                "pool is dormant, reloading...",
+            )
+            .await;
             inner.init().await?;
             drop(inner);
 
@@ -192,7 +347,7 @@ impl ConnectionPool {
             let health_check_timeout = inner.health_check_timeout;
 
             // perform health check to detect network issues
-            if !conn.health_check(health_check_timeout).await {
+            let Some(transaction_status) = conn.health_check(health_check_timeout).await else {
                 tracing::warn!("connection health check failed, spawning new connection");
                 conn.kill();
                 inner.failed_health_checks += 1;
@@ -202,7 +357,13 @@ impl ConnectionPool {
                         "connection unstable after {} consecutive failures, going dormant",
                         inner.failed_health_checks
                     );
-                    crate::stream::broadcast("Connection unstable, going dormant. Please check your network/VPN connection.").await;
+                    crate::stream::broadcast_pool_status(
+                        inner.config.name.as_deref(),
+                        &inner.config.database,
+                        PoolStatus::Dormant,
+                        "Connection unstable, going dormant. Please check your network/VPN connection.",
+                    )
+                    .await;
                     inner.go_dormant().await;
                     drop(inner);
                     return Err(eyre::eyre!(
@@ -213,16 +374,46 @@ impl ConnectionPool {
                 inner.spawn_conn().await?;
                 drop(inner);
                 return Box::pin(self.wait_for_conn()).await;
-            }
+            };
 
             // health check passed, reset failure counter
             inner.failed_health_checks = 0;
 
+            // connections are rolled back when they're returned, so this shouldn't
+            // happen; but if it does, don't hand out someone else's transaction
+            if transaction_status != db::TransactionStatus::Idle {
+                tracing::warn!("available connection was left {transaction_status}, rolling back");
+                // the health check already found the transaction, so roll it back directly
+                if let Err(err) = conn
+                    .batch_execute("ROLLBACK")
+                    .await
+                    .map_err(db::PgError::from)
+                {
+                    tracing::warn!("failed to roll back available connection: {err}");
+                    conn.kill();
+                    inner.spawn_conn().await?;
+                    drop(inner);
+                    return Box::pin(self.wait_for_conn()).await;
+                }
+            }
+
             if let Some(not_idle) = inner.not_idle.as_ref() {
                 let _ = not_idle.send(()).await;
             }
 
-            return Ok(conn);
+            inner.checked_out += 1;
+            // without a live listener, invalidations would be missed
+            let listening = inner.listener.as_mut().is_some_and(|l| l.is_live());
+            let cache = listening.then(|| Arc::clone(&inner.cache));
+            return Ok((conn, inner.generation, cache));
+        }
+
+        // if every connection has been lost (e.g. they couldn't be replaced when
+        // returned), no connection will ever be checked back in, so open one now
+        if inner.size() == 0 {
+            inner.spawn_conn().await?;
+            drop(inner);
+            return Box::pin(self.wait_for_conn()).await;
         }
 
         let mut conn_avail = inner.conn_avail.subscribe();
@@ -234,6 +425,13 @@ impl ConnectionPool {
         Box::pin(self.wait_for_conn()).await
     }
 
+    /// Whether the pool closed its connections after being idle (or unstable); they're
+    /// re-opened on next use.
+    pub async fn is_dormant(&self) -> bool {
+        let inner = self.inner.lock().await;
+        !inner.live
+    }
+
     pub async fn is_unstable(&self) -> bool {
         let inner = self.inner.lock().await;
         inner.failed_health_checks > 0
@@ -254,30 +452,99 @@ impl ConnectionPool {
         Ok(())
     }
 
-    pub async fn debug(&self) -> String {
-        let inner = self.inner.lock().await;
+    /// Close all connections in the pool. Any connections that are currently checked
+    /// out will be closed once they're returned.
+    pub async fn close(&self) {
+        let mut inner = self.inner.lock().await;
+        inner.go_dormant().await;
+    }
 
-        let live = inner.live;
-        let pool_size = inner.config.pool_size;
-        let available = inner.conns.len();
-        let checked_out = pool_size - available;
+    /// Describe the pool's state. The returned future doesn't borrow the pool, so it can
+    /// be awaited after letting go of whatever lock the pool is kept behind.
+    pub fn debug(&self) -> impl Future<Output = String> + Send + 'static {
+        let inner = Arc::clone(&self.inner);
+        async move {
+            let inner = inner.lock().await;
+
+            let live = inner.live;
+            let pool_size = inner.config.pool_size;
+            let size = inner.size();
+            let available = inner.conns.len();
+            let checked_out = inner.checked_out;
+
+            // available connections should never be left in a transaction, since they're
+            // rolled back when they're returned; each one is only given as long as a health
+            // check, so an unresponsive one can't hold up the pool
+            let (mut in_transaction, mut failed, mut unresponsive) = (0, 0, 0);
+            for conn in &inner.conns {
+                match conn.health_check(inner.health_check_timeout).await {
+                    Some(db::TransactionStatus::InTransaction) => in_transaction += 1,
+                    Some(db::TransactionStatus::Failed) => failed += 1,
+                    Some(db::TransactionStatus::Idle) => {}
+                    None => unresponsive += 1,
+                }
+            }
 
-        format!(
-            "live={live}, checked_out={checked_out}, available={available}, pool_size={pool_size}"
-        )
+            format!(
+                "live={live}, checked_out={checked_out}, available={available}, size={size}, pool_size={pool_size}, \
+                 in_transaction={in_transaction}, failed_transaction={failed}, unresponsive={unresponsive}"
+            )
+        }
     }
 }
 
 impl ConnectionPoolInner {
+    fn size(&self) -> usize {
+        self.conns.len() + self.checked_out
+    }
+
     async fn spawn_conn(&mut self) -> eyre::Result<()> {
         let conn = db::connect(&self.config).await?;
+        // catch a connection that can't run queries now, rather than on its first use
+        conn.validate(&self.config.validation_query).await?;
         self.conns.push_front(conn);
         Ok(())
     }
 
+    /// Open the pool's connections, broadcasting progress as each one opens. If some
+    /// (but not all) of them fail to open, the pool comes up with fewer connections.
     async fn init(&mut self) -> eyre::Result<()> {
-        for _ in 0..self.config.pool_size {
-            self.spawn_conn().await?;
+        self.generation += 1;
+
+        let total = self.config.pool_size;
+        let (mut opened, mut failed) = (0, 0);
+        for n in 1..=total {
+            let message = match self.spawn_conn().await {
+                Ok(()) => {
+                    opened += 1;
+                    format!("opened {opened}/{total}")
+                }
+                // if the first connection can't be opened, the rest won't be either
+                Err(err) if opened == 0 => return Err(err),
+                Err(err) => {
+                    failed += 1;
+                    tracing::warn!("connection {n}/{total} failed to open: {err}");
+                    format!("connection {n}/{total} failed to open: {err}")
+                }
+            };
+            crate::stream::broadcast_event(StreamEvent::PoolProgress {
+                connection: self.config.name.clone(),
+                database: self.config.database.clone(),
+                opened,
+                failed,
+                total,
+                message,
+            })
+            .await;
+        }
+
+        // any invalidations sent while the pool was closed (or listening on another
+        // database) were missed
+        self.cache.invalidate(&db::Invalidation::All).await;
+        if self.config.listen_for_invalidation {
+            self.listener = Some(spawn_listener(&self.config, Arc::clone(&self.cache)).await?);
+        } else {
+            self.listener = None;
         }
 
         self.live = true;
@@ -291,5 +558,322 @@ impl ConnectionPoolInner {
         self.conns.clear();
         self.not_idle = None;
         self.failed_health_checks = 0;
+        self.listener = None;
+    }
+}
+
+/// Open a connection that listens for cached result invalidations, dropping the results
+/// they apply to from `cache` and broadcasting them to any clients. The listener stops
+/// once the returned connection is dropped.
+async fn spawn_listener(
+    config: &db::Config,
+    cache: Arc<ResultCache>,
+) -> eyre::Result<db::Connection> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let conn = db::listen(config, db::INVALIDATE_CHANNEL, tx).await?;
+
+    let database = config.database.clone();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let AsyncMessage::Notification(notification) = message else {
+                continue;
+            };
+
+            crate::stream::broadcast_event(StreamEvent::Notification {
+                database: database.clone(),
+                channel: notification.channel().to_owned(),
+                payload: notification.payload().to_owned(),
+            })
+            .await;
+
+            let invalidation = db::Invalidation::from_payload(notification.payload());
+            let dropped = cache.invalidate(&invalidation).await;
+            match invalidation {
+                db::Invalidation::All => {
+                    tracing::info!("invalidated all {dropped} cached results for db \"{database}\"")
+                }
+                db::Invalidation::Table(table) => tracing::info!(
+                    "invalidated {dropped} cached results for table \"{table}\" in db \"{database}\""
+                ),
+            }
+        }
+    });
+
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    /// A single-connection pool for the test database (see `db::tests::test_config`).
+    async fn test_pool(reset_session: bool) -> Option<ConnectionPool> {
+        let mut config = db::tests::test_config()?;
+        crate::stream::init_for_tests();
+        config.pool_size = 1;
+        config.reset_session = reset_session;
+        Some(ConnectionPool::new(config).await.unwrap())
+    }
+
+    async fn backend_pid(conn: &CheckedOutConnection) -> i32 {
+        conn.query_one("SELECT pg_backend_pid()", &[])
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    #[tokio::test]
+    async fn fails_to_open_connections_that_cant_run_the_validation_query() {
+        let Some(mut config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        crate::stream::init_for_tests();
+        config.pool_size = 1;
+        config.session_setup = vec!["SET search_path = dbc_missing".to_owned()];
+        config.validation_query = "SELECT count(*) FROM pg_class, dbc_settings".to_owned();
+
+        let err = ConnectionPool::new(config).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "validation query \"SELECT count(*) FROM pg_class, dbc_settings\" failed: \
+             ERROR 42P01: relation \"dbc_settings\" does not exist (at position 32)"
+        );
+    }
+
+    #[tokio::test]
+    async fn rolls_back_aborted_transaction_on_return() {
+        let Some(mut pool) = test_pool(false).await else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+
+        let conn = pool.get_conn().await.unwrap();
+        let pid = backend_pid(&conn).await;
+        conn.batch_execute("BEGIN").await.unwrap();
+        assert!(conn.batch_execute("SELECT 1 / 0").await.is_err());
+        drop(conn);
+
+        // the same connection is handed out again, with no transaction left open
+        let conn = pool.get_conn().await.unwrap();
+        assert_eq!(backend_pid(&conn).await, pid);
+        conn.batch_execute("SELECT 1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_open_transactions() {
+        let Some(mut pool) = test_pool(false).await else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+
+        let conn = pool.get_conn().await.unwrap();
+        assert_eq!(
+            conn.transaction_status().await.unwrap(),
+            db::TransactionStatus::Idle
+        );
+        conn.batch_execute("BEGIN").await.unwrap();
+        assert_eq!(
+            conn.transaction_status().await.unwrap(),
+            db::TransactionStatus::InTransaction
+        );
+        assert!(conn.batch_execute("SELECT 1 / 0").await.is_err());
+        assert_eq!(
+            conn.transaction_status().await.unwrap(),
+            db::TransactionStatus::Failed
+        );
+        drop(conn);
+
+        // the transaction was rolled back once the connection was returned
+        let conn = pool.get_conn().await.unwrap();
+        assert_eq!(
+            conn.transaction_status().await.unwrap(),
+            db::TransactionStatus::Idle
+        );
+        drop(conn);
+        // wait for the connection to be checked back in
+        while pool.checked_out().await > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(
+            pool.debug()
+                .await
+                .ends_with("in_transaction=0, failed_transaction=0, unresponsive=0")
+        );
+    }
+
+    #[tokio::test]
+    async fn resets_session_state_on_return() {
+        let Some(mut pool) = test_pool(true).await else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+
+        let conn = pool.get_conn().await.unwrap();
+        let pid = backend_pid(&conn).await;
+        conn.batch_execute("SET statement_timeout = '42s'; CREATE TEMP TABLE scratch (id int)")
+            .await
+            .unwrap();
+        drop(conn);
+
+        let conn = pool.get_conn().await.unwrap();
+        assert_eq!(backend_pid(&conn).await, pid);
+        let timeout: String = conn
+            .query_one("SHOW statement_timeout", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(timeout, "0");
+        conn.batch_execute("CREATE TEMP TABLE scratch (id int)")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_connections_returned_after_reload() {
+        let Some(mut pool) = test_pool(false).await else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+
+        let conn = pool.get_conn().await.unwrap();
+        let pid = backend_pid(&conn).await;
+
+        let mut config = db::tests::test_config().unwrap();
+        config.pool_size = 1;
+        config.isolation_level = Some(db::IsolationLevel::Serializable);
+        pool.reload(config).await.unwrap();
+        assert_eq!(pool.size().await, 2);
+        drop(conn);
+
+        // the connection is returned in the background
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while pool.checked_out().await > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(pool.size().await, 1);
+
+        // only the connection opened with the new config is handed out
+        let conn = pool.get_conn().await.unwrap();
+        assert_ne!(backend_pid(&conn).await, pid);
+        let isolation_level: String = conn
+            .query_one("SHOW default_transaction_isolation", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(isolation_level, "serializable");
+    }
+
+    #[tokio::test]
+    async fn shrinks_if_returned_connection_cannot_be_replaced() {
+        let Some(mut pool) = test_pool(false).await else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        assert_eq!(pool.size().await, 1);
+
+        // terminate the checked out connection, and make sure it can't be replaced
+        let conn = pool.get_conn().await.unwrap();
+        let port = {
+            let mut inner = pool.inner.lock().await;
+            std::mem::replace(&mut inner.config.port, 1)
+        };
+        let _ = conn
+            .batch_execute("SELECT pg_terminate_backend(pg_backend_pid())")
+            .await;
+        drop(conn);
+
+        // the connection is returned in the background
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while pool.checked_out().await > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(pool.size().await, 0);
+
+        // rather than waiting for the pool timeout, checkouts try to open a connection
+        let started = std::time::Instant::now();
+        assert!(pool.get_conn().await.is_err());
+        assert!(started.elapsed() < pool.timeout);
+
+        pool.inner.lock().await.config.port = port;
+        let conn = pool.get_conn().await.unwrap();
+        conn.batch_execute("SELECT 1").await.unwrap();
+        assert_eq!(pool.size().await, 1);
+    }
+
+    #[tokio::test]
+    async fn opens_with_fewer_connections_if_some_fail() {
+        let Some(mut config) = db::tests::test_config() else {
+            eprintln!("PGHOST not set, skipping");
+            return;
+        };
+        crate::stream::init_for_tests();
+        // leave room for other tests' (replayed) events, since any that don't fit are dropped
+        let (tx, mut rx) = mpsc::channel(1024);
+        crate::stream::subscribe(tx).await.unwrap();
+
+        // a role that can only hold 2 connections at once
+        let admin = db::connect(&config).await.unwrap();
+        admin
+            .batch_execute(
+                "DROP ROLE IF EXISTS dbc_limited;
+                 CREATE ROLE dbc_limited LOGIN CONNECTION LIMIT 2 PASSWORD 'dbc'",
+            )
+            .await
+            .unwrap();
+        // drop the role even if the test fails, so it isn't left behind
+        let test = async {
+            config.username = "dbc_limited".to_owned();
+            config.password = "dbc".to_owned();
+            config.pool_size = 4;
+
+            let pool = ConnectionPool::new(config).await.unwrap();
+            assert_eq!(pool.available().await, 2);
+
+            // other tests share the stream, so only look at progress for this pool's size
+            let mut progress = Vec::new();
+            while progress.len() < 4 {
+                let msg = rx.recv().await.unwrap();
+                let msg = serde_json::from_str::<serde_json::Value>(&msg).unwrap();
+                if msg["type"] == "pool_progress" && msg["total"] == 4 {
+                    progress.push(msg);
+                }
+            }
+            let counts = progress
+                .iter()
+                .map(|msg| {
+                    (
+                        msg["opened"].as_u64().unwrap(),
+                        msg["failed"].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(counts, [(1, 0), (2, 0), (2, 1), (2, 2)]);
+            assert_eq!(progress[1]["message"], "opened 2/4");
+            let failure = progress[2]["message"].as_str().unwrap();
+            assert!(
+                failure.starts_with("connection 3/4 failed to open"),
+                "{failure}"
+            );
+        };
+        let result = std::panic::AssertUnwindSafe(test).catch_unwind().await;
+        admin
+            .batch_execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+                 WHERE usename = 'dbc_limited';
+                 DROP ROLE dbc_limited",
+            )
+            .await
+            .unwrap();
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
     }
 }
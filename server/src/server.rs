@@ -1,16 +1,246 @@
 pub mod routes;
-use poem::listener::{Acceptor, Listener, TcpAcceptor, TcpListener};
+use poem::listener::{Acceptor, AcceptorExt, BoxAcceptor, Listener, TcpAcceptor, TcpListener};
+use poem::web::{LocalAddr, RemoteAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 
 #[cfg(feature = "bundle")]
 pub mod webview;
 #[cfg(feature = "bundle")]
 pub use webview::WebView;
 
-pub async fn bind_acceptor(addr: &str) -> (TcpAcceptor, u16) {
+/// The host the headless server binds to unless `API_HOST` is set.
+pub const DEFAULT_API_HOST: &str = "127.0.0.1";
+
+/// The address to bind the server to, from an `API_HOST` (an IPv4 or IPv6 address,
+/// defaulting to `DEFAULT_API_HOST`) and port.
+pub fn server_addr(host: Option<&str>, port: u16) -> eyre::Result<SocketAddr> {
+    let host = host.map(str::trim).filter(|h| !h.is_empty());
+    let ip = host
+        .unwrap_or(DEFAULT_API_HOST)
+        // allow IPv6 addresses to be given in their bracketed URL form, e.g. `[::1]`
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|_| eyre::eyre!("API_HOST {:?} isn't a valid IP address", host.unwrap()))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// How long a client has to complete the TLS handshake before it's disconnected.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The certificate chain and (PKCS #8) private key to serve HTTPS with, both PEM files.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsConfig {
+    /// The config from `TLS_CERT` and `TLS_KEY`, if set; they must be set together.
+    pub fn from_env() -> eyre::Result<Option<Self>> {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+        match (var("TLS_CERT"), var("TLS_KEY")) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                cert: shellexpand::tilde(&cert).as_ref().into(),
+                key: shellexpand::tilde(&key).as_ref().into(),
+            })),
+            (None, None) => Ok(None),
+            _ => eyre::bail!("TLS_CERT and TLS_KEY must be set together"),
+        }
+    }
+
+    fn acceptor(&self) -> eyre::Result<tokio_native_tls::TlsAcceptor> {
+        let read = |path: &PathBuf| {
+            std::fs::read(path)
+                .map_err(|err| eyre::eyre!("failed to read {}: {err}", path.display()))
+        };
+        let identity = native_tls::Identity::from_pkcs8(&read(&self.cert)?, &read(&self.key)?)
+            .map_err(|err| eyre::eyre!("invalid TLS certificate or key: {err}"))?;
+        Ok(native_tls::TlsAcceptor::new(identity)?.into())
+    }
+}
+
+/// Bind the server to `addr`, serving HTTPS if `tls` is given. Returns the bound port,
+/// which is assigned by the system if `addr` uses port 0.
+pub async fn bind_acceptor(
+    addr: &str,
+    tls: Option<&TlsConfig>,
+) -> eyre::Result<(BoxAcceptor, u16)> {
     let acceptor = TcpListener::bind(addr)
         .into_acceptor()
         .await
-        .expect("valid server host/port");
+        .map_err(|err| eyre::eyre!("failed to bind to {addr}: {err}"))?;
     let server_port = acceptor.local_addr()[0].as_socket_addr().unwrap().port();
-    (acceptor, server_port)
+    let acceptor = match tls {
+        Some(tls) => TlsAcceptor::new(acceptor, tls.acceptor()?).boxed(),
+        None => acceptor.boxed(),
+    };
+    Ok((acceptor, server_port))
+}
+
+/// A TCP connection that has completed its TLS handshake.
+type TlsConnection = (
+    tokio_native_tls::TlsStream<TcpStream>,
+    LocalAddr,
+    RemoteAddr,
+);
+
+/// Accepts TLS connections. Handshakes happen in the background, so that a slow (or
+/// stalled) client doesn't hold up any others.
+struct TlsAcceptor {
+    local_addr: Vec<LocalAddr>,
+    connections: mpsc::Receiver<TlsConnection>,
+}
+
+impl TlsAcceptor {
+    fn new(mut tcp: TcpAcceptor, tls: tokio_native_tls::TlsAcceptor) -> Self {
+        let local_addr = tcp.local_addr();
+        let (tx, connections) = mpsc::channel(128);
+
+        tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = tcp.accept() => accepted,
+                    // stop accepting once the server has shut down
+                    _ = tx.closed() => break,
+                };
+                let (stream, local_addr, remote_addr, _) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("failed to accept connection: {err}");
+                        continue;
+                    }
+                };
+
+                let (tls, tx) = (tls.clone(), tx.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, local_addr, remote_addr)).await;
+                        }
+                        Ok(Err(err)) => tracing::debug!("TLS handshake failed: {err}"),
+                        Err(_) => tracing::debug!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Self {
+            local_addr,
+            connections,
+        }
+    }
+}
+
+impl Acceptor for TlsAcceptor {
+    type Io = tokio_native_tls::TlsStream<TcpStream>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.local_addr.clone()
+    }
+
+    async fn accept(
+        &mut self,
+    ) -> std::io::Result<(Self::Io, LocalAddr, RemoteAddr, poem::http::uri::Scheme)> {
+        match self.connections.recv().await {
+            Some((stream, local_addr, remote_addr)) => Ok((
+                stream,
+                local_addr,
+                remote_addr,
+                poem::http::uri::Scheme::HTTPS,
+            )),
+            None => Err(std::io::Error::other("TLS listener stopped")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_server_addr() {
+        let addr = |host| server_addr(host, 5100).map(|addr| addr.to_string());
+
+        assert_eq!(addr(None).unwrap(), "127.0.0.1:5100");
+        assert_eq!(addr(Some("")).unwrap(), "127.0.0.1:5100");
+        assert_eq!(addr(Some("0.0.0.0")).unwrap(), "0.0.0.0:5100");
+        assert_eq!(addr(Some("::1")).unwrap(), "[::1]:5100");
+        assert_eq!(addr(Some("[::]")).unwrap(), "[::]:5100");
+        assert_eq!(
+            addr(Some("localhost")).unwrap_err().to_string(),
+            "API_HOST \"localhost\" isn't a valid IP address"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_https_when_configured() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/server/testdata");
+        let tls = TlsConfig {
+            cert: testdata.join("localhost.pem"),
+            key: testdata.join("localhost.key"),
+        };
+        let (acceptor, port) = bind_acceptor("127.0.0.1:0", Some(&tls)).await.unwrap();
+        let app = poem::Route::new().at("/", poem::get(poem::endpoint::make_sync(|_| "hello")));
+        tokio::spawn(poem::Server::new_with_acceptor(acceptor).run(app));
+
+        // trust the self-signed certificate
+        let cert = std::fs::read(&tls.cert).unwrap();
+        let connector = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let connector = tokio_native_tls::TlsConnector::from(connector);
+
+        // plain HTTP isn't served
+        let mut plain = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        let _ = plain.read_to_end(&mut buf).await;
+        assert!(!buf.starts_with(b"HTTP/1.1 200"));
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut stream = connector.connect("localhost", stream).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("hello"), "{response}");
+    }
+
+    #[test]
+    fn rejects_invalid_tls_config() {
+        let tls = TlsConfig {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+        };
+        let err = tls.acceptor().map(drop).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("failed to read /nonexistent/cert.pem")
+        );
+
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/server/testdata");
+        let swapped = TlsConfig {
+            cert: testdata.join("localhost.key"),
+            key: testdata.join("localhost.pem"),
+        };
+        let err = swapped.acceptor().map(drop).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid TLS certificate or key")
+        );
+    }
 }
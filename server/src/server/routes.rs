@@ -2,11 +2,11 @@ use futures_util::SinkExt;
 use poem::{
     IntoResponse,
     web::{
-        Data, Json, Path, TypedHeader,
+        Data, Json, Multipart, Path, Query, TypedHeader,
         websocket::{Message, WebSocket},
     },
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, sync::Arc, time::Duration};
 use tokio::time::timeout;
 
@@ -28,29 +28,314 @@ pub async fn format_eyre<E: poem::Endpoint>(
     Ok(res)
 }
 
+/// Reject request bodies larger than `settings.max_query_length` with a 413, before
+/// they're read in full and parsed.
+pub async fn limit_query_length<E: poem::Endpoint>(
+    next: E,
+    mut req: poem::Request,
+) -> poem::Result<poem::Response> {
+    let max = match req.data::<Arc<crate::State>>() {
+        Some(state) => state.config.read().await.settings.max_query_length,
+        None => return Ok(next.call(req).await?.into_response()),
+    };
+
+    let body = read_body_limited(req.take_body(), max).await?;
+    req.set_body(body);
+    Ok(next.call(req).await?.into_response())
+}
+
+async fn read_body_limited(body: poem::Body, max: usize) -> poem::Result<poem::Body> {
+    match body.into_bytes_limit(max).await {
+        Ok(bytes) => Ok(bytes.into()),
+        Err(poem::error::ReadBodyError::PayloadTooLarge) => Err(poem::Error::from_string(
+            format!("request is larger than the maximum query length of {max} bytes"),
+            poem::http::StatusCode::PAYLOAD_TOO_LARGE,
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The token clients must present (as `Authorization: Bearer <token>`), if any. When
+/// unset, the server is open to anyone who can reach it.
+#[derive(Debug, Clone, Default)]
+pub struct ApiToken(pub Option<String>);
+
+/// Routes that never require the `ApiToken`, e.g. for health checks.
+const UNAUTHENTICATED_PATHS: &[&str] = &["/healthz", "/openapi.json"];
+
+#[derive(Debug, Deserialize)]
+struct AccessTokenParams {
+    access_token: Option<String>,
+}
+
+/// Reject requests that don't present the `ApiToken` with a 401. Since browsers can't
+/// set headers on websocket connections, those may pass the token as an `access_token`
+/// query parameter instead (see RFC 6750).
+pub async fn require_api_token<E: poem::Endpoint>(
+    next: E,
+    req: poem::Request,
+) -> poem::Result<poem::Response> {
+    use poem::http::header;
+
+    let expected = match req.data::<ApiToken>() {
+        Some(ApiToken(Some(token))) => token,
+        _ => return Ok(next.call(req).await?.into_response()),
+    };
+    if UNAUTHENTICATED_PATHS.contains(&req.uri().path()) {
+        return Ok(next.call(req).await?.into_response());
+    }
+
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_owned);
+    let is_websocket = req
+        .headers()
+        .get(header::UPGRADE)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    let token = match bearer {
+        Some(token) => Some(token),
+        None if is_websocket => req
+            .params::<AccessTokenParams>()
+            .ok()
+            .and_then(|params| params.access_token),
+        None => None,
+    };
+
+    if !token.is_some_and(|token| tokens_match(&token, expected)) {
+        return Ok(poem::Response::builder()
+            .status(poem::http::StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .content_type("text/plain")
+            .body("missing or invalid API token"));
+    }
+
+    Ok(next.call(req).await?.into_response())
+}
+
+/// Compare tokens in constant time, so that response times don't reveal how much of
+/// a guess was correct. Comparing digests also hides the expected token's length.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    use sha2::Digest;
+
+    let (given, expected) = (sha2::Sha256::digest(given), sha2::Sha256::digest(expected));
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[poem::handler]
+pub fn healthz() -> &'static str {
+    "ok"
+}
+
+/// An OpenAPI description of the query, config and introspection routes. It's
+/// written by hand, so keep it in sync when adding or changing routes.
+#[poem::handler]
+pub fn openapi() -> impl IntoResponse {
+    include_str!("openapi.json").with_content_type("application/json")
+}
+
+/// Server metrics, in the Prometheus text format.
+#[cfg(feature = "metrics")]
 #[poem::handler]
-pub async fn websocket(ws: WebSocket, Path(_channel): Path<String>) -> impl IntoResponse {
+pub async fn metrics(Data(state): Data<&Arc<crate::State>>) -> impl IntoResponse {
+    state
+        .metrics()
+        .await
+        .with_content_type("text/plain; version=0.0.4; charset=utf-8")
+}
+
+/// Responses (and websocket messages) smaller than this aren't worth compressing.
+const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// Marks a response whose body is streamed (e.g. a CSV export), so that
+/// `compress_response` passes it through instead of reading it all into memory.
+#[derive(Clone, Copy)]
+pub struct Streamed;
+
+/// Compress large text and JSON responses with gzip or deflate, if the client accepts
+/// either. Responses that are already encoded, streamed, or aren't text, are left alone.
+pub async fn compress_response<E: poem::Endpoint>(
+    next: E,
+    req: poem::Request,
+) -> poem::Result<poem::Response> {
+    use poem::{http::header, web::Compress};
+
+    let algo = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate_encoding);
+    let res = next.call(req).await?.into_response();
+
+    let Some(algo) = algo else {
+        return Ok(res);
+    };
+    let compressible = res.content_type().is_some_and(|content_type| {
+        content_type.starts_with("text/") || content_type.starts_with("application/json")
+    });
+    if !compressible
+        || res.headers().contains_key(header::CONTENT_ENCODING)
+        || res.extensions().get::<Streamed>().is_some()
+    {
+        return Ok(res);
+    }
+
+    let (parts, body) = res.into_parts();
+    let body = body.into_bytes().await?;
+    let large = body.len() >= COMPRESSION_THRESHOLD;
+    let res = poem::Response::from_parts(parts, body.into());
+    if !large {
+        return Ok(res);
+    }
+
+    let mut res = Compress::new(res, algo).into_response();
+    res.headers_mut().insert(
+        header::VARY,
+        header::HeaderValue::from_static("accept-encoding"),
+    );
+    Ok(res)
+}
+
+/// Pick gzip or deflate (in that order of preference) from an `Accept-Encoding`
+/// header, ignoring any with a quality of 0.
+fn negotiate_encoding(accept: &str) -> Option<poem::web::CompressionAlgo> {
+    use poem::web::CompressionAlgo;
+
+    let accepted = accept
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next()?;
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!rejected).then_some(name)
+        })
+        .collect::<Vec<_>>();
+
+    [
+        ("gzip", CompressionAlgo::GZIP),
+        ("deflate", CompressionAlgo::DEFLATE),
+    ]
+    .into_iter()
+    .find(|(name, _)| accepted.iter().any(|a| a.eq_ignore_ascii_case(name)))
+    .map(|(_, algo)| algo)
+}
+
+#[derive(Deserialize)]
+pub struct WebSocketParams {
+    /// Send messages larger than `COMPRESSION_THRESHOLD` as gzipped binary messages.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// Commands that a websocket client can send, as JSON text messages.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum SocketCommand {
+    /// Only send pool events (`pool_status` and `pool_progress`) for subscribed
+    /// connections, starting with the current status of each of this one's pools.
+    SubscribePoolStatus {
+        connection: String,
+    },
+    UnsubscribePoolStatus {
+        connection: String,
+    },
+}
+
+#[poem::handler]
+pub async fn websocket(
+    ws: WebSocket,
+    Path(_channel): Path<String>,
+    Query(params): Query<WebSocketParams>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> impl IntoResponse {
+    use futures_util::StreamExt;
+
     let (tx, mut rx) = tokio::sync::mpsc::channel(100);
     crate::stream::subscribe(tx).await.unwrap();
+    let state = Arc::clone(state);
 
-    ws.on_upgrade(|mut socket| async move {
-        // use futures_util::StreamExt;
-        // if let Some(Ok(Message::Text(text))) = socket.next().await {
-        //     dbg!(text);
-        //     let _ = socket.send(Message::Text("hello, world!".into())).await;
-        // }
+    ws.on_upgrade(move |mut socket| async move {
+        let encode = |line: String| {
+            if params.compress && line.len() >= COMPRESSION_THRESHOLD {
+                Message::Binary(gzip(line.as_bytes()))
+            } else {
+                Message::Text(line)
+            }
+        };
+        // every connection's pool events are sent until the first subscription
+        let mut subscriptions = None::<HashSet<String>>;
 
         loop {
-            if let Some(line) = rx.recv().await {
-                match socket.send(Message::Text(line)).await {
-                    Err(_) => break,
-                    _ => {}
+            let lines = tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) if should_forward(&subscriptions, &line) => vec![line],
+                    Some(_) => continue,
+                    None => break,
+                },
+                message = socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        match serde_json::from_str::<SocketCommand>(&text) {
+                            Ok(SocketCommand::SubscribePoolStatus { connection }) => {
+                                let events = state.pool_status_events(&connection).await;
+                                subscriptions.get_or_insert_default().insert(connection);
+                                events.iter().map(|event| event.to_message()).collect()
+                            }
+                            Ok(SocketCommand::UnsubscribePoolStatus { connection }) => {
+                                // unsubscribing from the last one leaves none, not every one
+                                subscriptions.get_or_insert_default().remove(&connection);
+                                continue;
+                            }
+                            Err(err) => vec![crate::stream::StreamEvent::Error {
+                                message: format!("invalid command: {err}"),
+                            }
+                            .to_message()],
+                        }
+                    }
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+
+            for line in lines {
+                if socket.send(encode(line)).await.is_err() {
+                    return;
                 }
             }
         }
     })
 }
 
+/// Whether to send a stream message to a socket that's subscribed to the given
+/// connections' pool events (or to every connection's, if it's never subscribed).
+fn should_forward(subscriptions: &Option<HashSet<String>>, msg: &str) -> bool {
+    let Some(subscriptions) = subscriptions else {
+        return true;
+    };
+    crate::stream::pool_event_connection(msg)
+        .is_none_or(|connection| subscriptions.contains(&connection))
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(bytes)
+        .expect("writing to a Vec doesn't fail");
+    encoder.finish().expect("writing to a Vec doesn't fail")
+}
+
 #[poem::handler]
 pub async fn get_config(
     Data(state): Data<&Arc<crate::State>>,
@@ -58,16 +343,95 @@ pub async fn get_config(
     let config = state.config.read().await;
     match timeout(Duration::from_secs(3), state.status()).await? {
         Ok(status) => Ok(Json(serde_json::json!({
-            "connections": config.connections,
+            "connections": config
+                .connections
+                .iter()
+                .map(crate::persistence::Connection::redacted)
+                .collect::<Vec<_>>(),
             "status": status
         }))),
         Err(_) => Err(eyre::eyre!("timed out waiting for status")),
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StatusParams {
+    /// Only include pools for connections with this tag.
+    pub tag: Option<String>,
+}
+
+#[poem::handler]
+pub async fn get_status(
+    Data(state): Data<&Arc<crate::State>>,
+    Query(params): Query<StatusParams>,
+) -> eyre::Result<Json<Vec<serde_json::Value>>> {
+    let mut status = match timeout(Duration::from_secs(3), state.status()).await {
+        Ok(status) => status?,
+        Err(_) => eyre::bail!("timed out waiting for status"),
+    };
+
+    if let Some(tag) = &params.tag {
+        let config = state.config.read().await;
+        let tagged = config
+            .connections
+            .iter()
+            .filter(|conn| conn.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            .map(|conn| conn.name.as_str())
+            .collect::<HashSet<_>>();
+        status.retain(|pool| {
+            pool["connection"]
+                .as_str()
+                .is_some_and(|name| tagged.contains(name))
+        });
+    }
+
+    Ok(Json(status))
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct UpdateConfig {
-    pub connections: Vec<crate::persistence::Connection>,
+    pub connections: Vec<ConnectionUpdate>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ConnectionUpdate {
+    #[serde(flatten)]
+    pub connection: crate::persistence::Connection,
+    /// The name the connection was saved under, if it's being renamed, so that its
+    /// saved credentials carry over.
+    #[serde(default)]
+    pub renamed_from: Option<String>,
+    /// Connect without a password from now on, rather than keeping the saved
+    /// credentials when none are sent.
+    #[serde(default)]
+    pub clear_credentials: bool,
+}
+
+/// Fill in the credentials that clients never see (see `Connection::redacted`) from
+/// the saved connections of the same (or previous) name.
+fn with_saved_credentials(
+    config: &crate::persistence::Store,
+    connections: Vec<ConnectionUpdate>,
+) -> Vec<crate::persistence::Connection> {
+    connections
+        .into_iter()
+        .map(|update| {
+            let mut conn = update.connection;
+            // a client may resend `renamed_from` after the rename was saved
+            let saved = update
+                .renamed_from
+                .iter()
+                .chain([&conn.name])
+                .find_map(|name| config.connections.iter().find(|c| &c.name == name));
+            if update.clear_credentials {
+                conn.clear_credentials();
+            } else if let Some(saved) = saved {
+                conn.keep_credentials(saved);
+            }
+            conn
+        })
+        .collect()
 }
 
 #[poem::handler]
@@ -76,12 +440,18 @@ pub async fn update_config(
     Data(state): Data<&Arc<crate::State>>,
 ) -> eyre::Result<poem::http::StatusCode> {
     let mut config = state.config.write().await;
-    config.connections = updated_config
-        .connections
-        .into_iter()
-        .map(crate::persistence::Connection::from)
-        .collect();
-    config.persist().unwrap();
+    let connections = with_saved_credentials(&config, updated_config.connections);
+    let problems = crate::persistence::validate_connections(&connections);
+    if !problems.is_empty() {
+        let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        eyre::bail!("invalid config:\n{}", problems.join("\n"));
+    }
+    let changes = crate::persistence::ConnectionChanges::between(&config.connections, &connections);
+    config.connections = connections;
+    config.persist()?;
+
+    // only let other clients know once the new config has been saved
+    crate::stream::broadcast_config_changed(changes).await;
 
     // TODO: only changed connections should restart their pools
     crate::stream::broadcast("Settings updated, restarting active connections...").await;
@@ -89,20 +459,20 @@ pub async fn update_config(
     let mut pools = state.pools.lock().await;
     let mut close_pools = HashSet::new();
     let mut reloaded_passwords = HashSet::new();
-    for (conn, pool) in pools.iter_mut() {
+    for (conn_key, pool) in pools.iter_mut() {
         match config
             .connections
             .iter_mut()
-            .find(|c| c.name.eq(&conn.connection))
+            .find(|c| c.name.eq(&conn_key.connection))
         {
             // if the connection is still present in the config, reload the pool
             Some(conn) => {
-                reload_pool(conn, pool, &mut reloaded_passwords).await?;
+                reload_pool(conn, &conn_key.database, pool, &mut reloaded_passwords).await?;
             }
 
             // otherwise, slate for removal
             None => {
-                close_pools.insert(conn.connection.clone());
+                close_pools.insert(conn_key.connection.clone());
             }
         }
     }
@@ -115,6 +485,82 @@ pub async fn update_config(
     Ok(poem::http::StatusCode::NO_CONTENT)
 }
 
+/// Check a proposed config for the problems `update_config` would reject it for,
+/// without saving it.
+#[poem::handler]
+pub async fn validate_config(
+    Json(proposed_config): Json<UpdateConfig>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> Json<Vec<crate::persistence::ConfigProblem>> {
+    let config = state.config.read().await;
+    let connections = with_saved_credentials(&config, proposed_config.connections);
+    Json(crate::persistence::validate_connections(&connections))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportConnectionsParams {
+    /// If set, passwords (and password helpers' arguments and environment) are encrypted
+    /// under this passphrase; otherwise, they're omitted.
+    pub passphrase: Option<String>,
+}
+
+#[poem::handler]
+pub async fn export_connections(
+    Json(params): Json<ExportConnectionsParams>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<Json<crate::persistence::ConnectionExport>> {
+    // deriving the passphrase key is slow, so don't hold up the runtime (or the config)
+    let config = state.config.read().await.clone();
+    let export = tokio::task::spawn_blocking(move || {
+        config.export_connections(
+            params.passphrase.as_deref(),
+            crate::persistence::PASSPHRASE_ROUNDS,
+        )
+    })
+    .await?;
+    Ok(Json(export))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportConnectionsParams {
+    pub export: crate::persistence::ConnectionExport,
+    pub passphrase: Option<String>,
+    #[serde(default)]
+    pub on_conflict: crate::persistence::ImportConflict,
+    /// Replace all existing connections instead of merging.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[poem::handler]
+pub async fn import_connections(
+    Json(params): Json<ImportConnectionsParams>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<Json<crate::persistence::ImportSummary>> {
+    // like exporting, decrypting is slow, so do it before taking the config lock
+    let (mut export, passphrase) = (params.export, params.passphrase);
+    let export = tokio::task::spawn_blocking(move || {
+        export.decrypt(passphrase.as_deref())?;
+        eyre::Ok(export)
+    })
+    .await??;
+
+    let mut config = state.config.write().await;
+    let summary = config.import_connections(export, None, params.on_conflict, params.replace)?;
+    config.persist()?;
+
+    // close pools for any connections that were removed or changed
+    let mut pools = state.pools.lock().await;
+    pools.retain(|k, _| {
+        config.connections.iter().any(|c| c.name == k.connection)
+            && !summary.overwritten.contains(&k.connection)
+            && !summary.imported.contains(&k.connection)
+    });
+
+    Ok(Json(summary))
+}
+
 #[poem::handler]
 pub async fn connection_info(
     Data(state): Data<&Arc<crate::State>>,
@@ -150,7 +596,7 @@ pub async fn reload_connection(
 
     let mut pools = state.pools.lock().await;
     let mut reloaded_passwords = HashSet::new();
-    for (_, pool) in pools
+    for (conn_key, pool) in pools
         .iter_mut()
         .filter(|(conn_key, _)| conn_key.connection == connection)
     {
@@ -159,15 +605,37 @@ pub async fn reload_connection(
             .find(|c| c.name.eq(&connection))
             .unwrap();
 
-        reload_pool(conn, pool, &mut reloaded_passwords).await?;
+        reload_pool(conn, &conn_key.database, pool, &mut reloaded_passwords).await?;
     }
 
     Ok(poem::http::StatusCode::NO_CONTENT)
 }
 
+#[poem::handler]
+pub async fn create_scratch_database(
+    Data(state): Data<&Arc<crate::State>>,
+    Path(connection): Path<String>,
+) -> eyre::Result<Json<serde_json::Value>> {
+    let database = state.create_scratch_database(connection.clone()).await?;
+    Ok(Json(serde_json::json!({
+        "connection": connection,
+        "database": database,
+    })))
+}
+
+#[poem::handler]
+pub async fn drop_scratch_database(
+    Data(state): Data<&Arc<crate::State>>,
+    Path((connection, database)): Path<(String, String)>,
+) -> eyre::Result<poem::http::StatusCode> {
+    state.drop_scratch_database(connection, database).await?;
+    Ok(poem::http::StatusCode::NO_CONTENT)
+}
+
 // TODO: do this without keeping the lock (use PoolState::Pending)
 async fn reload_pool(
     conn: &mut crate::persistence::Connection,
+    database: &str,
     pool: &mut crate::PoolState,
     reloaded_passwords: &mut HashSet<String>,
 ) -> eyre::Result<()> {
@@ -177,23 +645,29 @@ async fn reload_pool(
         reloaded_passwords.insert(conn.name.clone());
     }
 
+    // each pool is for a single database, which may not be the connection's default
+    let conn = &crate::persistence::Connection {
+        database: database.to_owned(),
+        ..conn.clone()
+    };
+
     match pool {
         // if the connection was previously successful, reload it
-        crate::PoolState::Active(pool) => match pool.reload((&*conn).into()).await {
+        crate::PoolState::Active(pool) => match pool.reload(conn.into()).await {
             Ok(_) => crate::stream::broadcast("Pool reloaded successfully.").await,
-            Err(err) => crate::stream::broadcast(err.to_string()).await,
+            Err(err) => crate::stream::broadcast_error(err.to_string()).await,
         },
 
         // if the connection failed previously, try to create it again
         // using the new configuration
         crate::PoolState::Failed(_) => {
-            *pool = crate::create_pool(&conn).await?;
+            *pool = crate::create_pool(conn).await?;
         }
 
         // if the connection is pending, cancel it and create a new one
         crate::PoolState::Pending { cancel, .. } => {
             cancel.take().unwrap().send(()).unwrap();
-            *pool = crate::create_pool(&conn).await?;
+            *pool = crate::create_pool(conn).await?;
         }
     }
 
@@ -209,35 +683,249 @@ pub async fn get_databases(
     Ok(Json(crate::db::list_databases(&conn).await?.row_maps()))
 }
 
+#[poem::handler]
+pub async fn get_session_info(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<Json<crate::db::SessionInfo>> {
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(crate::db::session_info(&conn).await?))
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    /// Only search this schema, rather than every non-system schema.
+    pub schema: Option<String>,
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    50
+}
+
+#[poem::handler]
+pub async fn search_objects(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Query(params): Query<SearchParams>,
+) -> eyre::Result<Json<Vec<crate::db::SearchResult>>> {
+    if params.q.trim().is_empty() {
+        eyre::bail!("search term can't be empty");
+    }
+    crate::db::validate_identifiers(params.schema.as_slice())?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(
+        crate::db::search_objects(&conn, &params.q, params.schema.as_deref(), params.limit).await?,
+    ))
+}
+
 #[poem::handler]
 pub async fn get_schemas(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
-    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    database: Option<TypedHeader<headers::XDatabase>>,
     Data(state): Data<&Arc<crate::State>>,
 ) -> eyre::Result<Json<crate::db::QueryRows>> {
-    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
     Ok(Json(crate::db::list_schemas(&conn).await?.row_maps()))
 }
 
+/// List the extensions available on the server, and which are installed.
 #[poem::handler]
-pub async fn get_tables(
+pub async fn get_extensions(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<Json<Vec<crate::db::ExtensionInfo>>> {
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(crate::db::list_extensions(&conn).await?))
+}
+
+#[derive(Deserialize)]
+pub struct CreateExtensionParams {
+    pub name: String,
+}
+
+/// Install an extension (`CREATE EXTENSION IF NOT EXISTS`), if `allow_create_extensions`
+/// is set. Since this changes the database, it has to be confirmed with the
+/// `X-Confirm-Destructive: true` header.
+#[poem::handler]
+pub async fn create_extension(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
     TypedHeader(database): TypedHeader<headers::XDatabase>,
+    confirm: Option<TypedHeader<headers::XConfirmDestructive>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<CreateExtensionParams>,
+) -> eyre::Result<Json<Vec<crate::db::ExtensionInfo>>> {
+    if !state.config.read().await.settings.allow_create_extensions {
+        eyre::bail!("installing extensions is disabled (see `settings.allow_create_extensions`)");
+    }
+    if !confirm.is_some_and(|TypedHeader(confirm)| confirm.into()) {
+        eyre::bail!(
+            "Installing extension {:?} must be confirmed with the `X-Confirm-Destructive: true` \
+             header.",
+            params.name
+        );
+    }
+    crate::db::validate_identifiers([&params.name])?;
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let result = crate::db::create_extension(&conn, &params.name).await;
+    conn.invalidate_cache().await;
+    result?;
+    Ok(Json(crate::db::list_extensions(&conn).await?))
+}
+
+#[derive(Deserialize)]
+pub struct ListTablesParams {
+    /// Whether to include pretty-printed sizes (e.g. `12 MB`) alongside sizes in bytes.
+    #[serde(default = "default_true")]
+    pub human_readable: bool,
+    /// Only include relations whose name matches this `ILIKE` pattern, e.g. `user%`.
+    pub name_like: Option<String>,
+    /// Only include relations of this kind (`table`, `view` or `matview`).
+    #[serde(rename = "type")]
+    pub kind: Option<crate::db::TableKind>,
+    /// Order by `name`, `size` or `row_estimate` rather than by kind and then name.
+    pub order_by: Option<crate::db::TableOrder>,
+    /// The direction for `order_by` (`asc` or `desc`), ascending by default.
+    pub direction: Option<crate::db::SortDirection>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[poem::handler]
+pub async fn get_tables(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
     Data(state): Data<&Arc<crate::State>>,
     Path(schema): Path<String>,
+    Query(params): Query<ListTablesParams>,
 ) -> eyre::Result<Json<crate::db::QueryRows>> {
-    let conn = state.get_conn(connection.into(), database.into()).await?;
-    Ok(Json(crate::db::list_tables(&conn, &schema).await?))
+    crate::db::validate_identifiers([&schema])?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    let filter = crate::db::TableFilter {
+        name_like: params.name_like,
+        kind: params.kind,
+    };
+    Ok(Json(
+        crate::db::list_tables(
+            &conn,
+            &schema,
+            &filter,
+            params.order_by.map(|order| {
+                (
+                    order,
+                    params.direction.unwrap_or(crate::db::SortDirection::Asc),
+                )
+            }),
+            params.human_readable,
+        )
+        .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ColumnStatsParams {
+    /// If the table hasn't been analyzed, compute statistics from a sample of its rows.
+    #[serde(default)]
+    pub sample: bool,
+}
+
+#[poem::handler]
+pub async fn get_column_stats(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, table)): Path<(String, String)>,
+    Query(params): Query<ColumnStatsParams>,
+) -> eyre::Result<Json<serde_json::Map<String, serde_json::Value>>> {
+    crate::db::validate_identifiers([&schema, &table])?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(
+        crate::db::column_stats(&conn, &schema, &table, params.sample).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ColumnValuesParams {
+    #[serde(default = "default_column_values_limit")]
+    pub limit: usize,
+    /// Only include values starting with this.
+    pub search: Option<String>,
+}
+
+fn default_column_values_limit() -> usize {
+    100
+}
+
+#[poem::handler]
+pub async fn get_column_values(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, table, column)): Path<(String, String, String)>,
+    Query(params): Query<ColumnValuesParams>,
+) -> eyre::Result<Json<crate::db::ColumnValues>> {
+    crate::db::validate_identifiers([&schema, &table, &column])?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(
+        crate::db::column_values(
+            &conn,
+            &schema,
+            &table,
+            &column,
+            params.limit,
+            params.search.as_deref(),
+        )
+        .await?,
+    ))
+}
+
+/// List a schema's functions, with each overload's arguments.
+#[poem::handler]
+pub async fn get_functions(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path(schema): Path<String>,
+) -> eyre::Result<Json<Vec<crate::db::FunctionInfo>>> {
+    crate::db::validate_identifiers([&schema])?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(crate::db::list_functions(&conn, &schema).await?))
 }
 
 #[poem::handler]
 pub async fn get_columns(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
-    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    database: Option<TypedHeader<headers::XDatabase>>,
     Data(state): Data<&Arc<crate::State>>,
     Path((schema, table)): Path<(String, String)>,
 ) -> eyre::Result<Json<serde_json::Value>> {
-    let conn = state.get_conn(connection.into(), database.into()).await?;
+    crate::db::validate_identifiers([&schema, &table])?;
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
     Ok(Json(
         crate::db::list_columns(&conn, &schema, &table)
             .await?
@@ -253,6 +941,43 @@ pub async fn get_columns(
     ))
 }
 
+#[derive(Deserialize)]
+pub struct ColumnsBatchTable {
+    pub schema: String,
+    pub table: String,
+}
+
+#[derive(Deserialize)]
+pub struct ColumnsBatchParams {
+    pub tables: Vec<ColumnsBatchTable>,
+}
+
+/// List the columns of several tables (possibly in different schemas) in one request,
+/// grouped by table.
+#[poem::handler]
+pub async fn get_columns_batch(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    database: Option<TypedHeader<headers::XDatabase>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<ColumnsBatchParams>,
+) -> eyre::Result<Json<Vec<crate::db::TableColumns>>> {
+    crate::db::validate_identifiers(
+        params
+            .tables
+            .iter()
+            .flat_map(|table| [&table.schema, &table.table]),
+    )?;
+    let tables = params
+        .tables
+        .into_iter()
+        .map(|table| (table.schema, table.table))
+        .collect::<Vec<_>>();
+    let conn = state
+        .get_conn_or_default(connection.into(), database.map(|TypedHeader(db)| db.into()))
+        .await?;
+    Ok(Json(crate::db::list_columns_batch(&conn, &tables).await?))
+}
+
 #[poem::handler]
 pub async fn get_table_ddl(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
@@ -260,47 +985,321 @@ pub async fn get_table_ddl(
     Data(state): Data<&Arc<crate::State>>,
     Path((schema, table)): Path<(String, String)>,
 ) -> eyre::Result<Json<serde_json::Value>> {
+    crate::db::validate_identifiers([&schema, &table])?;
     let conn = state.get_conn(connection.into(), database.into()).await?;
     let ddl = crate::db::table_ddl(&conn, &schema, &table).await?;
     Ok(Json(serde_json::json!({ "ddl": ddl })))
 }
 
 #[poem::handler]
-pub async fn get_view_ddl(
+pub async fn analyze_table(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
     TypedHeader(database): TypedHeader<headers::XDatabase>,
     Data(state): Data<&Arc<crate::State>>,
-    Path((schema, view)): Path<(String, String)>,
-) -> eyre::Result<Json<serde_json::Value>> {
+    Path((schema, table)): Path<(String, String)>,
+) -> eyre::Result<poem::http::StatusCode> {
+    crate::db::validate_identifiers([&schema, &table])?;
     let conn = state.get_conn(connection.into(), database.into()).await?;
-    let ddl = crate::db::view_ddl(&conn, &schema, &view).await?;
-    Ok(Json(serde_json::json!({ "ddl": ddl })))
+    crate::db::analyze_table(&conn, &schema, &table).await?;
+    Ok(poem::http::StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct NotifyParams {
+    pub channel: String,
+    #[serde(default)]
+    pub payload: String,
 }
 
+/// Send a notification to the channel's listeners, e.g. to trigger application events.
 #[poem::handler]
-pub async fn get_materialized_view_ddl(
+pub async fn notify(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
     TypedHeader(database): TypedHeader<headers::XDatabase>,
     Data(state): Data<&Arc<crate::State>>,
-    Path((schema, view)): Path<(String, String)>,
-) -> eyre::Result<Json<serde_json::Value>> {
+    Json(params): Json<NotifyParams>,
+) -> eyre::Result<poem::http::StatusCode> {
     let conn = state.get_conn(connection.into(), database.into()).await?;
-    let ddl = crate::db::materialized_view_ddl(&conn, &schema, &view).await?;
-    Ok(Json(serde_json::json!({ "ddl": ddl })))
+    crate::db::notify(&conn, &params.channel, &params.payload).await?;
+    Ok(poem::http::StatusCode::NO_CONTENT)
 }
 
 #[derive(Deserialize)]
-struct QueryParams {
-    pub query: String,
-    pub params: Option<Vec<serde_json::Value>>,
-    pub sort: Option<crate::db::Sort>,
+pub struct EditableRowsParams {
+    /// 1-indexed page number.
+    #[serde(default = "default_rows_page")]
     pub page: usize,
-    /// If page size is negative, return all rows.
-    pub page_size: isize,
-    pub filters: Option<Vec<crate::db::Filter>>,
+    /// If not set, the connection's `default_page_size` is used.
+    pub page_size: Option<usize>,
 }
 
-#[derive(Debug)]
+fn default_rows_page() -> usize {
+    1
+}
+
+/// Get a page of a table's rows for editing, along with each row's version.
+#[poem::handler]
+pub async fn get_editable_rows(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, table)): Path<(String, String)>,
+    Query(params): Query<EditableRowsParams>,
+) -> poem::Result<Json<crate::db::EditableRows>> {
+    let result = async {
+        crate::db::validate_identifiers([&schema, &table])?;
+        let conn = state.get_conn(connection.into(), database.into()).await?;
+        crate::db::editable_rows(&conn, &schema, &table, params.page, params.page_size).await
+    };
+    result.await.map(Json).map_err(row_editing_error)
+}
+
+/// Update a single row, responding with `409 Conflict` if it was changed since the
+/// version the client read.
+#[poem::handler]
+pub async fn update_row(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, table)): Path<(String, String)>,
+    Json(update): Json<crate::db::RowUpdate>,
+) -> poem::Result<Json<serde_json::Value>> {
+    let result = async {
+        crate::db::validate_identifiers([&schema, &table])?;
+        let conn = state.get_conn(connection.into(), database.into()).await?;
+        let result = crate::db::update_row(&conn, &schema, &table, &update).await;
+        conn.invalidate_cache().await;
+        result
+    };
+    let version = result.await.map_err(row_editing_error)?;
+    Ok(Json(serde_json::json!({ "version": version })))
+}
+
+/// Respond with `409 Conflict` to a `RowConflict`, and `400 Bad Request` to an
+/// `InvalidRequest`.
+fn row_editing_error(err: eyre::Report) -> poem::Error {
+    let status = if err.is::<crate::db::RowConflict>() {
+        poem::http::StatusCode::CONFLICT
+    } else if err.is::<crate::db::InvalidRequest>() {
+        poem::http::StatusCode::BAD_REQUEST
+    } else {
+        return err.into();
+    };
+    poem::Error::from_string(err.to_string(), status)
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceParams {
+    pub action: crate::db::MaintenanceAction,
+    /// Run `VACUUM FULL`, which locks the table for the duration.
+    #[serde(default)]
+    pub full: bool,
+}
+
+/// Run a maintenance command against a table on a dedicated connection,
+/// streaming its verbose output.
+#[poem::handler]
+pub async fn run_table_maintenance(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, table)): Path<(String, String)>,
+    Json(params): Json<MaintenanceParams>,
+) -> eyre::Result<poem::http::StatusCode> {
+    crate::db::validate_identifiers([&schema, &table])?;
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let conn = state
+        .connect_unpooled(connection.into(), database.into(), tx)
+        .await?;
+
+    // forward verbose output until the connection is closed
+    let output = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let tokio_postgres::AsyncMessage::Notice(notice) = message {
+                crate::stream::broadcast_event(crate::stream::StreamEvent::QueryProgress {
+                    message: notice.message().to_owned(),
+                })
+                .await;
+            }
+        }
+    });
+
+    let name = params.action.name();
+    let full = if params.full { " FULL" } else { "" };
+    crate::stream::broadcast(format!("Running {name}{full} on {schema}.{table}...")).await;
+
+    let res = crate::db::run_maintenance(&conn, params.action, &schema, &table, params.full).await;
+    drop(conn);
+    let _ = output.await;
+
+    match res {
+        Ok(_) => {
+            crate::stream::broadcast(format!("{name}{full} on {schema}.{table} complete.")).await;
+            Ok(poem::http::StatusCode::NO_CONTENT)
+        }
+        Err(err) => {
+            crate::stream::broadcast_error(format!(
+                "{name}{full} on {schema}.{table} failed: {err}"
+            ))
+            .await;
+            Err(err)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TableRef {
+    pub connection: String,
+    /// If not provided, the connection's default database is used.
+    pub database: Option<String>,
+    pub schema: String,
+    pub table: String,
+}
+
+impl TableRef {
+    async fn get_conn(
+        &self,
+        state: &crate::State,
+    ) -> eyre::Result<crate::pool::CheckedOutConnection> {
+        match &self.database {
+            Some(database) => {
+                state
+                    .get_conn(self.connection.clone(), database.clone())
+                    .await
+            }
+            None => state.get_default_conn(self.connection.clone()).await,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TableDdlDiffParams {
+    /// The table to be altered.
+    pub source: TableRef,
+    /// The table whose structure `source` should be altered to match.
+    pub target: TableRef,
+}
+
+#[poem::handler]
+pub async fn diff_table_ddl(
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<TableDdlDiffParams>,
+) -> eyre::Result<Json<serde_json::Value>> {
+    crate::db::validate_identifiers([
+        &params.source.schema,
+        &params.source.table,
+        &params.target.schema,
+        &params.target.table,
+    ])?;
+    let source_conn = params.source.get_conn(state).await?;
+    let target_conn = params.target.get_conn(state).await?;
+    let diff = crate::db::table_ddl_diff(
+        &source_conn,
+        (&params.source.schema, &params.source.table),
+        &target_conn,
+        (&params.target.schema, &params.target.table),
+    )
+    .await?;
+    Ok(Json(serde_json::json!({
+        "ddl": diff.statements.join("\n"),
+        "statements": diff.statements,
+        "warnings": diff.warnings,
+    })))
+}
+
+#[poem::handler]
+pub async fn get_view_ddl(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, view)): Path<(String, String)>,
+) -> eyre::Result<Json<serde_json::Value>> {
+    crate::db::validate_identifiers([&schema, &view])?;
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let ddl = crate::db::view_ddl(&conn, &schema, &view).await?;
+    Ok(Json(serde_json::json!({ "ddl": ddl })))
+}
+
+#[derive(Deserialize)]
+pub struct FunctionDdlParams {
+    /// The overload's identity arguments, as listed by `get_functions` (empty for a
+    /// function without any).
+    #[serde(default)]
+    pub args: String,
+}
+
+#[poem::handler]
+pub async fn get_function_ddl(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, function)): Path<(String, String)>,
+    Query(params): Query<FunctionDdlParams>,
+) -> eyre::Result<Json<serde_json::Value>> {
+    crate::db::validate_identifiers([&schema, &function])?;
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let ddl = crate::db::function_ddl(&conn, &schema, &function, &params.args).await?;
+    Ok(Json(serde_json::json!({ "ddl": ddl })))
+}
+
+#[poem::handler]
+pub async fn get_materialized_view_ddl(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Path((schema, view)): Path<(String, String)>,
+) -> eyre::Result<Json<serde_json::Value>> {
+    crate::db::validate_identifiers([&schema, &view])?;
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let ddl = crate::db::materialized_view_ddl(&conn, &schema, &view).await?;
+    Ok(Json(serde_json::json!({ "ddl": ddl })))
+}
+
+/// The body of `/query` (and the other routes that run a query). Its `page_size` may be
+/// negative to request every row on a single page; this is still clamped to the
+/// connection's `max_page_size` unless the query has its own `LIMIT`.
+#[derive(Deserialize)]
+struct QueryParams {
+    pub query: String,
+    pub params: Option<Vec<serde_json::Value>>,
+    #[serde(flatten)]
+    pub options: crate::db::QueryOptions,
+}
+
+#[derive(Serialize)]
+pub struct QueryResponse {
+    #[serde(flatten)]
+    pub result: crate::db::PaginatedQueryResult,
+    /// Set if the query had more than one statement, of which only the first was run
+    /// (see `QueryOptions::reject_multiple`).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub extra_statements_ignored: bool,
+    /// How many times the query was retried after conflicting with another transaction
+    /// (see `db::paginated_query_with_retries`).
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    /// Whether the result was reused from an earlier run of the query (see
+    /// `QueryOptions::cache`).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+impl QueryResponse {
+    fn new(query: &str, result: crate::db::PaginatedQueryResult) -> Self {
+        Self {
+            result,
+            extra_statements_ignored: crate::db::has_extra_statements(query),
+            retries: 0,
+            cached: false,
+        }
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Debug)]
 pub enum PaginatedQueryError {
     Eyre(eyre::Report),
     DbError(crate::db::PgError),
@@ -319,7 +1318,12 @@ impl std::fmt::Display for PaginatedQueryError {
 
 impl poem::error::ResponseError for PaginatedQueryError {
     fn status(&self) -> poem::http::StatusCode {
-        poem::http::StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            PaginatedQueryError::Eyre(err) if err.is::<crate::db::InvalidRequest>() => {
+                poem::http::StatusCode::BAD_REQUEST
+            }
+            _ => poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 
     fn as_response(&self) -> poem::Response
@@ -342,6 +1346,7 @@ impl poem::error::ResponseError for PaginatedQueryError {
                             "position": err.position(),
                             "message": err.message(),
                             "severity": err.severity(),
+                            "retryable": err.is_retryable(),
                         })
                         .to_string(),
                     );
@@ -357,34 +1362,352 @@ impl poem::error::ResponseError for PaginatedQueryError {
 pub async fn handle_query(
     TypedHeader(connection): TypedHeader<headers::XConnName>,
     TypedHeader(database): TypedHeader<headers::XDatabase>,
+    confirm: Option<TypedHeader<headers::XConfirmDestructive>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(mut params): Json<QueryParams>,
+) -> Result<Json<QueryResponse>, PaginatedQueryError> {
+    if let Some(TypedHeader(confirm)) = confirm {
+        params.options.confirm |= bool::from(confirm);
+    }
+
+    let (connection, database) = (String::from(connection), String::from(database));
+    let query_params = params.params.take().unwrap_or_default();
+
+    // if the connection was dropped (e.g. the server restarted), transparently retry
+    // read-only queries on a fresh connection
+    let read_only = crate::db::is_read_only(&params.query);
+    let (result, retries, cached) = crate::db::retry_if_disconnected(read_only, || {
+        let (connection, database) = (connection.clone(), database.clone());
+        let (params, query_params) = (&params, &query_params);
+        async move {
+            let mut conn = state.get_conn(connection, database).await?;
+            let key = (params.options.cache && read_only).then(|| {
+                crate::cache::ResultCache::key(&params.query, query_params, &params.options)
+            });
+            if let Some(key) = &key
+                && let Some(cache) = conn.result_cache()
+                && let Some(result) = cache.get(key).await
+            {
+                return Ok((result, 0, true));
+            }
+
+            let result = crate::db::paginated_query_with_retries(
+                &conn,
+                &params.query,
+                query_params,
+                &params.options,
+            )
+            .await;
+            if let Err(err) = &result
+                && crate::db::is_disconnect(err)
+            {
+                conn.kill();
+            }
+            conn.invalidate_cache_after(&params.query).await;
+            let (result, retries) = result?;
+
+            if let Some(key) = key
+                && let Some(cache) = conn.result_cache()
+            {
+                cache.insert(key, &params.query, result.clone()).await;
+            }
+            Ok((result, retries, false))
+        }
+    })
+    .await
+    .map_err(|err| match err.downcast::<crate::db::PgError>() {
+        Ok(err) => PaginatedQueryError::DbError(err),
+        Err(err) => PaginatedQueryError::Eyre(err),
+    })?;
+
+    Ok(Json(QueryResponse {
+        retries,
+        cached,
+        ..QueryResponse::new(&params.query, result)
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ScriptResponse {
+    /// Each statement's result, in order.
+    pub results: Vec<crate::db::PaginatedQueryResult>,
+}
+
+/// Run an uploaded SQL file (e.g. a migration) statement by statement. The request is
+/// `multipart/form-data`, with the script in its `file` field; an optional `params`
+/// field holds a JSON array of parameters (for a single-statement script), and
+/// `transaction=true` runs the whole script in one transaction (at the isolation level
+/// in the `isolation_level` field, e.g. `serializable`, if there is one).
+#[poem::handler]
+pub async fn run_script(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    confirm: Option<TypedHeader<headers::XConfirmDestructive>>,
+    Data(state): Data<&Arc<crate::State>>,
+    mut multipart: Multipart,
+) -> eyre::Result<Json<ScriptResponse>> {
+    let mut options = crate::db::ScriptOptions {
+        confirm: confirm.is_some_and(|TypedHeader(confirm)| confirm.into()),
+        ..Default::default()
+    };
+    let mut script = None;
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("file") => script = Some(field.text().await?),
+            Some("params") => options.params = serde_json::from_str(&field.text().await?)?,
+            Some("transaction") => options.transaction = field.text().await?.trim() == "true",
+            Some("isolation_level") => {
+                let level = field.text().await?.trim().to_owned();
+                options.isolation_level = Some(serde_json::from_value(level.into())?);
+            }
+            _ => {}
+        }
+    }
+    let Some(script) = script else {
+        eyre::bail!("missing `file` field with the script to run");
+    };
+
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let results = crate::db::run_script(&conn, &script, &options).await;
+    // statements before a failing one may have been committed
+    conn.invalidate_cache().await;
+    let results = results?;
+    Ok(Json(ScriptResponse { results }))
+}
+
+/// Run a query on the connection pinned to session `id` (see `State::session_conn`),
+/// so that temp tables and other session state persist across queries.
+#[poem::handler]
+pub async fn handle_session_query(
+    Path(id): Path<String>,
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    confirm: Option<TypedHeader<headers::XConfirmDestructive>>,
     Data(state): Data<&Arc<crate::State>>,
-    Json(params): Json<QueryParams>,
-) -> Result<Json<crate::db::PaginatedQueryResult>, PaginatedQueryError> {
+    Json(mut params): Json<QueryParams>,
+) -> Result<Json<QueryResponse>, PaginatedQueryError> {
+    if let Some(TypedHeader(confirm)) = confirm {
+        params.options.confirm |= bool::from(confirm);
+    }
+
     let conn = state
-        .get_conn(connection.into(), database.into())
+        .session_conn(&id, connection.into(), database.into())
         .await
-        .map_err(|err| PaginatedQueryError::Eyre(err))?;
-    Ok(Json(
-        crate::db::paginated_query(
-            &conn,
-            &params.query,
-            &params.params.unwrap_or_default(),
-            &params.filters.unwrap_or_default(),
-            params.page,
-            params.page_size,
-            params.sort,
-        )
+        .map_err(PaginatedQueryError::Eyre)?;
+    let conn = conn.lock().await;
+    let query_params = params.params.unwrap_or_default();
+    let result =
+        crate::db::paginated_query(&conn, &params.query, &query_params, &params.options).await;
+    conn.invalidate_cache_after(&params.query).await;
+    let result = result.map_err(|err| match err.downcast::<crate::db::PgError>() {
+        Ok(err) => PaginatedQueryError::DbError(err),
+        Err(err) => PaginatedQueryError::Eyre(err),
+    })?;
+
+    Ok(Json(QueryResponse::new(&params.query, result)))
+}
+
+/// End a session, dropping its temp tables and releasing its connection.
+#[poem::handler]
+pub async fn end_session(
+    Path(id): Path<String>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<poem::http::StatusCode> {
+    match state.end_session(&id).await? {
+        true => Ok(poem::http::StatusCode::NO_CONTENT),
+        false => Ok(poem::http::StatusCode::NOT_FOUND),
+    }
+}
+
+/// Start running a query in the background, returning a job id to poll for its
+/// result (see `get_async_query`) instead of holding the request open.
+#[poem::handler]
+pub async fn submit_async_query(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    confirm: Option<TypedHeader<headers::XConfirmDestructive>>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(mut params): Json<QueryParams>,
+) -> poem::Result<impl IntoResponse> {
+    if let Some(TypedHeader(confirm)) = confirm {
+        params.options.confirm |= bool::from(confirm);
+    }
+
+    let (connection, database) = (String::from(connection), String::from(database));
+    let key = crate::ConnectionKey {
+        connection: connection.clone(),
+        database: database.clone(),
+    };
+    let query_params = params.params.take().unwrap_or_default();
+    let pool = Arc::clone(state);
+    let id = state
+        .jobs
+        .submit(key, |job| async move {
+            let conn = job
+                .started(pool.get_conn(connection, database).await?)
+                .await?;
+            let result = crate::db::paginated_query_with_retries(
+                &conn,
+                &params.query,
+                &query_params,
+                &params.options,
+            )
+            .await;
+            conn.invalidate_cache_after(&params.query).await;
+            let (result, retries) = result?;
+            Ok(serde_json::to_value(QueryResponse {
+                retries,
+                ..QueryResponse::new(&params.query, result)
+            })?)
+        })
         .await
-        .map_err(|err| match err.downcast::<crate::db::PgError>() {
-            Ok(err) => PaginatedQueryError::DbError(err),
-            Err(err) => PaginatedQueryError::Eyre(err),
-        })?,
-    ))
+        .map_err(|err| {
+            poem::Error::from_string(err.to_string(), poem::http::StatusCode::TOO_MANY_REQUESTS)
+        })?;
+
+    Ok(Json(serde_json::json!({ "id": id })).with_status(poem::http::StatusCode::ACCEPTED))
+}
+
+/// Get a background query's status: `pending`, `running`, `done` (with its `result`),
+/// `error` or `cancelled`.
+#[poem::handler]
+pub async fn get_async_query(
+    Path(id): Path<String>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> poem::Result<Json<crate::jobs::JobStatus>> {
+    match state.jobs.status(&id).await {
+        Some(status) => Ok(Json(status)),
+        None => Err(poem::Error::from_status(poem::http::StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Cancel a background query, or discard its result if it's already finished.
+#[poem::handler]
+pub async fn cancel_async_query(
+    Path(id): Path<String>,
+    Data(state): Data<&Arc<crate::State>>,
+) -> eyre::Result<poem::http::StatusCode> {
+    match state.jobs.cancel(&id).await? {
+        true => Ok(poem::http::StatusCode::NO_CONTENT),
+        false => Ok(poem::http::StatusCode::NOT_FOUND),
+    }
+}
+
+/// How many connections a federated query is run against at once.
+const FEDERATED_QUERY_CONCURRENCY: usize = 4;
+
+#[derive(Deserialize)]
+pub struct FederatedQueryParams {
+    pub query: String,
+    pub params: Option<Vec<serde_json::Value>>,
+    pub connections: Vec<String>,
+    /// The database to query on every connection; if not set, each connection's
+    /// default database is used.
+    pub database: Option<String>,
+    #[serde(flatten)]
+    pub options: crate::db::QueryOptions,
+}
+
+#[derive(Serialize)]
+pub struct FederatedQueryResponse {
+    pub results: Vec<crate::db::FederatedQueryResult>,
+    pub merged: crate::db::MergedQueryResult,
+}
+
+/// Run a read-only query against several connections. A failure on one connection
+/// is reported in its result instead of failing the whole request.
+#[poem::handler]
+pub async fn federated_query(
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<FederatedQueryParams>,
+) -> eyre::Result<Json<FederatedQueryResponse>> {
+    use crate::db::{FederatedQueryResult, PaginatedQueryResult};
+    use futures_util::StreamExt;
+
+    if !crate::db::is_read_only(&params.query) {
+        eyre::bail!("only SELECT queries can be run across multiple connections");
+    }
+
+    let query_params = params.params.clone().unwrap_or_default();
+    let results = futures_util::stream::iter(params.connections.iter().cloned())
+        .map(|connection| {
+            let (params, query_params) = (&params, &query_params);
+            async move {
+                let result = async {
+                    let conn = state
+                        .get_conn_or_default(connection.clone(), params.database.clone())
+                        .await?;
+                    crate::db::paginated_query(&conn, &params.query, query_params, &params.options)
+                        .await
+                }
+                .await;
+
+                match result {
+                    Ok(result) => FederatedQueryResult::Ok { connection, result },
+                    Err(err) => FederatedQueryResult::Error {
+                        connection,
+                        error: err.to_string(),
+                    },
+                }
+            }
+        })
+        .buffered(FEDERATED_QUERY_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let merged = crate::db::merge_results(results.iter().filter_map(|res| match res {
+        FederatedQueryResult::Ok {
+            connection,
+            result: PaginatedQueryResult::Select { entries, .. },
+        } => Some((connection.as_str(), entries)),
+        _ => None,
+    }));
+
+    Ok(Json(FederatedQueryResponse { results, merged }))
+}
+
+#[derive(Deserialize)]
+pub struct ExportCsvParams {
+    pub query: String,
+    #[serde(flatten)]
+    pub options: crate::db::CsvOptions,
+}
+
+/// Export a query's results as CSV, streamed straight from Postgres.
+#[poem::handler]
+pub async fn export_csv(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<ExportCsvParams>,
+) -> eyre::Result<poem::Response> {
+    use futures_util::TryStreamExt;
+
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    let rows = crate::db::copy_csv(&conn, &params.query, &params.options).await?;
+
+    // hold on to the connection until the export finishes, so it isn't reused meanwhile
+    let rows = rows.map_err(std::io::Error::other).inspect_ok(move |_| {
+        let _conn = &conn;
+    });
+    Ok(poem::Response::builder()
+        .content_type("text/csv")
+        .header("content-disposition", "attachment; filename=\"export.csv\"")
+        .extension(Streamed)
+        .body(poem::Body::from_bytes_stream(rows)))
+}
+
+#[poem::handler]
+pub async fn format_query(Json(params): Json<PrepareQueryParams>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "query": crate::format::format(&params.query) }))
 }
 
 #[derive(Deserialize)]
 pub struct PrepareQueryParams {
     pub query: String,
+    /// Explicit parameter types (see `db::param_types`).
+    #[serde(default)]
+    pub param_types: Vec<String>,
 }
 
 #[poem::handler]
@@ -398,7 +1721,9 @@ pub async fn prepare_query(
         .get_conn(connection.into(), database.into())
         .await
         .map_err(|err| PaginatedQueryError::Eyre(err))?;
-    let stmt = crate::db::prepare(&conn, &params.query)
+    let param_types =
+        crate::db::param_types(&params.param_types).map_err(PaginatedQueryError::Eyre)?;
+    let stmt = crate::db::prepare_typed(&conn, &params.query, &param_types)
         .await
         .map_err(|err| match err.downcast::<crate::db::PgError>() {
             Ok(err) => PaginatedQueryError::DbError(err),
@@ -406,10 +1731,1097 @@ pub async fn prepare_query(
         })?;
 
     Ok(Json(serde_json::json!({
+        "params": stmt.prepared_params(),
         "columns": stmt.columns,
-        "params": stmt.params().iter().enumerate().map(|(i, p)| serde_json::json!({
-            "name": format!("${}", i + 1),
-            "type": p.name(),
-        })).collect::<Vec<_>>(),
     })))
 }
+
+#[derive(Deserialize)]
+pub struct PrepareBatchParams {
+    pub queries: Vec<String>,
+}
+
+/// Prepare several queries without running them, reporting each one's columns and
+/// params, or why it failed to prepare.
+#[poem::handler]
+pub async fn prepare_batch(
+    TypedHeader(connection): TypedHeader<headers::XConnName>,
+    TypedHeader(database): TypedHeader<headers::XDatabase>,
+    Data(state): Data<&Arc<crate::State>>,
+    Json(params): Json<PrepareBatchParams>,
+) -> eyre::Result<Json<Vec<crate::db::PrepareResult>>> {
+    let conn = state.get_conn(connection.into(), database.into()).await?;
+    Ok(Json(crate::db::prepare_batch(&conn, &params.queries).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::{Endpoint, EndpointExt};
+
+    /// A saved connection to the test database (see `db::tests::test_config`) and the
+    /// state serving it, for routes that need a database.
+    struct TestDb {
+        name: String,
+        config: crate::db::Config,
+        state: Arc<crate::State>,
+    }
+
+    impl TestDb {
+        /// Saves the connection as `name`, after `configure` adjusts it. `None` if `PGHOST`
+        /// isn't set, in which case the test should be skipped.
+        fn new(
+            name: &str,
+            configure: impl FnOnce(&mut crate::persistence::Connection),
+        ) -> Option<Self> {
+            let Some(config) = crate::db::tests::test_config() else {
+                eprintln!("PGHOST not set, skipping");
+                return None;
+            };
+            crate::stream::init_for_tests();
+            let mut connection = crate::tests::test_connection(name, &config);
+            configure(&mut connection);
+            Some(Self {
+                name: name.to_owned(),
+                state: Arc::new(crate::tests::state_with_connections(vec![connection])),
+                config,
+            })
+        }
+
+        /// A request to `uri` on the test database.
+        fn request(&self, method: poem::http::Method, uri: &str) -> poem::RequestBuilder {
+            poem::Request::builder()
+                .method(method)
+                .uri_str(uri)
+                .header("x-conn-name", self.name.as_str())
+                .header("x-database", self.config.database.as_str())
+        }
+
+        /// A `POST` of `body` to `uri` on the test database.
+        fn post(&self, uri: &str, body: serde_json::Value) -> poem::Request {
+            self.request(poem::http::Method::POST, uri)
+                .content_type("application/json")
+                .body(body.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_status_by_tag() {
+        let conn = |name: &str, tag: &str| -> crate::persistence::Connection {
+            toml::from_str(&format!(
+                r#"
+                name = "{name}"
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                database = "postgres"
+                tags = ["{tag}"]
+                "#
+            ))
+            .unwrap()
+        };
+        let pool = |name: &str| {
+            let key = crate::ConnectionKey {
+                connection: name.to_owned(),
+                database: "postgres".to_owned(),
+            };
+            (key, crate::PoolState::Failed("not connected".to_owned()))
+        };
+        let state = Arc::new(crate::State {
+            pools: tokio::sync::Mutex::new([pool("app"), pool("warehouse")].into()),
+            ..crate::tests::state_with_connections(vec![
+                conn("app", "prod"),
+                conn("warehouse", "analytics"),
+            ])
+        });
+
+        let status = |uri: &'static str| {
+            let state = Arc::clone(&state);
+            async move {
+                let mut req = poem::Request::builder()
+                    .uri(poem::http::Uri::from_static(uri))
+                    .finish();
+                req.extensions_mut().insert(state);
+                let res = get_status.call(req).await.unwrap();
+                let status: Vec<serde_json::Value> = res.into_body().into_json().await.unwrap();
+                let mut names = status
+                    .iter()
+                    .map(|pool| pool["connection"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names
+            }
+        };
+
+        assert_eq!(status("/status").await, ["app", "warehouse"]);
+        assert_eq!(status("/status?tag=prod").await, ["app"]);
+        assert_eq!(status("/status?tag=ANALYTICS").await, ["warehouse"]);
+        assert!(status("/status?tag=staging").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_queries_share_a_connection() {
+        let Some(db) = TestDb::new("test", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/session/:id", poem::delete(end_session))
+            .at("/session/:id/query", poem::post(handle_session_query))
+            .data(Arc::clone(&db.state));
+
+        let query = |sql: &str| {
+            db.post(
+                "/session/abc/query",
+                serde_json::json!({ "query": sql, "page": 1, "page_size": 10 }),
+            )
+        };
+        let end = || {
+            poem::Request::builder()
+                .method(poem::http::Method::DELETE)
+                .uri(poem::http::Uri::from_static("/session/abc"))
+                .finish()
+        };
+        let select = "SELECT answer FROM dbc_session_tmp";
+
+        let res = app
+            .get_response(query(
+                "CREATE TEMP TABLE dbc_session_tmp AS SELECT 42 AS answer",
+            ))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+
+        // the temp table is still there, since the session's connection is reused
+        let res = app.get_response(query(select)).await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let result: serde_json::Value = res.into_body().into_json().await.unwrap();
+        assert_eq!(result["entries"]["rows"], serde_json::json!([[42]]));
+
+        // ending the session drops its temp tables
+        assert_eq!(
+            app.get_response(end()).await.status(),
+            poem::http::StatusCode::NO_CONTENT
+        );
+        assert!(db.state.sessions.lock().await.is_empty());
+        let res = app.get_response(query(select)).await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        db.state.end_session("abc").await.unwrap();
+        assert_eq!(
+            app.get_response(end()).await.status(),
+            poem::http::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_uploaded_scripts() {
+        let Some(db) = TestDb::new("test", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query/script", poem::post(run_script))
+            .data(Arc::clone(&db.state));
+
+        let upload = |fields: &[(&str, &str)]| {
+            let mut body = String::new();
+            for (name, value) in fields {
+                let filename = if *name == "file" {
+                    "; filename=\"migration.sql\""
+                } else {
+                    ""
+                };
+                body += &format!(
+                    "--boundary\r\nContent-Disposition: form-data; name=\"{name}\"{filename}\r\n\r\n{value}\r\n"
+                );
+            }
+            body += "--boundary--\r\n";
+            db.request(poem::http::Method::POST, "/query/script")
+                .content_type("multipart/form-data; boundary=boundary")
+                .body(body)
+        };
+
+        let script = "CREATE TABLE dbc_script (n int);
+            INSERT INTO dbc_script VALUES (1), (2);
+            SELECT sum(n)::int FROM dbc_script;
+            DROP TABLE dbc_script;";
+        let res = app.get_response(upload(&[("file", script)])).await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let res: serde_json::Value = res.into_body().into_json().await.unwrap();
+        let results = res["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[1]["affected_rows"], 2);
+        assert_eq!(results[2]["entries"]["rows"], serde_json::json!([[3]]));
+
+        // in a transaction, a failed statement rolls back the ones before it
+        let script = "CREATE TABLE dbc_script (n int); SELECT 1 / 0;";
+        let res = app
+            .get_response(upload(&[("file", script), ("transaction", "true")]))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let err = res.into_body().into_string().await.unwrap();
+        assert!(err.starts_with("statement 2 of 2 failed"), "{err}");
+
+        // nor can a script in a transaction commit (or roll back) part of itself
+        let script = "CREATE TABLE dbc_script (n int); COMMIT; DROP TABLE dbc_script;";
+        let res = app
+            .get_response(upload(&[("file", script), ("transaction", "true")]))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let err = res.into_body().into_string().await.unwrap();
+        assert!(err.starts_with("statement 2 of 3 starts or ends"), "{err}");
+
+        // and can be run at a given isolation level
+        let res = app
+            .get_response(upload(&[
+                ("file", "SELECT current_setting('transaction_isolation')"),
+                ("transaction", "true"),
+                ("isolation_level", "serializable"),
+            ]))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let res: serde_json::Value = res.into_body().into_json().await.unwrap();
+        assert_eq!(
+            res["results"][0]["entries"]["rows"],
+            serde_json::json!([["serializable"]])
+        );
+
+        let res = app
+            .get_response(upload(&[
+                ("file", "SELECT to_regclass($1)::text AS t"),
+                ("params", r#"["dbc_script"]"#),
+            ]))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let res: serde_json::Value = res.into_body().into_json().await.unwrap();
+        assert_eq!(
+            res["results"][0]["entries"]["rows"],
+            serde_json::json!([[null]])
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_async_queries() {
+        let Some(db) = TestDb::new("async", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query/async", poem::post(submit_async_query))
+            .at(
+                "/query/async/:id",
+                poem::get(get_async_query).delete(cancel_async_query),
+            )
+            .data(Arc::clone(&db.state));
+
+        let res = app
+            .get_response(db.post(
+                "/query/async",
+                serde_json::json!({ "query": "SELECT 42 AS answer", "page": 1, "page_size": 10 }),
+            ))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::ACCEPTED);
+        let body: serde_json::Value = res.into_body().into_json().await.unwrap();
+        let uri = format!("/query/async/{}", body["id"].as_str().unwrap());
+
+        let job = loop {
+            let res = app
+                .get_response(poem::Request::builder().uri_str(&uri).finish())
+                .await;
+            assert_eq!(res.status(), poem::http::StatusCode::OK);
+            let job: serde_json::Value = res.into_body().into_json().await.unwrap();
+            if !matches!(job["status"].as_str(), Some("pending" | "running")) {
+                break job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(job["status"], "done");
+        assert_eq!(job["result"]["type"], "select");
+        assert_eq!(job["result"]["entries"]["rows"], serde_json::json!([[42]]));
+
+        let delete = || {
+            poem::Request::builder()
+                .method(poem::http::Method::DELETE)
+                .uri_str(&uri)
+                .finish()
+        };
+        let res = app.get_response(delete()).await;
+        assert_eq!(res.status(), poem::http::StatusCode::NO_CONTENT);
+        let res = app
+            .get_response(poem::Request::builder().uri_str(&uri).finish())
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::NOT_FOUND);
+        let res = app.get_response(delete()).await;
+        assert_eq!(res.status(), poem::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn queries_all_rows_with_a_negative_page_size() {
+        let Some(db) = TestDb::new("all-rows", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query", poem::post(handle_query))
+            .data(Arc::clone(&db.state));
+
+        let query = |sql: &str| {
+            db.post(
+                "/query",
+                serde_json::json!({ "query": sql, "page": 1, "page_size": -1 }),
+            )
+        };
+
+        let res = app
+            .get_response(query("SELECT generate_series(1, 250) LIMIT 250"))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let result: serde_json::Value = res.into_body().into_json().await.unwrap();
+        assert_eq!(result["page_size"], -1);
+        assert_eq!(result["page_count"], 250);
+        assert_eq!(result["total_count"], 250);
+        assert_eq!(result["total_pages"], 1);
+        assert_eq!(result["clamped"], false);
+
+        // without its own `LIMIT`, the query still gets a single page, up to `max_page_size`
+        let res = app
+            .get_response(query("SELECT generate_series(1, 250)"))
+            .await;
+        let result: serde_json::Value = res.into_body().into_json().await.unwrap();
+        assert_eq!(result["page_count"], 250);
+        assert_eq!(result["total_pages"], 1);
+        assert_eq!(result["clamped"], true);
+        assert_eq!(result["requested_page_size"], -1);
+    }
+
+    #[tokio::test]
+    async fn invalidates_cached_results_on_notify() {
+        let Some(db) = TestDb::new("cache", |connection| {
+            connection.listen_for_invalidation = true
+        }) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query", poem::post(handle_query))
+            .data(Arc::clone(&db.state));
+        let query = |sql: &str| {
+            let req = db.post(
+                "/query",
+                serde_json::json!({ "query": sql, "page": 1, "cache": true }),
+            );
+            let app = &app;
+            async move {
+                let res = app.get_response(req).await;
+                assert_eq!(res.status(), poem::http::StatusCode::OK);
+                let result: serde_json::Value = res.into_body().into_json().await.unwrap();
+                let rows = result["entries"]["rows"].as_array().map_or(0, Vec::len);
+                (rows, result["cached"] == true)
+            }
+        };
+
+        // writes go through a separate connection, as they would from another application
+        let other = crate::db::connect(&db.config).await.unwrap();
+        other
+            .batch_execute(
+                "DROP TABLE IF EXISTS dbc_cached; CREATE TABLE dbc_cached (n int); \
+                 INSERT INTO dbc_cached VALUES (1)",
+            )
+            .await
+            .unwrap();
+
+        let sql = "SELECT * FROM dbc_cached";
+        assert_eq!(query(sql).await, (1, false));
+        assert_eq!(query(sql).await, (1, true));
+        assert_eq!(query("SELECT 1").await, (1, false));
+
+        // until it's invalidated, the cached result is stale
+        other
+            .batch_execute("INSERT INTO dbc_cached VALUES (2)")
+            .await
+            .unwrap();
+        assert_eq!(query(sql).await, (1, true));
+
+        // notifications are received asynchronously
+        let invalidated = |sql: &'static str| {
+            let query = &query;
+            async move {
+                for _ in 0..100 {
+                    let (rows, cached) = query(sql).await;
+                    if !cached {
+                        return rows;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                panic!("cached result for {sql:?} was never invalidated");
+            }
+        };
+        other
+            .batch_execute("NOTIFY dbc_invalidate, 'dbc_cached'")
+            .await
+            .unwrap();
+        assert_eq!(invalidated(sql).await, 2);
+        // only results that reference the table are invalidated
+        assert_eq!(query("SELECT 1").await, (1, true));
+
+        // an empty payload invalidates everything
+        other.batch_execute("NOTIFY dbc_invalidate").await.unwrap();
+        assert_eq!(invalidated("SELECT 1").await, 1);
+
+        // so do writes made through the same pool
+        assert_eq!(query(sql).await, (2, false));
+        assert_eq!(query("INSERT INTO dbc_cached VALUES (3)").await, (0, false));
+        assert_eq!(query(sql).await, (3, false));
+
+        other.batch_execute("DROP TABLE dbc_cached").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalidates_cached_results_after_writes() {
+        let Some(db) = TestDb::new("cache_writes", |connection| {
+            connection.listen_for_invalidation = true
+        }) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query", poem::post(handle_query))
+            .at("/query/async", poem::post(submit_async_query))
+            .at("/query/script", poem::post(run_script))
+            .at("/session/:id/query", poem::post(handle_session_query))
+            .at(
+                "/db/schemas/:schema/tables/:table/rows",
+                poem::put(update_row),
+            )
+            .data(Arc::clone(&db.state));
+        let post = |uri: &'static str, body: serde_json::Value| {
+            let req = db.post(uri, body);
+            let app = &app;
+            async move {
+                let res = app.get_response(req).await;
+                assert!(res.status().is_success(), "{}", res.status());
+                res.into_body()
+                    .into_json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let cached_sum = || async {
+            let sql = "SELECT sum(n)::int FROM dbc_cache_writes";
+            let result = post(
+                "/query",
+                serde_json::json!({ "query": sql, "page": 1, "cache": true }),
+            )
+            .await;
+            (
+                result["entries"]["rows"][0][0].clone(),
+                result["cached"] == true,
+            )
+        };
+        let update = |n: i32| format!("UPDATE dbc_cache_writes SET n = {n}");
+
+        let other = crate::db::connect(&db.config).await.unwrap();
+        other
+            .batch_execute(
+                "DROP TABLE IF EXISTS dbc_cache_writes; \
+                 CREATE TABLE dbc_cache_writes (id int PRIMARY KEY, n int); \
+                 INSERT INTO dbc_cache_writes VALUES (1, 1)",
+            )
+            .await
+            .unwrap();
+        assert_eq!(cached_sum().await, (1.into(), false));
+        assert_eq!(cached_sum().await, (1.into(), true));
+
+        // a session's connection is checked out of the same pool
+        post(
+            "/session/s1/query",
+            serde_json::json!({ "query": update(2), "page": 1 }),
+        )
+        .await;
+        assert_eq!(cached_sum().await, (2.into(), false));
+
+        let script = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"file\"; \
+             filename=\"update.sql\"\r\n\r\n{};\r\n--boundary--\r\n",
+            update(3)
+        );
+        let res = app
+            .get_response(
+                db.request(poem::http::Method::POST, "/query/script")
+                    .content_type("multipart/form-data; boundary=boundary")
+                    .body(script),
+            )
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        assert_eq!(cached_sum().await, (3.into(), false));
+
+        let job = post(
+            "/query/async",
+            serde_json::json!({ "query": update(4), "page": 1 }),
+        )
+        .await;
+        let id = job["id"].as_str().unwrap();
+        loop {
+            match db.state.jobs.status(id).await {
+                Some(crate::jobs::JobStatus::Done { .. }) => break,
+                Some(crate::jobs::JobStatus::Pending | crate::jobs::JobStatus::Running) => {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                status => panic!("job didn't finish: {status:?}"),
+            }
+        }
+        assert_eq!(cached_sum().await, (4.into(), false));
+
+        let version: String = other
+            .query_one("SELECT xmin::text FROM dbc_cache_writes WHERE id = 1", &[])
+            .await
+            .unwrap()
+            .get(0);
+        let res = app
+            .get_response(
+                db.request(
+                    poem::http::Method::PUT,
+                    "/db/schemas/public/tables/dbc_cache_writes/rows",
+                )
+                .content_type("application/json")
+                .body(
+                    serde_json::json!({
+                        "key": { "id": 1 },
+                        "version": version,
+                        "values": { "n": 5 },
+                    })
+                    .to_string(),
+                ),
+            )
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        assert_eq!(cached_sum().await, (5.into(), false));
+
+        db.state.end_session("s1").await.unwrap();
+        other
+            .batch_execute("DROP TABLE dbc_cache_writes")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lists_schemas_in_the_default_database() {
+        let Some(db) = TestDb::new("schemas", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/db/schemas", poem::get(get_schemas))
+            .data(Arc::clone(&db.state));
+
+        // without `X-Database`, the connection's own database is used
+        let res = app
+            .get_response(
+                poem::Request::builder()
+                    .uri(poem::http::Uri::from_static("/db/schemas"))
+                    .header("x-conn-name", "schemas")
+                    .finish(),
+            )
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let schemas: Vec<serde_json::Value> = res.into_body().into_json().await.unwrap();
+        assert!(
+            schemas
+                .iter()
+                .any(|schema| schema["schema_name"] == "public")
+        );
+        assert!(
+            schemas
+                .iter()
+                .all(|schema| schema["catalog_name"] == db.config.database.as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_drop_statements_only_once_confirmed() {
+        let Some(db) = TestDb::new("confirm", |connection| {
+            connection.require_confirm_destructive = true
+        }) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/query", poem::post(handle_query))
+            .data(Arc::clone(&db.state));
+
+        let query = |sql: &str, confirm: bool| {
+            let mut req = db
+                .request(poem::http::Method::POST, "/query")
+                .content_type("application/json");
+            if confirm {
+                req = req.header("x-confirm-destructive", "true");
+            }
+            req.body(serde_json::json!({ "query": sql, "page": 1 }).to_string())
+        };
+
+        let res = app
+            .get_response(query(
+                "CREATE TABLE IF NOT EXISTS dbc_confirm_guard (n int)",
+                false,
+            ))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+
+        let res = app
+            .get_response(query("DROP TABLE dbc_confirm_guard", false))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let err = res.into_body().into_string().await.unwrap();
+        assert!(err.starts_with("Blocked DROP statement"), "{err}");
+        let res = app
+            .get_response(query("SELECT * FROM dbc_confirm_guard", false))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+
+        let res = app
+            .get_response(query("DROP TABLE dbc_confirm_guard", true))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let res = app
+            .get_response(query("SELECT * FROM dbc_confirm_guard", false))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn counts_queries_in_metrics() {
+        let Some(db) = TestDb::new("metrics", |_| {}) else {
+            return;
+        };
+        let app = poem::Route::new()
+            .at("/metrics", poem::get(metrics))
+            .at("/query", poem::post(handle_query))
+            .data(Arc::clone(&db.state));
+
+        let query = |sql: &str| {
+            db.post(
+                "/query",
+                serde_json::json!({ "query": sql, "page": 1, "page_size": 10 }),
+            )
+        };
+        let scrape = || async {
+            let res = app
+                .get_response(
+                    poem::Request::builder()
+                        .uri(poem::http::Uri::from_static("/metrics"))
+                        .finish(),
+                )
+                .await;
+            assert_eq!(res.status(), poem::http::StatusCode::OK);
+            res.into_body().into_string().await.unwrap()
+        };
+        // other tests run queries too, so counters may have gone up by more than expected
+        let value = |scraped: &str, name: &str| {
+            scraped
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .map_or(0, |value| value.parse::<u64>().unwrap())
+        };
+
+        let before = scrape().await;
+        let res = app.get_response(query("SELECT 1")).await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        let res = app
+            .get_response(query("SELECT * FROM dbc_missing_table"))
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let after = scrape().await;
+
+        let increase = |name: &str| value(&after, name) - value(&before, name);
+        assert!(increase("dbc_queries_total") >= 2);
+        assert!(increase("dbc_query_errors_total{code=\"42P01\"}") >= 1);
+        assert!(increase("dbc_query_duration_seconds_count") >= 2);
+        assert!(increase("dbc_pool_checkout_wait_seconds_count") >= 2);
+        // the pool opened for the queries is reported
+        let pool = format!(
+            "dbc_pool_connections{{connection=\"metrics\",database=\"{}\"}}",
+            db.config.database
+        );
+        assert!(value(&after, &pool) >= 1);
+        assert!(after.contains("# TYPE dbc_query_duration_seconds histogram"));
+    }
+
+    #[tokio::test]
+    async fn only_installs_extensions_if_allowed() {
+        let state = Arc::new(crate::tests::state_with_connections(Vec::new()));
+        let app = poem::Route::new()
+            .at("/db/extensions", poem::post(create_extension))
+            .data(Arc::clone(&state));
+
+        let res = app
+            .get_response(
+                poem::Request::builder()
+                    .method(poem::http::Method::POST)
+                    .uri(poem::http::Uri::from_static("/db/extensions"))
+                    .header("x-conn-name", "app")
+                    .header("x-database", "postgres")
+                    .header("x-confirm-destructive", "true")
+                    .content_type("application/json")
+                    .body(serde_json::json!({ "name": "hstore" }).to_string()),
+            )
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let err = res.into_body().into_string().await.unwrap();
+        assert!(
+            err.starts_with("installing extensions is disabled"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn validates_proposed_configs() {
+        let conn = |name: &str, extra: &str| -> crate::persistence::Connection {
+            toml::from_str(&format!(
+                r#"
+                name = "{name}"
+                host = "localhost"
+                port = 5432
+                username = "postgres"
+                database = "postgres"
+                {extra}
+                "#
+            ))
+            .unwrap()
+        };
+        let saved = conn("app", r#"password = "hunter2""#);
+        let state = Arc::new(crate::tests::state_with_connections(vec![saved.clone()]));
+        let app = poem::Route::new()
+            .at("/config/validate", poem::post(validate_config))
+            .data(Arc::clone(&state));
+        let validate = |connections: Vec<serde_json::Value>| {
+            let app = &app;
+            async move {
+                let req = poem::Request::builder()
+                    .method(poem::http::Method::POST)
+                    .uri(poem::http::Uri::from_static("/config/validate"))
+                    .content_type("application/json")
+                    .body(serde_json::json!({ "connections": connections }).to_string());
+                let res = app.get_response(req).await;
+                assert_eq!(res.status(), poem::http::StatusCode::OK);
+                res.into_body()
+                    .into_json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let json = |conn: crate::persistence::Connection| serde_json::to_value(conn).unwrap();
+        let redacted =
+            |conn: &crate::persistence::Connection| serde_json::to_value(conn.redacted()).unwrap();
+
+        // the saved connection's password counts, even though clients never see it
+        let added = conn("warehouse", r#"password_file = "/bin/pw""#);
+        assert_eq!(
+            validate(vec![redacted(&saved), json(added)]).await,
+            serde_json::json!([])
+        );
+
+        let duplicate = crate::persistence::Connection {
+            port: 0,
+            ..conn("app", "")
+        };
+        let problems = validate(vec![redacted(&saved), json(duplicate), json(conn("", ""))]).await;
+        let fields = problems
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| (p["index"].as_u64().unwrap(), p["field"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [(1, "name"), (1, "port"), (2, "name"), (2, "password")]
+        );
+        assert_eq!(problems[0]["connection"], "app");
+        assert_eq!(
+            problems[0]["message"],
+            "another connection is named \"app\""
+        );
+
+        // nothing was saved
+        assert_eq!(state.config.read().await.connections, [saved]);
+    }
+
+    #[tokio::test]
+    async fn requires_api_token_when_set() {
+        use poem::http::StatusCode;
+
+        let app = poem::Route::new()
+            .at("/healthz", poem::get(healthz))
+            .at(
+                "/config",
+                poem::get(poem::endpoint::make_sync(|_| "config")),
+            )
+            .around(require_api_token)
+            .data(ApiToken(Some("s3cret".to_owned())));
+        let get = |uri: &'static str, auth: Option<&'static str>| {
+            let app = &app;
+            async move {
+                let mut req = poem::Request::builder().uri(poem::http::Uri::from_static(uri));
+                if let Some(auth) = auth {
+                    req = req.header("authorization", auth);
+                }
+                app.get_response(req.finish()).await.status()
+            }
+        };
+
+        assert_eq!(get("/config", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            get("/config", Some("Bearer wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get("/config", Some("s3cret")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // only websockets may pass the token as a query parameter
+        assert_eq!(
+            get("/config?access_token=s3cret", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(get("/config", Some("Bearer s3cret")).await, StatusCode::OK);
+        assert_eq!(get("/healthz", None).await, StatusCode::OK);
+        let upgrade = |uri: &'static str| {
+            poem::Request::builder()
+                .uri(poem::http::Uri::from_static(uri))
+                .header("upgrade", "websocket")
+                .finish()
+        };
+        let res = app
+            .get_response(upgrade("/config?access_token=s3cret"))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .get_response(upgrade("/config?access_token=wrong"))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // without a token, everything is open
+        let open = poem::Route::new()
+            .at(
+                "/config",
+                poem::get(poem::endpoint::make_sync(|_| "config")),
+            )
+            .around(require_api_token)
+            .data(ApiToken(None));
+        let res = open.get_response(poem::Request::builder().uri_str("/config").finish());
+        assert_eq!(res.await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn serves_openapi_description() {
+        let app = poem::Route::new()
+            .at("/openapi.json", poem::get(openapi))
+            .around(require_api_token)
+            .data(ApiToken(Some("s3cret".to_owned())));
+        let res = app
+            .get_response(poem::Request::builder().uri_str("/openapi.json").finish())
+            .await;
+        assert_eq!(res.status(), poem::http::StatusCode::OK);
+        assert_eq!(res.content_type(), Some("application/json"));
+
+        let spec: serde_json::Value =
+            serde_json::from_str(&res.into_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(spec["openapi"], "3.1.0");
+
+        // every route registered in `main` is documented, except for the websocket and
+        // the ones that are only there for development
+        let undocumented = ["/:channel", "/metrics", "/state", "/reset"];
+        let documented = spec["paths"].as_object().unwrap();
+        let route = regex::Regex::new(r#"\.at\(\s*"([^"]+)""#).unwrap();
+        let routes = route
+            .captures_iter(include_str!("../main.rs"))
+            .map(|captures| captures[1].to_owned())
+            .filter(|route| !undocumented.contains(&route.as_str()))
+            .collect::<Vec<_>>();
+        assert!(routes.len() > 40, "{routes:?}");
+        for route in routes {
+            // routes nested under a prefix (e.g. `/db`) are registered without it
+            let path = regex::Regex::new(":([a-z_]+)")
+                .unwrap()
+                .replace_all(&route, "{$1}");
+            assert!(
+                documented.keys().any(|p| p.ends_with(path.as_ref())),
+                "missing {route}"
+            );
+        }
+        let headers = spec["components"]["parameters"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect::<HashSet<_>>();
+        assert!(headers.contains("x-conn-name"));
+        assert!(headers.contains("x-database"));
+
+        // every reference resolves
+        let text = serde_json::to_string(&spec).unwrap();
+        for reference in text.split("\"$ref\":\"").skip(1) {
+            let pointer = reference.split('"').next().unwrap();
+            let pointer = pointer.strip_prefix('#').unwrap();
+            assert!(spec.pointer(pointer).is_some(), "dangling $ref {pointer}");
+        }
+    }
+
+    #[test]
+    fn forwards_pool_events_for_subscribed_connections() {
+        use crate::stream::{PoolStatus, StreamEvent};
+
+        let status = |connection: &str| {
+            StreamEvent::PoolStatus {
+                connection: Some(connection.to_owned()),
+                database: "postgres".to_owned(),
+                status: PoolStatus::Open,
+                message: "open".to_owned(),
+            }
+            .to_message()
+        };
+        let log = StreamEvent::Log {
+            text: "hello\n".to_owned(),
+        }
+        .to_message();
+
+        let mut subscriptions = None;
+        assert!(should_forward(&subscriptions, &status("app")));
+        assert!(should_forward(&subscriptions, &status("warehouse")));
+
+        subscriptions = Some(HashSet::from(["app".to_owned()]));
+        assert!(should_forward(&subscriptions, &status("app")));
+        assert!(!should_forward(&subscriptions, &status("warehouse")));
+        assert!(should_forward(&subscriptions, &log));
+
+        // unsubscribing from every connection doesn't go back to forwarding them all
+        subscriptions = Some(HashSet::new());
+        assert!(!should_forward(&subscriptions, &status("app")));
+        assert!(!should_forward(&subscriptions, &status("warehouse")));
+        assert!(should_forward(&subscriptions, &log));
+    }
+
+    #[test]
+    fn carries_saved_credentials_over_renames_unless_cleared() {
+        let saved: crate::persistence::Connection = toml::from_str(
+            r#"
+            name = "app"
+            host = "localhost"
+            port = 5432
+            username = "postgres"
+            password = "hunter2"
+            database = "postgres"
+            "#,
+        )
+        .unwrap();
+        let config = crate::persistence::Store {
+            connections: vec![saved.clone()],
+            ..Default::default()
+        };
+        let update = |extra: serde_json::Value| -> ConnectionUpdate {
+            let mut update = serde_json::to_value(saved.redacted().connection).unwrap();
+            update["name"] = "renamed".into();
+            update
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value(update).unwrap()
+        };
+        let password = |update| {
+            with_saved_credentials(&config, vec![update])[0]
+                .password
+                .clone()
+        };
+
+        assert_eq!(password(update(serde_json::json!({}))), None);
+        assert_eq!(
+            password(update(serde_json::json!({ "renamed_from": "app" }))).as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(
+            password(update(
+                serde_json::json!({ "renamed_from": "app", "clear_credentials": true })
+            ))
+            .as_deref(),
+            Some("")
+        );
+    }
+
+    #[test]
+    fn responds_to_invalid_requests_with_bad_request() {
+        use poem::error::ResponseError;
+
+        let err = crate::db::InvalidRequest("invalid page 0".to_owned());
+        let err = PaginatedQueryError::Eyre(err.into());
+        assert_eq!(err.status(), poem::http::StatusCode::BAD_REQUEST);
+        let err = PaginatedQueryError::Eyre(eyre::eyre!("connection refused"));
+        assert_eq!(err.status(), poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn compresses_large_responses_unless_streamed() {
+        let text = "x".repeat(COMPRESSION_THRESHOLD);
+        let app = poem::Route::new()
+            .at(
+                "/buffered",
+                poem::endpoint::make_sync({
+                    let text = text.clone();
+                    move |_| text.clone()
+                }),
+            )
+            .at(
+                "/streamed",
+                poem::endpoint::make_sync(move |_| {
+                    let chunks = [Ok::<_, std::io::Error>(text.clone())];
+                    poem::Response::builder()
+                        .content_type("text/csv")
+                        .extension(Streamed)
+                        .body(poem::Body::from_bytes_stream(futures_util::stream::iter(
+                            chunks,
+                        )))
+                }),
+            )
+            .around(compress_response);
+        let get = |uri: &'static str| {
+            poem::Request::builder()
+                .uri(poem::http::Uri::from_static(uri))
+                .header("accept-encoding", "gzip")
+                .finish()
+        };
+
+        let res = app.get_response(get("/buffered")).await;
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+        let res = app.get_response(get("/streamed")).await;
+        assert!(!res.headers().contains_key("content-encoding"));
+        let body = res.into_body().into_string().await.unwrap();
+        assert_eq!(body.len(), COMPRESSION_THRESHOLD);
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3cre", "s3cret"));
+        assert!(!tokens_match("", "s3cret"));
+        assert!(!tokens_match("S3CRET", "s3cret"));
+    }
+
+    #[test]
+    fn flags_ignored_statements() {
+        let response = |query| {
+            let result = crate::db::PaginatedQueryResult::ModifyStructure;
+            serde_json::to_value(QueryResponse::new(query, result)).unwrap()
+        };
+        assert_eq!(
+            response("CREATE TABLE a (); CREATE TABLE b ()"),
+            serde_json::json!({ "type": "modify-structure", "extra_statements_ignored": true })
+        );
+        assert_eq!(
+            response("CREATE TABLE a ();"),
+            serde_json::json!({ "type": "modify-structure" })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_query_bodies() {
+        let body = read_body_limited(poem::Body::from("x".repeat(10)), 10).await;
+        assert_eq!(body.unwrap().into_string().await.unwrap().len(), 10);
+
+        let err = read_body_limited(poem::Body::from("x".repeat(11)), 10)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), poem::http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
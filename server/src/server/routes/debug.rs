@@ -1,7 +1,14 @@
-use poem::web::Data;
+use poem::web::{Data, Json};
 use std::sync::Arc;
 
 #[poem::handler]
 pub async fn get_state(Data(state): Data<&Arc<crate::State>>) -> eyre::Result<String> {
     Ok(state.debug().await)
 }
+
+/// Close every connection pool and end every session, so that each connection is
+/// re-opened from scratch on its next use.
+#[poem::handler]
+pub async fn reset_pools(Data(state): Data<&Arc<crate::State>>) -> Json<crate::PoolReset> {
+    Json(state.reset_pools().await)
+}
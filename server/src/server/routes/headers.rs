@@ -86,3 +86,38 @@ impl Deref for XDatabase {
         &self.0
     }
 }
+
+pub struct XConfirmDestructive(bool);
+
+static X_CONFIRM_DESTRUCTIVE: HeaderName = HeaderName::from_static("x-confirm-destructive");
+
+impl Header for XConfirmDestructive {
+    fn name() -> &'static HeaderName {
+        &X_CONFIRM_DESTRUCTIVE
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, poem::web::headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        Ok(Self(
+            values
+                .next()
+                .ok_or(poem::web::headers::Error::invalid())?
+                .to_str()
+                .map_err(|_| poem::web::headers::Error::invalid())?
+                .eq_ignore_ascii_case("true"),
+        ))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, _values: &mut E) {
+        panic!("not implemented")
+    }
+}
+
+impl From<XConfirmDestructive> for bool {
+    fn from(value: XConfirmDestructive) -> Self {
+        value.0
+    }
+}
@@ -1,7 +1,160 @@
 use std::sync::OnceLock;
 
+use serde::Serialize;
 use tokio::sync::mpsc::{Sender, channel, error::TrySendError};
 
+/// The version of the message envelope; bump this whenever `StreamEvent` changes
+/// in a way that isn't backwards compatible.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Messages larger than this many bytes are split into `Chunk` events, unless
+/// overridden by `STREAM_MAX_CHUNK_SIZE`.
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// An event broadcast to all stream subscribers, serialized to JSON as
+/// `{ "version": 1, "type": "log", ... }`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// Plain text output. `text` is displayed as-is, including its trailing newline (if any).
+    Log {
+        text: String,
+    },
+    /// A connection pool changed state.
+    PoolStatus {
+        /// The connection name, if known (pools only know which database they're for).
+        connection: Option<String>,
+        database: String,
+        status: PoolStatus,
+        message: String,
+    },
+    /// Progress opening the connections in a pool, sent as each one opens (or fails).
+    PoolProgress {
+        connection: Option<String>,
+        database: String,
+        opened: usize,
+        failed: usize,
+        total: usize,
+        message: String,
+    },
+    /// Progress of a long-running query.
+    QueryProgress {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    /// A `NOTIFY` received on a `LISTEN`ing connection.
+    Notification {
+        database: String,
+        channel: String,
+        payload: String,
+    },
+    /// The saved connections were updated; lists the names of those that changed.
+    ConfigChanged {
+        added: Vec<String>,
+        removed: Vec<String>,
+        modified: Vec<String>,
+    },
+    /// A piece of a message that was too large to send at once. The `data` of each
+    /// chunk with the same `id` (sent in order, and never interleaved with other
+    /// messages) concatenate to the original message.
+    Chunk {
+        id: u64,
+        part: ChunkPart,
+        data: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkPart {
+    Start,
+    Continue,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStatus {
+    Opening,
+    Open,
+    Failed,
+    Reloading,
+    Closing,
+    /// All connections were closed, and will be re-opened on next use.
+    Dormant,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a StreamEvent,
+}
+
+impl StreamEvent {
+    pub fn to_message(&self) -> String {
+        serde_json::to_string(&Envelope {
+            version: PROTOCOL_VERSION,
+            event: self,
+        })
+        .expect("stream events are always serializable")
+    }
+}
+
+/// The connection that a `PoolStatus` or `PoolProgress` message is about, if it's one
+/// of those (and names its connection).
+pub fn pool_event_connection(msg: &str) -> Option<String> {
+    // pool events are small enough never to be chunked, so any other message can be
+    // skipped without parsing it
+    let prefix = format!("{{\"version\":{PROTOCOL_VERSION},\"type\":\"pool_");
+    if !msg.starts_with(&prefix) {
+        return None;
+    }
+    let event = serde_json::from_str::<serde_json::Value>(msg).ok()?;
+    event["connection"].as_str().map(str::to_owned)
+}
+
+/// Split a message into `Chunk` events whose `data` is at most `max_size` bytes
+/// (or return it as-is, if it already fits).
+fn chunk_message(id: u64, msg: String, max_size: usize) -> Vec<String> {
+    if msg.len() <= max_size {
+        return vec![msg];
+    }
+
+    let mut pieces = Vec::new();
+    let mut rest = msg.as_str();
+    while !rest.is_empty() {
+        // never split a multi-byte character, but always make progress
+        let end = match rest.floor_char_boundary(max_size) {
+            0 => rest.ceil_char_boundary(1),
+            end => end,
+        };
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+
+    let last = pieces.len() - 1;
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, data)| {
+            StreamEvent::Chunk {
+                id,
+                part: match i {
+                    0 => ChunkPart::Start,
+                    i if i == last => ChunkPart::End,
+                    _ => ChunkPart::Continue,
+                },
+                data: data.to_owned(),
+            }
+            .to_message()
+        })
+        .collect()
+}
+
 pub struct StreamWorker {
     tx: Sender<WorkerMessage>,
 }
@@ -22,8 +175,25 @@ impl WorkerMessage {
 
 static GLOBAL: OnceLock<StreamWorker> = OnceLock::new();
 
-pub fn init() {
-    GLOBAL.get_or_init(|| StreamWorker::new());
+pub fn init(max_chunk_size: usize) {
+    GLOBAL.get_or_init(|| StreamWorker::new(max_chunk_size));
+}
+
+/// Like `init`, but runs the worker on its own runtime, since each `#[tokio::test]`
+/// shuts down its runtime (and so any worker spawned on it) once it finishes.
+#[cfg(test)]
+pub(crate) fn init_for_tests() {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    let runtime = RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .build()
+            .unwrap()
+    });
+    GLOBAL.get_or_init(|| {
+        let _guard = runtime.enter();
+        StreamWorker::new(DEFAULT_MAX_CHUNK_SIZE)
+    });
 }
 
 pub fn global() -> &'static StreamWorker {
@@ -34,51 +204,110 @@ pub async fn subscribe(tx: Sender<String>) -> Result<(), ()> {
     global().subscribe(tx).await
 }
 
+/// Broadcast a line of plain text as a `Log` event.
 pub async fn broadcast<S: Into<String>>(msg: S) {
-    if let Err(msg) = global().broadcast(format!("{}\n", msg.into())).await {
-        tracing::error!("Failed to broadcast message: {msg}");
-    }
+    broadcast_event(StreamEvent::Log {
+        text: format!("{}\n", msg.into()),
+    })
+    .await
 }
 
+/// Broadcast plain text as a `Log` event, without appending a newline.
 pub async fn broadcast_raw<S: Into<String>>(msg: S) {
-    if let Err(msg) = global().broadcast(msg.into()).await {
+    broadcast_event(StreamEvent::Log { text: msg.into() }).await
+}
+
+pub async fn broadcast_error<S: Into<String>>(msg: S) {
+    broadcast_event(StreamEvent::Error {
+        message: msg.into(),
+    })
+    .await
+}
+
+pub async fn broadcast_pool_status<S: Into<String>>(
+    connection: Option<&str>,
+    database: &str,
+    status: PoolStatus,
+    msg: S,
+) {
+    broadcast_event(StreamEvent::PoolStatus {
+        connection: connection.map(str::to_owned),
+        database: database.to_owned(),
+        status,
+        message: msg.into(),
+    })
+    .await
+}
+
+pub async fn broadcast_config_changed(changes: crate::persistence::ConnectionChanges) {
+    broadcast_event(StreamEvent::ConfigChanged {
+        added: changes.added,
+        removed: changes.removed,
+        modified: changes.modified,
+    })
+    .await
+}
+
+pub async fn broadcast_event(event: StreamEvent) {
+    if let Err(msg) = global().broadcast(event.to_message()).await {
         tracing::error!("Failed to broadcast message: {msg}");
     }
 }
 
 impl StreamWorker {
-    pub fn new() -> Self {
+    /// Messages larger than `max_chunk_size` bytes are split into `Chunk` events.
+    pub fn new(max_chunk_size: usize) -> Self {
         let (tx, mut rx) = channel::<WorkerMessage>(100);
 
         tokio::spawn(async move {
-            let mut cache: Vec<String> = Vec::new();
+            // each message's chunks, so that a message is replayed whole or not at all
+            let mut cache: Vec<Vec<String>> = Vec::new();
             let mut txs: Vec<Sender<String>> = Vec::new();
+            let mut next_chunk_id = 0;
 
             'outer: while let Some(msg) = rx.recv().await {
                 match msg {
                     WorkerMessage::Subscribe(tx) => {
-                        // replay all previous messages
-                        for msg in cache.iter() {
-                            match tx.try_send(msg.clone()) {
+                        // replay all previous messages; like broadcasting, a message
+                        // whose chunks don't all fit is skipped entirely
+                        for chunks in cache.iter() {
+                            match tx.try_reserve_many(chunks.len()) {
                                 // if the channel closes, no need to store it
-                                Err(TrySendError::Closed(_)) => continue 'outer,
-                                _ => {}
-                            };
+                                Err(TrySendError::Closed(())) => continue 'outer,
+                                Err(TrySendError::Full(())) => {}
+                                Ok(permits) => {
+                                    for (permit, text) in permits.zip(chunks) {
+                                        permit.send(text.clone());
+                                    }
+                                }
+                            }
                         }
 
                         // subscribe to future messages
                         txs.push(tx);
                     }
                     WorkerMessage::Broadcast(text) => {
-                        // send the message to all current subscribers
-                        txs.retain(|tx| match tx.try_send(text.clone()) {
-                            Err(TrySendError::Closed(_)) => false,
-                            Err(TrySendError::Full(_)) => true,
-                            Ok(_) => true,
+                        let chunks = chunk_message(next_chunk_id, text, max_chunk_size);
+                        if chunks.len() > 1 {
+                            next_chunk_id += 1;
+                        }
+
+                        // send the message to all current subscribers; a subscriber that
+                        // doesn't have room for every chunk misses the whole message,
+                        // rather than getting some of its chunks
+                        txs.retain(|tx| match tx.try_reserve_many(chunks.len()) {
+                            Err(TrySendError::Closed(())) => false,
+                            Err(TrySendError::Full(())) => true,
+                            Ok(permits) => {
+                                for (permit, text) in permits.zip(&chunks) {
+                                    permit.send(text.clone());
+                                }
+                                true
+                            }
                         });
 
                         // store the message for future replays
-                        cache.push(text);
+                        cache.push(chunks);
                     }
                 }
             }
@@ -114,3 +343,118 @@ impl StreamWorker {
             .map_err(|err| err.0.into_message())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn broadcasts_config_changes() {
+        init_for_tests();
+        // leave room for other tests' (replayed) events, since any that don't fit are dropped
+        let (tx, mut rx) = channel(1024);
+        subscribe(tx).await.unwrap();
+
+        broadcast_config_changed(crate::persistence::ConnectionChanges {
+            added: vec!["staging".to_owned()],
+            ..Default::default()
+        })
+        .await;
+
+        // other tests share the stream, so skip over any of their (replayed) events
+        let msg = loop {
+            let msg = rx.recv().await.unwrap();
+            let msg = serde_json::from_str::<serde_json::Value>(&msg).unwrap();
+            if msg["type"] == "config_changed" {
+                break msg;
+            }
+        };
+        assert_eq!(
+            msg,
+            serde_json::json!({
+                "version": PROTOCOL_VERSION,
+                "type": "config_changed",
+                "added": ["staging"],
+                "removed": [],
+                "modified": [],
+            })
+        );
+    }
+
+    #[test]
+    fn finds_the_connection_of_pool_events() {
+        let status = StreamEvent::PoolStatus {
+            connection: Some("app".to_owned()),
+            database: "postgres".to_owned(),
+            status: PoolStatus::Open,
+            message: "open".to_owned(),
+        };
+        assert_eq!(
+            pool_event_connection(&status.to_message()),
+            Some("app".to_owned())
+        );
+        let log = StreamEvent::Log {
+            text: "\"connection\":\"app\"".to_owned(),
+        };
+        assert_eq!(pool_event_connection(&log.to_message()), None);
+    }
+
+    #[tokio::test]
+    async fn reassembles_chunked_messages() {
+        init_for_tests();
+        let (tx, mut rx) = channel(1024);
+        subscribe(tx).await.unwrap();
+
+        // multi-byte characters, offset so that a chunk boundary falls inside one
+        let text = format!("x{}", "é".repeat(DEFAULT_MAX_CHUNK_SIZE));
+        broadcast_raw(text.clone()).await;
+
+        let mut parts = Vec::new();
+        let mut data = String::new();
+        let msg = loop {
+            let msg = rx.recv().await.unwrap();
+            let msg = serde_json::from_str::<serde_json::Value>(&msg).unwrap();
+            if msg["type"] != "chunk" {
+                continue;
+            }
+            assert!(msg["data"].as_str().unwrap().len() <= DEFAULT_MAX_CHUNK_SIZE);
+            if msg["part"] == "start" {
+                parts.clear();
+                data.clear();
+            }
+            parts.push(msg["part"].as_str().unwrap().to_owned());
+            data.push_str(msg["data"].as_str().unwrap());
+            if msg["part"] == "end" {
+                let msg = serde_json::from_str::<serde_json::Value>(&data).unwrap();
+                if msg["text"].as_str() == Some(&text) {
+                    break msg;
+                }
+            }
+        };
+
+        assert_eq!(parts, ["start", "continue", "end"]);
+        assert_eq!(msg["version"], PROTOCOL_VERSION);
+        assert_eq!(msg["type"], "log");
+    }
+
+    #[tokio::test]
+    async fn skips_chunked_messages_that_dont_fit() {
+        let worker = StreamWorker::new(4);
+        let (tx, mut rx) = channel(2);
+        worker.subscribe(tx).await.unwrap();
+
+        // 3 chunks don't fit in the subscriber's channel, so none of them are sent
+        worker.broadcast("abcdefghij".to_owned()).await.unwrap();
+        worker.broadcast("abc".to_owned()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "abc");
+        assert!(rx.try_recv().is_err());
+
+        // the same goes for replaying them to a new subscriber
+        let (tx, mut rx) = channel(2);
+        worker.subscribe(tx).await.unwrap();
+        worker.broadcast("xyz".to_owned()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "abc");
+        assert_eq!(rx.recv().await.unwrap(), "xyz");
+        assert!(rx.try_recv().is_err());
+    }
+}